@@ -1,7 +1,40 @@
+use crate::automaton::{self, Automaton, Cells, Rule};
+use crate::bricks::BrickAtlas;
+use crate::camera::Camera;
+use crate::chunk::{ChunkGrid, BRICK_CELLS};
+use crate::extract::gpu::{Counters, ExtractTarget, GpuExtractor};
+use crate::extract::{Backend, Extractor, Lod, Region, MAX_LOD};
+use crate::fluid::{self, Emitter, Fluid, FluidSettings};
+use crate::gizmo::{self, Drag, GizmoMode, GizmoSettings, HandleFrame};
+use crate::history::{History, HISTORY_BUDGET};
+use crate::import;
+use crate::lines::{LineRenderer, Lines};
+use crate::measure::{self, Measurements};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::outliner::Outliner;
+use crate::pick::{self, Pick, Picker, Ray};
+use crate::postprocess::PostProcess;
+use crate::profiler::Profiler;
+use crate::project::{self, Extraction, Field, Project, PROJECT_VERSION};
+use crate::reaction::{self, Chemicals, GrayScott, ReactionDiffusion};
+use crate::reconstruct;
+use crate::render::{self, Ffmpeg, Motion, Offscreen, RenderSettings};
+use crate::scene::{MaterialHandle, MeshHandle, Node, NodeId, Scene, Transform};
+use crate::sculpt::{Brush, BrushKind, Sculptor};
+use crate::sdf;
 use crate::shader;
+use crate::slice_view::SliceView;
+use crate::stats::{self, FrameStats, StatsOverlay};
+use crate::timeline::{DensityUniforms, Interpolation, Shape, Timeline};
+use crate::uniforms::{UniformRing, UploadBelt};
+use crate::volume::{self, Volume};
+use crate::volume_file::{self, VolumeReader};
 use egui::Context;
-use glam::{vec3, Mat4, UVec3, Vec2, Vec3};
-use std::mem;
+use glam::{uvec3, vec3, Mat4, UVec3, Vec2, Vec3};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::{iter, mem};
 use wgpu::util::DeviceExt;
 use zerocopy::{AsBytes, FromBytes};
 
@@ -11,6 +44,24 @@ pub(crate) struct TriUniforms {
     pub transform: [[f32; 4]; 4],
 }
 
+/// One triangle drawn by instance, see `Uniforms` in `shaders/tri.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, AsBytes, FromBytes)]
+pub(crate) struct TriInstance {
+    pub transform: [[f32; 4]; 4],
+    /// Zero alpha keeps the vertex colors.
+    pub color: [f32; 4],
+}
+
+impl TriInstance {
+    pub fn new(transform: Mat4, color: Option<[f32; 4]>) -> TriInstance {
+        TriInstance {
+            transform: transform.to_cols_array_2d(),
+            color: color.unwrap_or_default(),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Default, Copy, AsBytes, FromBytes)]
 pub(crate) struct Vertex {
@@ -35,33 +86,295 @@ static TRI_VERTEX_DATA: &[Vertex] = &[
 
 static TRI_INDEX_DATA: &[u16] = &[0, 1, 2];
 
+/// Triangles the instance buffer starts out with room for, it grows when
+/// more are drawn.
+const TRI_INSTANCE_CAPACITY: u32 = 64;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Mesh colors, picked with a dynamic offset into `mesh_tint_buf`. The first
+// one is the regular color, followed by one per level of detail and the color
+// of highlighted components.
+const MESH_TINTS: [[f32; 4]; MAX_LOD as usize + 3] = [
+    [0.8, 0.75, 0.7, 1.0],
+    [0.4, 0.8, 0.4, 1.0],
+    [0.9, 0.8, 0.3, 1.0],
+    [0.9, 0.4, 0.3, 1.0],
+    [0.3, 0.6, 1.0, 1.0],
+];
+const HIGHLIGHT_TINT: u32 = MAX_LOD + 2;
+const MESH_TINT_STRIDE: u32 = 256;
+// The colors of the scene's materials follow the fixed tints, materials past
+// `MAX_MATERIALS` get the regular color.
+const MAX_MATERIALS: usize = 16;
+const MATERIAL_TINT_BASE: u32 = MESH_TINTS.len() as u32;
+
+// Transforms of the surface nodes, picked with a dynamic offset into
+// `mesh_storage_buffer` like the tints. Surface nodes past `MAX_SURFACES`
+// aren't drawn.
+const MAX_SURFACES: u32 = 16;
+const MESH_LOCALS_STRIDE: u32 = 256;
+/// Most lines the cell lattice draws across a face of the bounding box.
+const LATTICE_LINES: u32 = 16;
+/// Shown instead of a simulation's settings when its state textures don't fit.
+const GRID_TOO_LARGE: &str = "Simulations need a grid that fits in one 3D texture.";
+
+/// What a gizmo handle moves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum GizmoTarget {
+    Node(NodeId),
+    /// The layer of the slice view, along its axis.
+    Slice,
+}
+
+fn create_depth_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn encode_density(
+    encoder: &mut wgpu::CommandEncoder,
+    cs_pipeline: &wgpu::ComputePipeline,
+    cs_bind_group: &wgpu::BindGroup,
+    texture_size: UVec3,
+) {
+    let mut cs_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+    cs_pass.set_pipeline(cs_pipeline);
+    cs_pass.set_bind_group(0, cs_bind_group, &[]);
+    cs_pass.insert_debug_marker("compute density values");
+    let workgroups = (texture_size + UVec3::splat(3)) / 4;
+    cs_pass.dispatch(workgroups.x, workgroups.y, workgroups.z);
+}
+
+/// A mesh uploaded as is, for meshes that don't come out of an
+/// `ExtractTarget`.
+struct MeshBuffers {
+    vertex_buf: wgpu::Buffer,
+    index_buf: wgpu::Buffer,
+    index_count: u32,
+    vertex_count: u32,
+}
+
+impl MeshBuffers {
+    fn new(device: &wgpu::Device, vertices: &[MeshVertex], indices: &[u32]) -> MeshBuffers {
+        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: vertices.as_bytes(),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: indices.as_bytes(),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        MeshBuffers {
+            vertex_buf,
+            index_buf,
+            index_count: indices.len() as u32,
+            vertex_count: vertices.len() as u32,
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        (self.vertex_count as usize * mem::size_of::<MeshVertex>()
+            + self.index_count as usize * mem::size_of::<u32>()) as u64
+    }
+
+    fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+        pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        pass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+}
+
 pub struct App {
-    x_pos: f32,
+    texture_size: UVec3,
+    camera: Camera,
+    // Size of the window, or of the frames when rendering.
+    width: u32,
+    height: u32,
+    aspect: f32,
+    color_format: wgpu::TextureFormat,
+    depth_view: wgpu::TextureView,
+    depth_bytes: u64,
 
     tri_vertex_buf: wgpu::Buffer,
     tri_index_buf: wgpu::Buffer,
 
-    _bind_group_layout: wgpu::BindGroupLayout,
-    bind_group: wgpu::BindGroup,
+    bind_group_layout: wgpu::BindGroupLayout,
     pipeline: wgpu::RenderPipeline,
-    shader_storage_buffer: wgpu::Buffer,
+    tri_instances: UniformRing<TriInstance>,
+    uploads: UploadBelt,
+
+    mesh_pipeline: wgpu::RenderPipeline,
+    highlight_pipeline: wgpu::RenderPipeline,
+    mesh_storage_buffer: wgpu::Buffer,
+    mesh_tint_buf: wgpu::Buffer,
+    mesh_bind_group: wgpu::BindGroup,
+
+    scene: Scene,
+    outliner: Outliner,
 
     cs_pipeline: wgpu::ComputePipeline,
+    cs_bind_group: wgpu::BindGroup,
     //cs_shader_storage_buffer: wgpu::Buffer,
-    cs_vertex_buf: wgpu::Buffer,
-    cs_index_buf: wgpu::Buffer,
-    // cs_vertex_bind_group_layout: wgpu::BindGroupLayout,
-    // cs_vertex_bind_group: wgpu::BindGroup,
-    // cs_index_bind_group_layout: wgpu::BindGroupLayout,
-    // cs_index_bind_group: wgpu::BindGroup,
-    scalar_data: wgpu::Texture,
+    atlas: BrickAtlas,
+    needs_density: bool,
+    volume: Volume,
+    // Whether a simulation stepped the atlas past `volume`, it's read back
+    // once something needs the CPU copy.
+    volume_behind: bool,
+    slice_view: SliceView,
+    profiler: Profiler,
+    stats: StatsOverlay,
+
+    sculptor: Sculptor,
+    sculpting: bool,
+    brush: Brush,
+    dab_requested: bool,
+    picker: Picker,
+    pick_tooltip: bool,
+    screen_size: Vec2,
+    pointer: Option<Vec2>,
+    /// Surface under the pointer in world space, for the tooltip.
+    hover: Option<Pick>,
+
+    measure_requested: bool,
+    measured_mesh: Mesh,
+    measurements: Option<Measurements>,
+    selected_component: Option<usize>,
+    // Triangles of the selected component.
+    highlight: Option<MeshBuffers>,
+
+    post_process: PostProcess,
+    preview_post_process: bool,
+    // The post-processed mesh drawn instead of the extracted one, rebuilt
+    // when the mesh or the settings change.
+    post_processed: Option<MeshBuffers>,
+    obj_path: String,
+    export_requested: bool,
+    history: History,
+    pending_uploads: Vec<Region>,
+    volume_path: String,
+    volume_save_requested: bool,
+    volume_load_requested: bool,
+    mesh_path: String,
+    points_path: String,
+    /// Imported meshes and point clouds are merged with the current field
+    /// instead of replacing it.
+    import_union: bool,
+    project_path: String,
+    recent_projects: Vec<String>,
+    // Window layout of a project just opened, given to egui on the next frame.
+    pending_layout: Option<egui::Memory>,
+    // Window layout of the project to save, it's written with the field once
+    // that's read back.
+    save_layout: Option<egui::Memory>,
+
+    // The simulations, none when the grid doesn't fit in one 3D texture.
+    reaction: Option<ReactionDiffusion>,
+    gray_scott: GrayScott,
+    reaction_running: bool,
+    // Steps run each frame while running.
+    reaction_steps: u32,
+    reaction_step_requested: bool,
+    reaction_reset_requested: bool,
+    reaction_check_requested: bool,
+    // Largest difference between the GPU and the CPU reference in the last
+    // check.
+    reaction_error: Option<f32>,
+
+    fluid: Option<Fluid>,
+    fluid_settings: FluidSettings,
+    fluid_running: bool,
+    fluid_step_requested: bool,
+    fluid_reset_requested: bool,
+    // Dragging on the view emits density instead of orbiting.
+    fluid_emitting: bool,
+    emit_held: bool,
+    // Where the emitter was on the last step, for the velocity it pushes with.
+    last_emitter: Option<Vec3>,
+
+    automaton: Option<Automaton>,
+    automaton_rule_text: String,
+    // The last rule that parsed, kept while the text is being edited.
+    automaton_rule: Rule,
+    automaton_rule_error: Option<String>,
+    automaton_running: bool,
+    // Frames between steps while running.
+    automaton_interval: u32,
+    automaton_frame: u32,
+    automaton_step_requested: bool,
+    automaton_reset_requested: bool,
+    automaton_check_requested: bool,
+    // Cells the GPU and the CPU reference disagreed on in the last check.
+    automaton_mismatches: Option<usize>,
+    // Share of the cells alive in a new soup.
+    automaton_fill: f32,
+    automaton_seed: u32,
+    automaton_voxels: bool,
+    // Cubes of the cells the automaton last stepped to, drawn instead of the
+    // surface until something else changes the field.
+    voxels: Option<MeshBuffers>,
+    voxels_current: bool,
+
+    // The animated field the density pass fills the grid with.
+    timeline: Timeline,
+    density_buf: wgpu::Buffer,
+    timeline_playing: bool,
+    // The time, the shape or the parameters changed since the field was
+    // last filled.
+    timeline_changed: bool,
+
+    render_settings: RenderSettings,
+    render_requested: bool,
+
+    gizmos: GizmoSettings,
+    lines: LineRenderer,
+    scene_lines: Lines,
+    overlay_lines: Lines,
+    /// Handle under the pointer, and its axis.
+    gizmo_hover: Option<(GizmoTarget, usize)>,
+    gizmo_drag: Option<(GizmoTarget, Drag)>,
+
+    gpu_extractor: GpuExtractor,
+    // The mesh of the whole grid, only there while not in bricks.
+    mesh_target: Option<ExtractTarget>,
+    chunks: ChunkGrid,
+    chunked: bool,
+    last_chunk_extracts: usize,
+    lod: bool,
+    lod_distance: f32,
+    lod_colors: bool,
+    extractor: Extractor,
+    backend: Backend,
+    iso: f32,
+    needs_extract: bool,
+    compare_all: bool,
+    extract_counts: HashMap<(Extractor, Backend), Counters>,
 }
 
 impl App {
     pub fn new(
         device: &wgpu::Device,
         surface_format: &wgpu::TextureFormat,
+        width: u32,
+        height: u32,
         texture_size: UVec3,
+        adapter_info: wgpu::AdapterInfo,
+        present_mode: wgpu::PresentMode,
     ) -> App {
         let tri_vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -75,20 +388,13 @@ impl App {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let shader_storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: mem::size_of::<TriUniforms>() as u64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
                 binding: 0,
                 visibility: wgpu::ShaderStages::VERTEX,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
+                    has_dynamic_offset: true,
                     min_binding_size: None,
                 },
                 count: None,
@@ -96,16 +402,7 @@ impl App {
             label: None,
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(
-                    shader_storage_buffer.as_entire_buffer_binding(),
-                ),
-            }],
-            label: None,
-        });
+        let tri_instances = UniformRing::new(device, &bind_group_layout, TRI_INSTANCE_CAPACITY);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
@@ -151,7 +448,13 @@ impl App {
                 polygon_mode: wgpu::PolygonMode::Fill,
                 conservative: false,
             },
-            depth_stencil: None,
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
             multisample: wgpu::MultisampleState {
                 count: 1,
                 mask: !0,
@@ -180,34 +483,76 @@ impl App {
             multiview: None,
         });
 
-        let cs_bind_group_layout =
+        let atlas = BrickAtlas::new(device, texture_size);
+        let sculptor = Sculptor::new(device, &atlas, texture_size);
+        let picker = Picker::new(device, &atlas);
+        let simulate = texture_size
+            .cmple(UVec3::splat(device.limits().max_texture_dimension_3d))
+            .all();
+        let reaction = simulate.then(|| ReactionDiffusion::new(device, &atlas, texture_size));
+        let fluid = simulate.then(|| Fluid::new(device, &atlas, texture_size));
+        let automaton = simulate.then(|| Automaton::new(device, &atlas, texture_size));
+        let paint_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mesh_storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (MAX_SURFACES * MESH_LOCALS_STRIDE) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut tint_data =
+            vec![0.0f32; (MESH_TINTS.len() + MAX_MATERIALS) * MESH_TINT_STRIDE as usize / 4];
+        for (tint, chunk) in MESH_TINTS
+            .iter()
+            .zip(tint_data.chunks_mut(MESH_TINT_STRIDE as usize / 4))
+        {
+            chunk[..4].copy_from_slice(tint);
+        }
+        let mesh_tint_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: tint_data.as_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mesh_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer {
-                            ty: (wgpu::BufferBindingType::Storage { read_only: false }),
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<TriUniforms>() as u64
+                            ),
                         },
                         count: None,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Storage { read_only: false },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(16),
                         },
                         count: None,
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
-                        visibility: wgpu::ShaderStages::COMPUTE,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
                             view_dimension: wgpu::TextureViewDimension::D3,
                             multisampled: false,
                         },
@@ -215,21 +560,202 @@ impl App {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: None,
+            });
+
+        let mesh_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &mesh_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &mesh_storage_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(mem::size_of::<TriUniforms>() as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &mesh_tint_buf,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(16),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(
+                        &sculptor
+                            .paint_texture()
+                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&paint_sampler),
+                },
+            ],
+            label: None,
+        });
+
+        let mesh_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&mesh_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (mesh_vs_module, mesh_fs_module) = shader::compile(
+            device,
+            include_str!("shaders/mesh.vert"),
+            include_str!("shaders/mesh.frag"),
+        );
+
+        // The highlight is drawn over the mesh with the same vertices, so it
+        // has to pass the depth test where the mesh wrote the same depth.
+        let create_mesh_pipeline = |depth_write_enabled, depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&mesh_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &mesh_vs_module,
+                    entry_point: "main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &[
+                            wgpu::VertexAttribute {
+                                format: wgpu::VertexFormat::Float32x3,
+                                offset: 0,
+                                shader_location: 0,
+                            },
+                            wgpu::VertexAttribute {
+                                format: wgpu::VertexFormat::Float32x3,
+                                offset: 3 * mem::size_of::<f32>() as u64,
+                                shader_location: 1,
+                            },
+                        ],
+                    }],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &mesh_fs_module,
+                    entry_point: "main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: *surface_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                multiview: None,
+            })
+        };
+        let mesh_pipeline = create_mesh_pipeline(true, wgpu::CompareFunction::Less);
+        let highlight_pipeline = create_mesh_pipeline(false, wgpu::CompareFunction::LessEqual);
+
+        let timeline = Timeline::default();
+        let density_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: timeline.uniforms().as_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let cs_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format: wgpu::TextureFormat::R32Float,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
                         visibility: wgpu::ShaderStages::COMPUTE,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
                         count: None,
                     },
                 ],
                 label: None,
             });
 
+        let cs_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cs_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &atlas
+                            .texture()
+                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: density_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: atlas.create_layout_buffer(device).as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
         let cs_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: &[&cs_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        let cs_module = shader::compile_cs(device, include_str!("shaders/compute_test.comp"));
+        let cs_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/density.wgsl")
+            ),
+        );
         let cs_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: None,
             layout: Some(&cs_pipeline_layout),
@@ -237,147 +763,2057 @@ impl App {
             entry_point: "main",
         });
 
-        let cell_count = texture_size.x * texture_size.y * texture_size.z;
-        let max_triangle_count = 4 * cell_count as usize;
-        let max_index_count = max_triangle_count * 3;
-        let mut vertex_data = vec![Vertex::default(); max_triangle_count];
-        let mut index_data = vec![u32::default(); max_index_count];
+        let gpu_extractor = GpuExtractor::new(device, &atlas);
+        let chunks = ChunkGrid::new(device, &gpu_extractor, texture_size);
 
-        let vertex_data_slice_size = vertex_data.len() * std::mem::size_of::<Vertex>();
-        let vertex_slice_size = vertex_data_slice_size as wgpu::BufferAddress;
+        let mut scene = Scene::new();
+        scene.add_material("Mesh colors", [0.0; 4]);
+        for (name, color) in [
+            ("Red", [0.9, 0.3, 0.3, 1.0]),
+            ("Green", [0.4, 0.8, 0.4, 1.0]),
+            ("Blue", [0.3, 0.5, 0.9, 1.0]),
+            ("Gold", [0.9, 0.75, 0.3, 1.0]),
+        ] {
+            scene.add_material(name, color);
+        }
+        scene.add(None, Node::new("Surface", Some(MeshHandle::Surface)));
+        scene.add(
+            None,
+            Node::new("Triangle", Some(MeshHandle::Triangle))
+                .with_transform(Transform::from_translation(vec3(1.0, 0.0, 0.0))),
+        );
 
-        let cs_vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: vertex_slice_size,
-            usage: wgpu::BufferUsages::STORAGE,
-            mapped_at_creation: false,
+        App {
+            texture_size,
+            camera: Camera::new(),
+            width,
+            height,
+            aspect: width as f32 / height as f32,
+            color_format: *surface_format,
+            depth_view: create_depth_view(device, width, height),
+            depth_bytes: stats::texture_bytes(uvec3(width, height, 1), DEPTH_FORMAT),
+            tri_vertex_buf,
+            tri_index_buf,
+            bind_group_layout,
+            tri_instances,
+            uploads: UploadBelt::default(),
+            pipeline,
+            mesh_pipeline,
+            highlight_pipeline,
+            mesh_storage_buffer,
+            mesh_tint_buf,
+            mesh_bind_group,
+            scene,
+            outliner: Outliner::new(),
+            cs_pipeline,
+            cs_bind_group,
+            //cs_shader_storage_buffer,
+            atlas,
+            needs_density: true,
+            volume: Volume::from_fn(texture_size, volume::sphere),
+            volume_behind: false,
+            slice_view: SliceView::new(),
+            profiler: Profiler::new(device),
+            stats: StatsOverlay::new(adapter_info, present_mode),
+            sculptor,
+            sculpting: false,
+            brush: Brush {
+                kind: BrushKind::Add,
+                radius: 0.08,
+                strength: 0.3,
+                color: [0.8, 0.2, 0.2],
+            },
+            dab_requested: false,
+            picker,
+            pick_tooltip: false,
+            screen_size: Vec2::new(width as f32, height as f32),
+            pointer: None,
+            hover: None,
+            measure_requested: false,
+            measured_mesh: Mesh::default(),
+            measurements: None,
+            selected_component: None,
+            highlight: None,
+            post_process: PostProcess::default(),
+            preview_post_process: false,
+            post_processed: None,
+            obj_path: "mesh.obj".to_owned(),
+            export_requested: false,
+            history: History::new(HISTORY_BUDGET),
+            pending_uploads: Vec::new(),
+            volume_path: "volume.isv".to_owned(),
+            volume_save_requested: false,
+            volume_load_requested: false,
+            mesh_path: "model.obj".to_owned(),
+            points_path: "scan.xyz".to_owned(),
+            import_union: false,
+            project_path: "project.ron".to_owned(),
+            recent_projects: project::recent_projects(),
+            pending_layout: None,
+            save_layout: None,
+            reaction,
+            gray_scott: GrayScott::default(),
+            reaction_running: false,
+            reaction_steps: 4,
+            reaction_step_requested: false,
+            reaction_reset_requested: false,
+            reaction_check_requested: false,
+            reaction_error: None,
+            fluid,
+            fluid_settings: FluidSettings::default(),
+            fluid_running: false,
+            fluid_step_requested: false,
+            fluid_reset_requested: false,
+            fluid_emitting: false,
+            emit_held: false,
+            last_emitter: None,
+            automaton,
+            automaton_rule_text: automaton::DEFAULT_RULE.to_owned(),
+            automaton_rule: automaton::DEFAULT_RULE.parse().unwrap(),
+            automaton_rule_error: None,
+            automaton_running: false,
+            automaton_interval: 4,
+            automaton_frame: 0,
+            automaton_step_requested: false,
+            automaton_reset_requested: false,
+            automaton_check_requested: false,
+            automaton_mismatches: None,
+            automaton_fill: 0.3,
+            automaton_seed: 0,
+            automaton_voxels: false,
+            voxels: None,
+            voxels_current: false,
+            timeline,
+            density_buf,
+            timeline_playing: false,
+            timeline_changed: false,
+            render_settings: RenderSettings::default(),
+            render_requested: false,
+            gizmos: GizmoSettings::default(),
+            lines: LineRenderer::new(device, *surface_format, DEPTH_FORMAT),
+            scene_lines: Lines::default(),
+            overlay_lines: Lines::default(),
+            gizmo_hover: None,
+            gizmo_drag: None,
+            gpu_extractor,
+            mesh_target: None,
+            chunks,
+            chunked: false,
+            last_chunk_extracts: 0,
+            lod: false,
+            lod_distance: 0.6,
+            lod_colors: false,
+            extractor: Extractor::MarchingCubes,
+            backend: Backend::Gpu,
+            iso: 0.0,
+            needs_extract: true,
+            compare_all: false,
+            extract_counts: HashMap::new(),
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.aspect = width as f32 / height as f32;
+        self.depth_view = create_depth_view(device, width, height);
+        self.depth_bytes = stats::texture_bytes(uvec3(width, height, 1), DEPTH_FORMAT);
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.stats.visible = !self.stats.visible;
+    }
+
+    /// GPU memory of the buffers and textures the app created, egui's aside.
+    fn gpu_bytes(&self) -> u64 {
+        let uniforms = (MAX_SURFACES * MESH_LOCALS_STRIDE) as usize
+            + (MESH_TINTS.len() + MAX_MATERIALS) * MESH_TINT_STRIDE as usize
+            + mem::size_of_val(TRI_VERTEX_DATA)
+            + mem::size_of_val(TRI_INDEX_DATA)
+            + mem::size_of::<DensityUniforms>();
+        let meshes = [&self.highlight, &self.post_processed, &self.voxels]
+            .into_iter()
+            .flatten()
+            .map(MeshBuffers::bytes)
+            .sum::<u64>();
+        uniforms as u64
+            + self.depth_bytes
+            + self.atlas.bytes()
+            + self.tri_instances.bytes()
+            + self.uploads.bytes()
+            + self.sculptor.bytes()
+            + self.picker.bytes()
+            + self.reaction.as_ref().map_or(0, ReactionDiffusion::bytes)
+            + self.fluid.as_ref().map_or(0, Fluid::bytes)
+            + self.automaton.as_ref().map_or(0, Automaton::bytes)
+            + self.profiler.bytes()
+            + self.gpu_extractor.bytes()
+            + self.mesh_target.as_ref().map_or(0, ExtractTarget::bytes)
+            + self.chunks.bytes()
+            + self.lines.bytes()
+            + meshes
+    }
+
+    /// Triangle and vertex count of the surface as drawn.
+    fn surface_counts(&self) -> (u32, u32) {
+        match &self.post_processed {
+            Some(mesh) => (mesh.index_count / 3, mesh.vertex_count),
+            None => {
+                let counters = self
+                    .extract_counts
+                    .get(&(self.extractor, self.backend))
+                    .copied()
+                    .unwrap_or_default();
+                (counters.index_count / 3, counters.vertex_count)
+            }
+        }
+    }
+
+    pub fn ui(&mut self, context: &Context) {
+        if let Some(layout) = self.pending_layout.take() {
+            *context.memory() = layout;
+        }
+
+        egui::Window::new("Project").show(context, |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.project_path);
+                if ui.button("Save").clicked() {
+                    self.save_layout = Some(context.memory().clone());
+                }
+                if ui.button("Open").clicked() {
+                    self.open_project(&self.project_path.clone());
+                }
+            });
+            if !self.recent_projects.is_empty() {
+                ui.label("Recent");
+            }
+            let mut open = None;
+            for path in &self.recent_projects {
+                if ui
+                    .selectable_label(*path == self.project_path, path)
+                    .clicked()
+                {
+                    open = Some(path.clone());
+                }
+            }
+            if let Some(path) = open {
+                self.open_project(&path);
+            }
+        });
+
+        self.outliner.ui(context, &mut self.scene);
+
+        egui::Window::new("Gizmos").show(context, |ui| {
+            ui.checkbox(&mut self.gizmos.grid, "Ground grid");
+            ui.checkbox(&mut self.gizmos.axes, "Axes");
+            ui.checkbox(&mut self.gizmos.bounds, "Bounding box");
+            ui.checkbox(&mut self.gizmos.lattice, "Cell lattice");
+            ui.checkbox(&mut self.gizmos.slice_plane, "Slice plane")
+                .on_hover_text("Drag its arrow to move through the layers");
+            ui.checkbox(&mut self.gizmos.handles, "Handles on the selected node");
+            ui.horizontal(|ui| {
+                for mode in GizmoMode::ALL {
+                    ui.radio_value(&mut self.gizmos.mode, mode, mode.name());
+                }
+            });
         });
 
-        let index_data_slice_size = index_data.len() * std::mem::size_of::<Vertex>();
-        let index_slice_size = index_data_slice_size as wgpu::BufferAddress;
+        egui::Window::new("Extraction").show(context, |ui| {
+            ui.horizontal(|ui| {
+                for extractor in Extractor::ALL {
+                    self.needs_extract |= ui
+                        .radio_value(&mut self.extractor, extractor, extractor.name())
+                        .changed();
+                }
+            });
+            ui.horizontal(|ui| {
+                for backend in Backend::ALL {
+                    self.needs_extract |= ui
+                        .radio_value(&mut self.backend, backend, backend.name())
+                        .changed();
+                }
+            });
+            self.needs_extract |= ui
+                .add(
+                    egui::DragValue::new(&mut self.iso)
+                        .speed(0.005)
+                        .prefix("iso: "),
+                )
+                .changed();
+            if ui
+                .add_enabled(!self.in_bricks(), egui::Button::new("Compare all"))
+                .clicked()
+            {
+                self.compare_all = true;
+                self.needs_extract = true;
+            }
+            self.needs_extract |= ui.checkbox(&mut self.chunked, "Chunked").changed();
+            if self.in_bricks() {
+                let bricks = self.chunks.bricks();
+                ui.label(format!(
+                    "{}x{}x{} bricks of {} cells, {} extracted last time",
+                    bricks.x, bricks.y, bricks.z, BRICK_CELLS, self.last_chunk_extracts
+                ));
+                ui.add_enabled_ui(self.extractor.supports_lod(), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.lod, "LOD");
+                        ui.add(
+                            egui::Slider::new(&mut self.lod_distance, 0.1..=2.0)
+                                .text("distance per level"),
+                        );
+                    });
+                    ui.checkbox(&mut self.lod_colors, "Color by LOD");
+                    let counts = self.chunks.lod_counts();
+                    ui.label(format!("Bricks per level: {:?}", counts));
+                });
+            }
 
-        // let cs_vertex_bind_group_layout = cs_pipeline.get_bind_group_layout(0);
-        // let cs_vertex_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        //     label: None,
-        //     layout: &cs_vertex_bind_group_layout,
-        //     entries: &[wgpu::BindGroupEntry {
-        //         binding: 0,
-        //         resource: cs_vertex_buf.as_entire_binding(),
-        //     }],
-        // });
+            ui.checkbox(&mut self.pick_tooltip, "Show the surface under the pointer")
+                .on_hover_text("In world space, on the first visible surface node");
 
-        let cs_index_buf = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: index_slice_size,
-            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::STORAGE,
-            mapped_at_creation: false,
+            ui.separator();
+            egui::Grid::new("extract_counts")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("");
+                    for backend in Backend::ALL {
+                        ui.label(format!("{} triangles", backend.name()));
+                        ui.label(format!("{} vertices", backend.name()));
+                    }
+                    ui.end_row();
+
+                    for extractor in Extractor::ALL {
+                        ui.label(extractor.name());
+                        for backend in Backend::ALL {
+                            match self.extract_counts.get(&(extractor, backend)) {
+                                Some(counters) => {
+                                    ui.label(counters.triangle_count().to_string());
+                                    ui.label(counters.vertex_count.to_string());
+                                }
+                                None => {
+                                    ui.label("-");
+                                    ui.label("-");
+                                }
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
         });
 
-        // let cs_index_bind_group_layout = cs_pipeline.get_bind_group_layout(1);
-        // let cs_index_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        //     label: None,
-        //     layout: &cs_index_bind_group_layout,
-        //     entries: &[wgpu::BindGroupEntry {
-        //         binding: 0,
-        //         resource: cs_index_buf.as_entire_binding(),
-        //     }],
-        // });
+        egui::Window::new("Sculpt").show(context, |ui| {
+            // Switches between extracting the whole grid and bricks.
+            self.needs_extract |= ui
+                .checkbox(&mut self.sculpting, "Sculpt")
+                .on_hover_text("Left drag applies the brush, right drag orbits")
+                .changed();
+            ui.horizontal(|ui| {
+                for kind in BrushKind::ALL {
+                    ui.radio_value(&mut self.brush.kind, kind, kind.name());
+                }
+            });
+            ui.add(egui::Slider::new(&mut self.brush.radius, 0.01..=0.3).text("radius"));
+            ui.add(egui::Slider::new(&mut self.brush.strength, 0.0..=1.0).text("strength"));
+            ui.horizontal(|ui| {
+                ui.label("Paint color");
+                ui.color_edit_button_rgb(&mut self.brush.color);
+            });
+            if !self.chunked {
+                ui.label("Sculpting extracts in bricks, a dab re-extracts the ones it touches.");
+            }
+            if ui.button("Reset").clicked() {
+                self.volume = self.timeline.field(self.texture_size);
+                self.history.clear();
+                self.needs_density = true;
+                self.needs_extract = true;
+                self.slice_view.mark_dirty();
+            }
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.volume_path);
+                if ui.button("Save volume").clicked() {
+                    self.volume_save_requested = true;
+                }
+                if ui.button("Load volume").clicked() {
+                    self.volume_load_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.mesh_path);
+                if ui.button("Import mesh").clicked() {
+                    if let Err(e) = self.import_mesh() {
+                        eprintln!("Failed to import {}: {}", self.mesh_path, e);
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.points_path);
+                if ui.button("Import points").clicked() {
+                    if let Err(e) = self.import_points() {
+                        eprintln!("Failed to import {}: {}", self.points_path, e);
+                    }
+                }
+            });
+            ui.checkbox(&mut self.import_union, "Union with the current field");
+        });
 
-        let scalar_data = device.create_texture(&wgpu::TextureDescriptor {
-            label: None,
-            size: wgpu::Extent3d {
-                width: texture_size.x,
-                height: texture_size.y,
-                depth_or_array_layers: texture_size.z,
+        egui::Window::new("Measure").show(context, |ui| {
+            if ui.button("Measure").clicked() {
+                self.measure_requested = true;
+            }
+            let measurements = match &self.measurements {
+                Some(measurements) => measurements,
+                None => {
+                    ui.label("Measures the extracted mesh.");
+                    return;
+                }
+            };
+            let bounds = measurements.bounds.size();
+            ui.label(format!("Area: {:.4}", measurements.area));
+            ui.label(format!("Volume: {:.4}", measurements.volume));
+            ui.label(format!(
+                "Bounds: {:.3} x {:.3} x {:.3}",
+                bounds.x, bounds.y, bounds.z
+            ));
+            ui.label(format!("{} components", measurements.components.len()));
+
+            ui.separator();
+            let mut selected = self.selected_component;
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("components").striped(true).show(ui, |ui| {
+                        ui.label("");
+                        ui.label("Area");
+                        ui.label("Volume");
+                        ui.end_row();
+                        for (i, component) in measurements.components.iter().enumerate() {
+                            if ui
+                                .selectable_label(selected == Some(i), format!("#{}", i))
+                                .clicked()
+                            {
+                                selected = if selected == Some(i) { None } else { Some(i) };
+                            }
+                            ui.label(format!("{:.4}", component.area));
+                            ui.label(format!("{:.4}", component.volume));
+                            ui.end_row();
+                        }
+                    });
+                });
+            if selected != self.selected_component {
+                self.selected_component = selected;
+                self.highlight = None;
+            }
+        });
+
+        egui::Window::new("Post-process").show(context, |ui| {
+            let before = self.post_process;
+            let post_process = &mut self.post_process;
+            ui.checkbox(&mut post_process.weld, "Weld vertices");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut post_process.smooth, "Taubin smoothing");
+                ui.add(
+                    egui::DragValue::new(&mut post_process.smooth_iterations)
+                        .clamp_range(1..=200)
+                        .suffix(" iterations"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut post_process.decimate, "Decimate");
+                ui.add(
+                    egui::DragValue::new(&mut post_process.target_triangles)
+                        .clamp_range(4..=1_000_000)
+                        .speed(50.0)
+                        .suffix(" triangles"),
+                );
+            });
+            let preview_changed = ui
+                .checkbox(&mut self.preview_post_process, "Preview")
+                .changed();
+            if preview_changed || self.post_process != before {
+                self.post_processed = None;
+                self.measurements = None;
+                self.selected_component = None;
+                self.highlight = None;
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.obj_path);
+                if ui.button("Export OBJ").clicked() {
+                    self.export_requested = true;
+                }
+            });
+        });
+
+        egui::Window::new("Reaction-diffusion").show(context, |ui| {
+            if self.reaction.is_none() {
+                ui.label(GRID_TOO_LARGE);
+                return;
+            }
+            ui.horizontal(|ui| {
+                let label = if self.reaction_running {
+                    "Pause"
+                } else {
+                    "Run"
+                };
+                if ui.button(label).clicked() {
+                    self.reaction_running = !self.reaction_running;
+                }
+                if ui.button("Step").clicked() {
+                    self.reaction_step_requested = true;
+                }
+                if ui.button("Reset").clicked() {
+                    self.reaction_reset_requested = true;
+                }
+            });
+            let rates = &mut self.gray_scott;
+            ui.add(egui::Slider::new(&mut rates.feed, 0.0..=0.1).text("feed"));
+            ui.add(egui::Slider::new(&mut rates.kill, 0.0..=0.1).text("kill"));
+            ui.add(
+                egui::Slider::new(&mut rates.diffusion_u, 0.0..=reaction::MAX_DIFFUSION)
+                    .text("u diffusion"),
+            );
+            ui.add(
+                egui::Slider::new(&mut rates.diffusion_v, 0.0..=reaction::MAX_DIFFUSION)
+                    .text("v diffusion"),
+            );
+            ui.add(egui::Slider::new(&mut self.reaction_steps, 1..=32).text("steps per frame"));
+            ui.label("Each step replaces the field with v, sculpting doesn't reach the chemicals.");
+            ui.horizontal(|ui| {
+                if ui.button("Check against CPU").clicked() {
+                    self.reaction_check_requested = true;
+                }
+                if let Some(error) = self.reaction_error {
+                    ui.label(format!(
+                        "{} steps differ by at most {:.2e}",
+                        reaction::CHECK_STEPS,
+                        error
+                    ));
+                }
+            });
+        });
+
+        egui::Window::new("Fluid").show(context, |ui| {
+            if self.fluid.is_none() {
+                ui.label(GRID_TOO_LARGE);
+                return;
+            }
+            ui.horizontal(|ui| {
+                let label = if self.fluid_running { "Pause" } else { "Run" };
+                if ui.button(label).clicked() {
+                    self.fluid_running = !self.fluid_running;
+                }
+                if ui.button("Step").clicked() {
+                    self.fluid_step_requested = true;
+                }
+                if ui.button("Clear").clicked() {
+                    self.fluid_reset_requested = true;
+                }
+            });
+            ui.checkbox(&mut self.fluid_emitting, "Emit with the left mouse button");
+            let settings = &mut self.fluid_settings;
+            ui.add(egui::Slider::new(&mut settings.dt, 0.05..=2.0).text("time step"));
+            ui.add(
+                egui::Slider::new(&mut settings.iterations, 2..=100).text("pressure iterations"),
+            );
+            ui.add(egui::Slider::new(&mut settings.buoyancy, 0.0..=2.0).text("buoyancy"));
+            ui.add(egui::Slider::new(&mut settings.dissipation, 0.0..=0.2).text("dissipation"));
+            ui.add(
+                egui::Slider::new(&mut settings.emitter_radius, 1.0..=16.0).text("emitter radius"),
+            );
+            ui.add(
+                egui::Slider::new(&mut settings.emitter_amount, 0.0..=4.0).text("emitter amount"),
+            );
+            ui.label("Each step replaces the field with the density.");
+        });
+
+        egui::Window::new("Cellular automaton").show(context, |ui| {
+            if self.automaton.is_none() {
+                ui.label(GRID_TOO_LARGE);
+                return;
+            }
+            ui.horizontal(|ui| {
+                let label = if self.automaton_running {
+                    "Pause"
+                } else {
+                    "Run"
+                };
+                if ui.button(label).clicked() {
+                    self.automaton_running = !self.automaton_running;
+                }
+                if ui.button("Step").clicked() {
+                    self.automaton_step_requested = true;
+                }
+                if ui.button("Reset").clicked() {
+                    self.automaton_reset_requested = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Rule");
+                let edit = ui
+                    .text_edit_singleline(&mut self.automaton_rule_text)
+                    .on_hover_text(
+                        "survival/birth/states/neighbourhood, like 4/4/5/M or 2,6,9/4,6,8-9/10/M",
+                    );
+                if edit.changed() {
+                    match self.automaton_rule_text.parse() {
+                        Ok(rule) => {
+                            self.automaton_rule = rule;
+                            self.automaton_rule_error = None;
+                        }
+                        Err(e) => self.automaton_rule_error = Some(e),
+                    }
+                }
+            });
+            if let Some(error) = &self.automaton_rule_error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            ui.add(egui::Slider::new(&mut self.automaton_fill, 0.0..=1.0).text("reset fill"));
+            ui.add(egui::Slider::new(&mut self.automaton_interval, 1..=60).text("frames per step"));
+            if ui
+                .checkbox(&mut self.automaton_voxels, "Draw as voxel cubes")
+                .changed()
+                && !self.automaton_voxels
+            {
+                self.voxels = None;
+            }
+            ui.label("Each step replaces the field with the cells, sculpting doesn't reach them.");
+            ui.horizontal(|ui| {
+                if ui.button("Check against CPU").clicked() {
+                    self.automaton_check_requested = true;
+                }
+                if let Some(mismatches) = self.automaton_mismatches {
+                    ui.label(format!(
+                        "{} cells differ after {} steps",
+                        mismatches,
+                        automaton::CHECK_STEPS
+                    ));
+                }
+            });
+        });
+
+        // Before the window, which doesn't run its contents when collapsed.
+        if self.timeline_playing {
+            self.timeline_playing = self.timeline.advance(context.input().unstable_dt);
+            self.timeline_changed = true;
+        }
+        egui::Window::new("Timeline").show(context, |ui| {
+            let timeline = &mut self.timeline;
+            ui.horizontal(|ui| {
+                let label = if self.timeline_playing {
+                    "Pause"
+                } else {
+                    "Play"
+                };
+                if ui.button(label).clicked() {
+                    self.timeline_playing = !self.timeline_playing;
+                    if self.timeline_playing && timeline.time >= timeline.duration {
+                        timeline.seek(0.0);
+                    }
+                }
+                ui.checkbox(&mut timeline.looping, "Loop");
+            });
+            let mut time = timeline.time;
+            if ui
+                .add(egui::Slider::new(&mut time, 0.0..=timeline.duration).text("time"))
+                .changed()
+            {
+                timeline.seek(time);
+                self.timeline_changed = true;
+            }
+            ui.add(egui::Slider::new(&mut timeline.speed, 0.1..=4.0).text("speed"));
+            ui.add(egui::Slider::new(&mut timeline.duration, 0.5..=30.0).text("duration"));
+            ui.horizontal(|ui| {
+                for shape in Shape::ALL {
+                    self.timeline_changed |= ui
+                        .radio_value(&mut timeline.shape, shape, shape.name())
+                        .changed();
+                }
+            });
+            let params = &mut timeline.params;
+            let sliders = [
+                egui::Slider::new(&mut params.radius, 0.01..=0.5).text("radius"),
+                egui::Slider::new(&mut params.orbit, 0.0..=0.4).text("orbit"),
+                egui::Slider::new(&mut params.frequency, 0.5..=16.0).text("frequency"),
+                egui::Slider::new(&mut params.amplitude, 0.0..=0.3).text("amplitude"),
+                egui::Slider::new(&mut params.speed, -2.0..=2.0).text("shape speed"),
+            ];
+            for slider in sliders {
+                self.timeline_changed |= ui.add(slider).changed();
+            }
+            ui.horizontal(|ui| {
+                for interpolation in Interpolation::ALL {
+                    self.timeline_changed |= ui
+                        .radio_value(
+                            &mut timeline.interpolation,
+                            interpolation,
+                            interpolation.name(),
+                        )
+                        .changed();
+                }
+            });
+            ui.horizontal(|ui| {
+                let keyed = timeline.key_at(timeline.time).is_some();
+                let label = if keyed { "Update key" } else { "Set key" };
+                if ui.button(label).clicked() {
+                    timeline.set_key();
+                }
+                if ui
+                    .add_enabled(keyed, egui::Button::new("Delete key"))
+                    .clicked()
+                {
+                    timeline.remove_key();
+                    timeline.seek(timeline.time);
+                    self.timeline_changed = true;
+                }
+            });
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Keys:");
+                let mut seek = None;
+                for key in timeline.keyframes() {
+                    if ui.small_button(format!("{:.2}s", key.time)).clicked() {
+                        seek = Some(key.time);
+                    }
+                }
+                if let Some(time) = seek {
+                    timeline.seek(time);
+                    self.timeline_changed = true;
+                }
+            });
+            ui.label("Playing, scrubbing or editing replaces the field on every frame.");
+        });
+
+        egui::Window::new("Render").show(context, |ui| {
+            let settings = &mut self.render_settings;
+            ui.horizontal(|ui| {
+                for motion in Motion::ALL {
+                    ui.radio_value(&mut settings.motion, motion, motion.name());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut settings.frames).clamp_range(1..=10000));
+                ui.label("frames at");
+                ui.add(egui::DragValue::new(&mut settings.fps).clamp_range(1..=240));
+                ui.label("fps");
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut settings.width).clamp_range(16..=8192));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut settings.height).clamp_range(16..=8192));
+            });
+            ui.checkbox(&mut settings.ffmpeg, "Encode a video with ffmpeg");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut settings.dir);
+                if ui.button("Render animation").clicked() {
+                    self.render_requested = true;
+                }
+            });
+        });
+
+        egui::Window::new("History").show(context, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Undo").on_hover_text("Ctrl+Z").clicked() {
+                    self.undo();
+                }
+                if ui.button("Redo").on_hover_text("Ctrl+Shift+Z").clicked() {
+                    self.redo();
+                }
+            });
+            let mut budget_mb = self.history.budget() >> 20;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut budget_mb)
+                        .clamp_range(1..=4096)
+                        .prefix("budget: ")
+                        .suffix(" MB"),
+                )
+                .changed()
+            {
+                self.history.set_budget(budget_mb << 20);
+            }
+            ui.label(format!("{} KB used", self.history.bytes() >> 10));
+
+            ui.separator();
+            let mut go_to = None;
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let position = self.history.position();
+                if ui.selectable_label(position == 0, "Start").clicked() {
+                    go_to = Some(0);
+                }
+                for (i, edit) in self.history.edits().iter().enumerate() {
+                    // Edits that can be redone are greyed out.
+                    let text = if i < position {
+                        egui::RichText::new(&edit.name)
+                    } else {
+                        egui::RichText::new(&edit.name).weak()
+                    };
+                    if ui.selectable_label(position == i + 1, text).clicked() {
+                        go_to = Some(i + 1);
+                    }
+                }
+            });
+            if let Some(position) = go_to {
+                self.go_to(position);
+            }
+        });
+
+        {
+            let input = context.input();
+            self.screen_size = Vec2::new(input.screen_rect().width(), input.screen_rect().height());
+            self.pointer = input
+                .pointer
+                .hover_pos()
+                .filter(|_| !context.is_pointer_over_area())
+                .map(|pos| Vec2::new(pos.x, pos.y));
+            self.dab_requested =
+                self.sculpting && self.pointer.is_some() && input.pointer.primary_down();
+            self.emit_held =
+                self.fluid_emitting && self.pointer.is_some() && input.pointer.primary_down();
+        }
+        self.update_gizmo_drag(context);
+        // A handle takes the button from the brush, the emitter and the
+        // camera.
+        if self.gizmo_drag.is_some() {
+            self.dab_requested = false;
+            self.emit_held = false;
+        }
+        if let (Some(pick), true) = (self.hover, self.pick_tooltip) {
+            egui::show_tooltip_at_pointer(context, egui::Id::new("pick"), |ui| {
+                ui.label(format!(
+                    "position: {:.3} {:.3} {:.3}",
+                    pick.pos.x, pick.pos.y, pick.pos.z
+                ));
+                ui.label(format!(
+                    "normal: {:.2} {:.2} {:.2}",
+                    pick.normal.x, pick.normal.y, pick.normal.z
+                ));
+                ui.label(format!("value: {:.4}", pick.value));
+            });
+        }
+
+        if self.sculpting || self.fluid_emitting || self.gizmo_drag.is_some() {
+            self.camera
+                .handle_input(context, egui::PointerButton::Secondary);
+        } else {
+            self.camera
+                .handle_input(context, egui::PointerButton::Primary);
+        }
+        // A stroke ends when the button is let go.
+        if self.history.in_stroke() && !context.input().pointer.primary_down() {
+            self.history.end_stroke(&self.volume);
+        }
+
+        self.slice_view.ui(context, &self.volume);
+        self.profiler.ui(context);
+        let (triangles, vertices) = self.surface_counts();
+        self.stats.ui(
+            context,
+            FrameStats {
+                frame_times: self.profiler.frame_times(),
+                triangles,
+                vertices,
+                gpu_bytes: self.gpu_bytes(),
             },
-            mip_level_count: 1,
-            dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R32Float,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
-            sample_count: 1,
+        );
+    }
+
+    /// Ray under `screen_pos` in the object space of the surface.
+    fn pointer_ray(&self, screen_pos: Vec2) -> Ray {
+        Ray::from_ndc(
+            self.camera.view_proj(self.aspect) * self.surface_transform(),
+            self.pointer_ndc(screen_pos),
+        )
+    }
+
+    /// Ray under `screen_pos` in world space.
+    fn world_ray(&self, screen_pos: Vec2) -> Ray {
+        Ray::from_ndc(
+            self.camera.view_proj(self.aspect),
+            self.pointer_ndc(screen_pos),
+        )
+    }
+
+    fn pointer_ndc(&self, screen_pos: Vec2) -> Vec2 {
+        Vec2::new(
+            screen_pos.x / self.screen_size.x * 2.0 - 1.0,
+            1.0 - screen_pos.y / self.screen_size.y * 2.0,
+        )
+    }
+
+    /// Every handle that can be grabbed, with its axis: those of the
+    /// selected node, and the arrow of the slice plane.
+    fn gizmo_handles(&self) -> Vec<(GizmoTarget, usize, HandleFrame, GizmoMode)> {
+        let eye = self.camera.eye();
+        let mut handles = Vec::new();
+        let selected = self
+            .outliner
+            .selected()
+            .filter(|&id| self.gizmos.handles && self.scene.contains(id));
+        if let Some(id) = selected {
+            let node = self.scene.node(id);
+            let parent = node
+                .parent()
+                .map_or(Mat4::IDENTITY, |parent| self.scene.world_transform(parent));
+            let mode = self.gizmos.mode;
+            let frame = HandleFrame::for_node(parent, &node.transform, mode, eye);
+            handles.extend((0..3).map(|axis| (GizmoTarget::Node(id), axis, frame, mode)));
+        }
+        if self.gizmos.slice_plane {
+            let frame = HandleFrame::new(self.surface_transform(), self.slice_center(), eye);
+            let axis = self.slice_view.slice().0 as usize;
+            handles.push((GizmoTarget::Slice, axis, frame, GizmoMode::Translate));
+        }
+        handles
+    }
+
+    /// Middle of the slice plane in the object space of the surface.
+    fn slice_center(&self) -> Vec3 {
+        let (axis, layer) = self.slice_view.slice();
+        let mut center = Vec3::ZERO;
+        center[axis as usize] = gizmo::slice_offset(self.texture_size, axis, layer);
+        center
+    }
+
+    /// Grabs the handle under the pointer when the primary button is
+    /// pressed, and moves what it belongs to until the button is let go.
+    fn update_gizmo_drag(&mut self, context: &Context) {
+        let (pressed, down) = {
+            let input = context.input();
+            let down = input.pointer.primary_down();
+            (input.pointer.any_pressed() && down, down)
+        };
+        let ray = self.pointer.map(|pos| self.world_ray(pos));
+        if !down {
+            self.gizmo_drag = None;
+        }
+        if let Some((target, drag)) = &mut self.gizmo_drag {
+            if let Some(transform) = ray.and_then(|ray| drag.update(ray)) {
+                match *target {
+                    GizmoTarget::Node(id) => {
+                        if self.scene.contains(id) {
+                            self.scene.node_mut(id).transform = transform;
+                        }
+                    }
+                    GizmoTarget::Slice => {
+                        let axis = self.slice_view.slice().0;
+                        let offset = transform.translation[axis as usize];
+                        let layer = gizmo::slice_layer(self.texture_size, axis, offset);
+                        self.slice_view.set_layer(layer);
+                    }
+                }
+            }
+            return;
+        }
+
+        let hit = ray.and_then(|ray| {
+            self.gizmo_handles()
+                .into_iter()
+                .filter_map(|handle| {
+                    let (_, axis, frame, mode) = handle;
+                    Some((frame.hit_axis(ray, mode, axis)?, handle))
+                })
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, handle)| handle)
         });
+        self.gizmo_hover = hit.map(|(target, axis, _, _)| (target, axis));
+        if let (Some((target, axis, frame, mode)), Some(ray), true) = (hit, ray, pressed) {
+            let transform = match target {
+                GizmoTarget::Node(id) => self.scene.node(id).transform,
+                GizmoTarget::Slice => Transform::from_translation(self.slice_center()),
+            };
+            self.gizmo_drag =
+                Drag::start(frame, mode, axis, ray, transform).map(|drag| (target, drag));
+        }
+    }
 
-        App {
-            x_pos: 0.0,
-            tri_vertex_buf,
-            tri_index_buf,
-            shader_storage_buffer,
-            _bind_group_layout: bind_group_layout,
-            bind_group,
-            pipeline,
-            cs_pipeline,
-            //cs_shader_storage_buffer,
-            cs_vertex_buf,
-            cs_index_buf,
-            // cs_vertex_bind_group_layout,
-            // cs_vertex_bind_group,
-            // cs_index_bind_group_layout,
-            // cs_index_bind_group,
-            scalar_data,
+    /// Lines of the guides turned on in the Gizmos window, and of the
+    /// handles.
+    fn update_lines(&mut self) {
+        self.scene_lines.clear();
+        self.overlay_lines.clear();
+        let view_proj = self.camera.view_proj(self.aspect);
+        let world = self.surface_transform();
+        if self.gizmos.grid {
+            let spacing = gizmo::grid_spacing(self.camera.distance);
+            gizmo::ground_grid(&mut self.scene_lines, self.camera.eye(), spacing);
+        }
+        if self.gizmos.axes {
+            // In the bottom left corner, drawn over everything.
+            let corner = Ray::from_ndc(view_proj, Vec2::new(-0.85, -0.8));
+            let origin = corner.origin + corner.dir * self.camera.distance;
+            gizmo::axis_triad(&mut self.overlay_lines, origin, self.camera.distance * 0.08);
+        }
+        if self.gizmos.bounds {
+            gizmo::bounding_box(&mut self.scene_lines, world);
+        }
+        if self.gizmos.lattice {
+            gizmo::lattice(
+                &mut self.scene_lines,
+                world,
+                self.texture_size,
+                LATTICE_LINES,
+            );
+        }
+        if self.gizmos.slice_plane {
+            let (axis, layer) = self.slice_view.slice();
+            gizmo::slice_plane(
+                &mut self.overlay_lines,
+                world,
+                self.texture_size,
+                axis,
+                layer,
+            );
+        }
+        let active = match &self.gizmo_drag {
+            Some((target, drag)) => Some((*target, drag.axis)),
+            None => self.gizmo_hover,
+        };
+        for (target, axis, frame, mode) in self.gizmo_handles() {
+            let highlighted = active == Some((target, axis));
+            frame.axis_lines(&mut self.overlay_lines, mode, axis, highlighted);
         }
     }
 
-    pub fn ui(&mut self, context: &Context) {
-        egui::Window::new("Window").show(context, |ui| {
-            ui.label("Hello world!");
-            ui.add(egui::DragValue::new(&mut self.x_pos).speed(0.1));
+    /// Picks the surface under `screen_pos`, in points from the top left of
+    /// the window, with the selected backend. Only the first visible surface
+    /// node is picked, the hit is in world space.
+    pub fn pick(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_pos: Vec2,
+    ) -> Option<Pick> {
+        self.pick_object(device, queue, screen_pos)
+            .map(|pick| pick.transformed(self.surface_transform()))
+    }
+
+    /// Like `pick`, but in the object space of the surface where the brushes
+    /// work.
+    fn pick_object(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        screen_pos: Vec2,
+    ) -> Option<Pick> {
+        let ray = self.pointer_ray(screen_pos);
+        match self.backend {
+            Backend::Gpu => self.picker.pick(device, queue, self.iso, ray),
+            Backend::Cpu => pick::cast_ray(&self.volume, self.iso, ray),
+        }
+    }
+
+    pub fn undo(&mut self) {
+        self.go_to(self.history.position().saturating_sub(1));
+    }
+
+    pub fn redo(&mut self) {
+        self.go_to(self.history.position() + 1);
+    }
+
+    /// Moves through the history, the changed samples are uploaded to
+    /// the atlas before the next extraction.
+    fn go_to(&mut self, position: usize) {
+        if self.history.in_stroke() {
+            return;
+        }
+        let changed = self.history.go_to(&mut self.volume, position);
+        if changed.is_empty() {
+            return;
+        }
+        for &samples in &changed {
+            if self.in_bricks() {
+                self.chunks.mark_dirty(samples);
+            } else {
+                self.needs_extract = true;
+            }
+        }
+        self.pending_uploads.extend(changed);
+        self.slice_view.mark_dirty();
+    }
+
+    /// Saves the session to `project_path` with the window `layout`, with the
+    /// samples of the field next to it once it has been sculpted.
+    fn save_project(&mut self, layout: egui::Memory) {
+        let path = self.project_path.clone();
+        let field = if self.volume == Volume::from_fn(self.texture_size, volume::sphere) {
+            Field::Sphere
+        } else {
+            Field::Samples(project::samples_file(&path))
+        };
+        let project = Project {
+            version: PROJECT_VERSION,
+            grid: self.texture_size,
+            field,
+            iso: self.iso,
+            extraction: Extraction {
+                extractor: self.extractor,
+                backend: self.backend,
+                chunked: self.chunked,
+                lod: self.lod,
+                lod_distance: self.lod_distance,
+                lod_colors: self.lod_colors,
+            },
+            camera: self.camera.clone(),
+            materials: self.scene.materials.clone(),
+            nodes: project::saved_nodes(&self.scene),
+            slice_view: self.slice_view.settings(),
+            window_layout: Some(layout),
+            timeline: self.timeline.clone(),
+        };
+        match project.write(&path, &self.volume) {
+            Ok(()) => project::add_recent(&mut self.recent_projects, &path),
+            Err(e) => eprintln!("Failed to write {}: {}", path, e),
+        }
+    }
+
+    pub fn open_project(&mut self, path: &str) {
+        if let Err(e) = Project::read(path).and_then(|project| self.load_project(path, project)) {
+            eprintln!("Failed to open {}: {}", path, e);
+        }
+    }
+
+    /// Replaces the session with `project`, read from `path`. A field saved on
+    /// another grid is resampled onto this one, the history and the paint are
+    /// cleared.
+    pub fn load_project(&mut self, path: &str, project: Project) -> io::Result<()> {
+        self.volume = project.volume(path)?.resample(self.texture_size);
+        self.scene = project.scene();
+        // The density pass clears the paint, the upload then overwrites its
+        // field.
+        self.needs_density = true;
+        self.pending_uploads.push(Region {
+            min: UVec3::ZERO,
+            max: self.texture_size,
         });
+        self.history.clear();
+        self.slice_view.set_settings(project.slice_view);
+
+        self.iso = project.iso;
+        let extraction = project.extraction;
+        self.extractor = extraction.extractor;
+        self.backend = extraction.backend;
+        self.chunked = extraction.chunked;
+        self.lod = extraction.lod;
+        self.lod_distance = extraction.lod_distance;
+        self.lod_colors = extraction.lod_colors;
+        self.needs_extract = true;
+
+        self.camera = project.camera;
+        self.outliner = Outliner::new();
+        // The field was saved as it was, the density pass only clears the
+        // paint.
+        self.timeline = project.timeline;
+        self.timeline_playing = false;
+        self.pending_layout = project.window_layout;
+
+        self.project_path = path.to_owned();
+        project::add_recent(&mut self.recent_projects, path);
+        Ok(())
+    }
+
+    /// Voxelizes the mesh at `mesh_path` into a signed distance field scaled
+    /// to fit the grid.
+    fn import_mesh(&mut self) -> io::Result<()> {
+        let mesh = import::read_mesh(&self.mesh_path)?;
+        let field = sdf::signed_distance(sdf::fit_to_grid(&mesh), self.texture_size);
+        self.import_field(field);
+        Ok(())
+    }
+
+    /// Reconstructs the surface through the point cloud at `points_path`,
+    /// scaled to fit the grid.
+    fn import_points(&mut self) -> io::Result<()> {
+        let cloud = import::read_points(&self.points_path)?;
+        let field =
+            reconstruct::implicit_surface(&reconstruct::fit_to_grid(&cloud), self.texture_size);
+        self.import_field(field);
+        Ok(())
+    }
+
+    /// Replaces the field with an imported one, or takes the union with it.
+    fn import_field(&mut self, mut field: Volume) {
+        if self.import_union {
+            let union = field.samples().zip(self.volume.samples());
+            field = Volume::from_samples(field.size, union.map(|(a, b)| a.min(b)));
+        }
+        self.volume = field;
+        // As when opening a project, the density pass clears the paint and
+        // the upload overwrites its field.
+        self.needs_density = true;
+        self.pending_uploads.push(Region {
+            min: UVec3::ZERO,
+            max: self.texture_size,
+        });
+        self.history.clear();
+        self.needs_extract = true;
+        self.slice_view.mark_dirty();
+    }
+
+    /// Reads the volume file at `volume_path`, uploading each brick to
+    /// the atlas as it's decoded. A file on another grid is read whole
+    /// and resampled.
+    fn load_volume(&mut self, queue: &wgpu::Queue) -> io::Result<()> {
+        let file = File::open(&self.volume_path)?;
+        let mut reader = VolumeReader::new(BufReader::new(file))?;
+        let header = *reader.header();
+        if header.size == self.texture_size {
+            let size = self.texture_size;
+            let mut volume = Volume::new(size);
+            loop {
+                match reader.next_brick() {
+                    Ok(Some((brick, samples))) => {
+                        volume_file::insert(&mut volume, brick, &samples);
+                        self.atlas.upload(queue, &volume, brick);
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        // Put back what the bricks read so far replaced.
+                        self.pending_uploads.push(Region {
+                            min: UVec3::ZERO,
+                            max: size,
+                        });
+                        return Err(e);
+                    }
+                }
+            }
+            self.volume = volume;
+        } else {
+            self.volume = reader.read_volume()?.resample(self.texture_size);
+            self.pending_uploads.push(Region {
+                min: UVec3::ZERO,
+                max: self.texture_size,
+            });
+        }
+        self.iso = header.iso;
+        self.history.clear();
+        self.needs_extract = true;
+        self.slice_view.mark_dirty();
+        Ok(())
+    }
+
+    pub fn profiler(&mut self) -> &mut Profiler {
+        &mut self.profiler
+    }
+
+    /// Call after submitting the frame `draw` encoded.
+    pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.uploads.recall(device);
+        self.profiler.end_frame(device, queue);
     }
 
     pub fn cs_fun(&mut self, encoder: &mut wgpu::CommandEncoder, texture_size: UVec3) {
-        let mut cs_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-        cs_pass.set_pipeline(&self.cs_pipeline);
-        //cs_pass.set_bind_group(0, &self.cs_vertex_bind_group, &[]);
-        //cs_pass.set_bind_group(1, &self.cs_index_bind_group, &[]);
-        cs_pass.insert_debug_marker("compute density values + mc");
-        cs_pass.dispatch(texture_size.x, texture_size.y, texture_size.z);
+        self.profiler.begin(encoder, "density");
+        encode_density(
+            encoder,
+            &self.cs_pipeline,
+            &self.cs_bind_group,
+            texture_size,
+        );
+        self.profiler.end(encoder);
     }
 
+    /// Fills the atlas when it was reset, uploads the samples undo, redo or
+    /// opening a project changed, picks the surface under the pointer, then
+    /// applies a dab there to the atlas and to the CPU copy of the field.
+    fn update_field(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.volume_load_requested {
+            self.volume_load_requested = false;
+            if let Err(e) = self.load_volume(queue) {
+                eprintln!("Failed to read {}: {}", self.volume_path, e);
+            }
+        }
+        if mem::take(&mut self.timeline_changed) {
+            self.volume = self.timeline.field(self.texture_size);
+            self.needs_density = true;
+            self.history.clear();
+            self.needs_extract = true;
+            self.slice_view.mark_dirty();
+        }
+        if self.needs_density {
+            self.needs_density = false;
+            queue.write_buffer(&self.density_buf, 0, self.timeline.uniforms().as_bytes());
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("density"),
+            });
+            self.cs_fun(&mut encoder, self.texture_size);
+            queue.submit(iter::once(encoder.finish()));
+            self.sculptor.clear_paint(queue);
+        }
+        for samples in self.pending_uploads.drain(..) {
+            self.atlas.upload(queue, &self.volume, samples);
+        }
+        self.update_reaction(device, queue);
+        self.update_fluid(device, queue);
+        self.update_automaton(device, queue);
+        if self.needs_volume() {
+            self.sync_volume(device, queue);
+        }
+        if mem::take(&mut self.volume_save_requested) {
+            let result = File::create(&self.volume_path)
+                .and_then(|file| volume_file::write(BufWriter::new(file), &self.volume, self.iso));
+            if let Err(e) = result {
+                eprintln!("Failed to write {}: {}", self.volume_path, e);
+            }
+        }
+        if let Some(layout) = self.save_layout.take() {
+            self.save_project(layout);
+        }
+
+        self.hover = match self.pointer {
+            Some(pos) if self.pick_tooltip => self.pick(device, queue, pos),
+            _ => None,
+        };
+        let hit = match self.pointer {
+            Some(pos) if self.dab_requested => self.pick_object(device, queue, pos),
+            _ => None,
+        };
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("sculpt"),
+        });
+        self.profiler.begin(&mut encoder, "sculpt");
+        self.sculptor.encode(
+            queue,
+            &mut encoder,
+            &self.atlas,
+            self.texture_size,
+            &self.brush,
+            &hit,
+        );
+        if self.brush.kind.edits_field() {
+            self.sync_volume(device, queue);
+            let bounds = self.brush.bounds(self.texture_size, &hit);
+            if !self.history.in_stroke() {
+                self.history.begin_stroke(self.brush.kind.name());
+            }
+            self.history.capture(&self.volume, bounds);
+            self.brush.apply(&mut self.volume, &hit);
+            self.slice_view.mark_dirty();
+            // Sculpting extracts in bricks.
+            self.chunks.mark_dirty(bounds);
+        }
+        self.profiler.end(&mut encoder);
+        queue.submit(iter::once(encoder.finish()));
+    }
+
+    /// Whether something reads the CPU copy of the field while the
+    /// simulations step the atlas: the slice view, extracting or picking on
+    /// the CPU, or saving. Once they stop it catches up.
+    fn needs_volume(&self) -> bool {
+        let simulating = self.reaction_running || self.fluid_running || self.automaton_running;
+        !simulating
+            || self.backend == Backend::Cpu
+            || self.compare_all
+            || self.slice_view.is_open()
+            || self.volume_save_requested
+            || self.save_layout.is_some()
+    }
+
+    /// Reads the field back from the atlas if a simulation stepped it past
+    /// the CPU copy.
+    fn sync_volume(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if mem::take(&mut self.volume_behind) {
+            self.volume = self.atlas.read(device, queue);
+            self.slice_view.mark_dirty();
+        }
+    }
+
+    /// Resets or steps the reaction-diffusion. Stepping leaves the CPU copy of
+    /// the field behind until `sync_volume`.
+    fn update_reaction(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let reaction = match &mut self.reaction {
+            Some(reaction) => reaction,
+            None => return,
+        };
+        let chemicals = if mem::take(&mut self.reaction_reset_requested) {
+            let chemicals = Chemicals::seeded(self.texture_size);
+            reaction.reset(queue, &chemicals);
+            // The shader only writes the field when stepping.
+            let seed = chemicals.field();
+            let samples = Region {
+                min: UVec3::ZERO,
+                max: self.texture_size,
+            };
+            self.atlas.upload(queue, &seed, samples);
+            Some(chemicals)
+        } else if mem::take(&mut self.reaction_check_requested) {
+            reaction.seed(queue);
+            let before = reaction.read_chemicals(device, queue);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("reaction-diffusion check"),
+            });
+            reaction.encode(queue, &mut encoder, &self.gray_scott, reaction::CHECK_STEPS);
+            queue.submit(iter::once(encoder.finish()));
+            let after = reaction.read_chemicals(device, queue);
+            let mut expected = before;
+            for _ in 0..reaction::CHECK_STEPS {
+                expected = expected.step(&self.gray_scott);
+            }
+            self.reaction_error = Some(after.max_difference(&expected));
+            Some(after)
+        } else {
+            let steps = if self.reaction_running {
+                self.reaction_steps
+            } else {
+                mem::take(&mut self.reaction_step_requested) as u32
+            };
+            if steps == 0 {
+                return;
+            }
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("reaction-diffusion"),
+            });
+            self.profiler.begin(&mut encoder, "reaction-diffusion");
+            reaction.encode(queue, &mut encoder, &self.gray_scott, steps);
+            self.profiler.end(&mut encoder);
+            queue.submit(iter::once(encoder.finish()));
+            None
+        };
+        match chemicals {
+            Some(chemicals) => {
+                self.volume = chemicals.field();
+                self.volume_behind = false;
+            }
+            None => self.volume_behind = true,
+        }
+        self.history.clear();
+        self.needs_extract = true;
+        self.slice_view.mark_dirty();
+    }
+
+    /// Clears or steps the fluid, emitting under the pointer while the button
+    /// is held. Stepping leaves the CPU copy of the field behind until
+    /// `sync_volume`.
+    fn update_fluid(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let fluid = match &self.fluid {
+            Some(fluid) => fluid,
+            None => return,
+        };
+        if mem::take(&mut self.fluid_reset_requested) {
+            fluid.clear(queue);
+            self.volume = Volume::from_fn(self.texture_size, |_| fluid::LEVEL);
+            let samples = Region {
+                min: UVec3::ZERO,
+                max: self.texture_size,
+            };
+            self.atlas.upload(queue, &self.volume, samples);
+            self.volume_behind = false;
+        } else {
+            let step = self.fluid_running || mem::take(&mut self.fluid_step_requested);
+            if !step {
+                return;
+            }
+            let emitter = match self.pointer {
+                Some(pos) if self.emit_held => {
+                    let position = mesh::object_to_grid(
+                        self.texture_size,
+                        fluid::emitter_position(self.pointer_ray(pos)),
+                    );
+                    let moved = self.last_emitter.map_or(Vec3::ZERO, |last| position - last);
+                    self.last_emitter = Some(position);
+                    Some(Emitter {
+                        position,
+                        velocity: moved / self.fluid_settings.dt,
+                    })
+                }
+                _ => {
+                    self.last_emitter = None;
+                    None
+                }
+            };
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("fluid"),
+            });
+            self.profiler.begin(&mut encoder, "fluid");
+            fluid.encode(queue, &mut encoder, &self.fluid_settings, emitter);
+            self.profiler.end(&mut encoder);
+            queue.submit(iter::once(encoder.finish()));
+            self.volume_behind = true;
+        }
+        self.history.clear();
+        self.needs_extract = true;
+        self.slice_view.mark_dirty();
+    }
+
+    /// Resets the automaton to a new soup or steps it. Stepping reads the cells
+    /// back only for the voxels, otherwise it leaves the CPU copy of the field
+    /// behind until `sync_volume`.
+    fn update_automaton(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let automaton = match &mut self.automaton {
+            Some(automaton) => automaton,
+            None => return,
+        };
+        let rule = self.automaton_rule;
+        let cells = if mem::take(&mut self.automaton_reset_requested) {
+            self.automaton_seed = self.automaton_seed.wrapping_add(1);
+            let cells = Cells::soup(
+                self.texture_size,
+                &rule,
+                self.automaton_fill,
+                self.automaton_seed,
+            );
+            automaton.reset(queue, &cells);
+            // The shader only writes the field when stepping.
+            let samples = Region {
+                min: UVec3::ZERO,
+                max: self.texture_size,
+            };
+            self.atlas.upload(queue, &cells.field(&rule), samples);
+            Some(cells)
+        } else if mem::take(&mut self.automaton_check_requested) {
+            let before = automaton.read_cells(device, queue);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("automaton check"),
+            });
+            automaton.encode(queue, &mut encoder, &rule, automaton::CHECK_STEPS);
+            queue.submit(iter::once(encoder.finish()));
+            let after = automaton.read_cells(device, queue);
+            let mut expected = before;
+            for _ in 0..automaton::CHECK_STEPS {
+                expected = expected.step(&rule);
+            }
+            self.automaton_mismatches = Some(after.differences(&expected));
+            Some(after)
+        } else {
+            let step = if self.automaton_running {
+                self.automaton_frame = (self.automaton_frame + 1) % self.automaton_interval;
+                self.automaton_frame == 0
+            } else {
+                mem::take(&mut self.automaton_step_requested)
+            };
+            if !step {
+                return;
+            }
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("automaton"),
+            });
+            self.profiler.begin(&mut encoder, "automaton");
+            automaton.encode(queue, &mut encoder, &rule, 1);
+            self.profiler.end(&mut encoder);
+            queue.submit(iter::once(encoder.finish()));
+            self.automaton_voxels
+                .then(|| automaton.read_cells(device, queue))
+        };
+        match cells {
+            Some(cells) => {
+                if self.automaton_voxels {
+                    let mesh = cells.voxel_mesh();
+                    self.voxels = Some(MeshBuffers::new(device, &mesh.vertices, &mesh.indices));
+                    self.voxels_current = true;
+                }
+                self.volume = cells.field(&rule);
+                self.volume_behind = false;
+            }
+            None => self.volume_behind = true,
+        }
+        self.history.clear();
+        self.needs_extract = true;
+        self.slice_view.mark_dirty();
+    }
+
+    /// Renders the animation the Render window asked for, blocking until
+    /// it's written.
+    pub fn render_if_requested(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if mem::take(&mut self.render_requested) {
+            let settings = self.render_settings.clone();
+            if let Err(e) = self.render_animation(device, queue, &settings) {
+                eprintln!("Failed to render {}: {}", settings.dir, e);
+            }
+        }
+    }
+
+    /// Writes the frames of `settings` as numbered PNGs, stepping the
+    /// timeline or the camera by whole frames so the result doesn't depend on
+    /// how fast they render. Pipes them to `ffmpeg` as well when asked to and
+    /// it's installed. The camera, the timeline and the viewport are restored
+    /// afterwards, and the field with its history and paint when the timeline
+    /// replaced it.
+    pub fn render_animation(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        fs::create_dir_all(&settings.dir)?;
+        let mut ffmpeg = if settings.ffmpeg {
+            match Ffmpeg::spawn(settings) {
+                Ok(ffmpeg) => Some(ffmpeg),
+                Err(e) => {
+                    eprintln!("Failed to start ffmpeg, writing only the frames: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (width, height) = (self.width, self.height);
+        let camera = self.camera.clone();
+        let timeline = self.timeline.clone();
+        let playing = mem::replace(&mut self.timeline_playing, false);
+        let gizmos = mem::replace(&mut self.gizmos, GizmoSettings::hidden());
+        // Timeline frames replace the field and clear the history and the
+        // paint like seeking does.
+        let field = (settings.motion == Motion::Timeline).then(|| {
+            self.sync_volume(device, queue);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("save paint"),
+            });
+            let paint = self.sculptor.save_paint(device, &mut encoder);
+            queue.submit(iter::once(encoder.finish()));
+            let history = History::new(self.history.budget());
+            (
+                self.volume.clone(),
+                mem::replace(&mut self.history, history),
+                paint,
+            )
+        });
+        self.resize(device, settings.width, settings.height);
+        let offscreen = Offscreen::new(device, settings.width, settings.height, self.color_format);
+
+        let mut result = Ok(());
+        for frame in 0..settings.frames {
+            match settings.motion {
+                Motion::Timeline => {
+                    self.timeline.seek(settings.time(frame));
+                    self.timeline_changed = true;
+                }
+                Motion::Turntable => self.camera.yaw = settings.yaw(camera.yaw, frame),
+            }
+            self.extract(device, queue);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("render animation"),
+            });
+            self.draw(device, &offscreen.view, &mut encoder);
+            self.profiler.resolve(&mut encoder);
+            queue.submit(iter::once(encoder.finish()));
+            self.end_frame(device, queue);
+
+            let png = render::encode_png(
+                settings.width,
+                settings.height,
+                &offscreen.read(device, queue),
+            );
+            result = png.and_then(|png| {
+                render::write_file(&settings.frame_path(frame), &png)?;
+                match &mut ffmpeg {
+                    Some(ffmpeg) => ffmpeg.write_frame(&png),
+                    None => Ok(()),
+                }
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+        if let Some(ffmpeg) = ffmpeg {
+            result = result.and(ffmpeg.finish());
+        }
+
+        self.camera = camera;
+        if let Some((volume, history, paint)) = field {
+            self.timeline = timeline;
+            self.volume = volume;
+            self.history = history;
+            self.pending_uploads.push(Region {
+                min: UVec3::ZERO,
+                max: self.texture_size,
+            });
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("restore paint"),
+            });
+            self.sculptor.restore_paint(&mut encoder, &paint);
+            queue.submit(iter::once(encoder.finish()));
+            self.needs_extract = true;
+            self.slice_view.mark_dirty();
+        }
+        self.timeline_playing = playing;
+        self.gizmos = gizmos;
+        self.resize(device, width, height);
+        result
+    }
+
+    /// Re-extracts the surface when the extraction settings or the field
+    /// changed, into `mesh_target` or, while `in_bricks`, into the bricks that
+    /// are dirty.
+    pub fn extract(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.update_field(device, queue);
+        if self.extract_mesh(device, queue) {
+            // The measurements and the preview were of the old mesh.
+            self.measurements = None;
+            self.selected_component = None;
+            self.highlight = None;
+            self.post_processed = None;
+            // Something besides the automaton changed the field.
+            if !mem::take(&mut self.voxels_current) {
+                self.voxels = None;
+            }
+        }
+        self.update_post_process(device, queue);
+        self.update_measurements(device, queue);
+    }
+
+    /// Measures the mesh when asked to and uploads the triangles of the
+    /// selected component for highlighting.
+    fn update_measurements(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.measure_requested {
+            self.measure_requested = false;
+            self.measured_mesh = self.shown_mesh(device, queue);
+            self.measurements = Some(measure::measure(&self.measured_mesh));
+            self.selected_component = None;
+            self.highlight = None;
+        }
+
+        let component = match (&self.measurements, self.selected_component) {
+            (Some(measurements), Some(i)) if self.highlight.is_none() => {
+                &measurements.components[i]
+            }
+            _ => return,
+        };
+        let indices: Vec<u32> = component
+            .triangles
+            .iter()
+            .flat_map(|&t| {
+                let t = 3 * t as usize;
+                self.measured_mesh.indices[t..t + 3].iter().copied()
+            })
+            .collect();
+        self.highlight = Some(MeshBuffers::new(
+            device,
+            &self.measured_mesh.vertices,
+            &indices,
+        ));
+    }
+
+    /// Reads the extracted mesh back from the GPU.
+    fn read_mesh(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Mesh {
+        if self.in_bricks() {
+            self.chunks.read_mesh(device, queue)
+        } else {
+            let counters = self
+                .extract_counts
+                .get(&(self.extractor, self.backend))
+                .copied()
+                .unwrap_or_default();
+            self.mesh_target
+                .as_ref()
+                .map_or_else(Mesh::default, |target| {
+                    target.read_mesh(device, queue, counters)
+                })
+        }
+    }
+
+    /// The extracted mesh as shown, post-processed when previewing.
+    fn shown_mesh(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Mesh {
+        let mesh = self.read_mesh(device, queue);
+        if self.preview_post_process {
+            self.post_process.apply(&mesh)
+        } else {
+            mesh
+        }
+    }
+
+    /// Rebuilds the post-processed preview and writes the export, both run the
+    /// post-process chain on the mesh read back from the GPU.
+    fn update_post_process(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.preview_post_process {
+            self.post_processed = None;
+        } else if self.post_processed.is_none() {
+            let mesh = self.shown_mesh(device, queue);
+            self.post_processed = Some(MeshBuffers::new(device, &mesh.vertices, &mesh.indices));
+        }
+
+        if self.export_requested {
+            self.export_requested = false;
+            let mesh = self.post_process.apply(&self.read_mesh(device, queue));
+            if let Err(e) = mesh::write_obj(&self.obj_path, &mesh) {
+                eprintln!("Failed to write {}: {}", self.obj_path, e);
+            }
+        }
+    }
+
+    /// Whether the surface is extracted in bricks, when asked to or while
+    /// sculpting so a dab only re-extracts the bricks it touches.
+    fn in_bricks(&self) -> bool {
+        self.chunked || self.sculpting
+    }
+
+    /// Re-extracts the mesh if needed, returns whether it did.
+    fn extract_mesh(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        if self.in_bricks() {
+            self.mesh_target = None;
+            self.chunks.update_lod(
+                self.surface_transform()
+                    .inverse()
+                    .transform_point3(self.camera.eye()),
+                self.lod_distance,
+                self.lod && self.extractor.supports_lod(),
+            );
+            if self.needs_extract {
+                self.needs_extract = false;
+                self.chunks.mark_all_dirty();
+            }
+            if self.chunks.dirty_count() == 0 {
+                return false;
+            }
+            self.last_chunk_extracts = self.chunks.extract(
+                device,
+                queue,
+                &self.gpu_extractor,
+                &self.volume,
+                self.extractor,
+                self.backend,
+                self.iso,
+            );
+            self.extract_counts
+                .insert((self.extractor, self.backend), self.chunks.counters());
+            return true;
+        }
+
+        if !self.needs_extract {
+            return false;
+        }
+        self.needs_extract = false;
+
+        let selected = (self.extractor, self.backend);
+        let mut runs = Vec::new();
+        if self.compare_all {
+            self.compare_all = false;
+            for extractor in Extractor::ALL {
+                for backend in Backend::ALL {
+                    runs.push((extractor, backend));
+                }
+            }
+        }
+        // Run the selected one last so it's what ends up in the buffers.
+        runs.retain(|&run| run != selected);
+        runs.push(selected);
+
+        let target = self.mesh_target.get_or_insert_with(|| {
+            self.gpu_extractor
+                .target(device, Region::all(self.texture_size))
+        });
+
+        for (extractor, backend) in runs {
+            self.extractor = extractor;
+            let counters = match backend {
+                // Extracts again into bigger buffers when the mesh didn't fit.
+                Backend::Gpu => loop {
+                    target.prepare(queue, self.iso, Lod::default());
+                    let mut encoder =
+                        device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("extract"),
+                        });
+                    self.profiler.begin(&mut encoder, "extraction");
+                    self.gpu_extractor.encode(&mut encoder, target, extractor);
+                    self.profiler.end(&mut encoder);
+                    queue.submit(iter::once(encoder.finish()));
+                    let counters = target.read_counters(device);
+                    if !self.gpu_extractor.grow_to_fit(device, target, counters) {
+                        break counters;
+                    }
+                },
+                Backend::Cpu => {
+                    let mesh = extractor.extract(&self.volume, self.iso);
+                    self.gpu_extractor
+                        .grow_to_fit(device, target, Counters::of_mesh(&mesh));
+                    target.write_mesh(queue, &mesh)
+                }
+            };
+            self.extract_counts.insert((extractor, backend), counters);
+        }
+        true
+    }
+
+    /// Where the first visible surface node puts the volume. Picking and the
+    /// level of detail work in its object space.
+    fn surface_transform(&self) -> Mat4 {
+        self.scene
+            .draw_list()
+            .into_iter()
+            .find(|item| item.mesh == MeshHandle::Surface)
+            .map_or(Mat4::IDENTITY, |item| item.world)
+    }
+
+    /// Draws the nodes of the scene, the surface nodes one by one and the
+    /// triangles instanced in one call.
     pub fn draw(
         &mut self,
         device: &wgpu::Device,
-        _queue: &wgpu::Queue,
         view: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
     ) {
-        // setup uniforms and send to gpu
-        let trans = Mat4::from_translation(vec3(self.x_pos, 0.0, 0.0));
-        let uniforms = TriUniforms {
-            transform: trans.to_cols_array_2d(),
+        let view_proj = self.camera.view_proj(self.aspect);
+        let items = self.scene.draw_list();
+
+        for (i, material) in self.scene.materials.iter().take(MAX_MATERIALS).enumerate() {
+            let offset = (MATERIAL_TINT_BASE as usize + i) * MESH_TINT_STRIDE as usize;
+            self.uploads.write(
+                device,
+                encoder,
+                &self.mesh_tint_buf,
+                offset as u64,
+                material.color.as_bytes(),
+            );
+        }
+        let materials = &self.scene.materials;
+        let material_tint = |material: MaterialHandle| match materials.get(material.0) {
+            Some(m) if material.0 < MAX_MATERIALS && m.color[3] > 0.0 => {
+                MATERIAL_TINT_BASE + material.0 as u32
+            }
+            _ => 0,
         };
 
-        let temp_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: uniforms.as_bytes(),
-            usage: wgpu::BufferUsages::COPY_SRC,
-        });
+        let surfaces: Vec<(u32, u32)> = items
+            .iter()
+            .filter(|item| item.mesh == MeshHandle::Surface)
+            .take(MAX_SURFACES as usize)
+            .enumerate()
+            .map(|(slot, item)| {
+                let locals = TriUniforms {
+                    transform: (view_proj * item.world).to_cols_array_2d(),
+                };
+                let offset = slot as u32 * MESH_LOCALS_STRIDE;
+                self.uploads.write(
+                    device,
+                    encoder,
+                    &self.mesh_storage_buffer,
+                    offset as u64,
+                    locals.as_bytes(),
+                );
+                (offset, material_tint(item.material))
+            })
+            .collect();
 
-        encoder.copy_buffer_to_buffer(
-            &temp_buffer,
-            0,
-            &self.shader_storage_buffer,
-            0,
-            mem::size_of::<TriUniforms>() as u64,
+        for item in items
+            .iter()
+            .filter(|item| item.mesh == MeshHandle::Triangle)
+        {
+            let color = materials.get(item.material.0).map_or([0.0; 4], |m| m.color);
+            self.tri_instances.push(TriInstance::new(
+                view_proj * item.world,
+                (color[3] > 0.0).then_some(color),
+            ));
+        }
+        let tri_count = self.tri_instances.len();
+        let tri_offset =
+            self.tri_instances
+                .upload(device, &self.bind_group_layout, &mut self.uploads, encoder);
+
+        self.update_lines();
+        self.lines.upload(
+            device,
+            &mut self.uploads,
+            encoder,
+            view_proj,
+            &self.scene_lines,
+            &self.overlay_lines,
         );
+        self.uploads.finish();
 
-        // Issue draw call
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: true,
-                },
-            }],
-            depth_stencil_attachment: None,
-        });
+        self.profiler.begin(encoder, "mesh draw");
+        {
+            let mut mesh_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.1,
+                            g: 0.1,
+                            b: 0.12,
+                            a: 1.0,
+                        }),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
 
-        render_pass.set_index_buffer(self.tri_index_buf.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.set_vertex_buffer(0, self.tri_vertex_buf.slice(..));
-        render_pass.set_pipeline(&self.pipeline);
-        render_pass.set_bind_group(0, &self.bind_group, &[]);
-        render_pass.draw_indexed(0..(TRI_INDEX_DATA.len() as u32), 0, 0..1);
+            mesh_pass.set_pipeline(&self.mesh_pipeline);
+            for &(locals, tint) in &surfaces {
+                if let Some(voxels) = &self.voxels {
+                    mesh_pass.set_bind_group(
+                        0,
+                        &self.mesh_bind_group,
+                        &[locals, tint * MESH_TINT_STRIDE],
+                    );
+                    voxels.draw(&mut mesh_pass);
+                } else if let Some(post_processed) = &self.post_processed {
+                    mesh_pass.set_bind_group(
+                        0,
+                        &self.mesh_bind_group,
+                        &[locals, tint * MESH_TINT_STRIDE],
+                    );
+                    post_processed.draw(&mut mesh_pass);
+                } else if self.in_bricks() {
+                    let (mesh_bind_group, lod_colors) = (&self.mesh_bind_group, self.lod_colors);
+                    self.chunks.draw(&mut mesh_pass, |pass, lod| {
+                        let tint = if lod_colors { lod.level + 1 } else { tint };
+                        pass.set_bind_group(0, mesh_bind_group, &[locals, tint * MESH_TINT_STRIDE]);
+                    });
+                } else if let Some(mesh_target) = &self.mesh_target {
+                    mesh_pass.set_bind_group(
+                        0,
+                        &self.mesh_bind_group,
+                        &[locals, tint * MESH_TINT_STRIDE],
+                    );
+                    mesh_target.draw(&mut mesh_pass);
+                }
+            }
+
+            // The measured surface is the first one.
+            if let (Some(highlight), Some(&(locals, _))) = (&self.highlight, surfaces.first()) {
+                mesh_pass.set_pipeline(&self.highlight_pipeline);
+                mesh_pass.set_bind_group(
+                    0,
+                    &self.mesh_bind_group,
+                    &[locals, HIGHLIGHT_TINT * MESH_TINT_STRIDE],
+                );
+                highlight.draw(&mut mesh_pass);
+            }
+        }
+        self.profiler.end(encoder);
+
+        self.profiler.begin(encoder, "triangle");
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_index_buffer(self.tri_index_buf.slice(..), wgpu::IndexFormat::Uint16);
+            render_pass.set_vertex_buffer(0, self.tri_vertex_buf.slice(..));
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, self.tri_instances.bind_group(), &[tri_offset]);
+            render_pass.draw_indexed(0..(TRI_INDEX_DATA.len() as u32), 0, 0..tri_count);
+
+            self.lines.draw(&mut render_pass);
+        }
+        self.profiler.end(encoder);
     }
 }
@@ -0,0 +1,621 @@
+use crate::bricks::{BrickAtlas, FIELD_FORMAT};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::readback::TextureReadback;
+use crate::shader;
+use crate::stats;
+use crate::volume::Volume;
+use glam::{IVec3, UVec3, Vec3};
+use std::mem;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Clouds of cells that grow into each other, 4/4/5/M.
+pub(crate) const DEFAULT_RULE: &str = "4/4/5/M";
+/// Steps the GPU is checked against `Cells::step` over.
+pub(crate) const CHECK_STEPS: u32 = 4;
+const MAX_NEIGHBOURS: u32 = 26;
+const CELL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Neighbourhood {
+    /// The 26 cells sharing a face, an edge or a corner.
+    Moore,
+    /// The 6 cells sharing a face.
+    VonNeumann,
+}
+
+impl Neighbourhood {
+    fn offsets(self) -> impl Iterator<Item = IVec3> {
+        (0..27)
+            .map(|i| IVec3::new(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1))
+            .filter(move |d| match self {
+                Neighbourhood::Moore => *d != IVec3::ZERO,
+                Neighbourhood::VonNeumann => d.abs().dot(IVec3::ONE) == 1,
+            })
+    }
+}
+
+/// A 3D cellular automaton rule, written "survival/birth/states/neighbourhood"
+/// like "4/4/5/M". Survival and birth list neighbour counts separated by
+/// commas, with ranges like "5-7". Cells that don't survive take `states - 2`
+/// steps to die, only live cells count as neighbours. The neighbourhood is M
+/// for Moore or N for von Neumann.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rule {
+    /// Bit n is set when a live cell with n live neighbours survives.
+    pub survival: u32,
+    /// Bit n is set when an empty cell with n live neighbours comes alive.
+    pub birth: u32,
+    /// At least 2, live cells are in the last state, empty ones in 0.
+    pub states: u32,
+    pub neighbourhood: Neighbourhood,
+}
+
+/// Neighbour counts like "2,6,9" or "5-7" as a bit set.
+fn parse_counts(text: &str) -> Result<u32, String> {
+    let mut counts = 0;
+    for part in text.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let count = |s: &str| {
+            s.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|&n| n <= MAX_NEIGHBOURS)
+                .ok_or_else(|| format!("{:?} isn't a neighbour count", s))
+        };
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (count(first)?, count(last)?),
+            None => (count(part)?, count(part)?),
+        };
+        if first > last {
+            return Err(format!("the range {:?} is empty", part));
+        }
+        for n in first..=last {
+            counts |= 1 << n;
+        }
+    }
+    Ok(counts)
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Rule, String> {
+        let parts: Vec<&str> = text.split('/').map(str::trim).collect();
+        let [survival, birth, states, neighbourhood] = parts[..] else {
+            return Err("expected survival/birth/states/neighbourhood".to_owned());
+        };
+        let states = states
+            .parse::<u32>()
+            .ok()
+            .filter(|s| (2..=u8::MAX as u32).contains(s))
+            .ok_or_else(|| format!("{:?} isn't a number of states from 2 to 255", states))?;
+        let neighbourhood = match neighbourhood {
+            "M" | "m" => Neighbourhood::Moore,
+            "N" | "n" => Neighbourhood::VonNeumann,
+            _ => return Err(format!("{:?} isn't M or N", neighbourhood)),
+        };
+        Ok(Rule {
+            survival: parse_counts(survival)?,
+            birth: parse_counts(birth)?,
+            states,
+            neighbourhood,
+        })
+    }
+}
+
+impl Rule {
+    fn alive(&self) -> u32 {
+        self.states - 1
+    }
+
+    /// CPU version of `shaders/automaton.wgsl`.
+    fn next(&self, state: u32, neighbours: u32) -> u32 {
+        if state == 0 {
+            if self.birth >> neighbours & 1 != 0 {
+                self.alive()
+            } else {
+                0
+            }
+        } else if state == self.alive() && self.survival >> neighbours & 1 != 0 {
+            state
+        } else {
+            state - 1
+        }
+    }
+}
+
+/// States of the cells on the grid, stored x-major like `Volume`. The grid
+/// wraps around.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cells {
+    pub size: UVec3,
+    pub states: Vec<u32>,
+}
+
+impl Cells {
+    /// Empty but for a cube of random live cells in the middle half of the
+    /// grid, `fill` of them alive.
+    pub fn soup(size: UVec3, rule: &Rule, fill: f32, seed: u32) -> Cells {
+        let mut random = seed.wrapping_mul(0x9e37_79b9) | 1;
+        let (min, max) = (size / 4, size - size / 4);
+        let mut states = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    // xorshift32
+                    random ^= random << 13;
+                    random ^= random >> 17;
+                    random ^= random << 5;
+                    let p = UVec3::new(x, y, z);
+                    let inside = p.cmpge(min).all() && p.cmplt(max).all();
+                    let live = inside && (random as f32 / u32::MAX as f32) < fill;
+                    states.push(if live { rule.alive() } else { 0 });
+                }
+            }
+        }
+        Cells { size, states }
+    }
+
+    fn index(&self, p: IVec3) -> usize {
+        let size = self.size.as_ivec3();
+        let p = IVec3::new(
+            p.x.rem_euclid(size.x),
+            p.y.rem_euclid(size.y),
+            p.z.rem_euclid(size.z),
+        )
+        .as_uvec3();
+        (p.x + self.size.x * (p.y + self.size.y * p.z)) as usize
+    }
+
+    pub fn step(&self, rule: &Rule) -> Cells {
+        let size = self.size;
+        let offsets: Vec<IVec3> = rule.neighbourhood.offsets().collect();
+        let mut states = Vec::with_capacity(self.states.len());
+        for z in 0..size.z as i32 {
+            for y in 0..size.y as i32 {
+                for x in 0..size.x as i32 {
+                    let p = IVec3::new(x, y, z);
+                    let neighbours = offsets
+                        .iter()
+                        .filter(|&&d| self.states[self.index(p + d)] == rule.alive())
+                        .count() as u32;
+                    states.push(rule.next(self.states[self.index(p)], neighbours));
+                }
+            }
+        }
+        Cells { size, states }
+    }
+
+    /// How many cells are in a different state in `other`.
+    pub fn differences(&self, other: &Cells) -> usize {
+        self.states
+            .iter()
+            .zip(&other.states)
+            .filter(|(a, b)| a != b)
+            .count()
+    }
+
+    /// The field the surface is extracted from, what the shader writes to
+    /// the `BrickAtlas`. -0.5 in live cells rising to 0.5 in empty ones.
+    pub fn field(&self, rule: &Rule) -> Volume {
+        let alive = rule.alive() as f32;
+        Volume::from_samples(
+            self.size,
+            self.states.iter().map(|&s| 0.5 - s as f32 / alive),
+        )
+    }
+
+    /// A cube for every cell that isn't empty, without the faces between
+    /// two of them, in object space.
+    pub fn voxel_mesh(&self) -> Mesh {
+        let size = self.size;
+        let half = 0.5 / size.as_vec3();
+        let mut mesh = Mesh::default();
+        for z in 0..size.z as i32 {
+            for y in 0..size.y as i32 {
+                for x in 0..size.x as i32 {
+                    let p = IVec3::new(x, y, z);
+                    if self.states[self.index(p)] == 0 {
+                        continue;
+                    }
+                    let center = mesh::grid_to_object(size, p.as_vec3());
+                    for axis in 0..3 {
+                        for sign in [-1, 1] {
+                            let mut normal = IVec3::ZERO;
+                            normal[axis] = sign;
+                            let neighbour = p + normal;
+                            let inside = neighbour.cmpge(IVec3::ZERO).all()
+                                && neighbour.cmplt(size.as_ivec3()).all();
+                            if inside && self.states[self.index(neighbour)] != 0 {
+                                continue;
+                            }
+                            push_face(&mut mesh, center, half, axis, sign as f32);
+                        }
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+/// The face of the box at `center` facing `sign` along `axis`,
+/// counter-clockwise seen from outside.
+fn push_face(mesh: &mut Mesh, center: Vec3, half: Vec3, axis: usize, sign: f32) {
+    let (mut normal, mut u, mut v) = (Vec3::ZERO, Vec3::ZERO, Vec3::ZERO);
+    normal[axis] = sign;
+    u[(axis + 1) % 3] = 1.0;
+    v[(axis + 2) % 3] = 1.0;
+    if sign < 0.0 {
+        mem::swap(&mut u, &mut v);
+    }
+    let first = mesh.vertices.len() as u32;
+    for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        let corner = center + (normal + u * a + v * b) * half;
+        mesh.vertices.push(MeshVertex::new(corner, normal));
+    }
+    mesh.indices
+        .extend([0, 1, 2, 0, 2, 3].into_iter().map(|i| first + i));
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct RuleUniforms {
+    survival: u32,
+    birth: u32,
+    states: u32,
+    moore: u32,
+}
+
+/// Steps a cellular automaton as compute passes, ping-ponging the states
+/// between two textures and writing their field to the `BrickAtlas` on every
+/// step.
+pub(crate) struct Automaton {
+    size: UVec3,
+    cells: [wgpu::Texture; 2],
+    /// `bind_groups[i]` steps from `cells[i]` to the other one.
+    bind_groups: [wgpu::BindGroup; 2],
+    rule_buf: wgpu::Buffer,
+    readback: TextureReadback,
+    pipeline: wgpu::ComputePipeline,
+    /// Which of `cells` holds the latest step.
+    current: usize,
+    bytes: u64,
+}
+
+impl Automaton {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas, size: UVec3) -> Automaton {
+        let texture = || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("automaton cells"),
+                size: wgpu::Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: size.z,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: CELL_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+            })
+        };
+        let cells = [texture(), texture()];
+        let rule_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<RuleUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = TextureReadback::new(device, size, CELL_FORMAT);
+
+        let storage_entry =
+            |binding: u32, format: wgpu::TextureFormat| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                },
+                count: None,
+            };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1, CELL_FORMAT),
+                storage_entry(2, FIELD_FORMAT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let field_view = view(atlas.texture());
+        let bricks_buf = atlas.create_layout_buffer(device);
+        let bind_group = |from: &wgpu::Texture, to: &wgpu::Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view(from)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view(to)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&field_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: rule_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: bricks_buf.as_entire_binding(),
+                    },
+                ],
+                label: None,
+            })
+        };
+        let bind_groups = [
+            bind_group(&cells[0], &cells[1]),
+            bind_group(&cells[1], &cells[0]),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/automaton.wgsl")
+            ),
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("automaton"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+
+        Automaton {
+            size,
+            cells,
+            bind_groups,
+            rule_buf,
+            bytes: 2 * stats::texture_bytes(size, CELL_FORMAT)
+                + readback.bytes()
+                + mem::size_of::<RuleUniforms>() as u64,
+            readback,
+            pipeline,
+            current: 0,
+        }
+    }
+
+    /// GPU memory of the textures and buffers.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Starts over from `cells`.
+    pub fn reset(&mut self, queue: &wgpu::Queue, cells: &Cells) {
+        let size = self.size;
+        queue.write_texture(
+            self.cells[0].as_image_copy(),
+            cells.states.as_bytes(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(size.x * mem::size_of::<u32>() as u32),
+                rows_per_image: NonZeroU32::new(size.y),
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+        self.current = 0;
+    }
+
+    /// Encodes `steps` steps of `rule`, each writing its field to the atlas.
+    /// The rule is written right away, like the brush uniforms.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        rule: &Rule,
+        steps: u32,
+    ) {
+        let uniforms = RuleUniforms {
+            survival: rule.survival,
+            birth: rule.birth,
+            states: rule.states,
+            moore: (rule.neighbourhood == Neighbourhood::Moore) as u32,
+        };
+        queue.write_buffer(&self.rule_buf, 0, uniforms.as_bytes());
+
+        let workgroups = (self.size + UVec3::splat(3)) / 4;
+        let mut cs_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        cs_pass.set_pipeline(&self.pipeline);
+        for _ in 0..steps {
+            cs_pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            cs_pass.dispatch(workgroups.x, workgroups.y, workgroups.z);
+            self.current = 1 - self.current;
+        }
+    }
+
+    /// Blocks until the latest step was read back.
+    pub fn read_cells(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Cells {
+        let states = self
+            .readback
+            .read(device, queue, &self.cells[self.current])
+            .chunks_exact(mem::size_of::<u32>())
+            .map(|bytes| u32::read_from(bytes).unwrap())
+            .collect();
+        Cells {
+            size: self.size,
+            states,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec3;
+
+    fn rule(text: &str) -> Rule {
+        text.parse().unwrap()
+    }
+
+    fn single_cell(size: UVec3, rule: &Rule, p: IVec3) -> Cells {
+        let mut cells = Cells {
+            size,
+            states: vec![0; (size.x * size.y * size.z) as usize],
+        };
+        let i = cells.index(p);
+        cells.states[i] = rule.alive();
+        cells
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(
+            rule("4/4/5/M"),
+            Rule {
+                survival: 1 << 4,
+                birth: 1 << 4,
+                states: 5,
+                neighbourhood: Neighbourhood::Moore,
+            }
+        );
+        let r = rule(" 2,6,9 / 4,6,8-10 / 10 / n");
+        assert_eq!(r.survival, 1 << 2 | 1 << 6 | 1 << 9);
+        assert_eq!(r.birth, 1 << 4 | 1 << 6 | 1 << 8 | 1 << 9 | 1 << 10);
+        assert_eq!(r.neighbourhood, Neighbourhood::VonNeumann);
+        assert_eq!(rule("/3/2/M").survival, 0);
+
+        for bad in [
+            "4/4/5",
+            "4/4/1/M",
+            "4/4/256/M",
+            "27/4/5/M",
+            "4/6-5/5/M",
+            "x/4/5/M",
+            "4/4/5/Q",
+        ] {
+            assert!(bad.parse::<Rule>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn neighbourhoods_have_the_right_cells() {
+        assert_eq!(Neighbourhood::Moore.offsets().count(), 26);
+        let faces: Vec<IVec3> = Neighbourhood::VonNeumann.offsets().collect();
+        assert_eq!(faces.len(), 6);
+        assert!(faces.contains(&IVec3::new(0, 0, -1)) && faces.contains(&IVec3::X));
+    }
+
+    #[test]
+    fn steps_follow_the_rule() {
+        let size = uvec3(6, 6, 6);
+        // Born next to exactly one live cell, nothing survives.
+        let r = rule("/1/2/M");
+        let cells = single_cell(size, &r, IVec3::splat(2)).step(&r);
+        assert_eq!(cells.states.iter().filter(|&&s| s == 1).count(), 26);
+        assert_eq!(cells.states[cells.index(IVec3::splat(2))], 0);
+
+        // The grid wraps around.
+        let cells = single_cell(size, &r, IVec3::ZERO).step(&r);
+        assert_eq!(cells.states[cells.index(IVec3::splat(5))], 1);
+        let r = rule("/1/2/N");
+        let cells = single_cell(size, &r, IVec3::ZERO).step(&r);
+        assert_eq!(cells.states[cells.index(IVec3::new(5, 0, 0))], 1);
+        assert_eq!(cells.states[cells.index(IVec3::new(5, 5, 0))], 0);
+    }
+
+    #[test]
+    fn dying_cells_fade_and_dont_count() {
+        let size = uvec3(5, 5, 5);
+        let r = rule("/1/4/M");
+        let mut cells = single_cell(size, &r, IVec3::splat(2));
+        let center = cells.index(IVec3::splat(2));
+        cells = cells.step(&r);
+        assert_eq!(cells.states[center], 2);
+        // The dying center doesn't count, the 26 newborn cells have too many
+        // live neighbours to be born again or survive.
+        cells = cells.step(&r);
+        assert_eq!(cells.states[center], 1);
+        let field = cells.field(&r).get(2, 2, 2);
+        assert!(field > -0.5 && field < 0.5);
+        cells = cells.step(&r);
+        assert_eq!(cells.states[center], 0);
+        assert_eq!(cells.field(&r).get(2, 2, 2), 0.5);
+    }
+
+    #[test]
+    fn soups_fill_the_middle() {
+        let size = uvec3(16, 16, 16);
+        let r = rule("4/4/5/M");
+        let cells = Cells::soup(size, &r, 0.5, 7);
+        let live = cells.states.iter().filter(|&&s| s == 4).count();
+        assert!((200..312).contains(&live), "{}", live);
+        assert_eq!(cells.states[cells.index(IVec3::ZERO)], 0);
+        assert_eq!(cells, Cells::soup(size, &r, 0.5, 7));
+    }
+
+    #[test]
+    fn voxel_meshes_skip_inner_faces() {
+        let size = uvec3(4, 4, 4);
+        let r = rule("/1/2/M");
+        let mut cells = single_cell(size, &r, IVec3::splat(1));
+        let mesh = cells.voxel_mesh();
+        assert_eq!(mesh.indices.len(), 6 * 6);
+        let measurements = crate::measure::measure(&mesh);
+        assert!((measurements.volume - 1.0 / 64.0).abs() < 1e-6);
+
+        let i = cells.index(IVec3::new(2, 1, 1));
+        cells.states[i] = 1;
+        assert_eq!(cells.voxel_mesh().indices.len(), 10 * 6);
+    }
+}
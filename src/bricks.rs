@@ -0,0 +1,262 @@
+use crate::chunk::BRICK_CELLS;
+use crate::extract::Region;
+use crate::readback::TextureReadback;
+use crate::stats;
+use crate::volume::{self, Volume, SLAB_SAMPLES};
+use glam::UVec3;
+use std::num::NonZeroU32;
+use wgpu::util::DeviceExt;
+use zerocopy::{AsBytes, FromBytes};
+
+pub(crate) const FIELD_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// Must be kept in sync with `Bricks` in `shaders/bricks.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct BrickLayout {
+    grid: [i32; 3],
+    _pad0: i32,
+    bricks: [i32; 3],
+    _pad1: i32,
+    tiles: [i32; 3],
+    _pad2: i32,
+}
+
+/// Slabs along each axis of an atlas of `bricks`, filling rows before
+/// layers. None when they don't fit in a 3D texture within `limits`.
+fn tiles(limits: &wgpu::Limits, bricks: UVec3) -> Option<UVec3> {
+    let most = (limits.max_texture_dimension_3d / SLAB_SAMPLES) as u64;
+    let count = bricks.x as u64 * bricks.y as u64 * bricks.z as u64;
+    let x = count.min(most);
+    let y = count.div_ceil(x).min(most);
+    let z = count.div_ceil(x * y);
+    (z <= most).then(|| UVec3::new(x as u32, y as u32, z as u32))
+}
+
+fn extent(size: UVec3) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: size.z,
+    }
+}
+
+fn origin(p: UVec3) -> wgpu::Origin3d {
+    wgpu::Origin3d {
+        x: p.x,
+        y: p.y,
+        z: p.z,
+    }
+}
+
+/// The field on the GPU, stored like `Volume`: the slab of every brick is a
+/// tile of one 3D texture, so the grid isn't bound by the largest texture.
+/// Passes address the samples through `shaders/bricks.wgsl`.
+pub(crate) struct BrickAtlas {
+    size: UVec3,
+    bricks: UVec3,
+    tiles: UVec3,
+    texture: wgpu::Texture,
+    // Made on the first `read`.
+    readback: Option<TextureReadback>,
+}
+
+impl BrickAtlas {
+    /// Whether an atlas within `limits` holds a grid of `size`.
+    pub fn holds(limits: &wgpu::Limits, size: UVec3) -> bool {
+        tiles(limits, volume::brick_count(size)).is_some()
+    }
+
+    pub fn new(device: &wgpu::Device, size: UVec3) -> BrickAtlas {
+        let bricks = volume::brick_count(size);
+        let tiles = tiles(&device.limits(), bricks).expect("grid too large for the brick atlas");
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bricks"),
+            size: extent(tiles * SLAB_SAMPLES),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: FIELD_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        BrickAtlas {
+            size,
+            bricks,
+            tiles,
+            texture,
+            readback: None,
+        }
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// A texture laid out like the atlas, for passes that write some of the
+    /// bricks aside before `copy` puts them in.
+    pub fn create_texture(
+        &self,
+        device: &wgpu::Device,
+        usage: wgpu::TextureUsages,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: extent(self.tiles * SLAB_SAMPLES),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: FIELD_FORMAT,
+            usage,
+        })
+    }
+
+    /// Uniforms `shaders/bricks.wgsl` finds the bricks with, the same for the
+    /// life of the atlas.
+    pub fn create_layout_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
+        let layout = BrickLayout {
+            grid: self.size.as_ivec3().to_array(),
+            bricks: self.bricks.as_ivec3().to_array(),
+            tiles: self.tiles.as_ivec3().to_array(),
+            ..Default::default()
+        };
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: layout.as_bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+
+    /// GPU memory of the texture, or of one made by `create_texture`.
+    pub fn texture_bytes(&self) -> u64 {
+        stats::texture_bytes(self.tiles * SLAB_SAMPLES, FIELD_FORMAT)
+    }
+
+    /// GPU memory of the texture and the readback buffer.
+    pub fn bytes(&self) -> u64 {
+        self.texture_bytes() + self.readback.as_ref().map_or(0, TextureReadback::bytes)
+    }
+
+    /// First texel of `brick`'s slab.
+    fn slab_origin(&self, brick: UVec3) -> UVec3 {
+        let slot = brick.x + self.bricks.x * (brick.y + self.bricks.y * brick.z);
+        let tile = UVec3::new(
+            slot % self.tiles.x,
+            slot / self.tiles.x % self.tiles.y,
+            slot / (self.tiles.x * self.tiles.y),
+        );
+        tile * SLAB_SAMPLES
+    }
+
+    /// Copies the slabs holding any sample in `samples` from the CPU copy of
+    /// the field.
+    pub fn upload(&self, queue: &wgpu::Queue, volume: &Volume, samples: Region) {
+        for brick in volume::bricks_holding(samples, self.bricks).cells() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: origin(self.slab_origin(brick)),
+                    aspect: wgpu::TextureAspect::All,
+                },
+                volume.slab(brick).as_bytes(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(SLAB_SAMPLES * 4),
+                    rows_per_image: NonZeroU32::new(SLAB_SAMPLES),
+                },
+                extent(UVec3::splat(SLAB_SAMPLES)),
+            );
+        }
+    }
+
+    /// Copies `samples` from `from` to `to`, both laid out like the atlas, in
+    /// every slab holding them.
+    pub fn copy(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        from: &wgpu::Texture,
+        to: &wgpu::Texture,
+        samples: Region,
+    ) {
+        for brick in volume::bricks_holding(samples, self.bricks).cells() {
+            let first = brick * BRICK_CELLS;
+            let min = samples.min.max(first) - first;
+            let max = samples.max.min(first + UVec3::splat(SLAB_SAMPLES)) - first;
+            let texel = origin(self.slab_origin(brick) + min);
+            encoder.copy_texture_to_texture(
+                wgpu::ImageCopyTexture {
+                    texture: from,
+                    mip_level: 0,
+                    origin: texel,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyTexture {
+                    texture: to,
+                    mip_level: 0,
+                    origin: texel,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                extent(max - min),
+            );
+        }
+    }
+
+    /// Blocks until the atlas was read back into a CPU copy of the field.
+    pub fn read(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Volume {
+        let size = self.tiles * SLAB_SAMPLES;
+        let readback = self
+            .readback
+            .get_or_insert_with(|| TextureReadback::new(device, size, FIELD_FORMAT));
+        let texels: Vec<f32> = readback
+            .read(device, queue, &self.texture)
+            .chunks_exact(4)
+            .map(|bytes| f32::read_from(bytes).unwrap())
+            .collect();
+
+        let mut volume = Volume::new(self.size);
+        let all = Region {
+            min: UVec3::ZERO,
+            max: self.bricks,
+        };
+        let row = SLAB_SAMPLES as usize;
+        for brick in all.cells() {
+            let first = self.slab_origin(brick);
+            let slab = volume.slab_mut(brick);
+            for z in 0..SLAB_SAMPLES {
+                for y in 0..SLAB_SAMPLES {
+                    let texel =
+                        (first.x + size.x * (first.y + y + size.y * (first.z + z))) as usize;
+                    let sample = ((y + SLAB_SAMPLES * z) * SLAB_SAMPLES) as usize;
+                    slab[sample..sample + row].copy_from_slice(&texels[texel..texel + row]);
+                }
+            }
+        }
+        volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec3;
+
+    #[test]
+    fn tiles_wrap_into_rows_and_layers() {
+        let limits = wgpu::Limits {
+            max_texture_dimension_3d: 4 * SLAB_SAMPLES + 3,
+            ..wgpu::Limits::default()
+        };
+        assert_eq!(tiles(&limits, uvec3(2, 1, 1)), Some(uvec3(2, 1, 1)));
+        assert_eq!(tiles(&limits, uvec3(3, 2, 1)), Some(uvec3(4, 2, 1)));
+        assert_eq!(tiles(&limits, uvec3(4, 4, 3)), Some(uvec3(4, 4, 3)));
+        assert_eq!(tiles(&limits, uvec3(4, 4, 5)), None);
+
+        // Larger than one texture along x.
+        let grid = uvec3(8 * BRICK_CELLS + 1, 2, 2);
+        assert!(grid.x > limits.max_texture_dimension_3d);
+        assert!(BrickAtlas::holds(&limits, grid));
+    }
+}
@@ -0,0 +1,61 @@
+use egui::{Context, PointerButton};
+use glam::{Mat4, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Orbit camera around `target`, driven by mouse input egui doesn't use.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Camera {
+    pub target: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub fov_y: f32,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            target: Vec3::ZERO,
+            yaw: 0.6,
+            pitch: 0.4,
+            distance: 2.0,
+            fov_y: 45f32.to_radians(),
+        }
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        let dir = Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        );
+        self.target + dir * self.distance
+    }
+
+    pub fn view(&self) -> Mat4 {
+        Mat4::look_at_rh(self.eye(), self.target, Vec3::Y)
+    }
+
+    pub fn projection(&self, aspect: f32) -> Mat4 {
+        Mat4::perspective_rh(self.fov_y, aspect, 0.01, 100.0)
+    }
+
+    pub fn view_proj(&self, aspect: f32) -> Mat4 {
+        self.projection(aspect) * self.view()
+    }
+
+    /// Orbits on `orbit_button` drag and zooms on scroll, unless the pointer
+    /// is over an egui window.
+    pub fn handle_input(&mut self, context: &Context, orbit_button: PointerButton) {
+        if context.is_pointer_over_area() {
+            return;
+        }
+        let input = context.input();
+        if input.pointer.button_down(orbit_button) {
+            let delta = input.pointer.delta();
+            self.yaw -= delta.x * 0.01;
+            self.pitch = (self.pitch + delta.y * 0.01).clamp(-1.5, 1.5);
+        }
+        self.distance = (self.distance * (1.0 - input.scroll_delta.y * 0.001)).clamp(0.1, 50.0);
+    }
+}
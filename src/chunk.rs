@@ -0,0 +1,267 @@
+use crate::extract::gpu::{Counters, ExtractTarget, GpuExtractor};
+use crate::extract::{cell_count, Backend, Extractor, Lod, Region, MAX_LOD};
+use crate::mesh::{self, Mesh};
+use crate::volume::{self, Volume};
+use glam::{IVec3, UVec3, Vec3};
+use std::iter;
+
+/// Cells along each side of a brick. Neighbouring bricks share the samples on
+/// the face between them, so every brick reads `BRICK_CELLS + 1` samples.
+pub(crate) const BRICK_CELLS: u32 = 16;
+
+struct Chunk {
+    target: ExtractTarget,
+    counters: Counters,
+    lod: Lod,
+    dirty: bool,
+}
+
+/// The grid split into bricks that are extracted on their own, each into its
+/// own mesh buffers. Only bricks marked dirty are extracted again.
+pub(crate) struct ChunkGrid {
+    size: UVec3,
+    bricks: UVec3,
+    chunks: Vec<Chunk>,
+}
+
+impl ChunkGrid {
+    pub fn new(device: &wgpu::Device, gpu_extractor: &GpuExtractor, size: UVec3) -> ChunkGrid {
+        let cells = cell_count(size);
+        let bricks = volume::brick_count(size);
+        let chunks = Region {
+            min: UVec3::ZERO,
+            max: bricks,
+        }
+        .cells()
+        .map(|brick| {
+            let min = brick * BRICK_CELLS;
+            let region = Region {
+                min,
+                max: (min + UVec3::splat(BRICK_CELLS)).min(cells),
+            };
+            Chunk {
+                target: gpu_extractor.target(device, region),
+                counters: Counters::default(),
+                lod: Lod::default(),
+                dirty: true,
+            }
+        })
+        .collect();
+
+        ChunkGrid {
+            size,
+            bricks,
+            chunks,
+        }
+    }
+
+    pub fn bricks(&self) -> UVec3 {
+        self.bricks
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.dirty = true;
+        }
+    }
+
+    /// Marks the bricks whose surface depends on any sample in `samples`.
+    /// Gradients and the dual extractors' apron reach two samples past a
+    /// brick's cells.
+    pub fn mark_dirty(&mut self, samples: Region) {
+        let reach = UVec3::splat(2);
+        for chunk in &mut self.chunks {
+            let region = chunk.target.region();
+            let min = region.min.max(reach) - reach;
+            // The cells read samples up to and including `region.max`.
+            let max = region.max + reach + UVec3::ONE;
+            if samples.min.cmplt(max).all() && samples.max.cmpgt(min).all() {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.dirty).count()
+    }
+
+    /// Picks the level of detail of every brick from its distance to `eye`, in
+    /// object space, going one level coarser every `lod_distance`. Neighbours
+    /// differ by at most one level so Transvoxel can stitch them. Bricks whose
+    /// level changed are marked dirty.
+    pub fn update_lod(&mut self, eye: Vec3, lod_distance: f32, enabled: bool) {
+        let mut levels: Vec<u32> = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let region = chunk.target.region();
+                // Coarse cells have to tile the brick.
+                let max_level = (0..=MAX_LOD)
+                    .rev()
+                    .find(|&level| (region.size() % (1 << level)) == UVec3::ZERO)
+                    .unwrap();
+                let center = (region.min + region.max).as_vec3() * 0.5;
+                let distance = mesh::grid_to_object(self.size, center).distance(eye);
+                if enabled {
+                    ((distance / lod_distance) as u32).min(max_level)
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let neighbour = |index: usize, face: u32| {
+            let brick = self.brick(index);
+            let axis = (face / 2) as usize;
+            let mut next = brick.as_ivec3();
+            next[axis] += if face % 2 == 1 { 1 } else { -1 };
+            (next.cmpge(IVec3::ZERO).all() && next.cmplt(self.bricks.as_ivec3()).all())
+                .then(|| self.brick_index(next.as_uvec3()))
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..levels.len() {
+                for face in 0..6 {
+                    if let Some(next) = neighbour(index, face) {
+                        if levels[index] > levels[next] + 1 {
+                            levels[index] = levels[next] + 1;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let lods: Vec<Lod> = (0..levels.len())
+            .map(|index| Lod {
+                level: levels[index],
+                transitions: (0..6).fold(0, |transitions, face| match neighbour(index, face) {
+                    Some(next) if levels[next] < levels[index] => transitions | 1 << face,
+                    _ => transitions,
+                }),
+            })
+            .collect();
+        for (chunk, lod) in self.chunks.iter_mut().zip(lods) {
+            if chunk.lod != lod {
+                chunk.lod = lod;
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    fn brick(&self, index: usize) -> UVec3 {
+        let index = index as u32;
+        UVec3::new(
+            index % self.bricks.x,
+            index / self.bricks.x % self.bricks.y,
+            index / (self.bricks.x * self.bricks.y),
+        )
+    }
+
+    fn brick_index(&self, brick: UVec3) -> usize {
+        (brick.x + self.bricks.x * (brick.y + self.bricks.y * brick.z)) as usize
+    }
+
+    /// How many bricks are at each level of detail.
+    pub fn lod_counts(&self) -> [usize; MAX_LOD as usize + 1] {
+        let mut counts = [0; MAX_LOD as usize + 1];
+        for chunk in &self.chunks {
+            counts[chunk.lod.level as usize] += 1;
+        }
+        counts
+    }
+
+    /// Sum of the counters of all bricks.
+    pub fn counters(&self) -> Counters {
+        self.chunks.iter().fold(Counters::new(0, 0), |sum, chunk| {
+            Counters::new(
+                sum.index_count + chunk.counters.index_count,
+                sum.vertex_count + chunk.counters.vertex_count,
+            )
+        })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.target.bytes()).sum()
+    }
+
+    /// Reads back the meshes of all bricks as one, see
+    /// `ExtractTarget::read_mesh`.
+    pub fn read_mesh(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Mesh {
+        let mut mesh = Mesh::default();
+        for chunk in &self.chunks {
+            let part = chunk.target.read_mesh(device, queue, chunk.counters);
+            let base = mesh.vertices.len() as u32;
+            mesh.vertices.extend(part.vertices);
+            mesh.indices.extend(part.indices.iter().map(|i| base + i));
+        }
+        mesh
+    }
+
+    /// Extracts the dirty bricks. Returns how many bricks were extracted.
+    #[allow(clippy::too_many_arguments)]
+    pub fn extract(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        gpu_extractor: &GpuExtractor,
+        volume: &Volume,
+        extractor: Extractor,
+        backend: Backend,
+        iso: f32,
+    ) -> usize {
+        let mut dirty: Vec<&mut Chunk> =
+            self.chunks.iter_mut().filter(|chunk| chunk.dirty).collect();
+        let count = dirty.len();
+        if count == 0 {
+            return 0;
+        }
+
+        match backend {
+            Backend::Gpu => {
+                // Bricks whose mesh didn't fit are extracted again into bigger
+                // buffers.
+                while !dirty.is_empty() {
+                    let mut encoder =
+                        device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                            label: Some("extract chunks"),
+                        });
+                    for chunk in &mut dirty {
+                        chunk.target.prepare(queue, iso, chunk.lod);
+                        gpu_extractor.encode(&mut encoder, &chunk.target, extractor);
+                    }
+                    queue.submit(iter::once(encoder.finish()));
+                    dirty.retain_mut(|chunk| {
+                        chunk.counters = chunk.target.read_counters(device);
+                        chunk.dirty =
+                            gpu_extractor.grow_to_fit(device, &mut chunk.target, chunk.counters);
+                        chunk.dirty
+                    });
+                }
+            }
+            Backend::Cpu => {
+                for chunk in dirty {
+                    let mesh = extractor.extract_lod(volume, iso, chunk.target.region(), chunk.lod);
+                    gpu_extractor.grow_to_fit(device, &mut chunk.target, Counters::of_mesh(&mesh));
+                    chunk.counters = chunk.target.write_mesh(queue, &mesh);
+                    chunk.dirty = false;
+                }
+            }
+        }
+        count
+    }
+
+    /// Draws every brick, `before_draw` gets to set up the pass for its level
+    /// of detail.
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        mut before_draw: impl FnMut(&mut wgpu::RenderPass<'a>, Lod),
+    ) {
+        for chunk in &self.chunks {
+            before_draw(pass, chunk.lod);
+            chunk.target.draw(pass);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use crate::volume::Slice;
+use glam::{vec2, Vec2};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::{fs, io, path::Path};
+
+// Grid edges are keyed by their lower corner and whether they run along v.
+type EdgeKey = (u32, u32, bool);
+
+// Cell edges: 0 = bottom, 1 = right, 2 = top, 3 = left.
+// Cases 5 and 10 are saddles and are resolved in `cell_segments`.
+static SEGMENT_TABLE: [&[(usize, usize)]; 16] = [
+    &[],
+    &[(3, 0)],
+    &[(0, 1)],
+    &[(3, 1)],
+    &[(1, 2)],
+    &[],
+    &[(0, 2)],
+    &[(3, 2)],
+    &[(2, 3)],
+    &[(0, 2)],
+    &[],
+    &[(1, 2)],
+    &[(1, 3)],
+    &[(0, 1)],
+    &[(3, 0)],
+    &[],
+];
+
+pub(crate) struct Contour {
+    pub iso: f32,
+    pub lines: Vec<Vec<Vec2>>,
+}
+
+impl Contour {
+    pub fn new(slice: &Slice, iso: f32) -> Contour {
+        Contour {
+            iso,
+            lines: marching_squares(slice, iso),
+        }
+    }
+
+    /// Point to put the iso label at, the middle of the longest line.
+    pub fn label_anchor(&self) -> Option<Vec2> {
+        self.lines
+            .iter()
+            .max_by_key(|line| line.len())
+            .map(|line| line[line.len() / 2])
+    }
+}
+
+fn cell_segments(case: usize, center_inside: bool) -> &'static [(usize, usize)] {
+    match (case, center_inside) {
+        (5, true) | (10, false) => &[(0, 1), (2, 3)],
+        (5, false) | (10, true) => &[(3, 0), (1, 2)],
+        _ => SEGMENT_TABLE[case],
+    }
+}
+
+/// Extracts the `iso` level set of `slice` as polylines in slice grid
+/// coordinates, where sample (u, v) sits at point (u, v). Closed loops repeat
+/// their first point at the end.
+pub(crate) fn marching_squares(slice: &Slice, iso: f32) -> Vec<Vec<Vec2>> {
+    if slice.width < 2 || slice.height < 2 {
+        return Vec::new();
+    }
+
+    let mut points: HashMap<EdgeKey, Vec2> = HashMap::new();
+    let mut segments: Vec<(EdgeKey, EdgeKey)> = Vec::new();
+
+    for v in 0..slice.height - 1 {
+        for u in 0..slice.width - 1 {
+            let corners = [
+                slice.get(u, v),
+                slice.get(u + 1, v),
+                slice.get(u + 1, v + 1),
+                slice.get(u, v + 1),
+            ];
+            let case = corners
+                .iter()
+                .enumerate()
+                .fold(0, |case, (i, &d)| case | ((d >= iso) as usize) << i);
+            let center_inside = corners.iter().sum::<f32>() * 0.25 >= iso;
+
+            for &(a, b) in cell_segments(case, center_inside) {
+                let mut key = |edge: usize| {
+                    let (c0, c1, key) = match edge {
+                        0 => (0, 1, (u, v, false)),
+                        1 => (1, 2, (u + 1, v, true)),
+                        2 => (3, 2, (u, v + 1, false)),
+                        _ => (0, 3, (u, v, true)),
+                    };
+                    points.entry(key).or_insert_with(|| {
+                        let t = (iso - corners[c0]) / (corners[c1] - corners[c0]);
+                        let p0 = vec2(u as f32, v as f32) + corner_offset(c0);
+                        let p1 = vec2(u as f32, v as f32) + corner_offset(c1);
+                        p0.lerp(p1, t.clamp(0.0, 1.0))
+                    });
+                    key
+                };
+                segments.push((key(a), key(b)));
+            }
+        }
+    }
+
+    join_segments(&segments, &points)
+}
+
+fn corner_offset(corner: usize) -> Vec2 {
+    match corner {
+        0 => vec2(0.0, 0.0),
+        1 => vec2(1.0, 0.0),
+        2 => vec2(1.0, 1.0),
+        _ => vec2(0.0, 1.0),
+    }
+}
+
+fn join_segments(
+    segments: &[(EdgeKey, EdgeKey)],
+    points: &HashMap<EdgeKey, Vec2>,
+) -> Vec<Vec<Vec2>> {
+    let mut links: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        links.entry(a).or_default().push(i);
+        links.entry(b).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let walk = |start: EdgeKey, used: &mut Vec<bool>| {
+        let mut keys = Vec::new();
+        let mut key = start;
+        while let Some(&next) = links[&key].iter().find(|&&s| !used[s]) {
+            used[next] = true;
+            let (a, b) = segments[next];
+            key = if a == key { b } else { a };
+            keys.push(key);
+        }
+        keys
+    };
+
+    let mut lines = Vec::new();
+    for i in 0..segments.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let (a, b) = segments[i];
+
+        let mut keys = walk(a, &mut used);
+        keys.reverse();
+        keys.push(a);
+        keys.push(b);
+        keys.extend(walk(b, &mut used));
+
+        lines.push(keys.iter().map(|key| points[key]).collect());
+    }
+    lines
+}
+
+/// Distinct color for iso level `i` out of `count`.
+pub(crate) fn level_color(i: usize, count: usize) -> [u8; 3] {
+    let hue = i as f32 / count.max(1) as f32;
+    let channel = |offset: f32| {
+        let x = ((hue + offset) * 6.0) % 6.0;
+        let c = (x - 3.0).abs() - 1.0;
+        (c.clamp(0.0, 1.0) * 255.0) as u8
+    };
+    [channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0)]
+}
+
+/// Writes the contours of a `width` x `height` slice as an SVG document, one
+/// group of polylines per iso level.
+pub(crate) fn to_svg(contours: &[Contour], width: u32, height: u32) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        width, height
+    );
+    for (i, contour) in contours.iter().enumerate() {
+        let [r, g, b] = level_color(i, contours.len());
+        let _ = writeln!(
+            svg,
+            r#"  <g stroke="rgb({},{},{})" stroke-width="0.25" fill="none">"#,
+            r, g, b
+        );
+        for line in &contour.lines {
+            let points = line
+                .iter()
+                .map(|p| format!("{:.3},{:.3}", p.x + 0.5, p.y + 0.5))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let _ = writeln!(svg, r#"    <polyline points="{}"/>"#, points);
+        }
+        if let Some(p) = contour.label_anchor() {
+            let _ = writeln!(
+                svg,
+                r#"    <text x="{:.3}" y="{:.3}" font-size="3" fill="rgb({},{},{})" stroke="none">{:.3}</text>"#,
+                p.x + 0.5,
+                p.y + 0.5,
+                r,
+                g,
+                b,
+                contour.iso
+            );
+        }
+        let _ = writeln!(svg, "  </g>");
+    }
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+pub(crate) fn write_svg(
+    path: impl AsRef<Path>,
+    contours: &[Contour],
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
+    fs::write(path, to_svg(contours, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn slice_from_fn(width: u32, height: u32, f: impl Fn(Vec2) -> f32) -> Slice {
+        let mut data = Vec::new();
+        for v in 0..height {
+            for u in 0..width {
+                data.push(f(vec2(u as f32, v as f32)));
+            }
+        }
+        Slice {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn length(line: &[Vec2]) -> f32 {
+        line.windows(2).map(|w| w[0].distance(w[1])).sum()
+    }
+
+    #[test]
+    fn circle_is_one_closed_loop() {
+        let center = vec2(15.5, 15.5);
+        let slice = slice_from_fn(32, 32, |p| 10.0 - p.distance(center));
+        let lines = marching_squares(&slice, 0.0);
+
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line.first(), line.last());
+        for p in line {
+            assert!((p.distance(center) - 10.0).abs() < 0.05, "{} is off", p);
+        }
+        let expected = 2.0 * PI * 10.0;
+        assert!((length(line) - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn circle_cut_by_the_border_is_open() {
+        let center = vec2(0.0, 15.5);
+        let slice = slice_from_fn(32, 32, |p| 10.0 - p.distance(center));
+        let lines = marching_squares(&slice, 0.0);
+
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_ne!(line.first(), line.last());
+        for end in [line[0], line[line.len() - 1]] {
+            assert_eq!(end.x, 0.0);
+        }
+        let expected = PI * 10.0;
+        assert!((length(line) - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn segments_join_across_cells() {
+        let slice = slice_from_fn(6, 4, |p| p.x - 2.5);
+        let lines = marching_squares(&slice, 0.0);
+
+        assert_eq!(lines.len(), 1);
+        let mut line = lines[0].clone();
+        line.sort_by(|a, b| a.y.total_cmp(&b.y));
+        let expected: Vec<Vec2> = (0..4).map(|v| vec2(2.5, v as f32)).collect();
+        assert_eq!(line, expected);
+    }
+
+    #[test]
+    fn saddles_follow_the_cell_center() {
+        // Corners 0 and 2 are inside, the center average is 0.5.
+        let slice = Slice {
+            width: 2,
+            height: 2,
+            data: vec![1.0, 0.0, 0.0, 1.0],
+        };
+        let cut_off = |iso: f32| {
+            let lines = marching_squares(&slice, iso);
+            assert_eq!(lines.len(), 2);
+            let mut corners: Vec<Vec2> = lines
+                .iter()
+                .map(|line| {
+                    assert_eq!(line.len(), 2);
+                    ((line[0] + line[1]) * 0.5).round()
+                })
+                .collect();
+            corners.sort_by(|a, b| a.x.total_cmp(&b.x));
+            corners
+        };
+
+        // A connected center cuts off the outside corners 1 and 3.
+        assert_eq!(cut_off(0.4), [vec2(0.0, 1.0), vec2(1.0, 0.0)]);
+        // A separated center cuts off the inside corners 0 and 2.
+        assert_eq!(cut_off(0.6), [vec2(0.0, 0.0), vec2(1.0, 1.0)]);
+    }
+
+    #[test]
+    fn svg_groups_and_labels_every_level() {
+        let center = vec2(7.5, 7.5);
+        let slice = slice_from_fn(16, 16, |p| 6.0 - p.distance(center));
+        let contours = [Contour::new(&slice, 0.0), Contour::new(&slice, 2.0)];
+        let svg = to_svg(&contours, 16, 16);
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"viewBox="0 0 16 16""#));
+        assert_eq!(svg.matches("<g ").count(), 2);
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        for (i, iso) in ["0.000", "2.000"].iter().enumerate() {
+            let [r, g, b] = level_color(i, 2);
+            let stroke = format!(r#"stroke="rgb({},{},{})""#, r, g, b);
+            assert!(svg.contains(&stroke), "{} is missing", stroke);
+            assert!(svg.contains(&format!(">{}</text>", iso)));
+        }
+        assert_ne!(level_color(0, 2), level_color(1, 2));
+    }
+}
@@ -0,0 +1,101 @@
+use super::{cell_case, cell_values, edge_crossing, Crossing, Region, EDGES};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::volume::Volume;
+use glam::{UVec3, Vec3};
+
+/// Shared part of the dual methods: one vertex per cell the surface passes
+/// through, placed by `place`, and one quad per crossed grid edge joining the
+/// vertices of the four cells around it.
+pub(super) fn extract(
+    volume: &Volume,
+    iso: f32,
+    region: Region,
+    place: impl Fn(&[Crossing], UVec3) -> Vec3,
+) -> Mesh {
+    let mut mesh = Mesh::default();
+    // Quads of edges on the low faces of the region need the vertices of the
+    // cells just below it, so those get vertices too. They're computed from the
+    // same samples as in the neighbouring region, which keeps seams closed.
+    let apron = Region {
+        min: region.min.max(UVec3::ONE) - UVec3::ONE,
+        max: region.max,
+    };
+    let size = apron.size();
+    let cell_index = |c: UVec3| {
+        let c = c - apron.min;
+        (c.x + size.x * (c.y + size.y * c.z)) as usize
+    };
+    let mut cell_vertex = vec![u32::MAX; (size.x * size.y * size.z) as usize];
+
+    for cell in apron.cells() {
+        let values = cell_values(volume, cell);
+        let case = cell_case(&values, iso);
+        if case == 0 || case == 255 {
+            continue;
+        }
+
+        let crossings: Vec<Crossing> = EDGES
+            .iter()
+            .enumerate()
+            .filter(|(_, &(a, b))| (case >> a) & 1 != (case >> b) & 1)
+            .map(|(edge, _)| edge_crossing(volume, cell, &values, edge, iso))
+            .collect();
+        let gradient = crossings.iter().fold(Vec3::ZERO, |sum, c| sum + c.gradient);
+
+        cell_vertex[cell_index(cell)] = mesh.vertices.len() as u32;
+        mesh.vertices.push(MeshVertex::new(
+            mesh::grid_to_object(volume.size, place(&crossings, cell)),
+            mesh::gradient_to_normal(volume.size, gradient),
+        ));
+    }
+
+    // Every edge starting at the lower corner of a region cell belongs to it.
+    for p in region.cells() {
+        for axis in 0..3 {
+            let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+            if p[b] == 0 || p[c] == 0 {
+                continue;
+            }
+            let mut q = p;
+            q[axis] += 1;
+            let inside = volume.get(p.x, p.y, p.z) < iso;
+            if inside == (volume.get(q.x, q.y, q.z) < iso) {
+                continue;
+            }
+
+            // The four cells around the edge, counter-clockwise around `axis`.
+            let mut e_b = UVec3::ZERO;
+            let mut e_c = UVec3::ZERO;
+            e_b[b] = 1;
+            e_c[c] = 1;
+            let quad =
+                [p - e_b - e_c, p - e_c, p, p - e_b].map(|cell| cell_vertex[cell_index(cell)]);
+
+            // Face along +axis when the edge leaves the inside.
+            if inside {
+                mesh.indices
+                    .extend_from_slice(&[quad[0], quad[1], quad[2], quad[0], quad[2], quad[3]]);
+            } else {
+                mesh.indices
+                    .extend_from_slice(&[quad[0], quad[2], quad[1], quad[0], quad[3], quad[2]]);
+            }
+        }
+    }
+
+    // Drop the apron vertices no quad ended up using.
+    compact(mesh)
+}
+
+fn compact(mesh: Mesh) -> Mesh {
+    let mut remap = vec![u32::MAX; mesh.vertices.len()];
+    let mut out = Mesh::default();
+    for &i in &mesh.indices {
+        let slot = &mut remap[i as usize];
+        if *slot == u32::MAX {
+            *slot = out.vertices.len() as u32;
+            out.vertices.push(mesh.vertices[i as usize]);
+        }
+        out.indices.push(*slot);
+    }
+    out
+}
@@ -0,0 +1,28 @@
+use super::{dual, Region};
+use crate::mesh::Mesh;
+use crate::volume::Volume;
+use glam::{Mat3, Vec3};
+
+// Pulls the QEF solution towards the mass point so flat and degenerate cells
+// stay well conditioned.
+const MASS_POINT_BIAS: f32 = 0.05;
+
+/// Dual contouring, each cell vertex minimizes the squared distances to the
+/// tangent planes at its edge crossings, which keeps sharp features.
+pub(super) fn extract(volume: &Volume, iso: f32, region: Region) -> Mesh {
+    dual::extract(volume, iso, region, |crossings, cell| {
+        let mass_point =
+            crossings.iter().fold(Vec3::ZERO, |sum, c| sum + c.pos) / crossings.len() as f32;
+
+        let mut ata = Mat3::from_diagonal(Vec3::splat(MASS_POINT_BIAS));
+        let mut atb = Vec3::ZERO;
+        for crossing in crossings {
+            let n = crossing.gradient.normalize_or_zero();
+            ata += Mat3::from_cols(n * n.x, n * n.y, n * n.z);
+            atb += n * n.dot(crossing.pos - mass_point);
+        }
+
+        let p = mass_point + ata.inverse() * atb;
+        p.clamp(cell.as_vec3(), cell.as_vec3() + Vec3::ONE)
+    })
+}
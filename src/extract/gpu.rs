@@ -0,0 +1,639 @@
+use super::{transition_table, tri_table, Extractor, Lod, Region};
+use crate::bricks::BrickAtlas;
+use crate::mesh::{Mesh, MeshVertex};
+use crate::shader;
+use glam::UVec3;
+use std::{iter, mem};
+use wgpu::util::DeviceExt;
+use zerocopy::{AsBytes, FromBytes};
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct Params {
+    iso: f32,
+    vertex_capacity: u32,
+    index_capacity: u32,
+    _pad: u32,
+    region_min: [i32; 3],
+    _pad_min: u32,
+    region_max: [i32; 3],
+    step: u32,
+    transitions: u32,
+    _pad_end: [u32; 3],
+}
+
+/// Indirect draw arguments followed by the vertex count and the counts before
+/// clamping, see `Counters` in `shaders/extract_common.wgsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, AsBytes, FromBytes)]
+pub(crate) struct Counters {
+    pub index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    pub vertex_count: u32,
+    /// What the extraction emitted before the counts above were clamped to
+    /// the capacity of the target.
+    pub emitted_index_count: u32,
+    pub emitted_vertex_count: u32,
+}
+
+impl Counters {
+    pub fn new(index_count: u32, vertex_count: u32) -> Counters {
+        Counters {
+            index_count,
+            instance_count: 1,
+            vertex_count,
+            emitted_index_count: index_count,
+            emitted_vertex_count: vertex_count,
+            ..Default::default()
+        }
+    }
+
+    /// Counts of a mesh extracted on the CPU.
+    pub fn of_mesh(mesh: &Mesh) -> Counters {
+        Counters::new(mesh.indices.len() as u32, mesh.vertices.len() as u32)
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.index_count / 3
+    }
+}
+
+/// Compute passes extracting the surface of the `BrickAtlas`, one region at a
+/// time, into the buffers of an `ExtractTarget`.
+pub(crate) struct GpuExtractor {
+    bind_group_layout: wgpu::BindGroupLayout,
+    scalar_view: wgpu::TextureView,
+    bricks_buf: wgpu::Buffer,
+    tri_table_buf: wgpu::Buffer,
+    transition_table_buf: wgpu::Buffer,
+    bytes: u64,
+
+    marching_cubes: wgpu::ComputePipeline,
+    marching_tetrahedra: wgpu::ComputePipeline,
+    surface_nets_vertex: wgpu::ComputePipeline,
+    dual_contouring_vertex: wgpu::ComputePipeline,
+    quads: wgpu::ComputePipeline,
+    transvoxel: wgpu::ComputePipeline,
+    finalize: wgpu::ComputePipeline,
+}
+
+/// Mesh buffers of one region, plus the counters it's drawn with.
+pub(crate) struct ExtractTarget {
+    region: Region,
+    lod: Lod,
+    vertex_capacity: u32,
+    index_capacity: u32,
+
+    vertex_buf: wgpu::Buffer,
+    index_buf: wgpu::Buffer,
+    // Vertex of every cell for the dual methods, including the cells just
+    // below the region whose vertices the quads on its lower faces need.
+    cell_vertex_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    params_buf: wgpu::Buffer,
+    counters_buf: wgpu::Buffer,
+    readback_buf: wgpu::Buffer,
+}
+
+fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Cells `cell_vertex_buf` has room for: the ones of `region` and the ones just
+/// below it.
+fn scratch_cells(region: Region) -> u32 {
+    let first = region.min.max(UVec3::ONE) - UVec3::ONE;
+    let cells = region.max - first;
+    (cells.x * cells.y * cells.z).max(1)
+}
+
+fn mesh_buffers(
+    device: &wgpu::Device,
+    vertex_capacity: u32,
+    index_capacity: u32,
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: vertex_capacity as u64 * mem::size_of::<MeshVertex>() as u64,
+        usage: wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let index_buf = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: index_capacity as u64 * mem::size_of::<u32>() as u64,
+        usage: wgpu::BufferUsages::INDEX
+            | wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    (vertex_buf, index_buf)
+}
+
+impl GpuExtractor {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas) -> GpuExtractor {
+        let tri_table_data: Vec<i32> = tri_table().iter().flatten().map(|&e| e as i32).collect();
+        let tri_table_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: tri_table_data.as_bytes(),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let transition_table_data: Vec<i32> = transition_table()
+            .iter()
+            .flatten()
+            .map(|&e| e as i32)
+            .collect();
+        let transition_table_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: transition_table_data.as_bytes(),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1, false),
+                storage_entry(2, false),
+                storage_entry(3, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(5, false),
+                storage_entry(6, true),
+                storage_entry(7, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = |module: &wgpu::ShaderModule, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module,
+                entry_point,
+            })
+        };
+
+        let mc_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("../shaders/bricks.wgsl"),
+                include_str!("../shaders/extract_common.wgsl"),
+                include_str!("../shaders/marching_cubes.wgsl")
+            ),
+        );
+        let mt_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("../shaders/bricks.wgsl"),
+                include_str!("../shaders/extract_common.wgsl"),
+                include_str!("../shaders/marching_tetrahedra.wgsl")
+            ),
+        );
+        let dual_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("../shaders/bricks.wgsl"),
+                include_str!("../shaders/extract_common.wgsl"),
+                include_str!("../shaders/dual.wgsl")
+            ),
+        );
+        let transvoxel_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("../shaders/bricks.wgsl"),
+                include_str!("../shaders/extract_common.wgsl"),
+                include_str!("../shaders/transvoxel.wgsl")
+            ),
+        );
+        let finalize_module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("../shaders/bricks.wgsl"),
+                include_str!("../shaders/extract_common.wgsl"),
+                include_str!("../shaders/extract_finalize.wgsl")
+            ),
+        );
+
+        let bytes = (mem::size_of_val(&tri_table_data[..])
+            + mem::size_of_val(&transition_table_data[..])) as u64;
+        GpuExtractor {
+            bind_group_layout,
+            scalar_view: atlas
+                .texture()
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            bricks_buf: atlas.create_layout_buffer(device),
+            tri_table_buf,
+            transition_table_buf,
+            bytes,
+            marching_cubes: pipeline(&mc_module, "main"),
+            marching_tetrahedra: pipeline(&mt_module, "main"),
+            surface_nets_vertex: pipeline(&dual_module, "surface_nets_vertex"),
+            dual_contouring_vertex: pipeline(&dual_module, "dual_contouring_vertex"),
+            quads: pipeline(&dual_module, "quads"),
+            transvoxel: pipeline(&transvoxel_module, "main"),
+            finalize: pipeline(&finalize_module, "main"),
+        }
+    }
+
+    /// GPU memory of the buffers shared by all targets.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Creates the buffers for the mesh of `region`, with room for a surface
+    /// crossing it a few times. `grow_to_fit` makes room for more.
+    pub fn target(&self, device: &wgpu::Device, region: Region) -> ExtractTarget {
+        let samples = region.size() + UVec3::ONE;
+        let faces = samples.x * samples.y + samples.y * samples.z + samples.z * samples.x;
+        let (vertex_capacity, index_capacity) = self.clamp_capacity(device, 4 * faces, 24 * faces);
+        let (vertex_buf, index_buf) = mesh_buffers(device, vertex_capacity, index_capacity);
+
+        let cell_vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: scratch_cells(region) as u64 * mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let params_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<Params>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let counters_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<Counters>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let readback_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<Counters>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.bind_group(
+            device,
+            [&vertex_buf, &index_buf],
+            &counters_buf,
+            &params_buf,
+            &cell_vertex_buf,
+        );
+        ExtractTarget {
+            region,
+            lod: Lod::default(),
+            vertex_capacity,
+            index_capacity,
+            vertex_buf,
+            index_buf,
+            cell_vertex_buf,
+            bind_group,
+            params_buf,
+            counters_buf,
+            readback_buf,
+        }
+    }
+
+    /// Grows the mesh buffers of `target` to hold what `counters` say an
+    /// extraction emitted, as far as the device can bind. Returns whether they
+    /// grew, the extraction has to run again then. The contents are lost.
+    pub fn grow_to_fit(
+        &self,
+        device: &wgpu::Device,
+        target: &mut ExtractTarget,
+        counters: Counters,
+    ) -> bool {
+        let grow = |emitted: u32, capacity: u32| {
+            if emitted > capacity {
+                emitted.checked_next_power_of_two().unwrap_or(u32::MAX)
+            } else {
+                capacity
+            }
+        };
+        let (vertex_capacity, index_capacity) = self.clamp_capacity(
+            device,
+            grow(counters.emitted_vertex_count, target.vertex_capacity),
+            grow(counters.emitted_index_count, target.index_capacity),
+        );
+        if vertex_capacity == target.vertex_capacity && index_capacity == target.index_capacity {
+            return false;
+        }
+        let (vertex_buf, index_buf) = mesh_buffers(device, vertex_capacity, index_capacity);
+        target.bind_group = self.bind_group(
+            device,
+            [&vertex_buf, &index_buf],
+            &target.counters_buf,
+            &target.params_buf,
+            &target.cell_vertex_buf,
+        );
+        target.vertex_buf = vertex_buf;
+        target.index_buf = index_buf;
+        target.vertex_capacity = vertex_capacity;
+        target.index_capacity = index_capacity;
+        true
+    }
+
+    /// Limits capacities to the largest storage buffers the device binds.
+    fn clamp_capacity(&self, device: &wgpu::Device, vertices: u32, indices: u32) -> (u32, u32) {
+        let max_bytes = device.limits().max_storage_buffer_binding_size;
+        let max_vertices = max_bytes / mem::size_of::<MeshVertex>() as u32;
+        let max_indices = max_bytes / mem::size_of::<u32>() as u32 / 3 * 3;
+        (vertices.min(max_vertices), indices.min(max_indices))
+    }
+
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        [vertex_buf, index_buf]: [&wgpu::Buffer; 2],
+        counters_buf: &wgpu::Buffer,
+        params_buf: &wgpu::Buffer,
+        cell_vertex_buf: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.scalar_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: vertex_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: index_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: counters_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: cell_vertex_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.tri_table_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: self.transition_table_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.bricks_buf.as_entire_binding(),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &ExtractTarget,
+        extractor: Extractor,
+    ) {
+        let groups = |size: UVec3| (size + UVec3::splat(3)) / 4;
+        let step = target.lod.step();
+        let cell_groups = groups(target.region.size() / step);
+        // The dual vertex pass also covers the cells just below the region.
+        let apron = target.region.min.min(UVec3::ONE);
+        let apron_groups = groups(target.region.size() + apron);
+
+        {
+            let mut cs_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cs_pass.set_bind_group(0, &target.bind_group, &[]);
+            cs_pass.insert_debug_marker(extractor.name());
+            match extractor {
+                Extractor::MarchingCubes => {
+                    cs_pass.set_pipeline(&self.marching_cubes);
+                    cs_pass.dispatch(cell_groups.x, cell_groups.y, cell_groups.z);
+                    if target.lod.transitions != 0 {
+                        let face_groups = cell_groups.max_element();
+                        cs_pass.set_pipeline(&self.transvoxel);
+                        cs_pass.dispatch(face_groups, face_groups, 6);
+                    }
+                }
+                Extractor::MarchingTetrahedra => {
+                    cs_pass.set_pipeline(&self.marching_tetrahedra);
+                    cs_pass.dispatch(cell_groups.x, cell_groups.y, cell_groups.z);
+                }
+                Extractor::SurfaceNets | Extractor::DualContouring => {
+                    cs_pass.set_pipeline(if extractor == Extractor::SurfaceNets {
+                        &self.surface_nets_vertex
+                    } else {
+                        &self.dual_contouring_vertex
+                    });
+                    cs_pass.dispatch(apron_groups.x, apron_groups.y, apron_groups.z);
+                    cs_pass.set_pipeline(&self.quads);
+                    cs_pass.dispatch(cell_groups.x, cell_groups.y, cell_groups.z);
+                }
+            }
+            cs_pass.set_pipeline(&self.finalize);
+            cs_pass.dispatch(1, 1, 1);
+        }
+
+        encoder.copy_buffer_to_buffer(
+            &target.counters_buf,
+            0,
+            &target.readback_buf,
+            0,
+            mem::size_of::<Counters>() as u64,
+        );
+    }
+}
+
+impl ExtractTarget {
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// GPU memory of the mesh buffers, the dual vertices, the uniforms and the
+    /// counters.
+    pub fn bytes(&self) -> u64 {
+        (self.vertex_capacity as usize * mem::size_of::<MeshVertex>()
+            + self.index_capacity as usize * mem::size_of::<u32>()
+            + scratch_cells(self.region) as usize * mem::size_of::<u32>()
+            + mem::size_of::<Params>()
+            + 2 * mem::size_of::<Counters>()) as u64
+    }
+
+    /// Resets the counters ahead of `GpuExtractor::encode`. Extractors that
+    /// don't `supports_lod` must be given the default `lod`.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, iso: f32, lod: Lod) {
+        self.lod = lod;
+        let params = Params {
+            iso,
+            vertex_capacity: self.vertex_capacity,
+            index_capacity: self.index_capacity,
+            region_min: self.region.min.as_ivec3().to_array(),
+            region_max: self.region.max.as_ivec3().to_array(),
+            step: lod.step(),
+            transitions: lod.transitions,
+            ..Default::default()
+        };
+        queue.write_buffer(&self.params_buf, 0, params.as_bytes());
+        queue.write_buffer(&self.counters_buf, 0, Counters::new(0, 0).as_bytes());
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_index_buffer(self.index_buf.slice(..), wgpu::IndexFormat::Uint32);
+        pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        pass.draw_indexed_indirect(&self.counters_buf, 0);
+    }
+
+    /// Blocks until the counters written by the last submitted `encode` are
+    /// available.
+    pub fn read_counters(&self, device: &wgpu::Device) -> Counters {
+        let slice = self.readback_buf.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).unwrap();
+
+        let counters = Counters::read_from(&slice.get_mapped_range()[..]).unwrap();
+        self.readback_buf.unmap();
+        counters
+    }
+
+    /// Reads the mesh back from the buffers, `counters` being what the last
+    /// extraction into them returned. Blocks until the copy is done.
+    pub fn read_mesh(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        counters: Counters,
+    ) -> Mesh {
+        let vertex_count = counters.vertex_count.min(self.vertex_capacity);
+        let index_count = counters.index_count.min(self.index_capacity);
+        let vertex_bytes = vertex_count as u64 * mem::size_of::<MeshVertex>() as u64;
+        let index_bytes = index_count as u64 * mem::size_of::<u32>() as u64;
+        if vertex_bytes == 0 || index_bytes == 0 {
+            return Mesh::default();
+        }
+
+        let staging_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: vertex_bytes + index_bytes,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("read mesh"),
+        });
+        encoder.copy_buffer_to_buffer(&self.vertex_buf, 0, &staging_buf, 0, vertex_bytes);
+        encoder.copy_buffer_to_buffer(&self.index_buf, 0, &staging_buf, vertex_bytes, index_bytes);
+        queue.submit(iter::once(encoder.finish()));
+
+        let slice = staging_buf.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).unwrap();
+
+        let data = slice.get_mapped_range();
+        let (vertex_data, index_data) = data.split_at(vertex_bytes as usize);
+        let vertices = vertex_data
+            .chunks_exact(mem::size_of::<MeshVertex>())
+            .map(|bytes| MeshVertex::read_from(bytes).unwrap())
+            .collect();
+        // Triangles whose vertices didn't fit were still counted.
+        let indices = index_data
+            .chunks_exact(3 * mem::size_of::<u32>())
+            .map(|bytes| <[u32; 3]>::read_from(bytes).unwrap())
+            .filter(|tri| tri.iter().all(|&i| i < vertex_count))
+            .flatten()
+            .collect();
+        Mesh { vertices, indices }
+    }
+
+    /// Uploads a CPU extracted mesh into the same buffers the compute passes
+    /// write, dropping whatever doesn't fit.
+    pub fn write_mesh(&self, queue: &wgpu::Queue, mesh: &Mesh) -> Counters {
+        let vertex_count = mesh.vertices.len().min(self.vertex_capacity as usize);
+        let indices: Vec<u32> = mesh
+            .indices
+            .chunks_exact(3)
+            .filter(|tri| tri.iter().all(|&i| (i as usize) < vertex_count))
+            .take(self.index_capacity as usize / 3)
+            .flatten()
+            .copied()
+            .collect();
+
+        if vertex_count > 0 {
+            queue.write_buffer(
+                &self.vertex_buf,
+                0,
+                mesh.vertices[..vertex_count].as_bytes(),
+            );
+        }
+        if !indices.is_empty() {
+            queue.write_buffer(&self.index_buf, 0, indices.as_bytes());
+        }
+
+        let counters = Counters::new(indices.len() as u32, vertex_count as u32);
+        queue.write_buffer(&self.counters_buf, 0, counters.as_bytes());
+        counters
+    }
+}
@@ -0,0 +1,139 @@
+use super::{cell_case, cell_values, edge_crossing, Region, CORNERS, EDGES};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::volume::Volume;
+use glam::UVec3;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Cell faces, corners wound counter-clockwise when seen from outside the cell.
+const FACES: [[usize; 4]; 6] = [
+    [4, 7, 3, 0],
+    [1, 2, 6, 5],
+    [1, 5, 4, 0],
+    [3, 7, 6, 2],
+    [3, 2, 1, 0],
+    [4, 5, 6, 7],
+];
+
+/// Joins the segments the surface leaves on the faces of a polyhedron into
+/// rings of crossed edges. `faces` are corner loops wound counter-clockwise
+/// seen from outside. Inside corners on ambiguous faces are always kept
+/// apart, which makes polyhedra sharing a face agree on it and leaves no
+/// holes. The rings are wound counter-clockwise seen from outside the surface.
+///
+/// Each ring starts where a fan triangulation needs no diagonal along a face,
+/// since the neighbour on that face could pick the same diagonal.
+pub(super) fn edge_rings(
+    faces: &[&[usize]],
+    edges: &[(usize, usize)],
+    inside: impl Fn(usize) -> bool,
+) -> Vec<Vec<usize>> {
+    let edge_between = |a: usize, b: usize| {
+        edges
+            .iter()
+            .position(|&(c0, c1)| (c0, c1) == (a, b) || (c1, c0) == (a, b))
+            .unwrap()
+    };
+    let on_face = |face: &[usize], edge: usize| {
+        let (a, b) = edges[edge];
+        face.contains(&a) && face.contains(&b)
+    };
+    let share_face = |e0: usize, e1: usize| {
+        faces
+            .iter()
+            .any(|face| on_face(face, e0) && on_face(face, e1))
+    };
+
+    // Segment entering the inside part of a face -> segment leaving it.
+    let mut next_edge = vec![None; edges.len()];
+    for face in faces {
+        let n = face.len();
+        for k in 0..n {
+            let (prev, corner) = (face[(k + n - 1) % n], face[k]);
+            if inside(prev) || !inside(corner) {
+                continue;
+            }
+            let mut j = k;
+            while inside(face[(j + 1) % n]) {
+                j += 1;
+            }
+            next_edge[edge_between(prev, corner)] =
+                Some(edge_between(face[j % n], face[(j + 1) % n]));
+        }
+    }
+
+    let mut rings = Vec::new();
+    while let Some(start) = next_edge.iter().position(Option::is_some) {
+        let mut ring = vec![start];
+        while let Some(edge) = next_edge[*ring.last().unwrap()].take() {
+            if edge == start {
+                break;
+            }
+            ring.push(edge);
+        }
+
+        let n = ring.len();
+        let origin = (0..n).find(|&s| (2..n - 1).all(|i| !share_face(ring[s], ring[(s + i) % n])));
+        ring.rotate_left(origin.unwrap_or(0));
+        rings.push(ring);
+    }
+    rings
+}
+
+/// Triangle table indexed by cell case, edge triples terminated by -1.
+///
+/// Instead of hardcoding the classic table it is built from the `edge_rings`
+/// of every case. Triangles are wound counter-clockwise seen from outside the
+/// surface.
+pub(crate) fn tri_table() -> &'static [[i8; 16]; 256] {
+    static TABLE: OnceLock<[[i8; 16]; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let faces: Vec<&[usize]> = FACES.iter().map(|face| &face[..]).collect();
+        let mut table = [[-1; 16]; 256];
+        for (case, row) in table.iter_mut().enumerate() {
+            let rings = edge_rings(&faces, &EDGES, |corner| case & (1 << corner) != 0);
+            let mut len = 0;
+            for ring in rings {
+                for i in 1..ring.len() - 1 {
+                    row[len..len + 3].copy_from_slice(&[
+                        ring[0] as i8,
+                        ring[i] as i8,
+                        ring[i + 1] as i8,
+                    ]);
+                    len += 3;
+                }
+            }
+        }
+        table
+    })
+}
+
+pub(super) fn extract(volume: &Volume, iso: f32, region: Region) -> Mesh {
+    let table = tri_table();
+    let mut mesh = Mesh::default();
+    // Vertices are shared between cells, keyed by the lower end of their edge.
+    let mut edge_vertices: HashMap<(UVec3, usize), u32> = HashMap::new();
+
+    for cell in region.cells() {
+        let values = cell_values(volume, cell);
+        let row = &table[cell_case(&values, iso)];
+
+        for &edge in row.iter().take_while(|&&edge| edge >= 0) {
+            let edge = edge as usize;
+            let (a, b) = EDGES[edge];
+            let lower = cell + UVec3::from(CORNERS[a]).min(UVec3::from(CORNERS[b]));
+            let axis = (0..3).find(|&i| CORNERS[a][i] != CORNERS[b][i]).unwrap();
+
+            let index = *edge_vertices.entry((lower, axis)).or_insert_with(|| {
+                let crossing = edge_crossing(volume, cell, &values, edge, iso);
+                mesh.vertices.push(MeshVertex::new(
+                    mesh::grid_to_object(volume.size, crossing.pos),
+                    mesh::gradient_to_normal(volume.size, crossing.gradient),
+                ));
+                mesh.vertices.len() as u32 - 1
+            });
+            mesh.indices.push(index);
+        }
+    }
+    mesh
+}
@@ -0,0 +1,90 @@
+use super::{cell_values, crossing, Region, CORNERS};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::volume::Volume;
+use glam::{UVec3, Vec3};
+use std::collections::HashMap;
+
+/// The six tetrahedra around the 0-6 diagonal of a cell. Every cell is split
+/// the same way, so neighbouring cells agree on their shared face diagonals.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 2, 6],
+    [0, 1, 5, 6],
+    [0, 3, 2, 6],
+    [0, 3, 7, 6],
+    [0, 4, 5, 6],
+    [0, 4, 7, 6],
+];
+
+/// Marching tetrahedra. Each tetrahedron has a single way to cut it for any
+/// sign configuration, so unlike marching cubes there are no ambiguous cases.
+pub(super) fn extract(volume: &Volume, iso: f32, region: Region) -> Mesh {
+    let mut mesh = Mesh::default();
+    // Vertices are shared between tetrahedra, keyed by their sample pair.
+    let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+
+    for cell in region.cells() {
+        let values = cell_values(volume, cell);
+        let points = CORNERS.map(|corner| cell + UVec3::from(corner));
+
+        for tet in TETRAHEDRA {
+            let (inside, outside): (Vec<usize>, Vec<usize>) =
+                tet.iter().partition(|&&corner| values[corner] < iso);
+            if inside.is_empty() || outside.is_empty() {
+                continue;
+            }
+
+            let mut vertex = |a: usize, b: usize| {
+                let (pa, pb) = (points[a], points[b]);
+                let key = (
+                    volume.index(pa.x, pa.y, pa.z),
+                    volume.index(pb.x, pb.y, pb.z),
+                );
+                let key = (key.0.min(key.1), key.0.max(key.1));
+                *edge_vertices.entry(key).or_insert_with(|| {
+                    let c = crossing(volume, pa, pb, values[a], values[b], iso);
+                    mesh.vertices.push(MeshVertex::new(
+                        mesh::grid_to_object(volume.size, c.pos),
+                        mesh::gradient_to_normal(volume.size, c.gradient),
+                    ));
+                    mesh.vertices.len() as u32 - 1
+                })
+            };
+
+            let polygon = match (inside.len(), outside.len()) {
+                (1, 3) => outside.iter().map(|&o| vertex(inside[0], o)).collect(),
+                (3, 1) => inside.iter().map(|&i| vertex(i, outside[0])).collect(),
+                _ => vec![
+                    vertex(inside[0], outside[0]),
+                    vertex(inside[1], outside[0]),
+                    vertex(inside[1], outside[1]),
+                    vertex(inside[0], outside[1]),
+                ],
+            };
+
+            // Wind the polygon so it faces from the inside to the outside corners.
+            let centroid = |corners: &[usize]| {
+                let sum = corners
+                    .iter()
+                    .fold(Vec3::ZERO, |sum, &c| sum + points[c].as_vec3());
+                mesh::grid_to_object(volume.size, sum / corners.len() as f32)
+            };
+            let outward = centroid(&outside) - centroid(&inside);
+            let pos = |i: u32| Vec3::from(mesh.vertices[i as usize].pos);
+            let normal = (0..polygon.len()).fold(Vec3::ZERO, |normal, i| {
+                let next = polygon[(i + 1) % polygon.len()];
+                normal + pos(polygon[i]).cross(pos(next))
+            });
+            let flip = normal.dot(outward) < 0.0;
+
+            for i in 1..polygon.len() - 1 {
+                let (b, c) = if flip {
+                    (polygon[i + 1], polygon[i])
+                } else {
+                    (polygon[i], polygon[i + 1])
+                };
+                mesh.indices.extend_from_slice(&[polygon[0], b, c]);
+            }
+        }
+    }
+    mesh
+}
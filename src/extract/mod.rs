@@ -0,0 +1,203 @@
+mod dual;
+mod dual_contouring;
+mod marching_cubes;
+mod marching_tetrahedra;
+mod surface_nets;
+#[cfg(test)]
+mod tests;
+mod transvoxel;
+
+pub(crate) mod gpu;
+
+use crate::mesh::Mesh;
+use crate::volume::Volume;
+use glam::{UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+pub(crate) use marching_cubes::tri_table;
+pub(crate) use transvoxel::{transition_table, Lod, MAX_LOD};
+
+// Corner and edge numbering of a cell, shared with the compute shaders.
+pub(crate) const CORNERS: [[u32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [1, 1, 0],
+    [0, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [1, 1, 1],
+    [0, 1, 1],
+];
+
+pub(crate) const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 0),
+    (4, 5),
+    (5, 6),
+    (6, 7),
+    (7, 4),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum Extractor {
+    MarchingCubes,
+    MarchingTetrahedra,
+    SurfaceNets,
+    DualContouring,
+}
+
+impl Extractor {
+    pub const ALL: [Extractor; 4] = [
+        Extractor::MarchingCubes,
+        Extractor::MarchingTetrahedra,
+        Extractor::SurfaceNets,
+        Extractor::DualContouring,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Extractor::MarchingCubes => "Marching Cubes",
+            Extractor::MarchingTetrahedra => "Marching Tetrahedra",
+            Extractor::SurfaceNets => "Surface Nets",
+            Extractor::DualContouring => "Dual Contouring",
+        }
+    }
+
+    /// Extracts the `iso` surface of `volume` on the CPU. Samples below `iso`
+    /// are inside, and normals point towards increasing density.
+    pub fn extract(self, volume: &Volume, iso: f32) -> Mesh {
+        self.extract_region(volume, iso, Region::all(volume.size))
+    }
+
+    /// Same as `extract` for the part of the surface inside `region`. Meshes
+    /// of neighbouring regions line up exactly along their shared faces.
+    pub fn extract_region(self, volume: &Volume, iso: f32, region: Region) -> Mesh {
+        match self {
+            Extractor::MarchingCubes => marching_cubes::extract(volume, iso, region),
+            Extractor::MarchingTetrahedra => marching_tetrahedra::extract(volume, iso, region),
+            Extractor::SurfaceNets => surface_nets::extract(volume, iso, region),
+            Extractor::DualContouring => dual_contouring::extract(volume, iso, region),
+        }
+    }
+
+    /// Only marching cubes can be extracted at a coarser level of detail, with
+    /// Transvoxel transition cells towards finer neighbours.
+    pub fn supports_lod(self) -> bool {
+        self == Extractor::MarchingCubes
+    }
+
+    /// Same as `extract_region` at level of detail `lod`, which is ignored by
+    /// extractors that don't `supports_lod`.
+    pub fn extract_lod(self, volume: &Volume, iso: f32, region: Region, lod: Lod) -> Mesh {
+        if lod == Lod::default() || !self.supports_lod() {
+            return self.extract_region(volume, iso, region);
+        }
+        transvoxel::extract(volume, iso, region, lod)
+    }
+}
+
+pub(crate) fn cell_count(size: UVec3) -> UVec3 {
+    size.max(UVec3::ONE) - UVec3::ONE
+}
+
+/// Box of cells `min..max`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub min: UVec3,
+    pub max: UVec3,
+}
+
+impl Region {
+    pub fn all(size: UVec3) -> Region {
+        Region {
+            min: UVec3::ZERO,
+            max: cell_count(size),
+        }
+    }
+
+    pub fn size(self) -> UVec3 {
+        self.max.max(self.min) - self.min
+    }
+
+    pub fn cells(self) -> impl Iterator<Item = UVec3> {
+        (self.min.z..self.max.z).flat_map(move |z| {
+            (self.min.y..self.max.y)
+                .flat_map(move |y| (self.min.x..self.max.x).map(move |x| UVec3::new(x, y, z)))
+        })
+    }
+}
+
+/// Where the surface crosses a cell edge, in grid coordinates.
+#[derive(Clone, Copy)]
+pub(crate) struct Crossing {
+    pub pos: Vec3,
+    pub gradient: Vec3,
+}
+
+fn cell_values(volume: &Volume, cell: UVec3) -> [f32; 8] {
+    CORNERS.map(|[x, y, z]| volume.get(cell.x + x, cell.y + y, cell.z + z))
+}
+
+fn cell_case(values: &[f32; 8], iso: f32) -> usize {
+    values
+        .iter()
+        .enumerate()
+        .fold(0, |case, (i, &d)| case | ((d < iso) as usize) << i)
+}
+
+fn edge_crossing(
+    volume: &Volume,
+    cell: UVec3,
+    values: &[f32; 8],
+    edge: usize,
+    iso: f32,
+) -> Crossing {
+    let (a, b) = EDGES[edge];
+    crossing(
+        volume,
+        cell + UVec3::from(CORNERS[a]),
+        cell + UVec3::from(CORNERS[b]),
+        values[a],
+        values[b],
+        iso,
+    )
+}
+
+/// Crossing on the segment between samples `pa` and `pb`, which don't have to
+/// be neighbours. The ends are ordered first so every cell sharing the segment
+/// gets a bit identical crossing, even across regions.
+fn crossing(volume: &Volume, pa: UVec3, pb: UVec3, da: f32, db: f32, iso: f32) -> Crossing {
+    if volume.index(pb.x, pb.y, pb.z) < volume.index(pa.x, pa.y, pa.z) {
+        return crossing(volume, pb, pa, db, da, iso);
+    }
+    let t = ((iso - da) / (db - da)).clamp(0.0, 1.0);
+    let ga = volume.gradient(pa.x, pa.y, pa.z);
+    let gb = volume.gradient(pb.x, pb.y, pb.z);
+    Crossing {
+        pos: pa.as_vec3().lerp(pb.as_vec3(), t),
+        gradient: ga.lerp(gb, t),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum Backend {
+    Cpu,
+    Gpu,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 2] = [Backend::Cpu, Backend::Gpu];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Cpu => "CPU",
+            Backend::Gpu => "GPU",
+        }
+    }
+}
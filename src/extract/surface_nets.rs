@@ -0,0 +1,11 @@
+use super::{dual, Region};
+use crate::mesh::Mesh;
+use crate::volume::Volume;
+use glam::Vec3;
+
+/// Naive surface nets, each cell vertex sits at the mean of its edge crossings.
+pub(super) fn extract(volume: &Volume, iso: f32, region: Region) -> Mesh {
+    dual::extract(volume, iso, region, |crossings, _| {
+        crossings.iter().fold(Vec3::ZERO, |sum, c| sum + c.pos) / crossings.len() as f32
+    })
+}
@@ -0,0 +1,404 @@
+use super::{cell_count, Extractor, Lod, Region};
+use crate::mesh::Mesh;
+use crate::volume::{self, Volume};
+use glam::{uvec3, IVec3, UVec3, Vec3};
+use std::collections::HashMap;
+
+const SIZE: u32 = 12;
+
+/// Forces the border samples outside so every surface is closed.
+fn closed(mut volume: Volume) -> Volume {
+    let max = volume.size - UVec3::ONE;
+    for z in 0..volume.size.z {
+        for y in 0..volume.size.y {
+            for x in 0..volume.size.x {
+                let p = uvec3(x, y, z);
+                if p.min_element() == 0 || p.cmpeq(max).any() {
+                    volume.set(x, y, z, 1.0);
+                }
+            }
+        }
+    }
+    volume
+}
+
+fn saddle() -> Volume {
+    closed(Volume::from_fn(UVec3::splat(SIZE), |p| {
+        (p.x - 0.5) * (p.y - 0.5) * 16.0 + (p.z - 0.5) * 0.01
+    }))
+}
+
+fn checkerboard() -> Volume {
+    let mut volume = Volume::new(UVec3::splat(SIZE));
+    for z in 0..SIZE {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                volume.set(x, y, z, if (x + y + z) % 2 == 0 { -1.0 } else { 1.0 });
+            }
+        }
+    }
+    closed(volume)
+}
+
+fn noise() -> Volume {
+    noise_of_size(UVec3::splat(SIZE))
+}
+
+/// Xorshift values in [-1, 1], closed.
+fn noise_of_size(size: UVec3) -> Volume {
+    let mut state = 0x2545_f491_u32;
+    let samples = (0..size.x * size.y * size.z).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    });
+    closed(Volume::from_samples(size, samples))
+}
+
+fn directed_edges(mesh: &Mesh) -> HashMap<(u32, u32), usize> {
+    let mut edges = HashMap::new();
+    for tri in mesh.indices.chunks_exact(3) {
+        for k in 0..3 {
+            *edges.entry((tri[k], tri[(k + 1) % 3])).or_insert(0) += 1;
+        }
+    }
+    edges
+}
+
+/// Every edge is shared by exactly two triangles.
+fn is_watertight(mesh: &Mesh) -> bool {
+    let edges = directed_edges(mesh);
+    edges
+        .iter()
+        .all(|(&(a, b), &count)| count + edges.get(&(b, a)).copied().unwrap_or(0) == 2)
+}
+
+/// Every edge is used once in each direction, and the triangles around every
+/// vertex form a single fan.
+fn is_oriented_manifold(mesh: &Mesh) -> bool {
+    let edges = directed_edges(mesh);
+    let edges_ok = edges
+        .iter()
+        .all(|(&(a, b), &count)| a != b && count == 1 && edges.get(&(b, a)) == Some(&1));
+    if !edges_ok {
+        return false;
+    }
+
+    // Walk the fan around each vertex, next[a] = b for every triangle (v, a, b).
+    let mut fans: HashMap<u32, HashMap<u32, u32>> = HashMap::new();
+    for tri in mesh.indices.chunks_exact(3) {
+        for k in 0..3 {
+            fans.entry(tri[k])
+                .or_default()
+                .insert(tri[(k + 1) % 3], tri[(k + 2) % 3]);
+        }
+    }
+    fans.values().all(|next| {
+        let start = *next.keys().next().unwrap();
+        let mut current = start;
+        let mut steps = 0;
+        loop {
+            current = next[&current];
+            steps += 1;
+            if current == start {
+                return steps == next.len();
+            }
+        }
+    })
+}
+
+fn check(name: &str, volume: &Volume) {
+    for extractor in [Extractor::MarchingCubes, Extractor::MarchingTetrahedra] {
+        let mesh = extractor.extract(volume, 0.0);
+        assert!(
+            !mesh.indices.is_empty(),
+            "{}: {} is empty",
+            name,
+            extractor.name()
+        );
+        assert!(
+            is_watertight(&mesh),
+            "{}: {} isn't watertight",
+            name,
+            extractor.name()
+        );
+        assert!(
+            is_oriented_manifold(&mesh),
+            "{}: {} isn't an oriented manifold",
+            name,
+            extractor.name()
+        );
+    }
+}
+
+#[test]
+fn sphere_is_closed() {
+    check(
+        "sphere",
+        &Volume::from_fn(UVec3::splat(SIZE), volume::sphere),
+    );
+}
+
+#[test]
+fn saddle_is_closed() {
+    check("saddle", &saddle());
+}
+
+#[test]
+fn checkerboard_is_closed() {
+    check("checkerboard", &checkerboard());
+}
+
+#[test]
+fn noise_is_closed() {
+    check("noise", &noise());
+}
+
+#[test]
+fn tetrahedra_enclose_the_same_volume() {
+    let volume = Volume::from_fn(uvec3(24, 24, 24), volume::sphere);
+    let enclosed = |mesh: &Mesh| {
+        mesh.indices.chunks_exact(3).fold(0.0, |sum, tri| {
+            let [a, b, c] = [0, 1, 2].map(|k| Vec3::from(mesh.vertices[tri[k] as usize].pos));
+            sum + a.dot(b.cross(c)) / 6.0
+        })
+    };
+    let mc = enclosed(&Extractor::MarchingCubes.extract(&volume, 0.0));
+    let mt = enclosed(&Extractor::MarchingTetrahedra.extract(&volume, 0.0));
+    let exact = 4.0 / 3.0 * std::f32::consts::PI * 0.3f32.powi(3);
+    assert!((mc - exact).abs() / exact < 0.02, "{} vs {}", mc, exact);
+    assert!((mt - exact).abs() / exact < 0.02, "{} vs {}", mt, exact);
+}
+
+/// Extracts `volume` in bricks of `brick` cells and appends the meshes.
+fn extract_bricks(extractor: Extractor, volume: &Volume, brick: u32) -> Mesh {
+    let cells = cell_count(volume.size);
+    let bricks = Region {
+        min: UVec3::ZERO,
+        max: (cells + UVec3::splat(brick - 1)) / brick,
+    };
+    let mut joined = Mesh::default();
+    for b in bricks.cells() {
+        let min = b * brick;
+        let region = Region {
+            min,
+            max: (min + UVec3::splat(brick)).min(cells),
+        };
+        let mesh = extractor.extract_region(volume, 0.0, region);
+        let base = joined.vertices.len() as u32;
+        joined.vertices.extend_from_slice(&mesh.vertices);
+        joined
+            .indices
+            .extend(mesh.indices.iter().map(|&i| base + i));
+    }
+    joined
+}
+
+/// Triangles by vertex positions, rotated to start at the smallest one.
+fn triangles(mesh: &Mesh) -> Vec<[[u32; 3]; 3]> {
+    let mut triangles: Vec<_> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|tri| {
+            let mut tri = [0, 1, 2].map(|k| mesh.vertices[tri[k] as usize].pos.map(f32::to_bits));
+            let first = (0..3).min_by_key(|&k| tri[k]).unwrap();
+            tri.rotate_left(first);
+            tri
+        })
+        .collect();
+    triangles.sort_unstable();
+    triangles
+}
+
+#[test]
+fn bricks_match_at_seams() {
+    for volume in [
+        Volume::from_fn(UVec3::splat(SIZE), volume::sphere),
+        saddle(),
+        noise(),
+    ] {
+        for extractor in Extractor::ALL {
+            let whole = extractor.extract(&volume, 0.0);
+            let joined = extract_bricks(extractor, &volume, 5);
+            assert!(
+                triangles(&whole) == triangles(&joined),
+                "{} differs when extracted in bricks",
+                extractor.name()
+            );
+        }
+    }
+}
+
+/// Joins meshes, welding vertices at the same position.
+fn weld(meshes: &[Mesh]) -> Mesh {
+    let mut joined = Mesh::default();
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    for mesh in meshes {
+        let remap: Vec<u32> = mesh
+            .vertices
+            .iter()
+            .map(|v| {
+                *welded.entry(v.pos.map(f32::to_bits)).or_insert_with(|| {
+                    joined.vertices.push(*v);
+                    joined.vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+        joined
+            .indices
+            .extend(mesh.indices.iter().map(|&i| remap[i as usize]));
+    }
+    joined
+}
+
+#[test]
+fn transitions_close_lod_seams() {
+    // Two bricks along x, one at full resolution and one at a quarter of it.
+    let size = uvec3(33, 17, 17);
+    let sphere = closed(Volume::from_fn(size, |p| {
+        (p - Vec3::new(0.5, 0.5, 0.5)).length() - 0.35
+    }));
+    let noise = noise_of_size(size);
+
+    let fine = Region {
+        min: UVec3::ZERO,
+        max: uvec3(16, 16, 16),
+    };
+    for (fine_region, coarse_region, face) in [
+        (
+            fine,
+            Region {
+                min: uvec3(16, 0, 0),
+                max: uvec3(32, 16, 16),
+            },
+            0,
+        ),
+        (
+            Region {
+                min: uvec3(16, 0, 0),
+                max: uvec3(32, 16, 16),
+            },
+            fine,
+            1,
+        ),
+    ] {
+        for level in 1..=2 {
+            for volume in [&sphere, &noise] {
+                let extractor = Extractor::MarchingCubes;
+                let fine_lod = Lod {
+                    level: level - 1,
+                    transitions: 0,
+                };
+                let coarse_lod = Lod {
+                    level,
+                    transitions: 1 << face,
+                };
+                let mesh = weld(&[
+                    extractor.extract_lod(volume, 0.0, fine_region, fine_lod),
+                    extractor.extract_lod(volume, 0.0, coarse_region, coarse_lod),
+                ]);
+                assert!(!mesh.indices.is_empty());
+                assert!(
+                    is_watertight(&mesh),
+                    "level {} transition on face {} has cracks",
+                    level,
+                    face
+                );
+                assert!(
+                    is_oriented_manifold(&mesh),
+                    "level {} transition on face {} isn't oriented",
+                    level,
+                    face
+                );
+            }
+        }
+    }
+}
+
+/// Extracts 16 cell bricks at their level, with transitions on the faces
+/// towards finer neighbours like `ChunkGrid` sets them, and welds them.
+fn extract_levels(volume: &Volume, levels: &[(UVec3, u32)]) -> Mesh {
+    let level_of = |brick: UVec3| {
+        levels
+            .iter()
+            .find(|&&(b, _)| b == brick)
+            .map(|&(_, level)| level)
+    };
+    let meshes: Vec<Mesh> = levels
+        .iter()
+        .map(|&(brick, level)| {
+            let transitions = (0..6).fold(0, |transitions, face| {
+                let mut neighbour = brick.as_ivec3();
+                neighbour[face / 2] += if face % 2 == 0 { -1 } else { 1 };
+                let finer = neighbour.cmpge(IVec3::ZERO).all()
+                    && level_of(neighbour.as_uvec3()).is_some_and(|next| next < level);
+                if finer {
+                    transitions | 1 << face
+                } else {
+                    transitions
+                }
+            });
+            let region = Region {
+                min: brick * 16,
+                max: brick * 16 + 16,
+            };
+            Extractor::MarchingCubes.extract_lod(volume, 0.0, region, Lod { level, transitions })
+        })
+        .collect();
+    weld(&meshes)
+}
+
+#[test]
+fn transitions_meet_at_brick_edges_and_corners() {
+    let size = uvec3(33, 33, 33);
+    let sphere = closed(Volume::from_fn(size, |p| {
+        (p - Vec3::splat(0.5)).length() - 0.35
+    }));
+    let noise = noise_of_size(size);
+    let bricks = Region {
+        min: UVec3::ZERO,
+        max: UVec3::splat(2),
+    };
+    for level in 1..=2 {
+        // One coarse brick with finer neighbours on two faces meeting at an
+        // edge, in a single layer of bricks, then on three faces meeting at a
+        // corner.
+        let edge: Vec<(UVec3, u32)> = bricks
+            .cells()
+            .filter(|b| b.z == 0)
+            .map(|b| (b, if b == UVec3::ZERO { level } else { level - 1 }))
+            .collect();
+        let corner: Vec<(UVec3, u32)> = bricks
+            .cells()
+            .map(|b| (b, if b == UVec3::ZERO { level } else { level - 1 }))
+            .collect();
+        // The single layer is closed above it.
+        for (name, levels, top) in [("edge", &edge, 16), ("corner", &corner, 32)] {
+            for volume in [&sphere, &noise] {
+                let mut volume = volume.clone();
+                let above = Region {
+                    min: uvec3(0, 0, top),
+                    max: size,
+                };
+                for p in above.cells() {
+                    volume.set(p.x, p.y, p.z, 1.0);
+                }
+                let mesh = extract_levels(&volume, levels);
+                assert!(!mesh.indices.is_empty());
+                assert!(
+                    is_watertight(&mesh),
+                    "level {} transitions meeting at a brick {} have cracks",
+                    level,
+                    name
+                );
+                assert!(
+                    is_oriented_manifold(&mesh),
+                    "level {} transitions meeting at a brick {} aren't oriented",
+                    level,
+                    name
+                );
+            }
+        }
+    }
+}
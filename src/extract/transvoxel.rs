@@ -0,0 +1,244 @@
+use super::marching_cubes::{edge_rings, tri_table};
+use super::{cell_case, crossing, Region, CORNERS, EDGES};
+use crate::mesh::{self, Mesh, MeshVertex};
+use crate::volume::Volume;
+use glam::{UVec3, Vec3};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Coarsest level of detail, cells are `1 << MAX_LOD` samples wide.
+pub(crate) const MAX_LOD: u32 = 2;
+
+/// Part of a coarse cell the transition cells take up, the regular cells next
+/// to a transition face are squeezed into the rest.
+pub(crate) const TRANSITION_WIDTH: f32 = 0.5;
+
+/// Entries per row of `transition_table`.
+pub(crate) const TRANSITION_ROW: usize = 40;
+
+/// Level of detail of a region. Bit `2 * axis + side` of `transitions` is set
+/// when the neighbour on that face is one level finer, side 0 being the min
+/// face.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Lod {
+    pub level: u32,
+    pub transitions: u32,
+}
+
+impl Lod {
+    pub fn step(self) -> u32 {
+        1 << self.level
+    }
+}
+
+// Transition cell corners. 0..9 are the 3x3 samples on the fine face, 9..13
+// the corners of the coarse face, copies of 0, 2, 6 and 8 moved inwards.
+const TRANSITION_CORNERS: [(u32, u32); 13] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (0, 1),
+    (1, 1),
+    (2, 1),
+    (0, 2),
+    (1, 2),
+    (2, 2),
+    (0, 0),
+    (2, 0),
+    (0, 2),
+    (2, 2),
+];
+
+/// Fine face edges first, then the coarse face edges. The last four join the
+/// faces and never cross the surface.
+pub(crate) const TRANSITION_EDGES: [(usize, usize); 20] = [
+    (0, 1),
+    (1, 2),
+    (3, 4),
+    (4, 5),
+    (6, 7),
+    (7, 8),
+    (0, 3),
+    (3, 6),
+    (1, 4),
+    (4, 7),
+    (2, 5),
+    (5, 8),
+    (9, 10),
+    (11, 12),
+    (9, 11),
+    (10, 12),
+    (0, 9),
+    (2, 10),
+    (6, 11),
+    (8, 12),
+];
+
+const FINE_EDGE_COUNT: usize = 12;
+
+// Faces wound counter-clockwise seen from outside, with the fine face at n = 0
+// and the coarse one towards +n in a right-handed (u, v, n) frame.
+const TRANSITION_FACES: [&[usize]; 9] = [
+    &[0, 3, 4, 1],
+    &[1, 4, 5, 2],
+    &[3, 6, 7, 4],
+    &[4, 7, 8, 5],
+    &[9, 10, 12, 11],
+    &[0, 1, 2, 10, 9],
+    &[8, 7, 6, 11, 12],
+    &[6, 3, 0, 9, 11],
+    &[2, 5, 8, 12, 10],
+];
+
+/// Triangle table of the transition cells indexed by the signs of the nine
+/// fine samples, `TRANSITION_EDGES` triples terminated by -1. Built the same
+/// way as `tri_table`, so the cells agree with the regular cells on both sides.
+pub(crate) fn transition_table() -> &'static [[i8; TRANSITION_ROW]; 512] {
+    static TABLE: OnceLock<[[i8; TRANSITION_ROW]; 512]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [[-1; TRANSITION_ROW]; 512];
+        for (case, row) in table.iter_mut().enumerate() {
+            let inside = |corner: usize| {
+                let (i, j) = TRANSITION_CORNERS[corner];
+                case & (1 << (i + 3 * j)) != 0
+            };
+            let mut len = 0;
+            for ring in edge_rings(&TRANSITION_FACES, &TRANSITION_EDGES, inside) {
+                for i in 1..ring.len() - 1 {
+                    row[len..len + 3].copy_from_slice(&[
+                        ring[0] as i8,
+                        ring[i] as i8,
+                        ring[i + 1] as i8,
+                    ]);
+                    len += 3;
+                }
+            }
+            assert!(len < TRANSITION_ROW);
+        }
+        table
+    })
+}
+
+/// Axis of a face and whether it's the max face of the region.
+fn face_axis(face: u32) -> (usize, bool) {
+    ((face / 2) as usize, face % 2 == 1)
+}
+
+/// Moves the vertices in the cell layer next to every transition face away
+/// from it, making room for the transition cells. `p` is in grid coordinates.
+pub(crate) fn squeeze(p: Vec3, region: Region, lod: Lod) -> Vec3 {
+    let step = lod.step() as f32;
+    let mut p = p;
+    for face in 0..6 {
+        if lod.transitions & (1 << face) == 0 {
+            continue;
+        }
+        let (axis, max) = face_axis(face);
+        let (plane, inward) = if max {
+            (region.max[axis] as f32, -1.0)
+        } else {
+            (region.min[axis] as f32, 1.0)
+        };
+        let d = (p[axis] - plane) * inward;
+        if (0.0..step).contains(&d) {
+            p[axis] = plane + inward * (TRANSITION_WIDTH * step + d * (1.0 - TRANSITION_WIDTH));
+        }
+    }
+    p
+}
+
+/// Marching cubes over cells `lod.step()` samples wide, plus transition cells
+/// stitching the faces towards finer neighbours. The region size must be a
+/// multiple of the step.
+pub(super) fn extract(volume: &Volume, iso: f32, region: Region, lod: Lod) -> Mesh {
+    let step = lod.step();
+    let mut mesh = Mesh::default();
+    // Vertices are shared between cells, keyed by their sample pair.
+    let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+    let mut vertex = |pa: UVec3, pb: UVec3, squeezed: bool| {
+        let key = (
+            volume.index(pa.x, pa.y, pa.z),
+            volume.index(pb.x, pb.y, pb.z),
+        );
+        let key = (key.0.min(key.1), key.0.max(key.1));
+        *edge_vertices.entry(key).or_insert_with(|| {
+            let da = volume.get(pa.x, pa.y, pa.z);
+            let db = volume.get(pb.x, pb.y, pb.z);
+            let c = crossing(volume, pa, pb, da, db, iso);
+            let pos = if squeezed {
+                squeeze(c.pos, region, lod)
+            } else {
+                c.pos
+            };
+            mesh.vertices.push(MeshVertex::new(
+                mesh::grid_to_object(volume.size, pos),
+                mesh::gradient_to_normal(volume.size, c.gradient),
+            ));
+            mesh.vertices.len() as u32 - 1
+        })
+    };
+    let mut indices = Vec::new();
+
+    let table = tri_table();
+    let cells = Region {
+        min: UVec3::ZERO,
+        max: region.size() / step,
+    };
+    for k in cells.cells() {
+        let cell = region.min + k * step;
+        let points = CORNERS.map(|corner| cell + UVec3::from(corner) * step);
+        let values = points.map(|p| volume.get(p.x, p.y, p.z));
+        for &edge in table[cell_case(&values, iso)]
+            .iter()
+            .take_while(|&&edge| edge >= 0)
+        {
+            let (a, b) = EDGES[edge as usize];
+            indices.push(vertex(points[a], points[b], true));
+        }
+    }
+
+    let table = transition_table();
+    for face in 0..6 {
+        if lod.transitions & (1 << face) == 0 {
+            continue;
+        }
+        let (axis, max) = face_axis(face);
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut origin = region.min;
+        if max {
+            origin[axis] = region.max[axis];
+        }
+        let half = step / 2;
+
+        for kv in 0..region.size()[v] / step {
+            for ku in 0..region.size()[u] / step {
+                let sample = |corner: usize| {
+                    let (i, j) = TRANSITION_CORNERS[corner];
+                    let mut p = origin;
+                    p[u] += ku * step + i * half;
+                    p[v] += kv * step + j * half;
+                    p
+                };
+                let case = (0..9).fold(0, |case, corner| {
+                    let p = sample(corner);
+                    case | ((volume.get(p.x, p.y, p.z) < iso) as usize) << corner
+                });
+
+                for tri in table[case].chunks_exact(3).take_while(|tri| tri[0] >= 0) {
+                    let mut tri = [tri[0], tri[1], tri[2]].map(|edge| {
+                        let (a, b) = TRANSITION_EDGES[edge as usize];
+                        vertex(sample(a), sample(b), edge as usize >= FINE_EDGE_COUNT)
+                    });
+                    // The frame is left-handed on max faces.
+                    if max {
+                        tri.swap(1, 2);
+                    }
+                    indices.extend_from_slice(&tri);
+                }
+            }
+        }
+    }
+
+    mesh.indices = indices;
+    mesh
+}
@@ -0,0 +1,434 @@
+use crate::bricks::{BrickAtlas, FIELD_FORMAT};
+use crate::pick::Ray;
+use crate::shader;
+use crate::stats;
+use glam::{UVec3, Vec3};
+use std::mem;
+use std::num::NonZeroU32;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Density the surface is extracted at, the field is `LEVEL - density`.
+pub(crate) const LEVEL: f32 = 0.5;
+const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const SCALAR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct FluidSettings {
+    /// In the time the fastest flow crosses a sample.
+    pub dt: f32,
+    /// Jacobi iterations of the pressure solve, run in pairs.
+    pub iterations: u32,
+    /// Upward acceleration per unit of density.
+    pub buoyancy: f32,
+    /// Part of the density lost per unit of time.
+    pub dissipation: f32,
+    /// In grid samples.
+    pub emitter_radius: f32,
+    /// Density added per unit of time at the emitter's center.
+    pub emitter_amount: f32,
+}
+
+impl Default for FluidSettings {
+    fn default() -> FluidSettings {
+        FluidSettings {
+            dt: 0.5,
+            iterations: 20,
+            buoyancy: 0.5,
+            dissipation: 0.01,
+            emitter_radius: 4.0,
+            emitter_amount: 1.0,
+        }
+    }
+}
+
+/// Where density is added, with the velocity it's pushed at, both in grid
+/// samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Emitter {
+    pub position: Vec3,
+    pub velocity: Vec3,
+}
+
+/// The point of `ray`, in object space, closest to the center of the grid.
+/// Emitters follow the pointer there.
+pub(crate) fn emitter_position(ray: Ray) -> Vec3 {
+    ray.origin - ray.dir * ray.origin.dot(ray.dir)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct FluidUniforms {
+    emitter: [f32; 3],
+    radius: f32,
+    emitter_velocity: [f32; 3],
+    amount: f32,
+    dt: f32,
+    buoyancy: f32,
+    dissipation: f32,
+    level: f32,
+}
+
+/// What a pass binds, by binding number of `shaders/fluid.wgsl`.
+enum Binding<'a> {
+    Read(u32, &'a wgpu::Texture),
+    Write(u32, &'a wgpu::Texture, wgpu::TextureFormat),
+    Uniforms(u32, &'a wgpu::Buffer),
+}
+
+/// An entry point of `shaders/fluid.wgsl` with its bind groups.
+struct Pass {
+    pipeline: wgpu::ComputePipeline,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Pass {
+    /// All of `groups` have to bind the same kinds of resources to the same
+    /// numbers.
+    fn new(
+        device: &wgpu::Device,
+        module: &wgpu::ShaderModule,
+        entry_point: &str,
+        groups: &[&[Binding<'_>]],
+    ) -> Pass {
+        let entries: Vec<wgpu::BindGroupLayoutEntry> = groups[0]
+            .iter()
+            .map(|binding| {
+                let (binding, ty) = match *binding {
+                    Binding::Read(binding, _) => (
+                        binding,
+                        wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                    ),
+                    Binding::Write(binding, _, format) => (
+                        binding,
+                        wgpu::BindingType::StorageTexture {
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                            format,
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                    ),
+                    Binding::Uniforms(binding, _) => (
+                        binding,
+                        wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                    ),
+                };
+                wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty,
+                    count: None,
+                }
+            })
+            .collect();
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some(entry_point),
+        });
+
+        let bind_groups = groups
+            .iter()
+            .map(|group| {
+                let views: Vec<Option<wgpu::TextureView>> = group
+                    .iter()
+                    .map(|binding| match binding {
+                        Binding::Read(_, texture) | Binding::Write(_, texture, _) => {
+                            Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+                        }
+                        Binding::Uniforms(..) => None,
+                    })
+                    .collect();
+                let entries: Vec<wgpu::BindGroupEntry> = group
+                    .iter()
+                    .zip(&views)
+                    .map(|(binding, view)| match (binding, view) {
+                        (Binding::Uniforms(binding, buffer), _) => wgpu::BindGroupEntry {
+                            binding: *binding,
+                            resource: buffer.as_entire_binding(),
+                        },
+                        (Binding::Read(binding, _) | Binding::Write(binding, ..), Some(view)) => {
+                            wgpu::BindGroupEntry {
+                                binding: *binding,
+                                resource: wgpu::BindingResource::TextureView(view),
+                            }
+                        }
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &bind_group_layout,
+                    entries: &entries,
+                    label: Some(entry_point),
+                })
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module,
+            entry_point,
+        });
+        Pass {
+            pipeline,
+            bind_groups,
+        }
+    }
+
+    fn dispatch<'a>(&'a self, cs_pass: &mut wgpu::ComputePass<'a>, group: usize, size: UVec3) {
+        cs_pass.set_pipeline(&self.pipeline);
+        cs_pass.set_bind_group(0, &self.bind_groups[group], &[]);
+        let workgroups = (size + UVec3::splat(3)) / 4;
+        cs_pass.dispatch(workgroups.x, workgroups.y, workgroups.z);
+    }
+}
+
+/// Stable fluids: semi-Lagrangian advection, buoyancy and a Jacobi pressure
+/// projection as compute passes over textures sized like the grid, writing
+/// the field of the density to the `BrickAtlas` every step.
+pub(crate) struct Fluid {
+    size: UVec3,
+    /// The velocity after a step, and after advection within one.
+    velocity: [wgpu::Texture; 2],
+    /// The density after a step, and the one advection writes.
+    density: [wgpu::Texture; 2],
+    /// Ping-ponged by the Jacobi iterations, the first holds the solution.
+    pressure: [wgpu::Texture; 2],
+    uniforms_buf: wgpu::Buffer,
+    advect_velocity: Pass,
+    advect_density: Pass,
+    divergence_pass: Pass,
+    jacobi: Pass,
+    project: Pass,
+    bytes: u64,
+}
+
+impl Fluid {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas, size: UVec3) -> Fluid {
+        let texture = |format: wgpu::TextureFormat| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("fluid"),
+                size: wgpu::Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: size.z,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+            })
+        };
+        let velocity = [texture(VELOCITY_FORMAT), texture(VELOCITY_FORMAT)];
+        let density = [texture(SCALAR_FORMAT), texture(SCALAR_FORMAT)];
+        let pressure = [texture(SCALAR_FORMAT), texture(SCALAR_FORMAT)];
+        let divergence = texture(SCALAR_FORMAT);
+        let uniforms_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<FluidUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bricks_buf = atlas.create_layout_buffer(device);
+
+        use Binding::{Read, Uniforms, Write};
+        let module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/fluid.wgsl")
+            ),
+        );
+        let pass = |entry_point: &str, groups: &[&[Binding<'_>]]| {
+            Pass::new(device, &module, entry_point, groups)
+        };
+        let advect_velocity = pass(
+            "advect_velocity",
+            &[&[
+                Read(0, &velocity[0]),
+                Read(1, &density[0]),
+                Write(4, &velocity[1], VELOCITY_FORMAT),
+                Uniforms(7, &uniforms_buf),
+            ]],
+        );
+        let advect_density = pass(
+            "advect_density",
+            &[&[
+                Read(0, &velocity[0]),
+                Read(1, &density[0]),
+                Write(5, &density[1], SCALAR_FORMAT),
+                Write(6, atlas.texture(), FIELD_FORMAT),
+                Uniforms(7, &uniforms_buf),
+                Uniforms(8, &bricks_buf),
+            ]],
+        );
+        let divergence_pass = pass(
+            "divergence",
+            &[&[Read(0, &velocity[1]), Write(5, &divergence, SCALAR_FORMAT)]],
+        );
+        // The velocity only gives the size of the grid here.
+        let jacobi = pass(
+            "jacobi",
+            &[
+                &[
+                    Read(0, &velocity[1]),
+                    Read(2, &pressure[0]),
+                    Read(3, &divergence),
+                    Write(5, &pressure[1], SCALAR_FORMAT),
+                ],
+                &[
+                    Read(0, &velocity[1]),
+                    Read(2, &pressure[1]),
+                    Read(3, &divergence),
+                    Write(5, &pressure[0], SCALAR_FORMAT),
+                ],
+            ],
+        );
+        let project = pass(
+            "project",
+            &[&[
+                Read(0, &velocity[1]),
+                Read(2, &pressure[0]),
+                Write(4, &velocity[0], VELOCITY_FORMAT),
+            ]],
+        );
+
+        let bytes = 2 * stats::texture_bytes(size, VELOCITY_FORMAT)
+            + 5 * stats::texture_bytes(size, SCALAR_FORMAT)
+            + mem::size_of::<FluidUniforms>() as u64;
+        Fluid {
+            size,
+            velocity,
+            density,
+            pressure,
+            uniforms_buf,
+            advect_velocity,
+            advect_density,
+            divergence_pass,
+            jacobi,
+            project,
+            bytes,
+        }
+    }
+
+    /// GPU memory of the textures and buffers.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Empties the grid, no density and no flow.
+    pub fn clear(&self, queue: &wgpu::Queue) {
+        let size = self.size;
+        for (texture, format) in [
+            (&self.velocity[0], VELOCITY_FORMAT),
+            (&self.density[0], SCALAR_FORMAT),
+            (&self.pressure[0], SCALAR_FORMAT),
+        ] {
+            let texel_bytes = format.describe().block_size as u32;
+            let zeros = vec![0u8; stats::texture_bytes(size, format) as usize];
+            queue.write_texture(
+                texture.as_image_copy(),
+                &zeros,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(size.x * texel_bytes),
+                    rows_per_image: NonZeroU32::new(size.y),
+                },
+                wgpu::Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: size.z,
+                },
+            );
+        }
+    }
+
+    /// Encodes one step, adding density at `emitter`. The uniforms are written
+    /// right away, like the brush uniforms.
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        settings: &FluidSettings,
+        emitter: Option<Emitter>,
+    ) {
+        let (position, velocity, amount) = match emitter {
+            Some(emitter) => (emitter.position, emitter.velocity, settings.emitter_amount),
+            None => (Vec3::ZERO, Vec3::ZERO, 0.0),
+        };
+        let uniforms = FluidUniforms {
+            emitter: position.to_array(),
+            radius: settings.emitter_radius,
+            emitter_velocity: velocity.to_array(),
+            amount,
+            dt: settings.dt,
+            buoyancy: settings.buoyancy,
+            dissipation: settings.dissipation,
+            level: LEVEL,
+        };
+        queue.write_buffer(&self.uniforms_buf, 0, uniforms.as_bytes());
+
+        let size = self.size;
+        {
+            let mut cs_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            self.advect_velocity.dispatch(&mut cs_pass, 0, size);
+            self.advect_density.dispatch(&mut cs_pass, 0, size);
+        }
+        encoder.copy_texture_to_texture(
+            self.density[1].as_image_copy(),
+            self.density[0].as_image_copy(),
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+        let mut cs_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        self.divergence_pass.dispatch(&mut cs_pass, 0, size);
+        // Starting from the last step's pressure, in pairs so the solution
+        // ends up in `pressure[0]`.
+        for _ in 0..settings.iterations.div_ceil(2) {
+            self.jacobi.dispatch(&mut cs_pass, 0, size);
+            self.jacobi.dispatch(&mut cs_pass, 1, size);
+        }
+        self.project.dispatch(&mut cs_pass, 0, size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emitters_follow_the_pointer_ray() {
+        let ray = Ray {
+            origin: Vec3::new(2.0, 0.25, 0.0),
+            dir: -Vec3::X,
+        };
+        assert!(emitter_position(ray).abs_diff_eq(Vec3::new(0.0, 0.25, 0.0), 1e-6));
+        let dir = Vec3::new(-1.0, -1.0, 0.5).normalize();
+        let ray = Ray {
+            origin: -dir * 3.0 + Vec3::Z * 0.1,
+            dir,
+        };
+        let p = emitter_position(ray);
+        assert!(p.dot(dir).abs() < 1e-6);
+        assert!(p.distance(Vec3::Z * 0.1 - dir * dir.z * 0.1) < 1e-6);
+    }
+}
@@ -0,0 +1,608 @@
+use crate::lines::Lines;
+use crate::pick::Ray;
+use crate::scene::Transform;
+use crate::volume::Axis;
+use glam::{Mat4, Quat, UVec3, Vec3};
+use std::f32::consts::{PI, TAU};
+
+pub(crate) const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.25, 0.25, 1.0],
+    [0.3, 0.85, 0.3, 1.0],
+    [0.3, 0.45, 0.95, 1.0],
+];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const GRID_COLOR: [f32; 4] = [0.55, 0.55, 0.6, 0.5];
+const BOUNDS_COLOR: [f32; 4] = [0.8, 0.8, 0.85, 0.8];
+const LATTICE_COLOR: [f32; 4] = [0.8, 0.8, 0.85, 0.25];
+const SLICE_COLOR: [f32; 4] = [0.95, 0.65, 0.2, 0.8];
+/// Grid lines on each side of the point under the camera.
+const GRID_LINES: i32 = 20;
+/// Handle length as a share of their distance to the camera, so they keep
+/// their size on screen.
+const HANDLE_SIZE: f32 = 0.15;
+/// How close to a handle the pointer ray has to pass, as a share of its
+/// length.
+const PICK_TOLERANCE: f32 = 0.08;
+const RING_SEGMENTS: usize = 48;
+const MIN_SCALE: f32 = 0.01;
+
+/// What dragging a handle does to the selected node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GizmoMode {
+    Translate,
+    /// Turns around the axes of the parent.
+    Rotate,
+    /// Stretches along the node's own, rotated, axes.
+    Scale,
+}
+
+impl GizmoMode {
+    pub const ALL: [GizmoMode; 3] = [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GizmoMode::Translate => "Translate",
+            GizmoMode::Rotate => "Rotate",
+            GizmoMode::Scale => "Scale",
+        }
+    }
+}
+
+/// Which of the viewport guides are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GizmoSettings {
+    pub grid: bool,
+    pub axes: bool,
+    pub bounds: bool,
+    pub lattice: bool,
+    pub slice_plane: bool,
+    pub handles: bool,
+    pub mode: GizmoMode,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> GizmoSettings {
+        GizmoSettings {
+            grid: true,
+            axes: true,
+            bounds: true,
+            lattice: false,
+            slice_plane: false,
+            handles: true,
+            mode: GizmoMode::Translate,
+        }
+    }
+}
+
+impl GizmoSettings {
+    /// Nothing drawn, for rendering animations.
+    pub fn hidden() -> GizmoSettings {
+        GizmoSettings {
+            grid: false,
+            axes: false,
+            bounds: false,
+            lattice: false,
+            slice_plane: false,
+            handles: false,
+            ..GizmoSettings::default()
+        }
+    }
+}
+
+fn with_alpha(color: [f32; 4], alpha: f32) -> [f32; 4] {
+    [color[0], color[1], color[2], color[3] * alpha]
+}
+
+/// Power of ten between grid lines, about a twentieth of the distance to
+/// the camera.
+pub(crate) fn grid_spacing(distance: f32) -> f32 {
+    10f32.powf((distance / 10.0).log10().floor())
+}
+
+/// Lines of the y = 0 plane around the point under `eye`, fading out
+/// towards the edge so the grid seems to go on. The world X and Z axes are
+/// drawn in their colors.
+pub(crate) fn ground_grid(lines: &mut Lines, eye: Vec3, spacing: f32) {
+    let center = (Vec3::new(eye.x, 0.0, eye.z) / spacing).round() * spacing;
+    let extent = GRID_LINES as f32 * spacing;
+    for i in -GRID_LINES..=GRID_LINES {
+        let offset = i as f32 * spacing;
+        for (axis, across) in [(Vec3::X, Vec3::Z), (Vec3::Z, Vec3::X)] {
+            let line = center + across * offset;
+            // On the world axis the line runs along.
+            let color = if line.dot(across).abs() < spacing * 0.5 {
+                AXIS_COLORS[if axis == Vec3::X { 0 } else { 2 }]
+            } else {
+                GRID_COLOR
+            };
+            for j in -GRID_LINES..GRID_LINES {
+                let a = line + axis * (j as f32 * spacing);
+                let b = a + axis * spacing;
+                let fade = 1.0 - ((a + b) * 0.5 - center).length() / extent;
+                if fade > 0.0 {
+                    lines.push(a, b, with_alpha(color, fade));
+                }
+            }
+        }
+    }
+}
+
+/// X, Y and Z arrows of `length` from `origin`.
+pub(crate) fn axis_triad(lines: &mut Lines, origin: Vec3, length: f32) {
+    for (axis, color) in [Vec3::X, Vec3::Y, Vec3::Z].into_iter().zip(AXIS_COLORS) {
+        lines.push(origin, origin + axis * length, color);
+    }
+}
+
+/// The 12 edges of the unit cube of the surface's object space, placed by
+/// `world`.
+pub(crate) fn bounding_box(lines: &mut Lines, world: Mat4) {
+    let corner = |i: u32| {
+        let bit = |b: u32| if i & (1 << b) != 0 { 0.5 } else { -0.5 };
+        world.transform_point3(Vec3::new(bit(0), bit(1), bit(2)))
+    };
+    for i in 0..8 {
+        for b in 0..3 {
+            if i & (1 << b) == 0 {
+                lines.push(corner(i), corner(i | 1 << b), BOUNDS_COLOR);
+            }
+        }
+    }
+}
+
+/// Lines between the cells on the faces of the bounding box, every few cells
+/// so no face gets more than `max_lines` across.
+pub(crate) fn lattice(lines: &mut Lines, world: Mat4, cells: UVec3, max_lines: u32) {
+    let step = (cells.max_element().div_ceil(max_lines)).max(1);
+    for normal in 0..3 {
+        let (u, v) = ((normal + 1) % 3, (normal + 2) % 3);
+        for side in [-0.5, 0.5] {
+            for (along, across) in [(u, v), (v, u)] {
+                for k in (step..cells[along]).step_by(step as usize) {
+                    let mut a = Vec3::ZERO;
+                    a[normal] = side;
+                    a[along] = k as f32 / cells[along] as f32 - 0.5;
+                    a[across] = -0.5;
+                    let mut b = a;
+                    b[across] = 0.5;
+                    lines.push(
+                        world.transform_point3(a),
+                        world.transform_point3(b),
+                        LATTICE_COLOR,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Object space coordinate along `axis` of the samples in `layer`.
+pub(crate) fn slice_offset(size: UVec3, axis: Axis, layer: u32) -> f32 {
+    let n = size[axis as usize] as f32;
+    (layer as f32 + 0.5) / n - 0.5
+}
+
+/// The layer of samples nearest to `offset` along `axis`.
+pub(crate) fn slice_layer(size: UVec3, axis: Axis, offset: f32) -> u32 {
+    let n = size[axis as usize];
+    ((offset + 0.5) * n as f32 - 0.5)
+        .round()
+        .clamp(0.0, (n - 1) as f32) as u32
+}
+
+/// Outline of the slice through `layer` inside the bounding box.
+pub(crate) fn slice_plane(lines: &mut Lines, world: Mat4, size: UVec3, axis: Axis, layer: u32) {
+    let a = axis as usize;
+    let (u, v) = ((a + 1) % 3, (a + 2) % 3);
+    let corners: Vec<Vec3> = [(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)]
+        .into_iter()
+        .map(|(s, t)| {
+            let mut p = Vec3::ZERO;
+            p[a] = slice_offset(size, axis, layer);
+            p[u] = s;
+            p[v] = t;
+            world.transform_point3(p)
+        })
+        .collect();
+    lines.push_loop(&corners, SLICE_COLOR);
+}
+
+/// Where the handles are and which way they point, all in world space.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct HandleFrame {
+    pub origin: Vec3,
+    /// Unit directions.
+    pub axes: [Vec3; 3],
+    /// World length of a unit along each axis of the parent.
+    pub axis_scale: [f32; 3],
+    /// World length of the handles.
+    pub size: f32,
+}
+
+impl HandleFrame {
+    /// Handles of a node with `transform` under `parent`: at its origin,
+    /// along the axes of the parent, or its own rotated ones for scaling since
+    /// the scale applies before the rotation.
+    pub fn for_node(
+        parent: Mat4,
+        transform: &Transform,
+        mode: GizmoMode,
+        eye: Vec3,
+    ) -> HandleFrame {
+        let rotation = match mode {
+            GizmoMode::Scale => transform.quat(),
+            GizmoMode::Translate | GizmoMode::Rotate => Quat::IDENTITY,
+        };
+        let space = parent * Mat4::from_rotation_translation(rotation, transform.translation);
+        HandleFrame::new(space, Vec3::ZERO, eye)
+    }
+
+    /// Handles at `origin` in the space of `parent`, along its axes, sized
+    /// for a camera at `eye`.
+    pub fn new(parent: Mat4, origin: Vec3, eye: Vec3) -> HandleFrame {
+        let origin = parent.transform_point3(origin);
+        let axis = |v: Vec3| parent.transform_vector3(v);
+        let axes = [axis(Vec3::X), axis(Vec3::Y), axis(Vec3::Z)];
+        HandleFrame {
+            origin,
+            axes: axes.map(|a| a.normalize_or_zero()),
+            axis_scale: axes.map(|a| a.length()),
+            size: HANDLE_SIZE * (eye - origin).length(),
+        }
+    }
+
+    fn ring(&self, axis: usize) -> Vec<Vec3> {
+        let (u, v) = (self.axes[(axis + 1) % 3], self.axes[(axis + 2) % 3]);
+        (0..RING_SEGMENTS)
+            .map(|i| {
+                let angle = TAU * i as f32 / RING_SEGMENTS as f32;
+                self.origin + (u * angle.cos() + v * angle.sin()) * self.size
+            })
+            .collect()
+    }
+
+    /// The handle of `axis`, highlighted when it's hovered or dragged.
+    pub fn axis_lines(&self, lines: &mut Lines, mode: GizmoMode, axis: usize, active: bool) {
+        let color = if active {
+            ACTIVE_COLOR
+        } else {
+            AXIS_COLORS[axis]
+        };
+        let (dir, side) = (self.axes[axis], self.axes[(axis + 1) % 3]);
+        let tip = self.origin + dir * self.size;
+        let head = self.size * 0.12;
+        match mode {
+            GizmoMode::Translate => {
+                lines.push(self.origin, tip, color);
+                lines.push(tip, tip - dir * head + side * head * 0.5, color);
+                lines.push(tip, tip - dir * head - side * head * 0.5, color);
+            }
+            GizmoMode::Rotate => lines.push_loop(&self.ring(axis), color),
+            GizmoMode::Scale => {
+                lines.push(self.origin, tip, color);
+                let other = self.axes[(axis + 2) % 3];
+                let corners: Vec<Vec3> = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                    .into_iter()
+                    .map(|(s, t)| tip + (side * s + other * t) * head * 0.5)
+                    .collect();
+                lines.push_loop(&corners, color);
+            }
+        }
+    }
+
+    /// How far along the ray the handle of `axis` is, when the ray passes
+    /// close enough to it.
+    pub fn hit_axis(&self, ray: Ray, mode: GizmoMode, axis: usize) -> Option<f32> {
+        let tolerance = self.size * PICK_TOLERANCE;
+        match mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let a = self.axes[axis];
+                let w = self.origin - ray.origin;
+                let (b, c, e) = (a.dot(ray.dir), a.dot(w), ray.dir.dot(w));
+                let denom = 1.0 - b * b;
+                let s = if denom > 1e-6 {
+                    ((b * e - c) / denom).clamp(0.0, self.size)
+                } else {
+                    0.0
+                };
+                let t = (e + s * b).max(0.0);
+                let distance = (w + a * s - ray.dir * t).length();
+                (distance < tolerance).then_some(t)
+            }
+            GizmoMode::Rotate => {
+                let (t, hit) = self.plane_hit(ray, axis)?;
+                let radius = (hit - self.origin).length();
+                ((radius - self.size).abs() < tolerance).then_some(t)
+            }
+        }
+    }
+
+    fn plane_hit(&self, ray: Ray, axis: usize) -> Option<(f32, Vec3)> {
+        let normal = self.axes[axis];
+        let facing = ray.dir.dot(normal);
+        if facing.abs() < 1e-6 {
+            return None;
+        }
+        let t = (self.origin - ray.origin).dot(normal) / facing;
+        (t >= 0.0).then(|| (t, ray.origin + ray.dir * t))
+    }
+
+    /// World distance along `axis` from the origin to the point of the axis
+    /// line nearest to the ray.
+    fn axis_param(&self, ray: Ray, axis: usize) -> Option<f32> {
+        let a = self.axes[axis];
+        let w = self.origin - ray.origin;
+        let b = a.dot(ray.dir);
+        let denom = 1.0 - b * b;
+        (denom > 1e-6).then(|| (b * ray.dir.dot(w) - a.dot(w)) / denom)
+    }
+
+    /// Angle around `axis` of where the ray crosses the plane of its ring,
+    /// counter-clockwise looking down the axis.
+    fn angle(&self, ray: Ray, axis: usize) -> Option<f32> {
+        let (_, hit) = self.plane_hit(ray, axis)?;
+        let local = hit - self.origin;
+        let (u, v) = (self.axes[(axis + 1) % 3], self.axes[(axis + 2) % 3]);
+        Some(local.dot(v).atan2(local.dot(u)))
+    }
+}
+
+/// A handle being dragged, from the node's transform when it was grabbed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Drag {
+    frame: HandleFrame,
+    mode: GizmoMode,
+    pub axis: usize,
+    start: f32,
+    // Rotations add up the angle from one update to the next, so they can go
+    // past half a turn.
+    last_angle: f32,
+    turned: f32,
+    transform: Transform,
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+impl Drag {
+    pub fn start(
+        frame: HandleFrame,
+        mode: GizmoMode,
+        axis: usize,
+        ray: Ray,
+        transform: Transform,
+    ) -> Option<Drag> {
+        let start = match mode {
+            GizmoMode::Translate | GizmoMode::Scale => frame.axis_param(ray, axis)?,
+            GizmoMode::Rotate => frame.angle(ray, axis)?,
+        };
+        Some(Drag {
+            frame,
+            mode,
+            axis,
+            start,
+            last_angle: start,
+            turned: 0.0,
+            transform,
+        })
+    }
+
+    /// The transform with the handle dragged to where the ray points, none
+    /// when the ray runs along the axis or the ring.
+    pub fn update(&mut self, ray: Ray) -> Option<Transform> {
+        let (frame, axis) = (&self.frame, self.axis);
+        let mut transform = self.transform;
+        match self.mode {
+            GizmoMode::Translate => {
+                let moved = frame.axis_param(ray, axis)? - self.start;
+                transform.translation[axis] += moved / frame.axis_scale[axis].max(1e-6);
+            }
+            GizmoMode::Rotate => {
+                let angle = frame.angle(ray, axis)?;
+                self.turned += wrap_angle(angle - self.last_angle);
+                self.last_angle = angle;
+                // Around the parent's axis, after the rotation the node had.
+                let mut around = Vec3::ZERO;
+                around[axis] = 1.0;
+                let turn = Quat::from_axis_angle(around, self.turned);
+                transform.set_quat(turn * self.transform.quat());
+            }
+            GizmoMode::Scale => {
+                if self.start.abs() < frame.size * PICK_TOLERANCE {
+                    return None;
+                }
+                let ratio = frame.axis_param(ray, axis)? / self.start;
+                transform.scale[axis] = (transform.scale[axis] * ratio).max(MIN_SCALE);
+            }
+        }
+        Some(transform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{uvec3, vec3};
+
+    /// The nearest handle the ray passes over.
+    fn hit(frame: &HandleFrame, ray: Ray, mode: GizmoMode) -> Option<usize> {
+        (0..3)
+            .filter_map(|axis| Some((axis, frame.hit_axis(ray, mode, axis)?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| axis)
+    }
+
+    fn ray(origin: Vec3, through: Vec3) -> Ray {
+        Ray {
+            origin,
+            dir: (through - origin).normalize(),
+        }
+    }
+
+    #[test]
+    fn draws_the_guides() {
+        let mut lines = Lines::default();
+        let world = Mat4::from_scale(Vec3::splat(2.0));
+        bounding_box(&mut lines, world);
+        let edges = lines.segments();
+        assert_eq!(edges.len(), 12);
+        assert!(edges
+            .iter()
+            .all(|(a, b)| (a.distance(*b) - 2.0).abs() < 1e-6));
+
+        lines.clear();
+        // 3 lines inside each side of 4 cells, 2 directions on 6 faces.
+        lattice(&mut lines, Mat4::IDENTITY, uvec3(4, 4, 4), 8);
+        assert_eq!(lines.segments().len(), 6 * 2 * 3);
+        lines.clear();
+        // Every other cell when there are too many.
+        lattice(&mut lines, Mat4::IDENTITY, uvec3(64, 64, 64), 32);
+        assert_eq!(lines.segments().len(), 6 * 2 * 31);
+
+        lines.clear();
+        ground_grid(&mut lines, vec3(3.04, 2.0, -1.0), 0.1);
+        assert!(lines
+            .segments()
+            .iter()
+            .all(|(a, b)| a.y == 0.0 && b.y == 0.0 && a.distance(*b) < 0.1 + 1e-5));
+        assert_eq!(grid_spacing(2.0), 0.1);
+        assert_eq!(grid_spacing(20.0), 1.0);
+    }
+
+    #[test]
+    fn slices_map_to_layers_and_back() {
+        let size = uvec3(65, 33, 17);
+        for axis in Axis::ALL {
+            for layer in [0, 5, size[axis as usize] - 1] {
+                assert_eq!(
+                    slice_layer(size, axis, slice_offset(size, axis, layer)),
+                    layer
+                );
+            }
+            assert_eq!(slice_layer(size, axis, 2.0), size[axis as usize] - 1);
+        }
+        let mut lines = Lines::default();
+        slice_plane(&mut lines, Mat4::IDENTITY, size, Axis::Y, 16);
+        assert!(lines.segments().iter().all(|(a, _)| a.y.abs() < 1e-6));
+    }
+
+    #[test]
+    fn picks_the_handle_under_the_pointer() {
+        let eye = vec3(0.0, 0.0, 10.0);
+        let frame = HandleFrame::new(Mat4::IDENTITY, Vec3::ZERO, eye);
+        assert!((frame.size - 1.5).abs() < 1e-6);
+        let translate = GizmoMode::Translate;
+        assert_eq!(
+            hit(&frame, ray(eye, vec3(1.0, 0.02, 0.0)), translate),
+            Some(0)
+        );
+        assert_eq!(
+            hit(&frame, ray(eye, vec3(0.0, 1.0, 0.0)), translate),
+            Some(1)
+        );
+        assert_eq!(hit(&frame, ray(eye, vec3(1.0, 1.0, 0.0)), translate), None);
+        // Past the tip.
+        assert_eq!(hit(&frame, ray(eye, vec3(2.0, 0.0, 0.0)), translate), None);
+        // On the ring around Z, which faces the camera.
+        let on_ring = vec3(1.5, 0.0, 0.0)
+            .lerp(vec3(0.0, 1.5, 0.0), 0.5)
+            .normalize()
+            * 1.5;
+        assert_eq!(hit(&frame, ray(eye, on_ring), GizmoMode::Rotate), Some(2));
+    }
+
+    #[test]
+    fn drags_move_turn_and_scale() {
+        let eye = vec3(0.0, 0.0, 10.0);
+        // A parent twice as big, so moving 1 in the world is 0.5 for the node.
+        let parent = Mat4::from_scale(Vec3::splat(2.0));
+        let frame = HandleFrame::new(parent, Vec3::ZERO, eye);
+        let node = Transform::IDENTITY;
+
+        let mut drag = Drag::start(
+            frame,
+            GizmoMode::Translate,
+            0,
+            ray(eye, vec3(1.0, 0.0, 0.0)),
+            node,
+        )
+        .unwrap();
+        let moved = drag.update(ray(eye, vec3(2.0, 0.0, 0.0))).unwrap();
+        assert!((moved.translation - vec3(0.5, 0.0, 0.0)).length() < 1e-5);
+
+        let mut drag = Drag::start(
+            frame,
+            GizmoMode::Scale,
+            1,
+            ray(eye, vec3(0.0, 1.0, 0.0)),
+            node,
+        )
+        .unwrap();
+        let scaled = drag.update(ray(eye, vec3(0.0, 2.0, 0.0))).unwrap();
+        assert!((scaled.scale - vec3(1.0, 2.0, 1.0)).length() < 1e-5);
+
+        let mut drag = Drag::start(
+            frame,
+            GizmoMode::Rotate,
+            2,
+            ray(eye, vec3(1.0, 0.0, 0.0)),
+            node,
+        )
+        .unwrap();
+        // A quarter turn, then on past a half turn.
+        let turned = drag.update(ray(eye, vec3(0.0, 1.0, 0.0))).unwrap();
+        assert!(turned.quat().angle_between(Quat::from_rotation_z(PI / 2.0)) < 1e-3);
+        drag.update(ray(eye, vec3(-1.0, 0.1, 0.0))).unwrap();
+        let turned = drag.update(ray(eye, vec3(-1.0, -1.0, 0.0))).unwrap();
+        assert!(
+            turned
+                .quat()
+                .angle_between(Quat::from_rotation_z(1.25 * PI))
+                < 1e-3
+        );
+    }
+
+    #[test]
+    fn rotated_nodes_scale_and_turn_the_way_the_handles_point() {
+        let eye = vec3(0.0, 0.0, 10.0);
+        // Turned a quarter around Z, so its own X points along the world Y.
+        let node = Transform {
+            rotation: vec3(0.0, 0.0, 90.0),
+            ..Transform::IDENTITY
+        };
+        let frame = HandleFrame::for_node(Mat4::IDENTITY, &node, GizmoMode::Scale, eye);
+        assert!(frame.axes[0].abs_diff_eq(Vec3::Y, 1e-6));
+        let mut drag = Drag::start(
+            frame,
+            GizmoMode::Scale,
+            0,
+            ray(eye, vec3(0.0, 1.0, 0.0)),
+            node,
+        )
+        .unwrap();
+        let scaled = drag.update(ray(eye, vec3(0.0, 2.0, 0.0))).unwrap();
+        assert!((scaled.scale - vec3(2.0, 1.0, 1.0)).length() < 1e-5);
+        let stretched = scaled.matrix().transform_vector3(Vec3::X);
+        assert!(stretched.abs_diff_eq(vec3(0.0, 2.0, 0.0), 1e-5));
+
+        // A quarter turn around the parent's X, seen from the side, on top of
+        // the rotation the node had.
+        let eye = vec3(10.0, 0.0, 0.0);
+        let frame = HandleFrame::for_node(Mat4::IDENTITY, &node, GizmoMode::Rotate, eye);
+        let mut drag = Drag::start(
+            frame,
+            GizmoMode::Rotate,
+            0,
+            ray(eye, vec3(0.0, 1.0, 0.0)),
+            node,
+        )
+        .unwrap();
+        let turned = drag.update(ray(eye, vec3(0.0, 0.0, 1.0))).unwrap();
+        let expected = Quat::from_rotation_x(PI / 2.0) * node.quat();
+        let matrix = turned.matrix();
+        for v in [Vec3::X, Vec3::Y, Vec3::Z] {
+            assert!(matrix.transform_vector3(v).abs_diff_eq(expected * v, 1e-5));
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use crate::chunk::BRICK_CELLS;
+use crate::extract::Region;
+use crate::volume::Volume;
+use crate::volume_file;
+use glam::UVec3;
+use std::collections::HashMap;
+use std::mem;
+
+/// Default memory budget of the history, in bytes.
+pub(crate) const HISTORY_BUDGET: usize = 64 << 20;
+
+/// One brick an edit changed, its samples before and after compressed like
+/// the bricks of volume files.
+struct BrickEdit {
+    samples: Region,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+impl BrickEdit {
+    fn bytes(&self) -> usize {
+        mem::size_of::<BrickEdit>() + self.before.len() + self.after.len()
+    }
+}
+
+/// One undoable edit of the field, like a brush stroke.
+pub(crate) struct Edit {
+    pub name: String,
+    bricks: Vec<BrickEdit>,
+}
+
+impl Edit {
+    pub fn bytes(&self) -> usize {
+        mem::size_of::<Edit>()
+            + self.name.len()
+            + self.bricks.iter().map(BrickEdit::bytes).sum::<usize>()
+    }
+
+    fn apply(&self, volume: &mut Volume, undo: bool) -> Vec<Region> {
+        for brick in &self.bricks {
+            let compressed = if undo { &brick.before } else { &brick.after };
+            let size = brick.samples.size();
+            let samples =
+                volume_file::decompress_samples(compressed, (size.x * size.y * size.z) as usize)
+                    .expect("history bricks decompress to their size");
+            volume_file::insert(volume, brick.samples, &samples);
+        }
+        self.bricks.iter().map(|brick| brick.samples).collect()
+    }
+}
+
+/// A stroke in progress, with the bricks it touched as they were before it.
+struct Stroke {
+    name: String,
+    before: HashMap<UVec3, Vec<f32>>,
+}
+
+/// Undo/redo history of the CPU copy of the field. Strokes snapshot the bricks
+/// they touch before changing them and keep the ones that changed, compressed,
+/// as they were before and after. Undoing and redoing return the sample boxes
+/// that have to be uploaded to the `BrickAtlas` again.
+pub(crate) struct History {
+    edits: Vec<Edit>,
+    // Number of edits that are applied, the rest can be redone.
+    position: usize,
+    budget: usize,
+    stroke: Option<Stroke>,
+}
+
+/// Samples that belong to `brick`. Unlike the cells, the samples on the faces
+/// between bricks are not shared.
+fn brick_samples(size: UVec3, brick: UVec3) -> Region {
+    let min = brick * BRICK_CELLS;
+    Region {
+        min,
+        max: (min + UVec3::splat(BRICK_CELLS)).min(size),
+    }
+}
+
+fn read_samples(volume: &Volume, samples: Region) -> Vec<f32> {
+    samples.cells().map(|p| volume.get(p.x, p.y, p.z)).collect()
+}
+
+impl History {
+    pub fn new(budget: usize) -> History {
+        History {
+            edits: Vec::new(),
+            position: 0,
+            budget,
+            stroke: None,
+        }
+    }
+
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Number of edits that are applied.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.edits.iter().map(Edit::bytes).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.edits.clear();
+        self.position = 0;
+        self.stroke = None;
+    }
+
+    pub fn in_stroke(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    pub fn begin_stroke(&mut self, name: &str) {
+        self.stroke = Some(Stroke {
+            name: name.to_owned(),
+            before: HashMap::new(),
+        });
+    }
+
+    /// Snapshots the bricks overlapping `samples` the current stroke hasn't
+    /// touched yet. Has to be called before they are changed.
+    pub fn capture(&mut self, volume: &Volume, samples: Region) {
+        let stroke = match &mut self.stroke {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        if samples.size().min_element() == 0 {
+            return;
+        }
+        let bricks = Region {
+            min: samples.min / BRICK_CELLS,
+            max: (samples.max - UVec3::ONE) / BRICK_CELLS + UVec3::ONE,
+        };
+        for brick in bricks.cells() {
+            stroke
+                .before
+                .entry(brick)
+                .or_insert_with(|| read_samples(volume, brick_samples(volume.size, brick)));
+        }
+    }
+
+    /// Turns the current stroke into an edit, dropping the edits that could be
+    /// redone and then the oldest ones until the history fits its budget.
+    pub fn end_stroke(&mut self, volume: &Volume) {
+        let stroke = match self.stroke.take() {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        let mut bricks: Vec<BrickEdit> = stroke
+            .before
+            .into_iter()
+            .filter_map(|(brick, before)| {
+                let samples = brick_samples(volume.size, brick);
+                let after = read_samples(volume, samples);
+                (after != before).then(|| BrickEdit {
+                    samples,
+                    before: volume_file::compress_samples(&before),
+                    after: volume_file::compress_samples(&after),
+                })
+            })
+            .collect();
+        if bricks.is_empty() {
+            return;
+        }
+        bricks.sort_by_key(|brick| {
+            volume.index(
+                brick.samples.min.x,
+                brick.samples.min.y,
+                brick.samples.min.z,
+            )
+        });
+
+        self.edits.truncate(self.position);
+        self.edits.push(Edit {
+            name: stroke.name,
+            bricks,
+        });
+        self.position = self.edits.len();
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let mut bytes = self.bytes();
+        while bytes > self.budget && self.edits.len() > 1 {
+            bytes -= self.edits[0].bytes();
+            self.edits.remove(0);
+            self.position = self.position.saturating_sub(1);
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Sets the budget, dropping the oldest edits if they no longer fit.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.trim();
+    }
+
+    /// Undoes or redoes edits until `position` of them are applied.
+    pub fn go_to(&mut self, volume: &mut Volume, position: usize) -> Vec<Region> {
+        let mut changed = Vec::new();
+        while self.position > position {
+            self.position -= 1;
+            changed.extend(self.edits[self.position].apply(volume, true));
+        }
+        while self.position < position.min(self.edits.len()) {
+            changed.extend(self.edits[self.position].apply(volume, false));
+            self.position += 1;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume;
+    use glam::uvec3;
+
+    /// Records a stroke setting the samples of `samples` to `value`.
+    fn stroke(history: &mut History, volume: &mut Volume, name: &str, samples: Region, value: f32) {
+        history.begin_stroke(name);
+        history.capture(volume, samples);
+        for p in samples.cells() {
+            volume.set(p.x, p.y, p.z, value);
+        }
+        history.end_stroke(volume);
+    }
+
+    fn region(min: u32, max: u32) -> Region {
+        Region {
+            min: UVec3::splat(min),
+            max: UVec3::splat(max),
+        }
+    }
+
+    /// Three overlapping strokes, across bricks, and the field after each.
+    fn three_strokes(history: &mut History, volume: &mut Volume) -> Vec<Vec<f32>> {
+        let mut states = vec![volume.samples().collect()];
+        for (i, samples) in [region(2, 6), region(4, 20), region(14, 30)]
+            .into_iter()
+            .enumerate()
+        {
+            stroke(history, volume, &format!("{}", i), samples, i as f32 + 1.0);
+            states.push(volume.samples().collect());
+        }
+        states
+    }
+
+    #[test]
+    fn undoes_and_redoes_in_order() {
+        let mut volume = Volume::from_fn(uvec3(33, 33, 33), volume::sphere);
+        let mut history = History::new(HISTORY_BUDGET);
+        let states = three_strokes(&mut history, &mut volume);
+        assert_eq!(history.position(), 3);
+        // Only the bricks that changed are kept.
+        assert_eq!(history.edits()[0].bricks.len(), 1);
+        assert_eq!(history.edits()[1].bricks.len(), 8);
+
+        for position in [2, 0, 3, 1, 3] {
+            let changed = history.go_to(&mut volume, position);
+            assert_eq!(history.position(), position);
+            assert_eq!(volume.samples().collect::<Vec<_>>(), states[position]);
+            assert!(!changed.is_empty());
+        }
+        // Past the end stops at the last edit.
+        assert!(history.go_to(&mut volume, 10).is_empty());
+        assert_eq!(history.position(), 3);
+
+        // Regions to upload cover the samples that changed.
+        let changed = history.go_to(&mut volume, 2);
+        let p = uvec3(29, 29, 29);
+        assert!(changed
+            .iter()
+            .any(|r| p.cmpge(r.min).all() && p.cmplt(r.max).all()));
+    }
+
+    #[test]
+    fn whole_bricks_take_less_than_their_samples() {
+        let mut volume = Volume::from_fn(uvec3(33, 33, 33), volume::sphere);
+        let mut history = History::new(HISTORY_BUDGET);
+        stroke(&mut history, &mut volume, "fill", region(0, 16), 1.0);
+
+        let edit = &history.edits()[0];
+        assert_eq!(edit.bricks.len(), 1);
+        let raw = (BRICK_CELLS * BRICK_CELLS * BRICK_CELLS) as usize * mem::size_of::<f32>();
+        assert!(edit.bytes() < raw, "{} bytes", edit.bytes());
+        assert_eq!(history.bytes(), edit.bytes());
+    }
+
+    #[test]
+    fn new_edits_drop_what_could_be_redone() {
+        let mut volume = Volume::from_fn(uvec3(33, 33, 33), volume::sphere);
+        let mut history = History::new(HISTORY_BUDGET);
+        three_strokes(&mut history, &mut volume);
+        history.go_to(&mut volume, 1);
+        stroke(&mut history, &mut volume, "new", region(0, 3), 9.0);
+        let names: Vec<&str> = history.edits().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["0", "new"]);
+        assert_eq!(history.position(), 2);
+
+        // A stroke that changes nothing isn't an edit.
+        stroke(&mut history, &mut volume, "same", region(0, 3), 9.0);
+        assert_eq!(history.edits().len(), 2);
+    }
+
+    #[test]
+    fn trims_the_oldest_edits_to_the_budget() {
+        let mut volume = Volume::from_fn(uvec3(33, 33, 33), volume::sphere);
+        let mut history = History::new(HISTORY_BUDGET);
+        let states = three_strokes(&mut history, &mut volume);
+        let newest = history.edits()[2].bytes();
+
+        history.set_budget(newest + history.edits()[1].bytes());
+        let names: Vec<&str> = history.edits().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["1", "2"]);
+        assert_eq!(history.position(), 2);
+        assert!(history.bytes() <= history.budget());
+        // Undoing everything left only goes back to before the kept edits.
+        history.go_to(&mut volume, 0);
+        assert_eq!(volume.samples().collect::<Vec<_>>(), states[1]);
+
+        // The newest edit stays even when it alone is over the budget.
+        history.go_to(&mut volume, 2);
+        history.set_budget(0);
+        assert_eq!(history.edits().len(), 1);
+        assert_eq!(history.position(), 1);
+    }
+}
@@ -0,0 +1,564 @@
+use crate::mesh::{Mesh, MeshVertex};
+use glam::Vec3;
+use std::path::Path;
+use std::{fs, io};
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Reads the triangles of an OBJ, STL or PLY file, picked by extension.
+/// Polygons are split into fans, normals are only read from PLY files.
+pub(crate) fn read_mesh(path: impl AsRef<Path>) -> io::Result<Mesh> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let bytes = fs::read(path)?;
+    let mesh = match extension.as_str() {
+        "obj" => parse_obj(&String::from_utf8_lossy(&bytes))?,
+        "stl" => parse_stl(&bytes)?,
+        "ply" => parse_ply(&bytes)?,
+        _ => {
+            return Err(invalid_data(format!(
+                "unknown mesh format {:?}, expected obj, stl or ply",
+                extension
+            )))
+        }
+    };
+    if mesh.indices.is_empty() {
+        return Err(invalid_data("the mesh has no triangles"));
+    }
+    Ok(mesh)
+}
+
+/// Reads the points and normals of an XYZ or PLY point cloud, picked by
+/// extension, into the vertices of a mesh without triangles. Faces are
+/// ignored.
+pub(crate) fn read_points(path: impl AsRef<Path>) -> io::Result<Mesh> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let bytes = fs::read(path)?;
+    let mut cloud = match extension.as_str() {
+        "xyz" => parse_xyz(&String::from_utf8_lossy(&bytes))?,
+        "ply" => parse_ply(&bytes)?,
+        _ => {
+            return Err(invalid_data(format!(
+                "unknown point cloud format {:?}, expected xyz or ply",
+                extension
+            )))
+        }
+    };
+    cloud.indices.clear();
+    if cloud.vertices.is_empty() {
+        return Err(invalid_data("the point cloud is empty"));
+    }
+    if cloud.vertices.iter().any(|v| v.normal == [0.0; 3]) {
+        return Err(invalid_data("the points need normals"));
+    }
+    Ok(cloud)
+}
+
+fn push_polygon(mesh: &mut Mesh, polygon: &[u32]) {
+    for i in 1..polygon.len().saturating_sub(1) {
+        mesh.indices
+            .extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+    }
+}
+
+fn check_indices(mesh: &Mesh) -> io::Result<()> {
+    let count = mesh.vertices.len() as u32;
+    match mesh.indices.iter().find(|&&i| i >= count) {
+        Some(i) => Err(invalid_data(format!(
+            "vertex {} out of range, there are {}",
+            i, count
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn parse_floats<'a>(tokens: impl Iterator<Item = &'a str>, line: usize) -> io::Result<Vec3> {
+    let v: Vec<f32> = tokens
+        .take(3)
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .map_err(|e| invalid_data(format!("line {}: {}", line, e)))?;
+    match v[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(invalid_data(format!(
+            "line {}: expected 3 coordinates",
+            line
+        ))),
+    }
+}
+
+/// Points of an XYZ file, one "x y z nx ny nz" per line. Blank lines and
+/// lines starting with '#' are skipped.
+pub(crate) fn parse_xyz(text: &str) -> io::Result<Mesh> {
+    let mut cloud = Mesh::default();
+    for (line, text) in text.lines().enumerate() {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let mut tokens = text.split_whitespace();
+        let p = parse_floats(&mut tokens, line + 1)?;
+        let normal = parse_floats(&mut tokens, line + 1)
+            .map_err(|_| invalid_data(format!("line {}: expected a normal", line + 1)))?;
+        cloud.vertices.push(MeshVertex::new(p, normal));
+    }
+    Ok(cloud)
+}
+
+/// Vertices and faces of a Wavefront OBJ document, everything else is
+/// skipped.
+pub(crate) fn parse_obj(text: &str) -> io::Result<Mesh> {
+    let mut mesh = Mesh::default();
+    let mut polygon = Vec::new();
+    for (line, text) in text.lines().enumerate() {
+        let line = line + 1;
+        let mut tokens = text.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let p = parse_floats(tokens, line)?;
+                mesh.vertices.push(MeshVertex::new(p, Vec3::ZERO));
+            }
+            Some("f") => {
+                polygon.clear();
+                for token in tokens {
+                    // "v", "v/vt", "v//vn" or "v/vt/vn", negative counts back
+                    // from the last vertex.
+                    let index: i64 = token
+                        .split('/')
+                        .next()
+                        .unwrap()
+                        .parse()
+                        .map_err(|e| invalid_data(format!("line {}: {}", line, e)))?;
+                    let index = match index {
+                        i if i > 0 => i - 1,
+                        i if i < 0 => mesh.vertices.len() as i64 + i,
+                        _ => return Err(invalid_data(format!("line {}: index 0", line))),
+                    };
+                    polygon.push(
+                        u32::try_from(index).map_err(|_| {
+                            invalid_data(format!("line {}: index out of range", line))
+                        })?,
+                    );
+                }
+                push_polygon(&mut mesh, &polygon);
+            }
+            _ => {}
+        }
+    }
+    check_indices(&mesh)?;
+    Ok(mesh)
+}
+
+/// Triangles of an ASCII or binary STL file, every corner its own vertex.
+pub(crate) fn parse_stl(bytes: &[u8]) -> io::Result<Mesh> {
+    // Binary files may start with "solid" too, their size gives them away.
+    let binary_size = |count: u32| 84 + 50 * count as usize;
+    let is_binary = bytes.len() >= 84
+        && binary_size(u32::from_le_bytes(bytes[80..84].try_into().unwrap())) == bytes.len();
+    let mut mesh = Mesh::default();
+    if is_binary {
+        for facet in bytes[84..].chunks_exact(50) {
+            // The normal comes first, then the corners.
+            for corner in facet[12..48].chunks_exact(12) {
+                let f = |i: usize| f32::from_le_bytes(corner[i..i + 4].try_into().unwrap());
+                mesh.vertices
+                    .push(MeshVertex::new(Vec3::new(f(0), f(4), f(8)), Vec3::ZERO));
+            }
+        }
+    } else {
+        let text = String::from_utf8_lossy(bytes);
+        if !text.trim_start().starts_with("solid") {
+            return Err(invalid_data("neither an ASCII nor a binary STL file"));
+        }
+        for (line, text) in text.lines().enumerate() {
+            let mut tokens = text.split_whitespace();
+            if tokens.next() == Some("vertex") {
+                let p = parse_floats(tokens, line + 1)?;
+                mesh.vertices.push(MeshVertex::new(p, Vec3::ZERO));
+            }
+        }
+        if mesh.vertices.len() % 3 != 0 {
+            return Err(invalid_data("a facet doesn't have 3 vertices"));
+        }
+    }
+    mesh.indices = (0..mesh.vertices.len() as u32).collect();
+    Ok(mesh)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> io::Result<PlyType> {
+        Ok(match name {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return Err(invalid_data(format!("unknown PLY type {}", name))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    /// The type of the count first for lists.
+    list: Option<PlyType>,
+    ty: PlyType,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads PLY values one at a time, from whitespace separated text or from
+/// binary data.
+struct PlyValues<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl PlyValues<'_> {
+    fn next(&mut self, ty: PlyType) -> io::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            let rest = &self.bytes[self.position..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or_else(|| invalid_data("the PLY data ends early"))?;
+            let len = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.position += start + len;
+            let token = String::from_utf8_lossy(&rest[start..start + len]);
+            return token
+                .parse()
+                .map_err(|e| invalid_data(format!("PLY value {:?}: {}", token, e)));
+        }
+
+        let size = ty.size();
+        let mut raw = self
+            .bytes
+            .get(self.position..self.position + size)
+            .ok_or_else(|| invalid_data("the PLY data ends early"))?
+            .to_vec();
+        self.position += size;
+        if self.format == PlyFormat::BigEndian {
+            raw.reverse();
+        }
+        Ok(match ty {
+            PlyType::I8 => raw[0] as i8 as f64,
+            PlyType::U8 => raw[0] as f64,
+            PlyType::I16 => i16::from_le_bytes(raw[..].try_into().unwrap()) as f64,
+            PlyType::U16 => u16::from_le_bytes(raw[..].try_into().unwrap()) as f64,
+            PlyType::I32 => i32::from_le_bytes(raw[..].try_into().unwrap()) as f64,
+            PlyType::U32 => u32::from_le_bytes(raw[..].try_into().unwrap()) as f64,
+            PlyType::F32 => f32::from_le_bytes(raw[..].try_into().unwrap()) as f64,
+            PlyType::F64 => f64::from_le_bytes(raw[..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Vertex positions, normals and faces of an ASCII or binary PLY file, other
+/// elements and properties are skipped.
+pub(crate) fn parse_ply(bytes: &[u8]) -> io::Result<Mesh> {
+    const END_HEADER: &[u8] = b"end_header";
+    let end = bytes
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or_else(|| invalid_data("no PLY header"))?;
+    let data_start = bytes[end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| end + i + 1);
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    if !header.starts_with("ply") {
+        return Err(invalid_data("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in header.lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["format", name, _] => {
+                format = Some(match name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    _ => return Err(invalid_data(format!("unknown PLY format {}", name))),
+                });
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_owned(),
+                count: count
+                    .parse()
+                    .map_err(|e| invalid_data(format!("PLY element count: {}", e)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid_data("PLY property outside an element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_owned(),
+                    list: Some(PlyType::parse(count)?),
+                    ty: PlyType::parse(ty)?,
+                });
+            }
+            ["property", ty, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid_data("PLY property outside an element"))?;
+                element.properties.push(PlyProperty {
+                    name: name.to_owned(),
+                    list: None,
+                    ty: PlyType::parse(ty)?,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut values = PlyValues {
+        format: format.ok_or_else(|| invalid_data("no PLY format"))?,
+        bytes: &bytes[data_start..],
+        position: 0,
+    };
+    let mut mesh = Mesh::default();
+    let mut polygon = Vec::new();
+    for element in &elements {
+        for _ in 0..element.count {
+            let (mut p, mut normal) = (Vec3::ZERO, Vec3::ZERO);
+            polygon.clear();
+            for property in &element.properties {
+                match property.list {
+                    Some(count_ty) => {
+                        let count = values.next(count_ty)? as usize;
+                        for _ in 0..count {
+                            let value = values.next(property.ty)?;
+                            if property.name == "vertex_indices" || property.name == "vertex_index"
+                            {
+                                polygon.push(value as u32);
+                            }
+                        }
+                    }
+                    None => {
+                        let value = values.next(property.ty)? as f32;
+                        match property.name.as_str() {
+                            "x" => p.x = value,
+                            "y" => p.y = value,
+                            "z" => p.z = value,
+                            "nx" => normal.x = value,
+                            "ny" => normal.y = value,
+                            "nz" => normal.z = value,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => mesh.vertices.push(MeshVertex::new(p, normal)),
+                "face" => push_polygon(&mut mesh, &polygon),
+                _ => {}
+            }
+        }
+    }
+    check_indices(&mesh)?;
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mesh::CUBE_QUADS;
+
+    /// Corners of the unit cube, in the order `CUBE_QUADS` indexes them.
+    fn cube_corners() -> Vec<[f32; 3]> {
+        Mesh::cuboid(Vec3::ZERO, Vec3::ONE)
+            .vertices
+            .iter()
+            .map(|v| v.pos)
+            .collect()
+    }
+
+    fn cube_triangles() -> Vec<[u32; 3]> {
+        CUBE_QUADS
+            .iter()
+            .flat_map(|&[a, b, c, d]| [[a, b, c], [a, c, d]])
+            .collect()
+    }
+
+    /// The positions of each triangle's corners.
+    fn corners(mesh: &Mesh) -> Vec<[[f32; 3]; 3]> {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|tri| [0, 1, 2].map(|i| mesh.vertices[tri[i] as usize].pos))
+            .collect()
+    }
+
+    fn expected_corners() -> Vec<[[f32; 3]; 3]> {
+        let corners = cube_corners();
+        cube_triangles()
+            .iter()
+            .map(|tri| tri.map(|i| corners[i as usize]))
+            .collect()
+    }
+
+    #[test]
+    fn parses_obj() {
+        let mut obj = String::from("# cube\no cube\n");
+        for [x, y, z] in cube_corners() {
+            obj += &format!("v {} {} {}\nvn 0 0 1\n", x, y, z);
+        }
+        // Mixed index forms, and the last face counted from the end.
+        for (i, [a, b, c, d]) in CUBE_QUADS.into_iter().enumerate() {
+            obj += &match i {
+                0 => format!("f {} {} {} {}\n", a + 1, b + 1, c + 1, d + 1),
+                1 => format!("f {}/1 {}/1 {}/1 {}/1\n", a + 1, b + 1, c + 1, d + 1),
+                2 => format!("f {}//1 {}//1 {}//1 {}//1\n", a + 1, b + 1, c + 1, d + 1),
+                _ => format!(
+                    "f {} {} {} {}\n",
+                    a as i32 - 8,
+                    b as i32 - 8,
+                    c as i32 - 8,
+                    d as i32 - 8
+                ),
+            };
+        }
+        let mesh = parse_obj(&obj).unwrap();
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(corners(&mesh), expected_corners());
+
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n").is_err());
+        assert!(parse_obj("v 0 0\n").is_err());
+    }
+
+    #[test]
+    fn parses_ascii_and_binary_stl() {
+        let mut ascii = String::from("solid cube\n");
+        let mut binary = vec![0u8; 80];
+        binary.extend_from_slice(&12u32.to_le_bytes());
+        for tri in expected_corners() {
+            ascii += "facet normal 0 0 0\nouter loop\n";
+            binary.extend_from_slice(&[0; 12]);
+            for [x, y, z] in tri {
+                ascii += &format!("vertex {} {} {}\n", x, y, z);
+                for v in [x, y, z] {
+                    binary.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            ascii += "endloop\nendfacet\n";
+            binary.extend_from_slice(&[0; 2]);
+        }
+        ascii += "endsolid cube\n";
+
+        for bytes in [ascii.as_bytes(), &binary] {
+            let mesh = parse_stl(bytes).unwrap();
+            assert_eq!(corners(&mesh), expected_corners());
+        }
+        assert!(parse_stl(&binary[..binary.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn parses_ascii_and_binary_ply() {
+        let header = |format: &str| {
+            format!(
+                "ply\nformat {} 1.0\ncomment cube\nelement vertex 8\nproperty float x\n\
+                 property float y\nproperty float z\nproperty uchar red\n\
+                 element face 6\nproperty list uchar int vertex_indices\nend_header\n",
+                format
+            )
+        };
+        let mut ascii = header("ascii");
+        let mut little = header("binary_little_endian").into_bytes();
+        let mut big = header("binary_big_endian").into_bytes();
+        for [x, y, z] in cube_corners() {
+            ascii += &format!("{} {} {} 255\n", x, y, z);
+            for v in [x, y, z] {
+                little.extend_from_slice(&v.to_le_bytes());
+                big.extend_from_slice(&v.to_be_bytes());
+            }
+            little.push(255);
+            big.push(255);
+        }
+        for quad in CUBE_QUADS {
+            ascii += &format!("4 {} {} {} {}\n", quad[0], quad[1], quad[2], quad[3]);
+            little.push(4);
+            big.push(4);
+            for i in quad {
+                little.extend_from_slice(&(i as i32).to_le_bytes());
+                big.extend_from_slice(&(i as i32).to_be_bytes());
+            }
+        }
+
+        for bytes in [ascii.as_bytes(), &little, &big] {
+            let mesh = parse_ply(bytes).unwrap();
+            assert_eq!(mesh.vertices.len(), 8);
+            assert_eq!(corners(&mesh), expected_corners());
+        }
+        assert!(parse_ply(&little[..little.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn parses_point_clouds() {
+        let xyz = "# x y z nx ny nz\n0 0 1 0 0 1\n\n1 2 3 -1 0 0\n";
+        let cloud = parse_xyz(xyz).unwrap();
+        assert_eq!(cloud.vertices.len(), 2);
+        assert_eq!(cloud.vertices[1].pos, [1.0, 2.0, 3.0]);
+        assert_eq!(cloud.vertices[1].normal, [-1.0, 0.0, 0.0]);
+        assert!(parse_xyz("0 0 1\n").is_err());
+
+        let ply = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                   property float y\nproperty float z\nproperty float nx\n\
+                   property float ny\nproperty float nz\nend_header\n\
+                   0 0 1 0 0 1\n1 2 3 -1 0 0\n";
+        let cloud = parse_ply(ply.as_bytes()).unwrap();
+        assert!(cloud.indices.is_empty());
+        assert_eq!(cloud.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(cloud.vertices[1].normal, [-1.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::shader;
+use crate::uniforms::UploadBelt;
+use glam::{Mat4, Vec3};
+use std::mem;
+use zerocopy::{AsBytes, FromBytes};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, AsBytes, FromBytes)]
+pub(crate) struct LineVertex {
+    pos: [f32; 3],
+    color: [f32; 4],
+}
+
+/// Line segments in world space, two vertices each.
+#[derive(Default)]
+pub(crate) struct Lines {
+    pub vertices: Vec<LineVertex>,
+}
+
+impl Lines {
+    pub fn push(&mut self, a: Vec3, b: Vec3, color: [f32; 4]) {
+        self.vertices.push(LineVertex {
+            pos: a.to_array(),
+            color,
+        });
+        self.vertices.push(LineVertex {
+            pos: b.to_array(),
+            color,
+        });
+    }
+
+    /// A closed polyline through `points`.
+    pub fn push_loop(&mut self, points: &[Vec3], color: [f32; 4]) {
+        for (i, &a) in points.iter().enumerate() {
+            self.push(a, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Segments, for tests.
+    #[cfg(test)]
+    pub fn segments(&self) -> Vec<(Vec3, Vec3)> {
+        self.vertices
+            .chunks_exact(2)
+            .map(|pair| (Vec3::from(pair[0].pos), Vec3::from(pair[1].pos)))
+            .collect()
+    }
+}
+
+/// Draws `Lines` as a line list, blended over the frame. The scene lines are
+/// hidden behind the surface, the overlay lines are drawn on top of
+/// everything.
+pub(crate) struct LineRenderer {
+    scene_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+    globals_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Grown to fit the lines of a frame, the scene lines first.
+    vertex_buf: wgpu::Buffer,
+    vertex_capacity: usize,
+    scene_count: u32,
+    overlay_count: u32,
+}
+
+fn create_vertex_buf(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("lines"),
+        size: (capacity * mem::size_of::<LineVertex>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl LineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+    ) -> LineRenderer {
+        let globals_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("line globals"),
+            size: mem::size_of::<Mat4>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: None,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: globals_buf.as_entire_binding(),
+            }],
+            label: None,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (vs_module, fs_module) = shader::compile(
+            device,
+            include_str!("shaders/lines.vert"),
+            include_str!("shaders/tri.frag"),
+        );
+        let create_pipeline = |depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("lines"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &vs_module,
+                    entry_point: "main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &[
+                            wgpu::VertexAttribute {
+                                format: wgpu::VertexFormat::Float32x3,
+                                offset: 0,
+                                shader_location: 0,
+                            },
+                            wgpu::VertexAttribute {
+                                format: wgpu::VertexFormat::Float32x4,
+                                offset: 3 * mem::size_of::<f32>() as u64,
+                                shader_location: 1,
+                            },
+                        ],
+                    }],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..wgpu::PrimitiveState::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: depth_format,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &fs_module,
+                    entry_point: "main",
+                    targets: &[wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                multiview: None,
+            })
+        };
+
+        let vertex_capacity = 1024;
+        LineRenderer {
+            scene_pipeline: create_pipeline(wgpu::CompareFunction::LessEqual),
+            overlay_pipeline: create_pipeline(wgpu::CompareFunction::Always),
+            globals_buf,
+            bind_group,
+            vertex_buf: create_vertex_buf(device, vertex_capacity),
+            vertex_capacity,
+            scene_count: 0,
+            overlay_count: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        (mem::size_of::<Mat4>() + self.vertex_capacity * mem::size_of::<LineVertex>()) as u64
+    }
+
+    /// Uploads the lines of this frame through `uploads`, growing the vertex
+    /// buffer when they don't fit.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        uploads: &mut UploadBelt,
+        encoder: &mut wgpu::CommandEncoder,
+        view_proj: Mat4,
+        scene: &Lines,
+        overlay: &Lines,
+    ) {
+        let count = scene.vertices.len() + overlay.vertices.len();
+        if count > self.vertex_capacity {
+            self.vertex_capacity = count.next_power_of_two();
+            self.vertex_buf = create_vertex_buf(device, self.vertex_capacity);
+        }
+        let globals = view_proj.to_cols_array();
+        uploads.write(device, encoder, &self.globals_buf, 0, globals.as_bytes());
+        uploads.write(
+            device,
+            encoder,
+            &self.vertex_buf,
+            0,
+            scene.vertices.as_bytes(),
+        );
+        uploads.write(
+            device,
+            encoder,
+            &self.vertex_buf,
+            scene.vertices.as_bytes().len() as u64,
+            overlay.vertices.as_bytes(),
+        );
+        self.scene_count = scene.vertices.len() as u32;
+        self.overlay_count = overlay.vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        if self.scene_count + self.overlay_count == 0 {
+            return;
+        }
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buf.slice(..));
+        let scene_end = self.scene_count;
+        if scene_end > 0 {
+            pass.set_pipeline(&self.scene_pipeline);
+            pass.draw(0..scene_end, 0..1);
+        }
+        if self.overlay_count > 0 {
+            pass.set_pipeline(&self.overlay_pipeline);
+            pass.draw(scene_end..scene_end + self.overlay_count, 0..1);
+        }
+    }
+}
@@ -1,23 +1,193 @@
 use std::iter;
 
 use crate::app::App;
+use crate::bricks::BrickAtlas;
+use crate::project::Project;
+use crate::render::{Motion, RenderSettings};
 use egui_wgpu_backend::{RenderPass, ScreenDescriptor};
-use glam::{uvec3, UVec3, Vec3};
-use wgpu::{util::DeviceExt, Extent3d};
-use winit::{event::Event, event_loop::ControlFlow};
+use glam::UVec3;
+use winit::event::{ElementState, Event, KeyboardInput, ModifiersState, VirtualKeyCode};
+use winit::event_loop::ControlFlow;
 mod app;
+mod automaton;
+mod bricks;
+mod camera;
+mod chunk;
+mod contour;
+mod extract;
+mod fluid;
+mod gizmo;
+mod history;
+mod import;
+mod lines;
+mod measure;
+mod mesh;
+mod outliner;
+mod pick;
+mod postprocess;
+mod profiler;
+mod project;
+mod reaction;
+mod readback;
+mod reconstruct;
+mod render;
+mod scene;
+mod sculpt;
+mod sdf;
 mod shader;
+mod slice_view;
+mod stats;
+mod timeline;
+mod uniforms;
+mod volume;
+mod volume_file;
 
 const INITIAL_WIDTH: u32 = 1920;
 const INITIAL_HEIGHT: u32 = 1080;
+/// 64 cells along each axis, a whole number of bricks.
+const DEFAULT_GRID: u32 = 65;
 
-#[derive(Default, Copy, Clone)]
-struct Vertex {
-    pos: Vec3,
-    normal: Vec3,
+/// The path after `--open`, if given.
+fn open_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--open" {
+            return args.next();
+        }
+    }
+    None
+}
+
+/// The grid of the opened project, or the default one when there's none or
+/// the device can't hold it.
+fn grid_size(project: Option<&(String, Project)>, device: &wgpu::Device) -> UVec3 {
+    let default = UVec3::splat(DEFAULT_GRID);
+    let (path, project) = match project {
+        Some(project) => project,
+        None => return default,
+    };
+    let grid = project.grid;
+    let samples = grid.x as u64 * grid.y as u64 * grid.z as u64;
+    if grid.cmpge(UVec3::splat(2)).all()
+        && samples <= volume_file::MAX_SAMPLES
+        && BrickAtlas::holds(&device.limits(), grid)
+    {
+        grid
+    } else {
+        eprintln!(
+            "Failed to use the {}x{}x{} grid of {}: it needs 2 samples along each axis \
+             and at most {} in all, in bricks the device can hold",
+            grid.x,
+            grid.y,
+            grid.z,
+            path,
+            volume_file::MAX_SAMPLES
+        );
+        default
+    }
+}
+
+/// Settings of an animation to render without a window, given `--render
+/// <dir>` and optionally `--frames <n>`, `--fps <n>`, `--size <w>x<h>`,
+/// `--timeline` instead of the turntable and `--no-ffmpeg`.
+fn render_args() -> Result<Option<RenderSettings>, String> {
+    let mut settings = RenderSettings::default();
+    let mut render = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| {
+            args.next()
+                .and_then(|value| value.parse::<u32>().ok())
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("{} needs a positive number", name))
+        };
+        match arg.as_str() {
+            "--render" => {
+                render = true;
+                settings.dir = args.next().ok_or("--render needs a directory")?;
+            }
+            "--frames" => settings.frames = number("--frames")?,
+            "--fps" => settings.fps = number("--fps")?,
+            "--size" => {
+                let size = args.next().unwrap_or_default();
+                let (width, height) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| w > 0 && h > 0)
+                    .ok_or("--size needs <width>x<height>")?;
+                settings.width = width;
+                settings.height = height;
+            }
+            "--timeline" => settings.motion = Motion::Timeline,
+            "--no-ffmpeg" => settings.ffmpeg = false,
+            _ => {}
+        }
+    }
+    Ok(render.then_some(settings))
+}
+
+/// Renders an animation of the project given with `--open`, or of the
+/// default field, without opening a window.
+fn render_headless(settings: &RenderSettings) {
+    let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+    let adapter = match pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    })) {
+        Some(adapter) => adapter,
+        None => {
+            eprintln!("Failed to render {}: no graphics adapter", settings.dir);
+            return;
+        }
+    };
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+            limits: wgpu::Limits::default(),
+            label: None,
+        },
+        None,
+    ))
+    .unwrap();
+
+    let project = open_arg().and_then(|path| match Project::read(&path) {
+        Ok(project) => Some((path, project)),
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", path, e);
+            None
+        }
+    });
+    let texture_size = grid_size(project.as_ref(), &device);
+    let mut app = App::new(
+        &device,
+        &wgpu::TextureFormat::Rgba8UnormSrgb,
+        settings.width,
+        settings.height,
+        texture_size,
+        adapter.get_info(),
+        wgpu::PresentMode::Fifo,
+    );
+    if let Some((path, project)) = project {
+        if let Err(e) = app.load_project(&path, project) {
+            eprintln!("Failed to open {}: {}", path, e);
+        }
+    }
+    if let Err(e) = app.render_animation(&device, &queue, settings) {
+        eprintln!("Failed to render {}: {}", settings.dir, e);
+    }
 }
 
 fn main() {
+    match render_args() {
+        Ok(Some(settings)) => return render_headless(&settings),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
         .with_decorations(true)
@@ -43,7 +213,8 @@ fn main() {
 
     let (device, queue) = pollster::block_on(adapter.request_device(
         &wgpu::DeviceDescriptor {
-            features: wgpu::Features::default(),
+            // Timestamps for the profiler, when the adapter has them.
+            features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
             limits: wgpu::Limits::default(),
             label: None,
         },
@@ -56,8 +227,8 @@ fn main() {
     let mut surface_config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
         format: surface_format,
-        width: size.width as u32,
-        height: size.height as u32,
+        width: size.width,
+        height: size.height,
         present_mode: wgpu::PresentMode::Fifo,
     };
     surface.configure(&device, &surface_config);
@@ -72,16 +243,36 @@ fn main() {
         ..Default::default()
     });
 
-    let texture_size = uvec3(200u32, 200u32, 2u32);//uvec3(size.width, size.height, 2u32);
+    let project = open_arg().and_then(|path| match Project::read(&path) {
+        Ok(project) => Some((path, project)),
+        Err(e) => {
+            eprintln!("Failed to open {}: {}", path, e);
+            None
+        }
+    });
+    let texture_size = grid_size(project.as_ref(), &device);
     let mut state = egui_winit::State::new(4096, &window);
     let context = egui::Context::default();
 
     let mut egui_rpass = RenderPass::new(&device, surface_format, 1);
 
-    let mut app = App::new(&device, &surface_format, texture_size);
+    let mut app = App::new(
+        &device,
+        &surface_format,
+        surface_config.width,
+        surface_config.height,
+        texture_size,
+        adapter.get_info(),
+        surface_config.present_mode,
+    );
+
+    if let Some((path, project)) = project {
+        if let Err(e) = app.load_project(&path, project) {
+            eprintln!("Failed to open {}: {}", path, e);
+        }
+    }
 
-    let mut encoder =
-        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    let mut modifiers = ModifiersState::empty();
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -111,11 +302,14 @@ fn main() {
                 let output = context.end_frame();
                 let paint_jobs = context.tessellate(output.shapes);
 
+                app.extract(&device, &queue);
+                app.render_if_requested(&device, &queue);
+
                 let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
                     label: Some("encoder"),
                 });
 
-                app.draw(&device, &queue, &output_view, &mut encoder);
+                app.draw(&device, &output_view, &mut encoder);
 
                 let screen_descriptor = ScreenDescriptor {
                     physical_width: surface_config.width,
@@ -129,6 +323,7 @@ fn main() {
                 egui_rpass.remove_textures(output.textures_delta).unwrap();
                 egui_rpass.update_buffers(&device, &queue, &paint_jobs, &screen_descriptor);
 
+                app.profiler().begin(&mut encoder, "egui");
                 egui_rpass
                     .execute(
                         &mut encoder,
@@ -138,8 +333,11 @@ fn main() {
                         None,
                     )
                     .unwrap();
+                app.profiler().end(&mut encoder);
+                app.profiler().resolve(&mut encoder);
 
                 queue.submit(iter::once(encoder.finish()));
+                app.end_frame(&device, &queue);
 
                 output_frame.present();
             }
@@ -152,11 +350,44 @@ fn main() {
                         surface_config.width = size.width;
                         surface_config.height = size.height;
                         surface.configure(&device, &surface_config);
+                        app.resize(&device, size.width, size.height);
                     }
                 }
                 winit::event::WindowEvent::CloseRequested => {
                     *control_flow = ControlFlow::Exit;
                 }
+                winit::event::WindowEvent::ModifiersChanged(new_modifiers) => {
+                    modifiers = new_modifiers;
+                    // egui tracks Ctrl and Shift for its text fields from this event only.
+                    state.on_event(&context, &event);
+                }
+                winit::event::WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Z),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && !context.wants_keyboard_input() => {
+                    if modifiers.shift() {
+                        app.redo();
+                    } else {
+                        app.undo();
+                    }
+                }
+                winit::event::WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F3),
+                            ..
+                        },
+                    ..
+                } if !context.wants_keyboard_input() => {
+                    app.toggle_stats();
+                    state.on_event(&context, &event);
+                }
                 event => {
                     state.on_event(&context, &event);
                 }
@@ -0,0 +1,216 @@
+use crate::mesh::Mesh;
+use glam::Vec3;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    pub fn empty() -> Bounds {
+        Bounds {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn grow(&mut self, p: Vec3) {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn size(&self) -> Vec3 {
+        (self.max - self.min).max(Vec3::ZERO)
+    }
+}
+
+/// A set of triangles connected through shared vertices.
+pub(crate) struct Component {
+    pub area: f32,
+    /// Signed, positive for closed surfaces wound counter-clockwise seen from
+    /// outside. Meaningless for surfaces cut open by the grid border.
+    pub volume: f32,
+    pub bounds: Bounds,
+    /// Indices of the triangles in the mesh.
+    pub triangles: Vec<u32>,
+}
+
+pub(crate) struct Measurements {
+    pub area: f32,
+    pub volume: f32,
+    pub bounds: Bounds,
+    /// Largest area first.
+    pub components: Vec<Component>,
+}
+
+fn find(parents: &mut [u32], mut i: u32) -> u32 {
+    while parents[i as usize] != i {
+        let parent = parents[i as usize];
+        parents[i as usize] = parents[parent as usize];
+        i = parent;
+    }
+    i
+}
+
+/// Measures `mesh`. Vertices at the same position are treated as one, the
+/// meshes read back from bricks duplicate the vertices on their faces.
+pub(crate) fn measure(mesh: &Mesh) -> Measurements {
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let vertex_ids: Vec<u32> = mesh
+        .vertices
+        .iter()
+        .map(|v| {
+            let key = v.pos.map(f32::to_bits);
+            let next = welded.len() as u32;
+            *welded.entry(key).or_insert(next)
+        })
+        .collect();
+
+    let mut parents: Vec<u32> = (0..welded.len() as u32).collect();
+    for tri in mesh.indices.chunks_exact(3) {
+        let a = find(&mut parents, vertex_ids[tri[0] as usize]);
+        for &i in &tri[1..] {
+            let b = find(&mut parents, vertex_ids[i as usize]);
+            parents[b as usize] = a;
+        }
+    }
+
+    let mut roots: HashMap<u32, usize> = HashMap::new();
+    let mut components: Vec<Component> = Vec::new();
+    for (t, tri) in mesh.indices.chunks_exact(3).enumerate() {
+        let root = find(&mut parents, vertex_ids[tri[0] as usize]);
+        let index = *roots.entry(root).or_insert_with(|| {
+            components.push(Component {
+                area: 0.0,
+                volume: 0.0,
+                bounds: Bounds::empty(),
+                triangles: Vec::new(),
+            });
+            components.len() - 1
+        });
+        let component = &mut components[index];
+
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vec3::from(mesh.vertices[i as usize].pos));
+        component.area += 0.5 * (b - a).cross(c - a).length();
+        component.volume += a.dot(b.cross(c)) / 6.0;
+        for p in [a, b, c] {
+            component.bounds.grow(p);
+        }
+        component.triangles.push(t as u32);
+    }
+    components.sort_by(|a, b| b.area.total_cmp(&a.area));
+
+    let mut bounds = Bounds::empty();
+    for component in &components {
+        bounds.grow(component.bounds.min);
+        bounds.grow(component.bounds.max);
+    }
+    Measurements {
+        area: components.iter().map(|c| c.area).sum(),
+        volume: components.iter().map(|c| c.volume).sum(),
+        bounds,
+        components,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Extractor;
+    use crate::volume::Volume;
+    use glam::UVec3;
+    use std::f32::consts::PI;
+
+    /// `actual` is within `tolerance` times `expected` of it.
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance * expected.abs(),
+            "{} is not within {} of {} relatively",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn unit_cube() {
+        let mut mesh = Mesh::default();
+        mesh.push_cuboid(Vec3::ZERO, Vec3::ONE);
+        let m = measure(&mesh);
+        assert_close(m.area, 6.0, 1e-6);
+        assert_close(m.volume, 1.0, 1e-6);
+        assert_eq!(
+            m.bounds,
+            Bounds {
+                min: Vec3::ZERO,
+                max: Vec3::ONE
+            }
+        );
+        assert_eq!(m.components.len(), 1);
+    }
+
+    #[test]
+    fn flipped_cube_has_negative_volume() {
+        let mut mesh = Mesh::default();
+        mesh.push_cuboid(Vec3::ZERO, Vec3::splat(2.0));
+        for tri in mesh.indices.chunks_exact_mut(3) {
+            tri.swap(1, 2);
+        }
+        assert_close(measure(&mesh).volume, -8.0, 1e-6);
+    }
+
+    #[test]
+    fn separate_boxes_are_separate_components() {
+        let mut mesh = Mesh::default();
+        mesh.push_cuboid(Vec3::ZERO, Vec3::ONE);
+        mesh.push_cuboid(Vec3::splat(3.0), Vec3::new(5.0, 4.0, 4.0));
+        let m = measure(&mesh);
+        assert_eq!(m.components.len(), 2);
+        // Largest first.
+        assert_close(m.components[0].area, 10.0, 1e-6);
+        assert_close(m.components[0].volume, 2.0, 1e-6);
+        assert_close(m.components[1].area, 6.0, 1e-6);
+        assert_close(m.components[1].volume, 1.0, 1e-6);
+        assert_eq!(m.components[0].triangles.len(), 12);
+        assert_close(m.volume, 3.0, 1e-6);
+        assert_eq!(m.bounds.min, Vec3::ZERO);
+        assert_eq!(m.bounds.max, Vec3::new(5.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn duplicated_vertices_are_welded() {
+        let mut mesh = Mesh::default();
+        mesh.push_cuboid(Vec3::ZERO, Vec3::ONE);
+        // Give every triangle its own vertices, like bricks do on their faces.
+        let indices = std::mem::take(&mut mesh.indices);
+        let vertices = std::mem::take(&mut mesh.vertices);
+        for i in indices {
+            mesh.indices.push(mesh.vertices.len() as u32);
+            mesh.vertices.push(vertices[i as usize]);
+        }
+        assert_eq!(measure(&mesh).components.len(), 1);
+    }
+
+    #[test]
+    fn extracted_spheres() {
+        let size = UVec3::splat(48);
+        // Two spheres of radius 0.2 and 0.1 in the unit cube.
+        let volume = Volume::from_fn(size, |p| {
+            let a = (p - Vec3::new(0.3, 0.5, 0.5)).length() - 0.2;
+            let b = (p - Vec3::new(0.75, 0.5, 0.5)).length() - 0.1;
+            a.min(b)
+        });
+        for extractor in Extractor::ALL {
+            let m = measure(&extractor.extract(&volume, 0.0));
+            assert_eq!(m.components.len(), 2, "{}", extractor.name());
+            for (component, r) in m.components.iter().zip([0.2f32, 0.1]) {
+                // Surface nets round the small sphere off the most.
+                assert_close(component.area, 4.0 * PI * r * r, 0.03);
+                assert_close(component.volume, 4.0 / 3.0 * PI * r * r * r, 0.06);
+                assert_close(component.bounds.size().x, 2.0 * r, 0.015);
+            }
+        }
+    }
+}
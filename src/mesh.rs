@@ -0,0 +1,119 @@
+use glam::{UVec3, Vec3};
+use std::fmt::Write;
+use std::path::Path;
+use std::{fs, io};
+use zerocopy::{AsBytes, FromBytes};
+
+/// Vertex layout shared by the CPU extractors, the extraction compute shaders
+/// and `cs_vertex_buf`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, AsBytes, FromBytes)]
+pub(crate) struct MeshVertex {
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl MeshVertex {
+    pub fn new(pos: Vec3, normal: Vec3) -> MeshVertex {
+        MeshVertex {
+            pos: pos.to_array(),
+            normal: normal.to_array(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Faces of a cube whose corner `i` has bit 0 of `i` for x, bit 1 for y and
+/// bit 2 for z, wound counter-clockwise seen from outside.
+#[cfg(test)]
+pub(crate) const CUBE_QUADS: [[u32; 4]; 6] = [
+    [0, 2, 3, 1],
+    [4, 5, 7, 6],
+    [0, 1, 5, 4],
+    [2, 6, 7, 3],
+    [0, 4, 6, 2],
+    [1, 3, 7, 5],
+];
+
+#[cfg(test)]
+impl Mesh {
+    /// Adds an axis aligned box with the corners and faces of `CUBE_QUADS`.
+    pub fn push_cuboid(&mut self, min: Vec3, max: Vec3) {
+        let base = self.vertices.len() as u32;
+        for i in 0..8 {
+            let pick = |bit: u32, lo: f32, hi: f32| if i & bit != 0 { hi } else { lo };
+            let p = Vec3::new(
+                pick(1, min.x, max.x),
+                pick(2, min.y, max.y),
+                pick(4, min.z, max.z),
+            );
+            self.vertices.push(MeshVertex::new(p, Vec3::ZERO));
+        }
+        for [a, b, c, d] in CUBE_QUADS {
+            self.indices.extend([a, b, c, a, c, d].map(|i| base + i));
+        }
+    }
+
+    pub fn cuboid(min: Vec3, max: Vec3) -> Mesh {
+        let mut mesh = Mesh::default();
+        mesh.push_cuboid(min, max);
+        mesh
+    }
+}
+
+/// Maps grid coordinates, where sample (x, y, z) sits at (x, y, z), into the
+/// unit cube centered on the origin that the meshes are drawn in.
+pub(crate) fn grid_to_object(size: UVec3, p: Vec3) -> Vec3 {
+    (p + 0.5) / size.as_vec3() - 0.5
+}
+
+/// Object space position `p` in grid samples, the inverse of
+/// `grid_to_object`.
+pub(crate) fn object_to_grid(size: UVec3, p: Vec3) -> Vec3 {
+    (p + 0.5) * size.as_vec3() - 0.5
+}
+
+/// Same as `grid_to_object` for a field gradient taken in grid coordinates.
+pub(crate) fn gradient_to_normal(size: UVec3, gradient: Vec3) -> Vec3 {
+    (gradient * size.as_vec3()).normalize_or_zero()
+}
+
+/// Writes `mesh` as a Wavefront OBJ document with positions and normals.
+pub(crate) fn to_obj(mesh: &Mesh) -> String {
+    let mut obj = String::new();
+    for v in &mesh.vertices {
+        let _ = writeln!(obj, "v {} {} {}", v.pos[0], v.pos[1], v.pos[2]);
+    }
+    for v in &mesh.vertices {
+        let _ = writeln!(obj, "vn {} {} {}", v.normal[0], v.normal[1], v.normal[2]);
+    }
+    // OBJ indices start at 1.
+    for tri in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+        let _ = writeln!(obj, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c);
+    }
+    obj
+}
+
+pub(crate) fn write_obj(path: impl AsRef<Path>, mesh: &Mesh) -> io::Result<()> {
+    fs::write(path, to_obj(mesh))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec3;
+
+    #[test]
+    fn object_to_grid_inverts_grid_to_object() {
+        let size = uvec3(8, 16, 32);
+        let p = Vec3::new(3.0, 0.5, 31.0);
+        let q = object_to_grid(size, grid_to_object(size, p));
+        assert!(q.abs_diff_eq(p, 1e-5));
+    }
+}
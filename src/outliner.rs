@@ -0,0 +1,159 @@
+use crate::scene::{MaterialHandle, MeshHandle, Node, NodeId, Scene, Transform};
+use egui::{Context, Ui};
+use glam::Vec3;
+
+/// Triangles along each side of the grid "Add triangle grid" adds.
+const TRIANGLE_GRID: u32 = 8;
+
+/// Egui window listing the scene as a tree, for selecting, renaming, hiding
+/// and transforming nodes.
+pub(crate) struct Outliner {
+    selected: Option<NodeId>,
+}
+
+fn drag_vec3(ui: &mut Ui, label: &str, v: &mut Vec3, speed: f32) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        for (axis, value) in ["x: ", "y: ", "z: "].into_iter().zip(v.as_mut()) {
+            ui.add(egui::DragValue::new(value).speed(speed).prefix(axis));
+        }
+    });
+}
+
+impl Outliner {
+    pub fn new() -> Outliner {
+        Outliner { selected: None }
+    }
+
+    pub fn selected(&self) -> Option<NodeId> {
+        self.selected
+    }
+
+    fn tree(&mut self, ui: &mut Ui, scene: &mut Scene, id: NodeId, depth: usize) {
+        ui.horizontal(|ui| {
+            ui.add_space(16.0 * depth as f32);
+            let node = scene.node_mut(id);
+            ui.checkbox(&mut node.visible, "");
+            let label = match node.mesh {
+                Some(mesh) => format!("{} ({})", node.name, mesh.name()),
+                None => node.name.clone(),
+            };
+            if ui
+                .selectable_label(self.selected == Some(id), label)
+                .clicked()
+            {
+                self.selected = Some(id);
+            }
+        });
+        for child in scene.node(id).children().to_vec() {
+            self.tree(ui, scene, child, depth + 1);
+        }
+    }
+
+    fn add(&mut self, scene: &mut Scene, node: Node) {
+        self.selected = Some(scene.add(self.selected, node));
+    }
+
+    pub fn ui(&mut self, context: &Context, scene: &mut Scene) {
+        if let Some(selected) = self.selected {
+            if !scene.contains(selected) {
+                self.selected = None;
+            }
+        }
+
+        egui::Window::new("Outliner").show(context, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Add group").clicked() {
+                    self.add(scene, Node::new("Group", None));
+                }
+                for mesh in MeshHandle::ALL {
+                    if ui.button(format!("Add {}", mesh.name())).clicked() {
+                        self.add(scene, Node::new(mesh.name(), Some(mesh)));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Add triangle grid").clicked() {
+                    let parent = self.selected;
+                    let group = scene.add(parent, Node::new("Triangle grid", None));
+                    let cell = 1.0 / TRIANGLE_GRID as f32;
+                    for i in 0..TRIANGLE_GRID * TRIANGLE_GRID {
+                        let (x, y) = (i % TRIANGLE_GRID, i / TRIANGLE_GRID);
+                        let transform = Transform {
+                            translation: Vec3::new(
+                                cell * (x as f32 + 0.5) - 0.5,
+                                cell * (y as f32 + 0.5) - 0.5,
+                                0.0,
+                            ),
+                            scale: Vec3::splat(cell),
+                            ..Transform::IDENTITY
+                        };
+                        let material = MaterialHandle(i as usize % scene.materials.len().max(1));
+                        scene.add(
+                            Some(group),
+                            Node::new(&format!("Triangle {}", i), Some(MeshHandle::Triangle))
+                                .with_transform(transform)
+                                .with_material(material),
+                        );
+                    }
+                    self.selected = Some(group);
+                }
+                if ui
+                    .add_enabled(self.selected.is_some(), egui::Button::new("Delete"))
+                    .clicked()
+                {
+                    scene.remove(self.selected.take().unwrap());
+                }
+            });
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(240.0)
+                .show(ui, |ui| {
+                    for root in scene.roots().to_vec() {
+                        self.tree(ui, scene, root, 0);
+                    }
+                });
+
+            let selected = match self.selected {
+                Some(selected) => selected,
+                None => return,
+            };
+            ui.separator();
+            let world = scene.world_transform(selected).transform_point3(Vec3::ZERO);
+            let parent = scene.node(selected).parent();
+            ui.horizontal(|ui| {
+                ui.label(format!(
+                    "World position: {:.2} {:.2} {:.2}",
+                    world.x, world.y, world.z
+                ));
+                if ui
+                    .add_enabled(parent.is_some(), egui::Button::new("Select parent"))
+                    .clicked()
+                {
+                    self.selected = parent;
+                }
+            });
+            let materials: Vec<String> = scene.materials.iter().map(|m| m.name.clone()).collect();
+            let node = scene.node_mut(selected);
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut node.name);
+            });
+            if node.mesh.is_some() {
+                let current = materials.get(node.material.0).cloned().unwrap_or_default();
+                egui::ComboBox::from_label("Material")
+                    .selected_text(current)
+                    .show_ui(ui, |ui| {
+                        for (i, name) in materials.iter().enumerate() {
+                            ui.selectable_value(&mut node.material, MaterialHandle(i), name);
+                        }
+                    });
+            }
+            let transform = &mut node.transform;
+            drag_vec3(ui, "Translation", &mut transform.translation, 0.01);
+            drag_vec3(ui, "Rotation", &mut transform.rotation, 1.0);
+            drag_vec3(ui, "Scale", &mut transform.scale, 0.01);
+        });
+    }
+}
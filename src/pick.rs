@@ -0,0 +1,387 @@
+use crate::bricks::BrickAtlas;
+use crate::shader;
+use crate::volume::Volume;
+use glam::{Mat4, Vec2, Vec3};
+use std::{iter, mem};
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    /// Ray through `ndc`, in normalized device coordinates, from the near to
+    /// the far plane of `view_proj`.
+    pub fn from_ndc(view_proj: Mat4, ndc: Vec2) -> Ray {
+        let inv_view_proj = view_proj.inverse();
+        let near = inv_view_proj.project_point3(ndc.extend(0.0));
+        let far = inv_view_proj.project_point3(ndc.extend(1.0));
+        Ray {
+            origin: near,
+            dir: (far - near).normalize(),
+        }
+    }
+}
+
+/// Where a ray first enters the inside of the field, in the space of the ray.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Pick {
+    pub pos: Vec3,
+    pub normal: Vec3,
+    /// Interpolated field value at `pos`, the iso value up to the bisection
+    /// error.
+    pub value: f32,
+}
+
+impl Pick {
+    /// The hit moved by `transform`, its normal by the inverse transpose so it
+    /// stays perpendicular to the surface under non-uniform scale.
+    pub fn transformed(self, transform: Mat4) -> Pick {
+        Pick {
+            pos: transform.transform_point3(self.pos),
+            normal: transform
+                .inverse()
+                .transpose()
+                .transform_vector3(self.normal)
+                .normalize_or_zero(),
+            value: self.value,
+        }
+    }
+}
+
+/// Marches `ray` through `volume`, stepping half a sample and bisecting the
+/// crossing. Kept in sync with `shaders/pick.wgsl`.
+pub(crate) fn cast_ray(volume: &Volume, iso: f32, ray: Ray) -> Option<Pick> {
+    let size = volume.size.as_vec3();
+    let to_grid = |p: Vec3| (p + 0.5) * size - 0.5;
+    let value = |t: f32| volume.sample(to_grid(ray.origin + ray.dir * t));
+
+    // Clip the ray to the unit cube the volume is drawn in.
+    let inv = ray.dir.recip();
+    let t0 = (Vec3::splat(-0.5) - ray.origin) * inv;
+    let t1 = (Vec3::splat(0.5) - ray.origin) * inv;
+    let (near, far) = (t0.min(t1).max_element().max(0.0), t0.max(t1).min_element());
+    if near >= far {
+        return None;
+    }
+
+    let step = 0.5 / size.max_element();
+    let mut t = near;
+    while t < far {
+        let next = (t + step).min(far);
+        if value(next) < iso {
+            // Bisect down to the crossing.
+            let (mut lo, mut hi) = (t, next);
+            for _ in 0..16 {
+                let mid = 0.5 * (lo + hi);
+                if value(mid) < iso {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            let pos = ray.origin + ray.dir * hi;
+            let p = to_grid(pos);
+            let gradient = Vec3::new(
+                volume.sample(p + Vec3::X) - volume.sample(p - Vec3::X),
+                volume.sample(p + Vec3::Y) - volume.sample(p - Vec3::Y),
+                volume.sample(p + Vec3::Z) - volume.sample(p - Vec3::Z),
+            );
+            return Some(Pick {
+                pos,
+                normal: (gradient * size).normalize_or_zero(),
+                value: volume.sample(p),
+            });
+        }
+        t = next;
+    }
+    None
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct RayUniforms {
+    origin: [f32; 3],
+    iso: f32,
+    dir: [f32; 3],
+    _pad: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct PickResult {
+    pos: [f32; 3],
+    hit: u32,
+    normal: [f32; 3],
+    value: f32,
+}
+
+/// Picks on the GPU by marching a single ray through the `BrickAtlas` and
+/// reading the result back.
+pub(crate) struct Picker {
+    ray_buf: wgpu::Buffer,
+    result_buf: wgpu::Buffer,
+    readback_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl Picker {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas) -> Picker {
+        let ray_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<RayUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let result_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<PickResult>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<PickResult>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &atlas
+                            .texture()
+                            .create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: ray_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: result_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: atlas.create_layout_buffer(device).as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/pick.wgsl")
+            ),
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("pick"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+
+        Picker {
+            ray_buf,
+            result_buf,
+            readback_buf,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        (mem::size_of::<RayUniforms>() + 2 * mem::size_of::<PickResult>()) as u64
+    }
+
+    /// Blocks until the ray was marched through the atlas.
+    pub fn pick(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        iso: f32,
+        ray: Ray,
+    ) -> Option<Pick> {
+        let uniforms = RayUniforms {
+            origin: ray.origin.to_array(),
+            iso,
+            dir: ray.dir.to_array(),
+            _pad: 0.0,
+        };
+        queue.write_buffer(&self.ray_buf, 0, uniforms.as_bytes());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("pick"),
+        });
+        {
+            let mut cs_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cs_pass.set_pipeline(&self.pipeline);
+            cs_pass.set_bind_group(0, &self.bind_group, &[]);
+            cs_pass.dispatch(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.result_buf,
+            0,
+            &self.readback_buf,
+            0,
+            mem::size_of::<PickResult>() as u64,
+        );
+        queue.submit(iter::once(encoder.finish()));
+
+        let slice = self.readback_buf.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).unwrap();
+        let result = PickResult::read_from(&slice.get_mapped_range()[..]).unwrap();
+        self.readback_buf.unmap();
+
+        (result.hit != 0).then(|| Pick {
+            pos: Vec3::from(result.pos),
+            normal: Vec3::from(result.normal),
+            value: result.value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume;
+    use glam::uvec3;
+
+    #[test]
+    fn rays_go_through_the_projected_point() {
+        let eye = Vec3::new(1.0, 2.0, 3.0);
+        let view = Mat4::look_at_rh(eye, Vec3::ZERO, Vec3::Y);
+        let view_proj = Mat4::perspective_rh(1.0, 1.5, 0.1, 100.0) * view;
+        let ray = Ray::from_ndc(view_proj, Vec2::ZERO);
+        assert!(ray.dir.abs_diff_eq(-eye.normalize(), 1e-5));
+        assert!((ray.origin - eye).cross(ray.dir).length() < 1e-4);
+
+        let p = Vec3::new(0.3, -0.2, 0.1);
+        let ray = Ray::from_ndc(view_proj, view_proj.project_point3(p).truncate());
+        assert!((ray.dir.length() - 1.0).abs() < 1e-6);
+        assert!((p - ray.origin).cross(ray.dir).length() < 1e-4);
+    }
+
+    #[test]
+    fn cast_rays_hit_and_miss_a_sphere() {
+        // Radius 0.3 around the origin of object space.
+        let volume = Volume::from_fn(uvec3(33, 33, 33), volume::sphere);
+        let ray = |origin: Vec3, dir: Vec3| Ray {
+            origin,
+            dir: dir.normalize(),
+        };
+
+        let hit = cast_ray(&volume, 0.0, ray(Vec3::new(0.0, 0.0, 2.0), -Vec3::Z)).unwrap();
+        assert!(
+            hit.pos.abs_diff_eq(Vec3::new(0.0, 0.0, 0.3), 2e-3),
+            "{}",
+            hit.pos
+        );
+        assert!(hit.normal.abs_diff_eq(Vec3::Z, 1e-2), "{}", hit.normal);
+        assert!(hit.value.abs() < 1e-3);
+
+        // Off center, and on a bigger iso surface.
+        let origin = Vec3::new(0.1, 0.2, 2.0);
+        let hit = cast_ray(&volume, 0.0, ray(origin, -Vec3::Z)).unwrap();
+        let expected = Vec3::new(0.1, 0.2, 0.2);
+        assert!(hit.pos.abs_diff_eq(expected, 2e-3), "{}", hit.pos);
+        assert!(
+            hit.normal.abs_diff_eq(expected / 0.3, 2e-2),
+            "{}",
+            hit.normal
+        );
+        let hit = cast_ray(&volume, 0.1, ray(Vec3::new(-2.0, 0.0, 0.0), Vec3::X)).unwrap();
+        assert!(
+            hit.pos.abs_diff_eq(Vec3::new(-0.4, 0.0, 0.0), 2e-3),
+            "{}",
+            hit.pos
+        );
+        assert!((hit.value - 0.1).abs() < 1e-3);
+
+        // Past the side, away from the sphere, and outside the cube.
+        assert!(cast_ray(&volume, 0.0, ray(Vec3::new(0.35, 0.0, 2.0), -Vec3::Z)).is_none());
+        assert!(cast_ray(&volume, 0.0, ray(Vec3::new(0.0, 0.0, 2.0), Vec3::Z)).is_none());
+        assert!(cast_ray(&volume, 0.0, ray(Vec3::new(0.0, 2.0, 2.0), -Vec3::Z)).is_none());
+    }
+
+    #[test]
+    fn transformed_normals_stay_perpendicular() {
+        let pick = Pick {
+            pos: Vec3::new(1.0, 1.0, 0.0),
+            normal: Vec3::new(1.0, 1.0, 0.0).normalize(),
+            value: 0.5,
+        };
+        let transform = Mat4::from_translation(Vec3::new(0.0, 0.0, 5.0))
+            * Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0));
+        let moved = pick.transformed(transform);
+
+        assert!(moved.pos.abs_diff_eq(Vec3::new(2.0, 1.0, 5.0), 1e-6));
+        // The tangent (1, -1, 0) stretches to (2, -1, 0).
+        assert!(moved.normal.dot(Vec3::new(2.0, -1.0, 0.0)).abs() < 1e-6);
+        assert!((moved.normal.length() - 1.0).abs() < 1e-6);
+        assert_eq!(moved.value, 0.5);
+    }
+}
@@ -0,0 +1,510 @@
+use crate::mesh::{Mesh, MeshVertex};
+use glam::{DMat3, DVec3, Vec3};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Steps applied to an extracted mesh before it is shown or exported, in
+/// order. Smoothing and decimation need shared vertices, so they weld first
+/// even when `weld` is off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PostProcess {
+    pub weld: bool,
+    pub smooth: bool,
+    pub smooth_iterations: u32,
+    pub decimate: bool,
+    pub target_triangles: usize,
+}
+
+impl Default for PostProcess {
+    fn default() -> PostProcess {
+        PostProcess {
+            weld: true,
+            smooth: false,
+            smooth_iterations: 10,
+            decimate: false,
+            target_triangles: 5000,
+        }
+    }
+}
+
+impl PostProcess {
+    pub fn is_identity(&self) -> bool {
+        !self.weld && !self.smooth && !self.decimate
+    }
+
+    pub fn apply(&self, mesh: &Mesh) -> Mesh {
+        if self.is_identity() {
+            return mesh.clone();
+        }
+        let mut mesh = weld(mesh);
+        if self.smooth {
+            taubin(&mut mesh, self.smooth_iterations);
+        }
+        if self.decimate {
+            mesh = decimate(&mesh, self.target_triangles);
+        }
+        mesh
+    }
+}
+
+/// Merges vertices at the same position into one with the averaged normal and
+/// drops the triangles that collapse.
+pub(crate) fn weld(mesh: &Mesh) -> Mesh {
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let remap: Vec<u32> = mesh
+        .vertices
+        .iter()
+        .map(|v| {
+            let next = positions.len() as u32;
+            let i = *welded.entry(v.pos.map(f32::to_bits)).or_insert(next);
+            if i == next {
+                positions.push(v.pos);
+                normals.push(Vec3::ZERO);
+            }
+            normals[i as usize] += Vec3::from(v.normal);
+            i
+        })
+        .collect();
+
+    Mesh {
+        vertices: positions
+            .iter()
+            .zip(normals)
+            .map(|(&pos, normal)| MeshVertex::new(Vec3::from(pos), normal.normalize_or_zero()))
+            .collect(),
+        indices: mesh
+            .indices
+            .chunks_exact(3)
+            .map(|tri| [tri[0], tri[1], tri[2]].map(|i| remap[i as usize]))
+            .filter(|&[a, b, c]| a != b && b != c && c != a)
+            .flatten()
+            .collect(),
+    }
+}
+
+/// Recomputes the normals from the area weighted normals of the triangles
+/// around each vertex.
+fn recompute_normals(mesh: &mut Mesh) {
+    let mut normals = vec![Vec3::ZERO; mesh.vertices.len()];
+    for tri in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| Vec3::from(mesh.vertices[i as usize].pos));
+        let normal = (b - a).cross(c - a);
+        for &i in tri {
+            normals[i as usize] += normal;
+        }
+    }
+    for (v, normal) in mesh.vertices.iter_mut().zip(normals) {
+        v.normal = normal.normalize_or_zero().to_array();
+    }
+}
+
+/// Edges used by a single triangle, on surfaces cut open by the grid border.
+fn boundary_edges(indices: &[u32]) -> HashSet<(u32, u32)> {
+    let mut edges = HashMap::new();
+    for tri in indices.chunks_exact(3) {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    edges
+        .into_iter()
+        .filter(|&(_, count)| count == 1)
+        .map(|(edge, _)| edge)
+        .collect()
+}
+
+/// Taubin smoothing, alternating a shrinking and an inflating Laplacian step
+/// so the surface doesn't lose volume. Boundary vertices stay in place. Needs
+/// a welded mesh.
+pub(crate) fn taubin(mesh: &mut Mesh, iterations: u32) {
+    const LAMBDA: f32 = 0.5;
+    const MU: f32 = -0.53;
+
+    let mut neighbours: Vec<Vec<u32>> = vec![Vec::new(); mesh.vertices.len()];
+    for tri in mesh.indices.chunks_exact(3) {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            for (from, to) in [(a, b), (b, a)] {
+                if !neighbours[from as usize].contains(&to) {
+                    neighbours[from as usize].push(to);
+                }
+            }
+        }
+    }
+    let mut fixed = vec![false; mesh.vertices.len()];
+    for (a, b) in boundary_edges(&mesh.indices) {
+        fixed[a as usize] = true;
+        fixed[b as usize] = true;
+    }
+
+    let mut positions: Vec<Vec3> = mesh.vertices.iter().map(|v| Vec3::from(v.pos)).collect();
+    for _ in 0..iterations {
+        for factor in [LAMBDA, MU] {
+            positions = positions
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| {
+                    if fixed[i] || neighbours[i].is_empty() {
+                        return p;
+                    }
+                    let sum = neighbours[i]
+                        .iter()
+                        .fold(Vec3::ZERO, |sum, &j| sum + positions[j as usize]);
+                    let average = sum / neighbours[i].len() as f32;
+                    p + (average - p) * factor
+                })
+                .collect();
+        }
+    }
+    for (v, p) in mesh.vertices.iter_mut().zip(positions) {
+        v.pos = p.to_array();
+    }
+    recompute_normals(mesh);
+}
+
+/// Symmetric 4x4 error quadric of Garland and Heckbert, the sum of the
+/// squared distances to a set of planes.
+#[derive(Clone, Copy)]
+struct Quadric {
+    a: DMat3,
+    b: DVec3,
+    c: f64,
+}
+
+impl Quadric {
+    // Not `Default`, that would start from the identity matrix.
+    const ZERO: Quadric = Quadric {
+        a: DMat3::ZERO,
+        b: DVec3::ZERO,
+        c: 0.0,
+    };
+
+    fn plane(normal: DVec3, d: f64, weight: f64) -> Quadric {
+        Quadric {
+            a: DMat3::from_cols(normal * normal.x, normal * normal.y, normal * normal.z) * weight,
+            b: normal * d * weight,
+            c: d * d * weight,
+        }
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        Quadric {
+            a: DMat3::from_cols(
+                self.a.x_axis + other.a.x_axis,
+                self.a.y_axis + other.a.y_axis,
+                self.a.z_axis + other.a.z_axis,
+            ),
+            b: self.b + other.b,
+            c: self.c + other.c,
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        p.dot(self.a * p) + 2.0 * self.b.dot(p) + self.c
+    }
+
+    /// Position with the least error, or the best of `a`, `b` and their
+    /// midpoint when the quadric is close to singular.
+    fn optimum(&self, a: DVec3, b: DVec3) -> DVec3 {
+        let trace = self.a.x_axis.x + self.a.y_axis.y + self.a.z_axis.z;
+        if self.a.determinant().abs() > 1e-9 * trace.powi(3) {
+            let p = -(self.a.inverse() * self.b);
+            // Far away optima come from nearly flat regions.
+            if p.distance(a) < 2.0 * a.distance(b) + 1e-6 {
+                return p;
+            }
+        }
+        [a, b, (a + b) * 0.5]
+            .into_iter()
+            .min_by(|&p, &q| self.error(p).total_cmp(&self.error(q)))
+            .unwrap()
+    }
+}
+
+struct Candidate {
+    cost: f64,
+    a: u32,
+    b: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    // Reversed, so the heap pops the cheapest collapse first.
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+/// Collapses edges in order of quadric error until at most `target_triangles`
+/// remain, or no collapse keeps the surface manifold without flipping a
+/// triangle. Boundary edges are held in place by perpendicular planes. Needs a
+/// welded mesh.
+pub(crate) fn decimate(mesh: &Mesh, target_triangles: usize) -> Mesh {
+    const BOUNDARY_WEIGHT: f64 = 1000.0;
+
+    let mut positions: Vec<DVec3> = mesh
+        .vertices
+        .iter()
+        .map(|v| Vec3::from(v.pos).as_dvec3())
+        .collect();
+    let mut triangles: Vec<[u32; 3]> = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|tri| [tri[0], tri[1], tri[2]])
+        .collect();
+    let mut alive = vec![true; triangles.len()];
+    let mut alive_count = triangles.len();
+    let mut incident: Vec<Vec<u32>> = vec![Vec::new(); positions.len()];
+    let mut quadrics = vec![Quadric::ZERO; positions.len()];
+    for (t, tri) in triangles.iter().enumerate() {
+        let [a, b, c] = tri.map(|i| positions[i as usize]);
+        let cross = (b - a).cross(c - a);
+        // Triangles without area still matter for the topology.
+        let normal = cross.normalize_or_zero();
+        let plane = Quadric::plane(normal, -normal.dot(a), 0.5 * cross.length());
+        for &i in tri {
+            quadrics[i as usize] = quadrics[i as usize].add(&plane);
+            incident[i as usize].push(t as u32);
+        }
+    }
+    let boundary = boundary_edges(&mesh.indices);
+    for tri in &triangles {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            if !boundary.contains(&(a.min(b), a.max(b))) {
+                continue;
+            }
+            let [pa, pb, pc] = [a, b, tri[(k + 2) % 3]].map(|i| positions[i as usize]);
+            let edge = pb - pa;
+            let normal = edge.cross((pb - pa).cross(pc - pa)).normalize_or_zero();
+            let plane = Quadric::plane(
+                normal,
+                -normal.dot(pa),
+                BOUNDARY_WEIGHT * edge.length_squared(),
+            );
+            quadrics[a as usize] = quadrics[a as usize].add(&plane);
+            quadrics[b as usize] = quadrics[b as usize].add(&plane);
+        }
+    }
+
+    let mut versions = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    let candidate =
+        |a: u32, b: u32, positions: &[DVec3], quadrics: &[Quadric], versions: &[u32]| {
+            let q = quadrics[a as usize].add(&quadrics[b as usize]);
+            let p = q.optimum(positions[a as usize], positions[b as usize]);
+            Candidate {
+                cost: q.error(p),
+                a,
+                b,
+                versions: (versions[a as usize], versions[b as usize]),
+            }
+        };
+    for tri in &triangles {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            if a < b {
+                heap.push(candidate(a, b, &positions, &quadrics, &versions));
+            }
+        }
+    }
+
+    let neighbours = |v: u32, triangles: &[[u32; 3]], incident: &[Vec<u32>]| {
+        let mut set: Vec<u32> = incident[v as usize]
+            .iter()
+            .flat_map(|&t| triangles[t as usize])
+            .filter(|&i| i != v)
+            .collect();
+        set.sort_unstable();
+        set.dedup();
+        set
+    };
+
+    while alive_count > target_triangles {
+        let Candidate {
+            a, b, versions: v, ..
+        } = match heap.pop() {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        if v != (versions[a as usize], versions[b as usize]) {
+            continue;
+        }
+        let shared: Vec<u32> = incident[a as usize]
+            .iter()
+            .copied()
+            .filter(|t| incident[b as usize].contains(t))
+            .collect();
+        if shared.is_empty() {
+            continue;
+        }
+
+        // Link condition, the collapse must not pinch the surface.
+        let na = neighbours(a, &triangles, &incident);
+        let nb = neighbours(b, &triangles, &incident);
+        let common = na.iter().filter(|i| nb.contains(i)).count();
+        if common != shared.len() {
+            continue;
+        }
+
+        let q = quadrics[a as usize].add(&quadrics[b as usize]);
+        let p = q.optimum(positions[a as usize], positions[b as usize]);
+
+        // Reject collapses that flip a remaining triangle.
+        let flips = [a, b].iter().any(|&v| {
+            incident[v as usize]
+                .iter()
+                .filter(|t| !shared.contains(t))
+                .any(|&t| {
+                    let tri = triangles[t as usize];
+                    let [pa, pb, pc] = tri.map(|i| positions[i as usize]);
+                    let moved = tri.map(|i| {
+                        if i == a || i == b {
+                            p
+                        } else {
+                            positions[i as usize]
+                        }
+                    });
+                    let before = (pb - pa).cross(pc - pa);
+                    let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+                    before != DVec3::ZERO && before.dot(after) <= 0.0
+                })
+        });
+        if flips {
+            continue;
+        }
+
+        // Collapse b into a.
+        for &t in &shared {
+            alive[t as usize] = false;
+            alive_count -= 1;
+            for i in triangles[t as usize] {
+                incident[i as usize].retain(|&u| u != t);
+            }
+        }
+        for t in std::mem::take(&mut incident[b as usize]) {
+            for i in &mut triangles[t as usize] {
+                if *i == b {
+                    *i = a;
+                }
+            }
+            incident[a as usize].push(t);
+        }
+        positions[a as usize] = p;
+        quadrics[a as usize] = q;
+        versions[a as usize] += 1;
+        versions[b as usize] += 1;
+
+        for n in neighbours(a, &triangles, &incident) {
+            let (x, y) = (a.min(n), a.max(n));
+            heap.push(candidate(x, y, &positions, &quadrics, &versions));
+        }
+    }
+
+    // Keep only the vertices still in use.
+    let mut remap = vec![u32::MAX; positions.len()];
+    let mut result = Mesh::default();
+    for (tri, _) in triangles.iter().zip(&alive).filter(|(_, &alive)| alive) {
+        for &i in tri {
+            if remap[i as usize] == u32::MAX {
+                remap[i as usize] = result.vertices.len() as u32;
+                result
+                    .vertices
+                    .push(MeshVertex::new(positions[i as usize].as_vec3(), Vec3::ZERO));
+            }
+            result.indices.push(remap[i as usize]);
+        }
+    }
+    recompute_normals(&mut result);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Extractor;
+    use crate::measure::measure;
+    use crate::volume::{self, Volume};
+    use glam::UVec3;
+
+    fn sphere_mesh() -> Mesh {
+        let volume = Volume::from_fn(UVec3::splat(40), volume::sphere);
+        Extractor::MarchingCubes.extract(&volume, 0.0)
+    }
+
+    /// Every edge is used once in each direction.
+    fn is_closed_manifold(mesh: &Mesh) -> bool {
+        let mut edges = HashMap::new();
+        for tri in mesh.indices.chunks_exact(3) {
+            for k in 0..3 {
+                *edges.entry((tri[k], tri[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1))
+    }
+
+    #[test]
+    fn weld_merges_duplicates() {
+        let mut mesh = sphere_mesh();
+        // Split every triangle off, like a per-cell extractor would.
+        let indices = std::mem::take(&mut mesh.indices);
+        let vertices = std::mem::take(&mut mesh.vertices);
+        for i in indices {
+            mesh.indices.push(mesh.vertices.len() as u32);
+            mesh.vertices.push(vertices[i as usize]);
+        }
+        let welded = weld(&mesh);
+        assert!(welded.vertices.len() <= vertices.len());
+        assert!(is_closed_manifold(&welded));
+    }
+
+    #[test]
+    fn taubin_keeps_volume() {
+        let mesh = weld(&sphere_mesh());
+        let mut smoothed = mesh.clone();
+        taubin(&mut smoothed, 20);
+        let (before, after) = (measure(&mesh).volume, measure(&smoothed).volume);
+        assert!(
+            (after - before).abs() < 0.02 * before,
+            "{} -> {}",
+            before,
+            after
+        );
+    }
+
+    #[test]
+    fn decimate_reaches_target() {
+        let mesh = weld(&sphere_mesh());
+        let target = mesh.indices.len() / 3 / 4;
+        let decimated = decimate(&mesh, target);
+        assert!(decimated.indices.len() / 3 <= target);
+        assert!(is_closed_manifold(&decimated));
+        let (before, after) = (measure(&mesh), measure(&decimated));
+        assert_eq!(after.components.len(), 1);
+        assert!(
+            (after.volume - before.volume).abs() < 0.02 * before.volume,
+            "{} -> {}",
+            before.volume,
+            after.volume
+        );
+    }
+}
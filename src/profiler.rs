@@ -0,0 +1,296 @@
+use egui::plot::{Legend, Line, Plot, Value, Values};
+use egui::Context;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{self, Poll, Waker};
+use std::time::Instant;
+
+/// Number of frames the graph shows.
+const HISTORY_FRAMES: usize = 240;
+/// Most scopes timed in one frame, the rest are skipped.
+const MAX_SCOPES: u32 = 16;
+/// Frames whose timestamps can be on their way back at once. Frames that find
+/// all of them busy aren't timed on the GPU.
+const READBACK_FRAMES: usize = 3;
+const TIMESTAMP_SIZE: u64 = wgpu::QUERY_SIZE as u64;
+
+type MapFuture = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+struct Readback {
+    buf: wgpu::Buffer,
+    frame: u64,
+    // Names of the scopes resolved into `buf`, empty when it's free.
+    scopes: Vec<&'static str>,
+    mapping: Option<MapFuture>,
+}
+
+/// The timestamp queries, only there when the device supports them.
+struct Queries {
+    query_set: wgpu::QuerySet,
+    readbacks: Vec<Readback>,
+    // Readback the current frame resolves into, none when all are busy.
+    current: Option<usize>,
+    // Scopes of the current frame, query `2 * i` is the start of scope `i`
+    // and `2 * i + 1` its end.
+    scopes: Vec<&'static str>,
+    // Scopes begun but not ended yet, none for the skipped ones.
+    open: Vec<Option<u32>>,
+}
+
+/// GPU time of the passes measured with timestamp queries, read back a few
+/// frames later without stalling, next to the CPU frame time. Without
+/// `Features::TIMESTAMP_QUERY` only the CPU frame time is measured.
+pub(crate) struct Profiler {
+    queries: Option<Queries>,
+    // Nanoseconds per timestamp tick.
+    period: f32,
+    frame: u64,
+    last_frame: Option<Instant>,
+    // Frame and CPU frame time in milliseconds.
+    cpu: VecDeque<(u64, f32)>,
+    // Frame and GPU time of each scope in milliseconds.
+    gpu: VecDeque<(u64, Vec<(&'static str, f32)>)>,
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device) -> Profiler {
+        let queries = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| {
+                let size = 2 * MAX_SCOPES as u64 * TIMESTAMP_SIZE;
+                let readbacks = (0..READBACK_FRAMES)
+                    .map(|_| Readback {
+                        buf: device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("timestamp readback"),
+                            size,
+                            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }),
+                        frame: 0,
+                        scopes: Vec::new(),
+                        mapping: None,
+                    })
+                    .collect();
+                Queries {
+                    query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("timestamps"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: 2 * MAX_SCOPES,
+                    }),
+                    readbacks,
+                    current: Some(0),
+                    scopes: Vec::new(),
+                    open: Vec::new(),
+                }
+            });
+        Profiler {
+            queries,
+            period: 1.0,
+            frame: 0,
+            last_frame: None,
+            cpu: VecDeque::new(),
+            gpu: VecDeque::new(),
+        }
+    }
+
+    pub fn gpu_supported(&self) -> bool {
+        self.queries.is_some()
+    }
+
+    /// Recent CPU frame times in milliseconds, oldest first.
+    pub fn frame_times(&self) -> Vec<f32> {
+        self.cpu.iter().map(|&(_, ms)| ms).collect()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        let readback = 2 * MAX_SCOPES as u64 * TIMESTAMP_SIZE;
+        self.queries
+            .as_ref()
+            .map_or(0, |queries| queries.readbacks.len() as u64 * readback)
+    }
+
+    /// Starts timing the work `encoder` records until the matching `end`.
+    /// Has to be called outside of passes.
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+        let queries = match &mut self.queries {
+            Some(queries) => queries,
+            None => return,
+        };
+        let scope = queries.current.and_then(|_| {
+            let scope = queries.scopes.len() as u32;
+            (scope < MAX_SCOPES).then_some(scope)
+        });
+        if let Some(scope) = scope {
+            encoder.write_timestamp(&queries.query_set, 2 * scope);
+            queries.scopes.push(name);
+        }
+        queries.open.push(scope);
+    }
+
+    /// Ends the scope begun last.
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let queries = match &mut self.queries {
+            Some(queries) => queries,
+            None => return,
+        };
+        if let Some(Some(scope)) = queries.open.pop() {
+            encoder.write_timestamp(&queries.query_set, 2 * scope + 1);
+        }
+    }
+
+    /// Resolves the timestamps of this frame into a readback buffer. Has to be
+    /// recorded after all scopes of the frame ended, into the last command
+    /// buffer submitted.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let queries = match &mut self.queries {
+            Some(queries) => queries,
+            None => return,
+        };
+        debug_assert!(queries.open.is_empty(), "profiler scope not ended");
+        let current = match queries.current {
+            Some(current) if !queries.scopes.is_empty() => current,
+            _ => return,
+        };
+        let count = 2 * queries.scopes.len() as u32;
+        let readback = &mut queries.readbacks[current];
+        encoder.resolve_query_set(&queries.query_set, 0..count, &readback.buf, 0);
+        readback.frame = self.frame;
+        readback.scopes = queries.scopes.drain(..).collect();
+    }
+
+    /// Call after submitting the frame. Starts reading back its timestamps,
+    /// collects the ones that arrived and records the CPU frame time.
+    pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            let ms = (now - last_frame).as_secs_f32() * 1000.0;
+            push_bounded(&mut self.cpu, (self.frame, ms));
+        }
+        self.last_frame = Some(now);
+        self.frame += 1;
+
+        let queries = match &mut self.queries {
+            Some(queries) => queries,
+            None => return,
+        };
+        self.period = queue.get_timestamp_period();
+        if let Some(current) = queries.current.take() {
+            let readback = &mut queries.readbacks[current];
+            if !readback.scopes.is_empty() {
+                readback.mapping = Some(Box::pin(
+                    readback.buf.slice(..).map_async(wgpu::MapMode::Read),
+                ));
+            }
+            // Timestamps the frame took but didn't resolve are dropped.
+            queries.scopes.clear();
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        let mut context = task::Context::from_waker(Waker::noop());
+        for readback in &mut queries.readbacks {
+            let result = match &mut readback.mapping {
+                Some(mapping) => match mapping.as_mut().poll(&mut context) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => continue,
+                },
+                None => continue,
+            };
+            readback.mapping = None;
+            let scopes = std::mem::take(&mut readback.scopes);
+            if result.is_err() {
+                continue;
+            }
+            let times = {
+                let data = readback.buf.slice(..).get_mapped_range();
+                let timestamp = |i: usize| {
+                    let bytes = &data[i * TIMESTAMP_SIZE as usize..][..TIMESTAMP_SIZE as usize];
+                    u64::from_le_bytes(bytes.try_into().unwrap())
+                };
+                scopes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &name)| {
+                        let ticks = timestamp(2 * i + 1).wrapping_sub(timestamp(2 * i));
+                        (name, ticks as f32 * self.period / 1e6)
+                    })
+                    .collect()
+            };
+            readback.buf.unmap();
+            push_bounded(&mut self.gpu, (readback.frame, times));
+        }
+        self.gpu.make_contiguous().sort_by_key(|&(frame, _)| frame);
+
+        queries.current = queries
+            .readbacks
+            .iter()
+            .position(|readback| readback.mapping.is_none() && readback.scopes.is_empty());
+    }
+
+    pub fn ui(&self, context: &Context) {
+        egui::Window::new("Profiler").show(context, |ui| {
+            let average = |values: &mut dyn Iterator<Item = f32>| {
+                let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+                sum / count.max(1) as f32
+            };
+            ui.label(format!(
+                "CPU frame: {:.2} ms",
+                average(&mut self.cpu.iter().map(|&(_, ms)| ms))
+            ));
+            if !self.gpu_supported() {
+                ui.label("GPU timings need timestamp queries, which this adapter lacks.");
+            }
+
+            // Scopes in the order they were first seen.
+            let mut names: Vec<&'static str> = Vec::new();
+            for (_, times) in &self.gpu {
+                for &(name, _) in times {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+            let gpu_times = |name: &'static str| {
+                self.gpu.iter().flat_map(move |(frame, times)| {
+                    times
+                        .iter()
+                        .filter(move |&&(n, _)| n == name)
+                        .map(move |&(_, ms)| (*frame, ms))
+                })
+            };
+            for &name in &names {
+                ui.label(format!(
+                    "GPU {}: {:.3} ms",
+                    name,
+                    average(&mut gpu_times(name).map(|(_, ms)| ms))
+                ));
+            }
+
+            let line = |values: &mut dyn Iterator<Item = (u64, f32)>| {
+                Line::new(Values::from_values_iter(
+                    values.map(|(frame, ms)| Value::new(frame as f64, ms)),
+                ))
+            };
+            Plot::new("profiler")
+                .height(160.0)
+                .include_y(0.0)
+                .legend(Legend::default())
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.line(line(&mut self.cpu.iter().copied()).name("CPU frame"));
+                    for &name in &names {
+                        plot_ui.line(line(&mut gpu_times(name)).name(name));
+                    }
+                });
+        });
+    }
+}
+
+fn push_bounded<T>(history: &mut VecDeque<T>, value: T) {
+    if history.len() == HISTORY_FRAMES {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
@@ -0,0 +1,379 @@
+use crate::camera::Camera;
+use crate::extract::{Backend, Extractor};
+use crate::scene::{Material, MaterialHandle, MeshHandle, Node, NodeId, Scene, Transform};
+use crate::slice_view::SliceSettings;
+use crate::timeline::Timeline;
+use crate::volume::{self, Volume};
+use crate::volume_file::{self, VolumeReader};
+use glam::UVec3;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+/// Version written into new project files. Version 1 stored sampled fields
+/// as raw little endian `f32`s, version 2 stores them in a `volume_file`.
+pub(crate) const PROJECT_VERSION: u32 = 2;
+/// Projects the "Recent" list keeps.
+const MAX_RECENT: usize = 8;
+/// Recently saved or opened projects, one path per line.
+const RECENT_PATH: &str = "recent_projects.txt";
+
+/// Where the scalar field of a project comes from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Field {
+    /// `volume::sphere`, what the density shader fills the grid with.
+    Sphere,
+    /// A sculpted field, in a `volume_file` next to the project.
+    Samples(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Extraction {
+    pub extractor: Extractor,
+    pub backend: Backend,
+    pub chunked: bool,
+    pub lod: bool,
+    pub lod_distance: f32,
+    pub lod_colors: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SavedNode {
+    pub name: String,
+    pub transform: Transform,
+    pub mesh: Option<MeshHandle>,
+    pub material: MaterialHandle,
+    pub visible: bool,
+    /// Index into `Project::nodes`, which lists parents before their children.
+    pub parent: Option<usize>,
+}
+
+/// A session saved to a RON file.
+///
+/// Fields added after version 1 have a default so older files still parse.
+/// A change defaults can't cover bumps `PROJECT_VERSION` and converts the
+/// older layout in `Project::parse`, or in `Project::volume` for the samples.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Project {
+    pub version: u32,
+    pub grid: UVec3,
+    pub field: Field,
+    pub iso: f32,
+    pub extraction: Extraction,
+    pub camera: Camera,
+    pub materials: Vec<Material>,
+    pub nodes: Vec<SavedNode>,
+    #[serde(default)]
+    pub slice_view: SliceSettings,
+    /// Egui's memory, for the position, size and state of the windows.
+    #[serde(default)]
+    pub window_layout: Option<egui::Memory>,
+    /// The animated field and its keys.
+    #[serde(default)]
+    pub timeline: Timeline,
+}
+
+/// Just the version, read before the rest to know what layout to expect.
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Name of the samples file of the project at `path`, relative to it.
+pub(crate) fn samples_file(path: &str) -> String {
+    let path = Path::new(path).with_extension("field");
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+/// The nodes of `scene` in depth first order.
+pub(crate) fn saved_nodes(scene: &Scene) -> Vec<SavedNode> {
+    let mut nodes = Vec::new();
+    let mut stack: Vec<(NodeId, Option<usize>)> =
+        scene.roots().iter().rev().map(|&id| (id, None)).collect();
+    while let Some((id, parent)) = stack.pop() {
+        let node = scene.node(id);
+        nodes.push(SavedNode {
+            name: node.name.clone(),
+            transform: node.transform,
+            mesh: node.mesh,
+            material: node.material,
+            visible: node.visible,
+            parent,
+        });
+        let index = nodes.len() - 1;
+        stack.extend(
+            node.children()
+                .iter()
+                .rev()
+                .map(|&child| (child, Some(index))),
+        );
+    }
+    nodes
+}
+
+impl Project {
+    pub fn read(path: &str) -> io::Result<Project> {
+        Project::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> io::Result<Project> {
+        let Version { version } = ron::from_str(text).map_err(invalid_data)?;
+        match version {
+            1..=PROJECT_VERSION => ron::from_str(text).map_err(invalid_data),
+            _ => Err(invalid_data(format!(
+                "project version {} isn't supported, this build reads 1 to {}",
+                version, PROJECT_VERSION
+            ))),
+        }
+    }
+
+    fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).unwrap()
+    }
+
+    /// Writes the project to `path`, and `volume` next to it when the field
+    /// is sampled.
+    pub fn write(&self, path: &str, volume: &Volume) -> io::Result<()> {
+        if let Field::Samples(file) = &self.field {
+            let samples = File::create(Path::new(path).with_file_name(file))?;
+            volume_file::write(BufWriter::new(samples), volume, self.iso)?;
+        }
+        fs::write(path, self.to_ron())
+    }
+
+    /// The field of the project saved at `path`, on the grid it was saved on.
+    pub fn volume(&self, path: &str) -> io::Result<Volume> {
+        match &self.field {
+            Field::Sphere => Ok(Volume::from_fn(self.grid, volume::sphere)),
+            Field::Samples(file) if self.version == 1 => {
+                self.raw_samples(file, &fs::read(Path::new(path).with_file_name(file))?)
+            }
+            Field::Samples(file) => {
+                let samples = File::open(Path::new(path).with_file_name(file))?;
+                VolumeReader::new(BufReader::new(samples))?.read_volume()
+            }
+        }
+    }
+
+    /// Samples of a version 1 project, little endian `f32`s in x, y, z order.
+    fn raw_samples(&self, file: &str, bytes: &[u8]) -> io::Result<Volume> {
+        let count = (self.grid.x * self.grid.y * self.grid.z) as usize;
+        if bytes.len() != count * 4 {
+            return Err(invalid_data(format!(
+                "{} has {} bytes, a {}x{}x{} grid needs {}",
+                file,
+                bytes.len(),
+                self.grid.x,
+                self.grid.y,
+                self.grid.z,
+                count * 4
+            )));
+        }
+        let samples = bytes
+            .chunks_exact(4)
+            .map(|d| f32::from_le_bytes(d.try_into().unwrap()));
+        Ok(Volume::from_samples(self.grid, samples))
+    }
+
+    pub fn scene(&self) -> Scene {
+        let mut scene = Scene::new();
+        scene.materials = self.materials.clone();
+        let mut ids = Vec::with_capacity(self.nodes.len());
+        for saved in &self.nodes {
+            // A material that isn't in the list falls back to the first.
+            let material = match saved.material {
+                MaterialHandle(i) if i < self.materials.len() => saved.material,
+                _ => MaterialHandle::default(),
+            };
+            let mut node = Node::new(&saved.name, saved.mesh)
+                .with_transform(saved.transform)
+                .with_material(material);
+            node.visible = saved.visible;
+            // A parent listed after its child is dropped, the child becomes a
+            // root.
+            let parent = saved.parent.and_then(|i| ids.get(i).copied());
+            ids.push(scene.add(parent, node));
+        }
+        scene
+    }
+}
+
+/// Moves `path` to the front of `recent`, keeping at most `MAX_RECENT`.
+fn push_recent(recent: &mut Vec<String>, path: &str) {
+    recent.retain(|p| p != path);
+    recent.insert(0, path.to_owned());
+    recent.truncate(MAX_RECENT);
+}
+
+pub(crate) fn recent_projects() -> Vec<String> {
+    fs::read_to_string(RECENT_PATH)
+        .map(|text| text.lines().map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+/// Puts `path` at the front of the recent projects and saves the list.
+pub(crate) fn add_recent(recent: &mut Vec<String>, path: &str) {
+    push_recent(recent, path);
+    if let Err(e) = fs::write(RECENT_PATH, recent.join("\n")) {
+        eprintln!("Failed to write {}: {}", RECENT_PATH, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{uvec3, Vec3};
+
+    fn project() -> Project {
+        let mut scene = Scene::new();
+        scene.add_material("Mesh colors", [0.0; 4]);
+        let red = scene.add_material("Red", [0.9, 0.3, 0.3, 1.0]);
+        let group = scene.add(None, Node::new("Group", None));
+        scene.add(
+            Some(group),
+            Node::new("Triangle", Some(MeshHandle::Triangle))
+                .with_transform(Transform::from_translation(Vec3::X))
+                .with_material(red),
+        );
+        scene.add(None, Node::new("Surface", Some(MeshHandle::Surface)));
+        let mut timeline = Timeline::default();
+        timeline.time = 1.5;
+        timeline.set_key();
+        Project {
+            version: PROJECT_VERSION,
+            grid: uvec3(5, 6, 7),
+            field: Field::Samples("test.field".to_owned()),
+            iso: 0.125,
+            extraction: Extraction {
+                extractor: Extractor::SurfaceNets,
+                backend: Backend::Cpu,
+                chunked: true,
+                lod: true,
+                lod_distance: 0.7,
+                lod_colors: false,
+            },
+            camera: Camera::new(),
+            materials: scene.materials.clone(),
+            nodes: saved_nodes(&scene),
+            slice_view: SliceSettings::default(),
+            window_layout: Some(egui::Memory::default()),
+            timeline,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_ron() {
+        let saved = project();
+        let loaded = Project::parse(&saved.to_ron()).unwrap();
+        assert_eq!(loaded.grid, saved.grid);
+        assert_eq!(loaded.field, saved.field);
+        assert_eq!(loaded.iso, saved.iso);
+        assert_eq!(loaded.extraction, saved.extraction);
+        assert_eq!(loaded.camera, saved.camera);
+        assert_eq!(loaded.materials, saved.materials);
+        assert_eq!(loaded.nodes, saved.nodes);
+        assert!(loaded.window_layout.is_some());
+        assert_eq!(loaded.timeline, saved.timeline);
+
+        let scene = loaded.scene();
+        assert_eq!(saved_nodes(&scene), saved.nodes);
+        let items = scene.draw_list();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].material, MaterialHandle(1));
+    }
+
+    #[test]
+    fn stale_materials_fall_back_to_the_first() {
+        let mut project = project();
+        project.nodes[1].material = MaterialHandle(7);
+        let scene = project.scene();
+        assert_eq!(scene.draw_list()[0].material, MaterialHandle(0));
+    }
+
+    #[test]
+    fn missing_optional_fields_get_defaults() {
+        let text = project().to_ron();
+        let start = text.find("    slice_view:").unwrap();
+        let loaded = Project::parse(&format!("{})", &text[..start])).unwrap();
+        assert_eq!(loaded.slice_view, SliceSettings::default());
+        assert!(loaded.window_layout.is_none());
+        assert_eq!(loaded.timeline, Timeline::default());
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let text = project().to_ron();
+        let version = format!("version: {}", PROJECT_VERSION);
+        for unknown in [0, PROJECT_VERSION + 1] {
+            let text = text.replacen(&version, &format!("version: {}", unknown), 1);
+            let e = Project::parse(&text).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+        assert!(Project::parse("(grid: (1, 1, 1))").is_err());
+    }
+
+    #[test]
+    fn samples_round_trip_and_resample() {
+        let dir = std::env::temp_dir().join(format!("isafo_project_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.ron").to_string_lossy().into_owned();
+        let mut project = project();
+        project.field = Field::Samples(samples_file(&path));
+        let volume = Volume::from_fn(project.grid, |p| p.x + 2.0 * p.y - p.z);
+
+        project.write(&path, &volume).unwrap();
+        let loaded = Project::read(&path).unwrap();
+        assert_eq!(loaded.field, Field::Samples("test.field".to_owned()));
+        assert!(loaded.volume(&path).unwrap() == volume);
+
+        // A linear field survives resampling away from the borders.
+        let resampled = volume.resample(uvec3(9, 9, 9));
+        let expected = Volume::from_fn(uvec3(9, 9, 9), |p| p.x + 2.0 * p.y - p.z);
+        assert!((resampled.get(4, 4, 4) - expected.get(4, 4, 4)).abs() < 1e-5);
+
+        fs::write(dir.join("test.field"), [0u8; 12]).unwrap();
+        assert!(loaded.volume(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn opens_version_1_projects() {
+        let dir = std::env::temp_dir().join(format!("isafo_project_v1_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.ron").to_string_lossy().into_owned();
+        let mut project = project();
+        project.version = 1;
+        project.field = Field::Samples(samples_file(&path));
+        let volume = Volume::from_fn(project.grid, |p| p.x - p.y * p.z);
+        let raw: Vec<u8> = volume.samples().flat_map(f32::to_le_bytes).collect();
+        fs::write(dir.join("old.field"), &raw).unwrap();
+        fs::write(&path, project.to_ron()).unwrap();
+
+        let loaded = Project::read(&path).unwrap();
+        assert_eq!(loaded.version, 1);
+        assert!(loaded.volume(&path).unwrap() == volume);
+
+        fs::write(dir.join("old.field"), &raw[4..]).unwrap();
+        let e = loaded.volume(&path).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recent_projects_move_to_the_front() {
+        let mut recent = Vec::new();
+        for i in 0..10 {
+            push_recent(&mut recent, &format!("{}.ron", i));
+        }
+        assert_eq!(recent.len(), MAX_RECENT);
+        assert_eq!(recent[0], "9.ron");
+        push_recent(&mut recent, "5.ron");
+        assert_eq!(recent[..3], ["5.ron", "9.ron", "8.ron"]);
+        assert_eq!(recent.len(), MAX_RECENT);
+    }
+}
@@ -0,0 +1,434 @@
+use crate::bricks::{BrickAtlas, FIELD_FORMAT};
+use crate::readback::TextureReadback;
+use crate::shader;
+use crate::stats;
+use crate::volume::Volume;
+use glam::{IVec3, UVec3};
+use std::mem;
+use std::num::NonZeroU32;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Concentration of v the surface is extracted at, the field is `LEVEL - v`
+/// so it's negative where there's more v. Must be kept in sync with
+/// `shaders/reaction.wgsl`.
+pub(crate) const LEVEL: f32 = 0.2;
+/// Largest diffusion rate the explicit step stays stable with, on a 6
+/// neighbour Laplacian.
+pub(crate) const MAX_DIFFUSION: f32 = 1.0 / 6.0;
+/// Steps the GPU is checked against `Chemicals::step` over.
+pub(crate) const CHECK_STEPS: u32 = 4;
+const STATE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+
+/// Rates of the Gray-Scott model, per step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct GrayScott {
+    pub feed: f32,
+    pub kill: f32,
+    pub diffusion_u: f32,
+    pub diffusion_v: f32,
+}
+
+impl Default for GrayScott {
+    fn default() -> GrayScott {
+        GrayScott {
+            feed: 0.04,
+            kill: 0.06,
+            diffusion_u: 0.12,
+            diffusion_v: 0.06,
+        }
+    }
+}
+
+/// Concentrations of u and v on the grid, stored x-major like `Volume`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Chemicals {
+    pub size: UVec3,
+    pub uv: Vec<[f32; 2]>,
+}
+
+impl Chemicals {
+    /// All u, with a cube of v in the middle to start the reaction.
+    pub fn seeded(size: UVec3) -> Chemicals {
+        let half = (size / 12).max(UVec3::ONE).min(size / 2);
+        let (min, max) = (size / 2 - half, size / 2 + half);
+        let mut uv = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let p = UVec3::new(x, y, z);
+                    let seed = p.cmpge(min).all() && p.cmplt(max).all();
+                    uv.push(if seed { [0.5, 0.25] } else { [1.0, 0.0] });
+                }
+            }
+        }
+        Chemicals { size, uv }
+    }
+
+    fn get(&self, p: IVec3) -> [f32; 2] {
+        let p = p.clamp(IVec3::ZERO, self.size.as_ivec3() - 1).as_uvec3();
+        self.uv[(p.x + self.size.x * (p.y + self.size.y * p.z)) as usize]
+    }
+
+    /// CPU version of `shaders/reaction.wgsl`, for checking a few steps.
+    /// The borders are closed, samples outside repeat the nearest one.
+    pub fn step(&self, rates: &GrayScott) -> Chemicals {
+        let size = self.size;
+        let mut uv = Vec::with_capacity(self.uv.len());
+        for z in 0..size.z as i32 {
+            for y in 0..size.y as i32 {
+                for x in 0..size.x as i32 {
+                    let c = IVec3::new(x, y, z);
+                    let [u, v] = self.get(c);
+                    let mut laplacian = [-6.0 * u, -6.0 * v];
+                    for e in [IVec3::X, IVec3::Y, IVec3::Z] {
+                        for n in [self.get(c - e), self.get(c + e)] {
+                            laplacian[0] += n[0];
+                            laplacian[1] += n[1];
+                        }
+                    }
+                    let uvv = u * v * v;
+                    uv.push([
+                        u + rates.diffusion_u * laplacian[0] - uvv + rates.feed * (1.0 - u),
+                        v + rates.diffusion_v * laplacian[1] + uvv - (rates.feed + rates.kill) * v,
+                    ]);
+                }
+            }
+        }
+        Chemicals { size, uv }
+    }
+
+    /// Largest difference in either concentration.
+    pub fn max_difference(&self, other: &Chemicals) -> f32 {
+        self.uv
+            .iter()
+            .zip(&other.uv)
+            .map(|(a, b)| (a[0] - b[0]).abs().max((a[1] - b[1]).abs()))
+            .fold(0.0, f32::max)
+    }
+
+    /// The field the surface is extracted from, what the shader writes to
+    /// the `BrickAtlas`.
+    pub fn field(&self) -> Volume {
+        Volume::from_samples(self.size, self.uv.iter().map(|[_, v]| LEVEL - v))
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct RateUniforms {
+    feed: f32,
+    kill: f32,
+    diffusion_u: f32,
+    diffusion_v: f32,
+}
+
+/// Runs Gray-Scott reaction-diffusion as compute passes, ping-ponging u and v
+/// between two textures and writing the field of v to the `BrickAtlas` on
+/// every step.
+pub(crate) struct ReactionDiffusion {
+    size: UVec3,
+    states: [wgpu::Texture; 2],
+    /// `bind_groups[i]` steps from `states[i]` to the other one.
+    bind_groups: [wgpu::BindGroup; 2],
+    rates_buf: wgpu::Buffer,
+    readback: TextureReadback,
+    pipeline: wgpu::ComputePipeline,
+    /// Which of `states` holds the latest step.
+    current: usize,
+    seeded: bool,
+    bytes: u64,
+}
+
+impl ReactionDiffusion {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas, size: UVec3) -> ReactionDiffusion {
+        let state = || {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("reaction state"),
+                size: wgpu::Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: size.z,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: STATE_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+            })
+        };
+        let states = [state(), state()];
+
+        let rates_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<RateUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = TextureReadback::new(device, size, STATE_FORMAT);
+
+        let storage_entry =
+            |binding: u32, format: wgpu::TextureFormat| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                },
+                count: None,
+            };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(1, STATE_FORMAT),
+                storage_entry(2, FIELD_FORMAT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let field_view = view(atlas.texture());
+        let bricks_buf = atlas.create_layout_buffer(device);
+        let bind_group = |from: &wgpu::Texture, to: &wgpu::Texture| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&view(from)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&view(to)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&field_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: rates_buf.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: bricks_buf.as_entire_binding(),
+                    },
+                ],
+                label: None,
+            })
+        };
+        let bind_groups = [
+            bind_group(&states[0], &states[1]),
+            bind_group(&states[1], &states[0]),
+        ];
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/reaction.wgsl")
+            ),
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("reaction-diffusion"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+        });
+
+        ReactionDiffusion {
+            size,
+            states,
+            bind_groups,
+            rates_buf,
+            bytes: 2 * stats::texture_bytes(size, STATE_FORMAT)
+                + readback.bytes()
+                + mem::size_of::<RateUniforms>() as u64,
+            readback,
+            pipeline,
+            current: 0,
+            seeded: false,
+        }
+    }
+
+    /// GPU memory of the textures and buffers.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Starts over from `chemicals`.
+    pub fn reset(&mut self, queue: &wgpu::Queue, chemicals: &Chemicals) {
+        let size = self.size;
+        queue.write_texture(
+            self.states[0].as_image_copy(),
+            chemicals.uv.as_bytes(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(size.x * mem::size_of::<[f32; 2]>() as u32),
+                rows_per_image: NonZeroU32::new(size.y),
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+        self.current = 0;
+        self.seeded = true;
+    }
+
+    /// Starts from `Chemicals::seeded` unless it was reset already.
+    pub fn seed(&mut self, queue: &wgpu::Queue) {
+        if !self.seeded {
+            self.reset(queue, &Chemicals::seeded(self.size));
+        }
+    }
+
+    /// Encodes `steps` steps, each writing its field to the atlas, after
+    /// `seed`. The rates are written right away, like the brush uniforms.
+    pub fn encode(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        rates: &GrayScott,
+        steps: u32,
+    ) {
+        self.seed(queue);
+        let uniforms = RateUniforms {
+            feed: rates.feed,
+            kill: rates.kill,
+            diffusion_u: rates.diffusion_u,
+            diffusion_v: rates.diffusion_v,
+        };
+        queue.write_buffer(&self.rates_buf, 0, uniforms.as_bytes());
+
+        let workgroups = (self.size + UVec3::splat(3)) / 4;
+        let mut cs_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        cs_pass.set_pipeline(&self.pipeline);
+        for _ in 0..steps {
+            cs_pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+            cs_pass.dispatch(workgroups.x, workgroups.y, workgroups.z);
+            self.current = 1 - self.current;
+        }
+    }
+
+    /// Blocks until the latest step was read back.
+    pub fn read_chemicals(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Chemicals {
+        let uv = self
+            .readback
+            .read(device, queue, &self.states[self.current])
+            .chunks_exact(mem::size_of::<[f32; 2]>())
+            .map(|bytes| <[f32; 2]>::read_from(bytes).unwrap())
+            .collect();
+        Chemicals {
+            size: self.size,
+            uv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec3;
+
+    #[test]
+    fn the_trivial_state_is_steady() {
+        let size = uvec3(4, 5, 6);
+        let chemicals = Chemicals {
+            size,
+            uv: vec![[1.0, 0.0]; 120],
+        };
+        assert_eq!(chemicals.step(&GrayScott::default()), chemicals);
+    }
+
+    #[test]
+    fn diffusion_conserves_mass() {
+        let size = uvec3(8, 8, 8);
+        let mut chemicals = Chemicals::seeded(size);
+        let rates = GrayScott {
+            feed: 0.0,
+            kill: 0.0,
+            diffusion_u: MAX_DIFFUSION,
+            diffusion_v: 0.1,
+        };
+        // Without v there's no reaction, u only spreads out.
+        for uv in &mut chemicals.uv {
+            uv[1] = 0.0;
+        }
+        let total = |c: &Chemicals| c.uv.iter().map(|uv| uv[0] as f64).sum::<f64>();
+        let before = total(&chemicals);
+        for _ in 0..10 {
+            chemicals = chemicals.step(&rates);
+        }
+        assert!((total(&chemicals) - before).abs() < 1e-3);
+        let min = chemicals.uv.iter().map(|uv| uv[0]).fold(1.0, f32::min);
+        assert!(min > 0.5);
+    }
+
+    #[test]
+    fn steps_match_the_model() {
+        let size = uvec3(24, 24, 24);
+        let rates = GrayScott::default();
+        let seeded = Chemicals::seeded(size);
+        let next = seeded.step(&rates);
+
+        // In the middle of the seed the Laplacian is zero.
+        let [u, v] = next.get(IVec3::splat(12));
+        let uvv = 0.5 * 0.25 * 0.25;
+        assert!((u - (0.5 - uvv + rates.feed * 0.5)).abs() < 1e-6);
+        assert!((v - (0.25 + uvv - (rates.feed + rates.kill) * 0.25)).abs() < 1e-6);
+        // Just outside it v diffuses in from one neighbour.
+        let [u, v] = next.get(IVec3::new(9, 12, 12));
+        assert_eq!(seeded.get(IVec3::new(9, 12, 12)), [1.0, 0.0]);
+        assert!((u - (1.0 - 0.5 * rates.diffusion_u)).abs() < 1e-6);
+        assert!((v - 0.25 * rates.diffusion_v).abs() < 1e-6);
+
+        // The surface of v grows out of the seed.
+        let inside = |c: &Chemicals| c.field().samples().filter(|&d| d < 0.0).count();
+        let mut chemicals = seeded.clone();
+        for _ in 0..100 {
+            chemicals = chemicals.step(&rates);
+        }
+        assert!(inside(&chemicals) > inside(&seeded));
+        assert!(chemicals.uv.iter().flatten().all(|c| c.is_finite()));
+    }
+}
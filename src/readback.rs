@@ -0,0 +1,107 @@
+use glam::UVec3;
+use std::iter;
+use std::num::NonZeroU32;
+
+/// Reads whole textures back to the CPU through a buffer kept between reads.
+/// 2D textures have a depth of 1.
+pub(crate) struct TextureReadback {
+    buf: wgpu::Buffer,
+    size: UVec3,
+    texel_bytes: u32,
+}
+
+/// Bytes per row of `width` texels, padded for `copy_texture_to_buffer`.
+fn padded_row(width: u32, texel_bytes: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * texel_bytes).div_ceil(align) * align
+}
+
+/// Drops the padding at the end of each row.
+fn unpad(data: &[u8], row: usize, padded_row: usize) -> Vec<u8> {
+    data.chunks_exact(padded_row)
+        .flat_map(|padded| &padded[..row])
+        .copied()
+        .collect()
+}
+
+impl TextureReadback {
+    pub fn new(device: &wgpu::Device, size: UVec3, format: wgpu::TextureFormat) -> TextureReadback {
+        let texel_bytes = format.describe().block_size as u32;
+        let buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row(size.x, texel_bytes) * size.y * size.z) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        TextureReadback {
+            buf,
+            size,
+            texel_bytes,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        (padded_row(self.size.x, self.texel_bytes) * self.size.y * self.size.z) as u64
+    }
+
+    /// Blocks until `texture` was copied back, returns its texels x-major
+    /// like `Volume`.
+    pub fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> Vec<u8> {
+        let size = self.size;
+        let row = padded_row(size.x, self.texel_bytes);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback"),
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buf,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(row),
+                    rows_per_image: NonZeroU32::new(size.y),
+                },
+            },
+            wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+        queue.submit(iter::once(encoder.finish()));
+
+        let slice = self.buf.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).unwrap();
+        let data = unpad(
+            &slice.get_mapped_range(),
+            (size.x * self.texel_bytes) as usize,
+            row as usize,
+        );
+        self.buf.unmap();
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_padded_and_unpadded() {
+        assert_eq!(padded_row(64, 4), 256);
+        assert_eq!(padded_row(65, 4), 512);
+        assert_eq!(padded_row(3, 8), 256);
+
+        let padded: Vec<u8> = (0..2)
+            .flat_map(|row| (0..8).map(move |i| if i < 3 { row * 3 + i } else { 0xff }))
+            .collect();
+        assert_eq!(unpad(&padded, 3, 8), [0, 1, 2, 3, 4, 5]);
+    }
+}
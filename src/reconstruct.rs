@@ -0,0 +1,299 @@
+use crate::measure::Bounds;
+use crate::mesh::{self, Mesh};
+use crate::sdf;
+use crate::volume::Volume;
+use glam::{ivec3, IVec3, UVec3, Vec3};
+
+/// Radius of the kernel, in grid samples.
+const SUPPORT_SAMPLES: f32 = 2.5;
+/// No point found yet.
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Point {
+    pub position: Vec3,
+    /// Unit length, pointing out of the surface.
+    pub normal: Vec3,
+}
+
+/// The points of `cloud`, placed by `sdf::fit`, with unit normals.
+pub(crate) fn fit_to_grid(cloud: &Mesh) -> Vec<Point> {
+    let fit = sdf::fit(cloud);
+    cloud
+        .vertices
+        .iter()
+        .map(|v| Point {
+            position: fit(Vec3::from(v.pos)),
+            normal: Vec3::from(v.normal).normalize_or_zero(),
+        })
+        .collect()
+}
+
+/// Points bucketed into cubic cells as wide as the kernel support, so the
+/// points within reach of a sample are in the 27 cells around it.
+struct PointGrid {
+    min: Vec3,
+    cell: f32,
+    dims: UVec3,
+    /// Where the points of each cell start in `order`, one extra at the end.
+    starts: Vec<u32>,
+    order: Vec<u32>,
+}
+
+impl PointGrid {
+    fn new(points: &[Point], cell: f32) -> PointGrid {
+        let mut bounds = Bounds::empty();
+        for point in points {
+            bounds.grow(point.position);
+        }
+        let min = if points.is_empty() {
+            Vec3::ZERO
+        } else {
+            bounds.min
+        };
+        let dims = (bounds.size() / cell).as_uvec3() + 1;
+        let mut grid = PointGrid {
+            min,
+            cell,
+            dims,
+            starts: vec![0; (dims.x * dims.y * dims.z) as usize + 1],
+            order: vec![0; points.len()],
+        };
+
+        // Counting sort by cell.
+        let cells: Vec<usize> = points
+            .iter()
+            .map(|point| grid.index(grid.cell_of(point.position)).unwrap())
+            .collect();
+        for &c in &cells {
+            grid.starts[c + 1] += 1;
+        }
+        for c in 1..grid.starts.len() {
+            grid.starts[c] += grid.starts[c - 1];
+        }
+        let mut next = grid.starts.clone();
+        for (i, &c) in cells.iter().enumerate() {
+            grid.order[next[c] as usize] = i as u32;
+            next[c] += 1;
+        }
+        grid
+    }
+
+    fn cell_of(&self, p: Vec3) -> IVec3 {
+        ((p - self.min) / self.cell).floor().as_ivec3()
+    }
+
+    fn index(&self, c: IVec3) -> Option<usize> {
+        let dims = self.dims.as_ivec3();
+        if c.cmplt(IVec3::ZERO).any() || c.cmpge(dims).any() {
+            return None;
+        }
+        Some((c.x + dims.x * (c.y + dims.y * c.z)) as usize)
+    }
+
+    /// Calls `f` with the index of every point in the cells around `p`.
+    fn near(&self, p: Vec3, mut f: impl FnMut(u32)) {
+        let center = self.cell_of(p);
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    if let Some(c) = self.index(center + ivec3(x, y, z)) {
+                        let (start, end) = (self.starts[c], self.starts[c + 1]);
+                        self.order[start as usize..end as usize]
+                            .iter()
+                            .for_each(|&i| f(i));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Implicit surface through `points`, sampled on a grid of `size` in object
+/// space, negative inside.
+///
+/// Near the points the field is the distance to their tangent planes,
+/// averaged with a compactly supported kernel. Further out it's the distance
+/// to the nearest point, signed by the side of its tangent plane, with the
+/// nearest point swept across the grid as in a distance transform.
+pub(crate) fn implicit_surface(points: &[Point], size: UVec3) -> Volume {
+    let radius = SUPPORT_SAMPLES / size.max_element() as f32;
+    let grid = PointGrid::new(points, radius);
+    let mut samples = vec![0.0; (size.x * size.y * size.z) as usize];
+    let position = |i: usize| {
+        let (x, y, z) = (
+            i as u32 % size.x,
+            i as u32 / size.x % size.y,
+            i as u32 / (size.x * size.y),
+        );
+        mesh::grid_to_object(size, UVec3::new(x, y, z).as_vec3())
+    };
+
+    let mut nearest = vec![NONE; samples.len()];
+    let mut covered = vec![false; samples.len()];
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let p = position(i);
+        let (mut sum, mut weights, mut best) = (0.0, 0.0, f32::INFINITY);
+        grid.near(p, |j| {
+            let point = &points[j as usize];
+            let d2 = p.distance_squared(point.position);
+            if d2 < radius * radius {
+                let w = (1.0 - d2 / (radius * radius)).powi(4);
+                sum += w * (p - point.position).dot(point.normal);
+                weights += w;
+            }
+            if d2 < best {
+                best = d2;
+                nearest[i] = j;
+            }
+        });
+        if weights > 0.0 {
+            *sample = sum / weights;
+            covered[i] = true;
+        }
+    }
+
+    let strides = [1, size.x as usize, (size.x * size.y) as usize];
+    let distance = |i: usize, j: u32| position(i).distance_squared(points[j as usize].position);
+    for _ in 0..2 {
+        for forward in [true, false] {
+            let order: Box<dyn Iterator<Item = usize>> = if forward {
+                Box::new(0..nearest.len())
+            } else {
+                Box::new((0..nearest.len()).rev())
+            };
+            for i in order {
+                let p = [
+                    i % strides[1],
+                    i / strides[1] % size.y as usize,
+                    i / strides[2],
+                ];
+                for axis in 0..3 {
+                    let neighbor = if forward {
+                        (p[axis] > 0).then(|| i - strides[axis])
+                    } else {
+                        (p[axis] + 1 < size[axis] as usize).then(|| i + strides[axis])
+                    };
+                    let candidate = match neighbor {
+                        Some(n) if nearest[n] != NONE => nearest[n],
+                        _ => continue,
+                    };
+                    if nearest[i] == NONE || distance(i, candidate) < distance(i, nearest[i]) {
+                        nearest[i] = candidate;
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, sample) in samples.iter_mut().enumerate() {
+        if covered[i] {
+            continue;
+        }
+        *sample = match nearest[i] {
+            NONE => f32::MAX,
+            j => {
+                let point = &points[j as usize];
+                let offset = position(i) - point.position;
+                offset.length().copysign(offset.dot(point.normal))
+            }
+        };
+    }
+    Volume::from_samples(size, samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Extractor;
+    use crate::measure;
+    use glam::uvec3;
+    use std::f32::consts::PI;
+
+    const RADIUS: f32 = 0.3;
+
+    /// `count` points spread evenly over a sphere around the origin.
+    fn sphere_points(count: usize) -> Vec<Point> {
+        let golden = PI * (3.0 - 5f32.sqrt());
+        (0..count)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+                let r = (1.0 - z * z).sqrt();
+                let angle = golden * i as f32;
+                let normal = Vec3::new(r * angle.cos(), r * angle.sin(), z);
+                Point {
+                    position: normal * RADIUS,
+                    normal,
+                }
+            })
+            .collect()
+    }
+
+    fn sphere_distance(size: UVec3, i: usize) -> f32 {
+        let p = uvec3(
+            i as u32 % size.x,
+            i as u32 / size.x % size.y,
+            i as u32 / (size.x * size.y),
+        );
+        mesh::grid_to_object(size, p.as_vec3()).length() - RADIUS
+    }
+
+    #[test]
+    fn matches_a_sampled_sphere() {
+        let size = uvec3(32, 32, 32);
+        let voxel = 1.0 / 32.0;
+        let volume = implicit_surface(&sphere_points(4000), size);
+        for (i, d) in volume.samples().enumerate() {
+            let expected = sphere_distance(size, i);
+            let tolerance = if expected.abs() < 2.0 * voxel {
+                0.25 * voxel
+            } else {
+                voxel
+            };
+            assert!(
+                (d - expected).abs() < tolerance,
+                "sample {}: {} != {}",
+                i,
+                d,
+                expected
+            );
+        }
+
+        let measurements = measure::measure(&Extractor::MarchingCubes.extract(&volume, 0.0));
+        let area = 4.0 * PI * RADIUS * RADIUS;
+        assert_eq!(measurements.components.len(), 1);
+        assert!((measurements.area - area).abs() < 0.02 * area);
+    }
+
+    #[test]
+    fn sparse_points_keep_the_sign() {
+        // Points further apart than the kernel reaches.
+        let size = uvec3(48, 48, 48);
+        let volume = implicit_surface(&sphere_points(150), size);
+        for (i, d) in volume.samples().enumerate() {
+            let expected = sphere_distance(size, i);
+            if expected.abs() > 0.05 {
+                assert_eq!(d < 0.0, expected < 0.0, "sample {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn fits_clouds_to_the_grid() {
+        let mut cloud = Mesh::default();
+        for point in sphere_points(100) {
+            let p = point.position * 10.0 + Vec3::new(5.0, 0.0, -3.0);
+            cloud
+                .vertices
+                .push(mesh::MeshVertex::new(p, point.normal * 2.0));
+        }
+        let points = fit_to_grid(&cloud);
+        let mut bounds = Bounds::empty();
+        for point in &points {
+            bounds.grow(point.position);
+            assert!((point.normal.length() - 1.0).abs() < 1e-5);
+        }
+        assert!((bounds.size().max_element() - 0.8).abs() < 1e-5);
+        assert!(((bounds.min + bounds.max) * 0.5).length() < 1e-5);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::readback::TextureReadback;
+use glam::uvec3;
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// What changes from one frame of an animation to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Motion {
+    /// Plays the timeline from the start.
+    Timeline,
+    /// Orbits the camera once around the surface.
+    Turntable,
+}
+
+impl Motion {
+    pub const ALL: [Motion; 2] = [Motion::Timeline, Motion::Turntable];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Motion::Timeline => "Timeline",
+            Motion::Turntable => "Turntable",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RenderSettings {
+    pub motion: Motion,
+    pub frames: u32,
+    pub fps: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Where the numbered PNGs, and the video, are written.
+    pub dir: String,
+    /// Also pipes the frames to `ffmpeg` for a video, when it's installed.
+    pub ffmpeg: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            motion: Motion::Turntable,
+            frames: 120,
+            fps: 30,
+            width: 1280,
+            height: 720,
+            dir: "frames".to_owned(),
+            ffmpeg: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Timeline time of `frame`, in seconds.
+    pub fn time(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps as f32
+    }
+
+    /// Camera yaw of `frame` for a turntable starting at `start`.
+    pub fn yaw(&self, start: f32, frame: u32) -> f32 {
+        start + TAU * frame as f32 / self.frames as f32
+    }
+
+    pub fn frame_path(&self, frame: u32) -> PathBuf {
+        Path::new(&self.dir).join(format!("frame_{:05}.png", frame))
+    }
+
+    pub fn video_path(&self) -> PathBuf {
+        Path::new(&self.dir).join("animation.mp4")
+    }
+
+    /// Reads PNGs from stdin, writes an H.264 video most players take.
+    fn ffmpeg_args(&self) -> Vec<String> {
+        let input = format!(
+            "-y -loglevel error -f image2pipe -framerate {} -c:v png -i - -pix_fmt yuv420p",
+            self.fps
+        );
+        input
+            .split(' ')
+            .map(str::to_owned)
+            .chain(iter::once(self.video_path().to_string_lossy().into_owned()))
+            .collect()
+    }
+}
+
+/// An `ffmpeg` process encoding the PNGs written to it.
+pub(crate) struct Ffmpeg {
+    child: Child,
+}
+
+impl Ffmpeg {
+    pub fn spawn(settings: &RenderSettings) -> io::Result<Ffmpeg> {
+        let child = Command::new("ffmpeg")
+            .args(settings.ffmpeg_args())
+            .stdin(Stdio::piped())
+            .spawn()?;
+        Ok(Ffmpeg { child })
+    }
+
+    pub fn write_frame(&mut self, png: &[u8]) -> io::Result<()> {
+        self.child.stdin.as_mut().unwrap().write_all(png)
+    }
+
+    /// Closes the pipe and waits for the video to be written.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.child.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg exited with {}", status)))
+        }
+    }
+}
+
+/// `rgba` rows top to bottom as an 8 bit sRGB PNG.
+pub(crate) fn encode_png(width: u32, height: u32, rgba: &[u8]) -> io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(png)
+}
+
+pub(crate) fn write_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(bytes)?;
+    file.flush()
+}
+
+/// Swaps blue and red, turning BGRA texels into RGBA ones.
+fn swap_red_blue(texels: &mut [u8]) {
+    for texel in texels.chunks_exact_mut(4) {
+        texel.swap(0, 2);
+    }
+}
+
+/// A color texture frames are drawn into instead of the window, and read
+/// back from.
+pub(crate) struct Offscreen {
+    texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    readback: TextureReadback,
+    bgra: bool,
+}
+
+impl Offscreen {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Offscreen {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Offscreen {
+            texture,
+            view,
+            readback: TextureReadback::new(device, uvec3(width, height, 1), format),
+            bgra: matches!(
+                format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+        }
+    }
+
+    /// Blocks until the frame drawn into `view` was copied back, returns its
+    /// rows top to bottom as RGBA.
+    pub fn read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<u8> {
+        let mut texels = self.readback.read(device, queue, &self.texture);
+        if self.bgra {
+            swap_red_blue(&mut texels);
+        }
+        texels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_numbered_and_timed() {
+        let settings = RenderSettings {
+            frames: 4,
+            fps: 24,
+            dir: "out".to_owned(),
+            ..RenderSettings::default()
+        };
+        assert_eq!(settings.frame_path(7), Path::new("out/frame_00007.png"));
+        assert_eq!(settings.time(12), 0.5);
+        assert_eq!(settings.yaw(1.0, 2), 1.0 + TAU / 2.0);
+
+        let args = settings.ffmpeg_args();
+        let framerate = args.iter().position(|arg| arg == "-framerate").unwrap();
+        assert_eq!(args[framerate + 1], "24");
+        assert!(framerate < args.iter().position(|arg| arg == "-i").unwrap());
+        assert_eq!(args.last().unwrap(), "out/animation.mp4");
+    }
+
+    #[test]
+    fn bgra_becomes_rgba() {
+        let mut texels = [0, 1, 2, 3, 4, 5, 6, 7];
+        swap_red_blue(&mut texels);
+        assert_eq!(texels, [2, 1, 0, 3, 6, 5, 4, 7]);
+    }
+
+    #[test]
+    fn encodes_pngs() {
+        let rgba: Vec<u8> = (0..2 * 3 * 4).map(|i| i as u8).collect();
+        let png = encode_png(2, 3, &rgba).unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (2, 3));
+        assert_eq!(decoded, rgba);
+    }
+}
@@ -0,0 +1,326 @@
+use glam::{EulerRot, Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Index of a node, stays valid until the node is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct NodeId(usize);
+
+/// The meshes the renderer knows how to draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum MeshHandle {
+    Triangle,
+    /// The extracted isosurface.
+    Surface,
+}
+
+impl MeshHandle {
+    pub const ALL: [MeshHandle; 2] = [MeshHandle::Triangle, MeshHandle::Surface];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MeshHandle::Triangle => "Triangle",
+            MeshHandle::Surface => "Surface",
+        }
+    }
+}
+
+/// Index into `Scene::materials`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct MaterialHandle(pub usize);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Material {
+    pub name: String,
+    /// Zero alpha keeps the mesh's own colors.
+    pub color: [f32; 4],
+}
+
+/// Scale, then rotation, then translation, relative to the parent.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Transform {
+    pub translation: Vec3,
+    /// Euler angles in degrees, applied around X, then Y, then Z.
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3::ZERO,
+        rotation: Vec3::ZERO,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn quat(&self) -> Quat {
+        let r = self.rotation * (std::f32::consts::PI / 180.0);
+        Quat::from_euler(EulerRot::ZYX, r.z, r.y, r.x)
+    }
+
+    /// Sets the Euler angles to those of `quat`.
+    pub fn set_quat(&mut self, quat: Quat) {
+        let (z, y, x) = quat.to_euler(EulerRot::ZYX);
+        self.rotation = Vec3::new(x, y, z) * (180.0 / std::f32::consts::PI);
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.quat(), self.translation)
+    }
+}
+
+pub(crate) struct Node {
+    pub name: String,
+    pub transform: Transform,
+    pub mesh: Option<MeshHandle>,
+    pub material: MaterialHandle,
+    /// Hidden nodes hide their children too.
+    pub visible: bool,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn new(name: &str, mesh: Option<MeshHandle>) -> Node {
+        Node {
+            name: name.to_owned(),
+            transform: Transform::IDENTITY,
+            mesh,
+            material: MaterialHandle::default(),
+            visible: true,
+            parent: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn with_transform(self, transform: Transform) -> Node {
+        Node { transform, ..self }
+    }
+
+    pub fn with_material(self, material: MaterialHandle) -> Node {
+        Node { material, ..self }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// A node to draw, with its transform from object to world space.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DrawItem {
+    pub mesh: MeshHandle,
+    pub material: MaterialHandle,
+    pub world: Mat4,
+}
+
+/// Tree of nodes, each placed relative to its parent.
+pub(crate) struct Scene {
+    // Removed nodes leave a hole so the ids of the others stay valid.
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeId>,
+    pub materials: Vec<Material>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn add_material(&mut self, name: &str, color: [f32; 4]) -> MaterialHandle {
+        self.materials.push(Material {
+            name: name.to_owned(),
+            color,
+        });
+        MaterialHandle(self.materials.len() - 1)
+    }
+
+    /// Adds `node` as the last child of `parent`, or as a root.
+    pub fn add(&mut self, parent: Option<NodeId>, mut node: Node) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        node.parent = parent;
+        node.children.clear();
+        self.nodes.push(Some(node));
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Removes `id` and everything below it.
+    pub fn remove(&mut self, id: NodeId) {
+        let node = match self.nodes[id.0].take() {
+            Some(node) => node,
+            None => return,
+        };
+        let siblings = match node.parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != id);
+        for child in node.children {
+            // The parent is gone already, only the holes are left to make.
+            self.remove_subtree(child);
+        }
+    }
+
+    fn remove_subtree(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes[id.0].take() {
+            for child in node.children {
+                self.remove_subtree(child);
+            }
+        }
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.nodes.get(id.0).is_some_and(Option::is_some)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        self.nodes[id.0].as_ref().unwrap()
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id.0].as_mut().unwrap()
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Transform from the node's space to world space.
+    pub fn world_transform(&self, id: NodeId) -> Mat4 {
+        let node = self.node(id);
+        let local = node.transform.matrix();
+        match node.parent {
+            Some(parent) => self.world_transform(parent) * local,
+            None => local,
+        }
+    }
+
+    /// The visible nodes with a mesh, parents before their children.
+    pub fn draw_list(&self) -> Vec<DrawItem> {
+        let mut items = Vec::new();
+        let mut stack: Vec<(NodeId, Mat4)> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&id| (id, Mat4::IDENTITY))
+            .collect();
+        while let Some((id, parent)) = stack.pop() {
+            let node = self.node(id);
+            if !node.visible {
+                continue;
+            }
+            let world = parent * node.transform.matrix();
+            if let Some(mesh) = node.mesh {
+                items.push(DrawItem {
+                    mesh,
+                    material: node.material,
+                    world,
+                });
+            }
+            stack.extend(node.children.iter().rev().map(|&child| (child, world)));
+        }
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_mat_eq(a: Mat4, b: Mat4) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn world_transforms_compose_parent_first() {
+        let mut scene = Scene::new();
+        let parent = scene.add(
+            None,
+            Node::new("parent", None).with_transform(Transform {
+                translation: Vec3::X,
+                rotation: Vec3::new(0.0, 0.0, 90.0),
+                scale: Vec3::splat(2.0),
+            }),
+        );
+        let child = scene.add(
+            Some(parent),
+            Node::new("child", Some(MeshHandle::Triangle))
+                .with_transform(Transform::from_translation(Vec3::X)),
+        );
+        // One along the child's x is two along the parent's rotated y.
+        let p = scene.world_transform(child).transform_point3(Vec3::ZERO);
+        assert!(p.abs_diff_eq(Vec3::new(1.0, 2.0, 0.0), 1e-5), "{:?}", p);
+
+        let items = scene.draw_list();
+        assert_eq!(items.len(), 1);
+        assert_mat_eq(items[0].world, scene.world_transform(child));
+    }
+
+    #[test]
+    fn hidden_nodes_hide_their_children() {
+        let mut scene = Scene::new();
+        let group = scene.add(None, Node::new("group", None));
+        scene.add(Some(group), Node::new("a", Some(MeshHandle::Surface)));
+        scene.add(Some(group), Node::new("b", Some(MeshHandle::Triangle)));
+        scene.add(
+            None,
+            Node::new("c", Some(MeshHandle::Triangle))
+                .with_transform(Transform::from_translation(Vec3::X)),
+        );
+
+        let meshes = |scene: &Scene| -> Vec<MeshHandle> {
+            scene.draw_list().iter().map(|item| item.mesh).collect()
+        };
+        assert_eq!(
+            meshes(&scene),
+            [
+                MeshHandle::Surface,
+                MeshHandle::Triangle,
+                MeshHandle::Triangle
+            ]
+        );
+        scene.node_mut(group).visible = false;
+        let items = scene.draw_list();
+        assert_eq!(items.len(), 1);
+        assert_mat_eq(items[0].world, Mat4::from_translation(Vec3::X));
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree() {
+        let mut scene = Scene::new();
+        let root = scene.add(None, Node::new("root", None));
+        let group = scene.add(Some(root), Node::new("group", None));
+        let leaf = scene.add(Some(group), Node::new("leaf", Some(MeshHandle::Triangle)));
+        let other = scene.add(Some(root), Node::new("other", Some(MeshHandle::Triangle)));
+
+        scene.remove(group);
+        assert!(!scene.contains(group));
+        assert!(!scene.contains(leaf));
+        assert_eq!(scene.node(root).children(), [other]);
+        assert_eq!(scene.draw_list().len(), 1);
+
+        // Ids of the remaining nodes stay valid.
+        let added = scene.add(Some(other), Node::new("added", None));
+        assert_eq!(scene.node(added).parent(), Some(other));
+        scene.remove(root);
+        assert!(scene.roots().is_empty());
+        assert!(!scene.contains(added));
+    }
+}
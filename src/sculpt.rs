@@ -0,0 +1,477 @@
+use crate::bricks::{BrickAtlas, FIELD_FORMAT};
+use crate::extract::Region;
+use crate::pick::Pick;
+use crate::shader;
+use crate::stats;
+use crate::volume::Volume;
+use glam::{IVec3, UVec3, Vec3};
+use std::mem;
+use zerocopy::{AsBytes, FromBytes};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BrushKind {
+    Add,
+    Subtract,
+    Smooth,
+    Flatten,
+    Paint,
+}
+
+impl BrushKind {
+    pub const ALL: [BrushKind; 5] = [
+        BrushKind::Add,
+        BrushKind::Subtract,
+        BrushKind::Smooth,
+        BrushKind::Flatten,
+        BrushKind::Paint,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BrushKind::Add => "Add",
+            BrushKind::Subtract => "Subtract",
+            BrushKind::Smooth => "Smooth",
+            BrushKind::Flatten => "Flatten",
+            BrushKind::Paint => "Paint",
+        }
+    }
+
+    /// Whether the brush changes the field, rather than only its color.
+    pub fn edits_field(self) -> bool {
+        self != BrushKind::Paint
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Brush {
+    pub kind: BrushKind,
+    /// In normalized [0, 1] grid coordinates.
+    pub radius: f32,
+    pub strength: f32,
+    pub color: [f32; 3],
+}
+
+/// Center of a dab at `pick` in normalized [0, 1] grid coordinates, the
+/// space the brushes work in.
+fn dab_center(pick: &Pick) -> Vec3 {
+    pick.pos + 0.5
+}
+
+impl Brush {
+    /// Box of samples a dab at `pick` can change.
+    pub fn bounds(&self, size: UVec3, pick: &Pick) -> Region {
+        let size_f = size.as_vec3();
+        let min = ((dab_center(pick) - self.radius) * size_f - 0.5)
+            .floor()
+            .as_ivec3();
+        let max = ((dab_center(pick) + self.radius) * size_f - 0.5)
+            .ceil()
+            .as_ivec3()
+            + IVec3::ONE;
+        Region {
+            min: min.max(IVec3::ZERO).as_uvec3(),
+            max: max.max(IVec3::ZERO).as_uvec3().min(size),
+        }
+    }
+
+    fn weight(&self, p: Vec3, pick: &Pick) -> f32 {
+        let t = (p.distance(dab_center(pick)) / self.radius).min(1.0);
+        let falloff = 1.0 - t * t;
+        self.strength * falloff * falloff
+    }
+
+    /// CPU version of `shaders/brush.wgsl`, keeps the CPU copy of the field in
+    /// sync with the `BrickAtlas`. Painting doesn't touch the field.
+    pub fn apply(&self, volume: &mut Volume, pick: &Pick) {
+        if !self.kind.edits_field() {
+            return;
+        }
+        let bounds = self.bounds(volume.size, pick);
+        let size = volume.size.as_vec3();
+        let max = volume.size - UVec3::ONE;
+        let field = |p: IVec3| {
+            let p = p.clamp(IVec3::ZERO, max.as_ivec3()).as_uvec3();
+            volume.get(p.x, p.y, p.z)
+        };
+
+        let values: Vec<f32> = bounds
+            .cells()
+            .map(|coord| {
+                let c = coord.as_ivec3();
+                let p = (coord.as_vec3() + 0.5) / size;
+                let w = self.weight(p, pick);
+                let value = field(c);
+                match self.kind {
+                    BrushKind::Add => value - w * self.radius,
+                    BrushKind::Subtract => value + w * self.radius,
+                    BrushKind::Smooth => {
+                        let average = [IVec3::X, IVec3::Y, IVec3::Z]
+                            .iter()
+                            .map(|&e| field(c - e) + field(c + e))
+                            .sum::<f32>()
+                            / 6.0;
+                        value + (average - value) * w
+                    }
+                    BrushKind::Flatten => {
+                        value + ((p - dab_center(pick)).dot(pick.normal) - value) * w
+                    }
+                    BrushKind::Paint => value,
+                }
+            })
+            .collect();
+
+        for (coord, value) in bounds.cells().zip(values) {
+            volume.set(coord.x, coord.y, coord.z, value);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+struct BrushUniforms {
+    center: [f32; 3],
+    radius: f32,
+    normal: [f32; 3],
+    strength: f32,
+    color: [f32; 4],
+    box_min: [i32; 3],
+    kind: u32,
+    box_max: [i32; 3],
+    _pad: u32,
+}
+
+/// Runs the brushes as compute passes on the `BrickAtlas` and on the paint
+/// texture the mesh is colored with. The paint covers the grid at up to the
+/// largest 3D texture.
+pub(crate) struct Sculptor {
+    paint: wgpu::Texture,
+    paint_size: UVec3,
+    // Laid out like the atlas.
+    field_scratch: wgpu::Texture,
+    paint_scratch: wgpu::Texture,
+    brush_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    sculpt: wgpu::ComputePipeline,
+    paint_pipeline: wgpu::ComputePipeline,
+    bytes: u64,
+}
+
+pub(crate) const PAINT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+fn extent(size: UVec3) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: size.z,
+    }
+}
+
+fn create_texture(
+    device: &wgpu::Device,
+    size: UVec3,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size: extent(size),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format,
+        usage,
+    })
+}
+
+fn texture_entry(binding: u32, filterable: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+            view_dimension: wgpu::TextureViewDimension::D3,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn storage_texture_entry(binding: u32, format: wgpu::TextureFormat) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D3,
+        },
+        count: None,
+    }
+}
+
+impl Sculptor {
+    pub fn new(device: &wgpu::Device, atlas: &BrickAtlas, size: UVec3) -> Sculptor {
+        let paint_size = size.min(UVec3::splat(device.limits().max_texture_dimension_3d));
+        let paint = create_texture(
+            device,
+            paint_size,
+            PAINT_FORMAT,
+            wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
+        );
+        let field_scratch = atlas.create_texture(
+            device,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        );
+        let paint_scratch = create_texture(
+            device,
+            paint_size,
+            PAINT_FORMAT,
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        );
+
+        let brush_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: mem::size_of::<BrushUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, false),
+                storage_texture_entry(1, FIELD_FORMAT),
+                texture_entry(2, true),
+                storage_texture_entry(3, PAINT_FORMAT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let view =
+            |texture: &wgpu::Texture| texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view(atlas.texture())),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view(&field_scratch)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&view(&paint)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&view(&paint_scratch)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: brush_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: atlas.create_layout_buffer(device).as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let module = shader::compile_cs(
+            device,
+            concat!(
+                include_str!("shaders/bricks.wgsl"),
+                include_str!("shaders/brush.wgsl")
+            ),
+        );
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+
+        Sculptor {
+            paint,
+            paint_size,
+            field_scratch,
+            paint_scratch,
+            brush_buf,
+            bind_group,
+            sculpt: pipeline("sculpt"),
+            paint_pipeline: pipeline("paint"),
+            bytes: 2 * stats::texture_bytes(paint_size, PAINT_FORMAT)
+                + atlas.texture_bytes()
+                + mem::size_of::<BrushUniforms>() as u64,
+        }
+    }
+
+    /// GPU memory of the textures and buffers.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Texture the mesh is colored with, transparent where nothing was
+    /// painted.
+    pub fn paint_texture(&self) -> &wgpu::Texture {
+        &self.paint
+    }
+
+    /// Applies a dab at `pick` to `atlas` or the paint texture. The brush
+    /// uniforms are written right away, so only one dab can be encoded per
+    /// submit.
+    pub fn encode(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        atlas: &BrickAtlas,
+        size: UVec3,
+        brush: &Brush,
+        pick: &Pick,
+    ) {
+        let size = if brush.kind.edits_field() {
+            size
+        } else {
+            self.paint_size
+        };
+        let bounds = brush.bounds(size, pick);
+        let extent = bounds.size();
+        if extent.min_element() == 0 {
+            return;
+        }
+
+        let uniforms = BrushUniforms {
+            center: dab_center(pick).to_array(),
+            radius: brush.radius,
+            normal: pick.normal.to_array(),
+            strength: brush.strength,
+            color: [brush.color[0], brush.color[1], brush.color[2], 1.0],
+            box_min: bounds.min.as_ivec3().to_array(),
+            kind: BrushKind::ALL
+                .iter()
+                .position(|&k| k == brush.kind)
+                .unwrap() as u32,
+            box_max: bounds.max.as_ivec3().to_array(),
+            _pad: 0,
+        };
+        queue.write_buffer(&self.brush_buf, 0, uniforms.as_bytes());
+
+        {
+            let mut cs_pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cs_pass.set_pipeline(if brush.kind.edits_field() {
+                &self.sculpt
+            } else {
+                &self.paint_pipeline
+            });
+            cs_pass.set_bind_group(0, &self.bind_group, &[]);
+            cs_pass.insert_debug_marker(brush.kind.name());
+            let workgroups = (extent + UVec3::splat(3)) / 4;
+            cs_pass.dispatch(workgroups.x, workgroups.y, workgroups.z);
+        }
+
+        if brush.kind.edits_field() {
+            atlas.copy(encoder, &self.field_scratch, atlas.texture(), bounds);
+            return;
+        }
+        let origin = wgpu::Origin3d {
+            x: bounds.min.x,
+            y: bounds.min.y,
+            z: bounds.min.z,
+        };
+        encoder.copy_texture_to_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.paint_scratch,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyTexture {
+                texture: &self.paint,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d {
+                width: extent.x,
+                height: extent.y,
+                depth_or_array_layers: extent.z,
+            },
+        );
+    }
+
+    /// Copies the paint texture to a new texture `restore_paint` puts back.
+    pub fn save_paint(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> wgpu::Texture {
+        let saved = create_texture(
+            device,
+            self.paint_size,
+            PAINT_FORMAT,
+            wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+        );
+        encoder.copy_texture_to_texture(
+            self.paint.as_image_copy(),
+            saved.as_image_copy(),
+            extent(self.paint_size),
+        );
+        saved
+    }
+
+    pub fn restore_paint(&self, encoder: &mut wgpu::CommandEncoder, saved: &wgpu::Texture) {
+        encoder.copy_texture_to_texture(
+            saved.as_image_copy(),
+            self.paint.as_image_copy(),
+            extent(self.paint_size),
+        );
+    }
+
+    /// Clears the paint texture back to transparent.
+    pub fn clear_paint(&self, queue: &wgpu::Queue) {
+        let size = self.paint_size;
+        let data = vec![0u8; (size.x * size.y * size.z * 4) as usize];
+        queue.write_texture(
+            self.paint.as_image_copy(),
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(size.x * 4),
+                rows_per_image: std::num::NonZeroU32::new(size.y),
+            },
+            extent(size),
+        );
+    }
+}
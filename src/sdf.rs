@@ -0,0 +1,374 @@
+use crate::measure::Bounds;
+use crate::mesh::{self, Mesh};
+use crate::volume::Volume;
+use glam::{UVec3, Vec2, Vec3};
+
+/// Most triangles in a leaf of the BVH.
+const LEAF_TRIANGLES: usize = 4;
+/// Part of the unit cube the largest side of an imported mesh spans.
+const FIT: f32 = 0.8;
+/// Shift of the sign rays off the grid, so they don't run along the edges of
+/// meshes built on round coordinates.
+const RAY_JITTER: [f32; 2] = [1.3e-4, 0.7e-4];
+
+type Triangle = [Vec3; 3];
+
+struct BvhNode {
+    bounds: Bounds,
+    /// First triangle of a leaf, first child of an inner node.
+    first: u32,
+    /// Triangles of a leaf, zero for inner nodes, whose children are at
+    /// `first` and `first + 1`.
+    count: u32,
+}
+
+/// Bounding volume hierarchy over triangles, for closest point and ray
+/// queries.
+pub(crate) struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+}
+
+fn triangle_bounds(triangles: &[Triangle]) -> Bounds {
+    let mut bounds = Bounds::empty();
+    for p in triangles.iter().flatten() {
+        bounds.grow(*p);
+    }
+    bounds
+}
+
+/// Squared distance from `p` to the box, zero inside.
+fn bounds_distance_squared(bounds: &Bounds, p: Vec3) -> f32 {
+    let d = (bounds.min - p).max(p - bounds.max).max(Vec3::ZERO);
+    d.length_squared()
+}
+
+/// Closest point to `p` on the triangle, from Ericson's Real-Time Collision
+/// Detection.
+pub(crate) fn closest_point(p: Vec3, [a, b, c]: Triangle) -> Vec3 {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+/// Where the line through `origin` along `axis` crosses the triangle, as the
+/// coordinate along `axis`. Only the other two coordinates of `origin` count.
+fn line_crossing(origin: Vec3, axis: usize, tri: &Triangle) -> Option<f32> {
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let flat = |p: Vec3| Vec2::new(p[u], p[v]);
+    let (a, b, c) = (flat(tri[0]), flat(tri[1]), flat(tri[2]));
+    let p = flat(origin);
+    let cross = |o: Vec2, x: Vec2, y: Vec2| (x - o).perp_dot(y - o);
+    let area = cross(a, b, c);
+    if area == 0.0 {
+        return None;
+    }
+    // Barycentric weights, all the same sign inside.
+    let (wa, wb, wc) = (
+        cross(p, b, c) / area,
+        cross(p, c, a) / area,
+        cross(p, a, b) / area,
+    );
+    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+        return None;
+    }
+    Some(wa * tri[0][axis] + wb * tri[1][axis] + wc * tri[2][axis])
+}
+
+impl Bvh {
+    pub fn new(mut triangles: Vec<Triangle>) -> Bvh {
+        let mut nodes = vec![BvhNode {
+            bounds: triangle_bounds(&triangles),
+            first: 0,
+            count: triangles.len() as u32,
+        }];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let (first, count) = (nodes[node].first as usize, nodes[node].count as usize);
+            if count <= LEAF_TRIANGLES {
+                continue;
+            }
+            // Split at the median centroid along the longest side.
+            let slice = &mut triangles[first..first + count];
+            let mut centroids = Bounds::empty();
+            for tri in slice.iter() {
+                centroids.grow((tri[0] + tri[1] + tri[2]) / 3.0);
+            }
+            let size = centroids.size();
+            let axis = if size.x >= size.y && size.x >= size.z {
+                0
+            } else if size.y >= size.z {
+                1
+            } else {
+                2
+            };
+            let centroid = |tri: &Triangle| tri[0][axis] + tri[1][axis] + tri[2][axis];
+            let half = count / 2;
+            slice.select_nth_unstable_by(half, |a, b| centroid(a).total_cmp(&centroid(b)));
+
+            let child = nodes.len();
+            for (first, count) in [(first, half), (first + half, count - half)] {
+                nodes.push(BvhNode {
+                    bounds: triangle_bounds(&triangles[first..first + count]),
+                    first: first as u32,
+                    count: count as u32,
+                });
+            }
+            nodes[node].first = child as u32;
+            nodes[node].count = 0;
+            stack.extend([child, child + 1]);
+        }
+        Bvh { nodes, triangles }
+    }
+
+    fn leaf(&self, node: &BvhNode) -> &[Triangle] {
+        &self.triangles[node.first as usize..(node.first + node.count) as usize]
+    }
+
+    /// The closest point on any triangle to `p`, none without triangles.
+    pub fn closest(&self, p: Vec3) -> Option<Vec3> {
+        let mut best: Option<(f32, Vec3)> = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let best_distance = best.map_or(f32::INFINITY, |(d, _)| d);
+            if self.triangles.is_empty()
+                || bounds_distance_squared(&node.bounds, p) >= best_distance
+            {
+                continue;
+            }
+            if node.count > 0 {
+                for &tri in self.leaf(node) {
+                    let q = closest_point(p, tri);
+                    let d = q.distance_squared(p);
+                    if d < best.map_or(f32::INFINITY, |(d, _)| d) {
+                        best = Some((d, q));
+                    }
+                }
+                continue;
+            }
+            // Nearer child last, so it's searched first.
+            let (a, b) = (node.first as usize, node.first as usize + 1);
+            let distance = |i: usize| bounds_distance_squared(&self.nodes[i].bounds, p);
+            if distance(a) < distance(b) {
+                stack.extend([b, a]);
+            } else {
+                stack.extend([a, b]);
+            }
+        }
+        best.map(|(_, q)| q)
+    }
+
+    /// Coordinates along `axis` where the line through `origin` along `axis`
+    /// crosses the triangles, unsorted.
+    pub fn line_crossings(&self, origin: Vec3, axis: usize) -> Vec<f32> {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut crossings = Vec::new();
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let bounds = &node.bounds;
+            let outside = |i: usize| origin[i] < bounds.min[i] || origin[i] > bounds.max[i];
+            if self.triangles.is_empty() || outside(u) || outside(v) {
+                continue;
+            }
+            if node.count > 0 {
+                crossings.extend(
+                    self.leaf(node)
+                        .iter()
+                        .filter_map(|tri| line_crossing(origin, axis, tri)),
+                );
+            } else {
+                stack.extend([node.first as usize, node.first as usize + 1]);
+            }
+        }
+        crossings
+    }
+}
+
+/// Uniform scale and offset that center the vertices of `mesh` in the unit
+/// cube the field covers, in object space.
+pub(crate) fn fit(mesh: &Mesh) -> impl Fn(Vec3) -> Vec3 {
+    let mut bounds = Bounds::empty();
+    for v in &mesh.vertices {
+        bounds.grow(Vec3::from(v.pos));
+    }
+    let center = (bounds.min + bounds.max) * 0.5;
+    let scale = FIT / bounds.size().max_element().max(f32::EPSILON);
+    move |p| (p - center) * scale
+}
+
+/// The triangles of `mesh`, placed by `fit`.
+pub(crate) fn fit_to_grid(mesh: &Mesh) -> Vec<Triangle> {
+    let fit = fit(mesh);
+    let place = |i: u32| fit(Vec3::from(mesh.vertices[i as usize].pos));
+    mesh.indices
+        .chunks_exact(3)
+        .map(|tri| [place(tri[0]), place(tri[1]), place(tri[2])])
+        .collect()
+}
+
+/// Signed distance to `triangles`, in object space, sampled on a grid of
+/// `size`. Inside, where it's negative, is where at least two of the rays
+/// along the three axes through a sample cross the surface an odd number of
+/// times before it, so small holes in the mesh don't flip whole rows.
+pub(crate) fn signed_distance(triangles: Vec<Triangle>, size: UVec3) -> Volume {
+    let bvh = Bvh::new(triangles);
+    let mut volume = Volume::new(size);
+    let position = |x: u32, y: u32, z: u32| {
+        mesh::grid_to_object(size, Vec3::new(x as f32, y as f32, z as f32))
+    };
+
+    let mut votes = vec![0u8; volume.len()];
+    for axis in 0..3 {
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for j in 0..size[v] {
+            for i in 0..size[u] {
+                let mut start = UVec3::ZERO;
+                start[u] = i;
+                start[v] = j;
+                let mut origin = position(start.x, start.y, start.z);
+                origin[u] += RAY_JITTER[0];
+                origin[v] += RAY_JITTER[1];
+                let mut crossings = bvh.line_crossings(origin, axis);
+                crossings.sort_by(f32::total_cmp);
+
+                let mut crossed = 0;
+                for k in 0..size[axis] {
+                    let mut p = start;
+                    p[axis] = k;
+                    let t = position(p.x, p.y, p.z)[axis];
+                    while crossed < crossings.len() && crossings[crossed] < t {
+                        crossed += 1;
+                    }
+                    if crossed % 2 == 1 {
+                        votes[volume.index(p.x, p.y, p.z)] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    for z in 0..size.z {
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let p = position(x, y, z);
+                let i = volume.index(x, y, z);
+                let distance = bvh.closest(p).map_or(f32::INFINITY, |q| q.distance(p));
+                volume.set(x, y, z, if votes[i] >= 2 { -distance } else { distance });
+            }
+        }
+    }
+    volume
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::uvec3;
+
+    /// Distance to an axis aligned box centered on the origin.
+    fn box_distance(p: Vec3, half: Vec3) -> f32 {
+        let q = p.abs() - half;
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+    }
+
+    #[test]
+    fn closest_points_match_brute_force() {
+        // A bumpy grid of triangles, enough for a few levels.
+        let mut triangles = Vec::new();
+        let height = |x: f32, y: f32| (x * 7.0).sin() * (y * 5.0).cos() * 0.1;
+        for j in 0..12 {
+            for i in 0..12 {
+                let corner = |di: u32, dj: u32| {
+                    let (x, y) = ((i + di) as f32 / 12.0, (j + dj) as f32 / 12.0);
+                    Vec3::new(x, y, height(x, y))
+                };
+                triangles.push([corner(0, 0), corner(1, 0), corner(1, 1)]);
+                triangles.push([corner(0, 0), corner(1, 1), corner(0, 1)]);
+            }
+        }
+        let bvh = Bvh::new(triangles.clone());
+        assert!(bvh.nodes.len() > 1);
+        for i in 0..200 {
+            let t = i as f32;
+            let p = Vec3::new(
+                (t * 0.37).fract(),
+                (t * 0.61).fract(),
+                (t * 0.23).fract() - 0.5,
+            ) * 1.4
+                - 0.2;
+            let expected = triangles
+                .iter()
+                .map(|&tri| closest_point(p, tri).distance(p))
+                .fold(f32::INFINITY, f32::min);
+            let found = bvh.closest(p).unwrap().distance(p);
+            assert!((found - expected).abs() < 1e-6, "{} != {}", found, expected);
+        }
+        assert!(Bvh::new(Vec::new()).closest(Vec3::ZERO).is_none());
+    }
+
+    #[test]
+    fn box_field_matches_the_analytic_one() {
+        // Corners on round coordinates, the worst case for the sign rays.
+        let mesh = Mesh::cuboid(Vec3::new(-1.0, -0.5, -0.5), Vec3::new(1.0, 0.5, 0.5));
+        let size = uvec3(21, 21, 21);
+        let triangles = fit_to_grid(&mesh);
+        let half = Vec3::new(0.4, 0.2, 0.2);
+        let volume = signed_distance(triangles, size);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let p = mesh::grid_to_object(size, Vec3::new(x as f32, y as f32, z as f32));
+                    let expected = box_distance(p, half);
+                    let d = volume.get(x, y, z);
+                    assert!(
+                        (d - expected).abs() < 1e-4,
+                        "{:?}: {} != {}",
+                        p,
+                        d,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_small_hole_keeps_the_sign() {
+        let mut mesh = Mesh::cuboid(Vec3::splat(-0.5), Vec3::splat(0.5));
+        // Drop one triangle of the top face.
+        mesh.indices.drain(6..9);
+        let size = uvec3(16, 16, 16);
+        let volume = signed_distance(fit_to_grid(&mesh), size);
+        assert!(volume.get(8, 8, 8) < 0.0);
+        assert!(volume.get(8, 8, 15) > 0.0);
+        assert!(volume.get(0, 0, 0) > 0.0);
+    }
+}
@@ -43,6 +43,11 @@ pub(crate) fn compile(
     )
 }
 
+// naga's GLSL frontend can't parse storage images yet, so compute shaders are
+// written in WGSL and handed to wgpu directly.
 pub(crate) fn compile_cs(device: &wgpu::Device, cs_src: &str) -> ShaderModule {
-    compile_stage(device, cs_src, ShaderStage::Compute)
+    device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl(cs_src.into()),
+    })
 }
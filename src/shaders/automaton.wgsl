@@ -0,0 +1,64 @@
+// One step of a 3D cellular automaton, mirrors `Cells::step` in
+// `automaton.rs`. Reads the states from one texture and writes the next step
+// to the other, and their field to the brick atlas. The grid wraps around.
+
+struct Rule {
+    // Bit n is set when n live neighbours keep a live cell alive.
+    survival: u32;
+    // Bit n is set when n live neighbours bring an empty cell to life.
+    birth: u32;
+    states: u32;
+    // 1 for the 26 cell Moore neighbourhood, 0 for the 6 faces.
+    moore: u32;
+};
+
+[[group(0), binding(0)]]
+var cells_in: texture_3d<u32>;
+[[group(0), binding(1)]]
+var cells_out: texture_storage_3d<r32uint, write>;
+[[group(0), binding(2)]]
+var field_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(3)]]
+var<uniform> rule: Rule;
+[[group(0), binding(4)]]
+var<uniform> bricks: Bricks;
+
+fn live(coord: vec3<i32>) -> u32 {
+    let size = textureDimensions(cells_in);
+    let wrapped = (coord + size) % size;
+    return select(0u, 1u, textureLoad(cells_in, wrapped, 0).x == rule.states - 1u);
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (any(coord >= textureDimensions(cells_in))) {
+        return;
+    }
+
+    var neighbours = 0u;
+    for (var i = 0; i < 27; i = i + 1) {
+        let d = vec3<i32>(i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1);
+        let distance = abs(d.x) + abs(d.y) + abs(d.z);
+        if (distance == 1 || (distance > 1 && rule.moore != 0u)) {
+            neighbours = neighbours + live(coord + d);
+        }
+    }
+
+    let alive = rule.states - 1u;
+    var state = textureLoad(cells_in, coord, 0).x;
+    if (state == 0u) {
+        if (((rule.birth >> neighbours) & 1u) != 0u) {
+            state = alive;
+        }
+    } else if (state != alive || ((rule.survival >> neighbours) & 1u) == 0u) {
+        state = state - 1u;
+    }
+    textureStore(cells_out, coord, vec4<u32>(state));
+    for (var i = 0; i < 8; i = i + 1) {
+        let texel = copy_texel(bricks, coord, i);
+        if (texel.x >= 0) {
+            textureStore(field_out, texel, vec4<f32>(0.5 - f32(state) / f32(alive)));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// Addressing the field in a `BrickAtlas`. Every brick keeps a slab of `SLAB`
+// samples along each side, the samples on the faces between bricks are in the
+// slabs of both. Passes load a sample from one slab and store it to all of
+// them.
+
+struct Bricks {
+    // Samples along each axis.
+    grid: vec3<i32>;
+    bricks: vec3<i32>;
+    // Slabs along each axis of the atlas, see `tiles` in `bricks.rs`.
+    tiles: vec3<i32>;
+};
+
+let BRICK_CELLS: i32 = 16;
+let SLAB: i32 = 17;
+
+// Atlas texel of sample `p` in the slab of `brick`.
+fn slab_texel(b: Bricks, brick: vec3<i32>, p: vec3<i32>) -> vec3<i32> {
+    let slot = brick.x + b.bricks.x * (brick.y + b.bricks.y * brick.z);
+    let tile = vec3<i32>(
+        slot % b.tiles.x,
+        slot / b.tiles.x % b.tiles.y,
+        slot / (b.tiles.x * b.tiles.y),
+    );
+    return tile * SLAB + p - brick * BRICK_CELLS;
+}
+
+// Texel to load sample `p` from, clamped to the grid.
+fn brick_texel(b: Bricks, p: vec3<i32>) -> vec3<i32> {
+    let p = clamp(p, vec3<i32>(0), b.grid - 1);
+    return slab_texel(b, min(p / BRICK_CELLS, b.bricks - 1), p);
+}
+
+// Texel of copy `i` of 8 of sample `p`, with x at -1 when there's no such
+// copy. The copies are in the slabs one brick lower along the axes of the bits
+// of `i`.
+fn copy_texel(b: Bricks, p: vec3<i32>, i: i32) -> vec3<i32> {
+    let lower = vec3<i32>(i & 1, (i >> 1u) & 1, (i >> 2u) & 1);
+    let brick = min(p / BRICK_CELLS, b.bricks - 1) - lower;
+    if (any(brick < vec3<i32>(0)) || any(p - brick * BRICK_CELLS > vec3<i32>(BRICK_CELLS))) {
+        return vec3<i32>(-1);
+    }
+    return slab_texel(b, brick, p);
+}
@@ -0,0 +1,89 @@
+// Sculpting brushes, mirrors `Brush::apply` in `sculpt.rs`. The brush reads
+// the current values and writes into scratch textures, the touched box is
+// copied back afterwards.
+
+struct Brush {
+    // In normalized [0, 1] grid coordinates, like `volume::sphere`.
+    center: vec3<f32>;
+    radius: f32;
+    normal: vec3<f32>;
+    strength: f32;
+    color: vec4<f32>;
+    box_min: vec3<i32>;
+    kind: u32;
+    box_max: vec3<i32>;
+};
+
+[[group(0), binding(0)]]
+var field_in: texture_3d<f32>;
+[[group(0), binding(1)]]
+var field_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(2)]]
+var paint_in: texture_3d<f32>;
+[[group(0), binding(3)]]
+var paint_out: texture_storage_3d<rgba8unorm, write>;
+[[group(0), binding(4)]]
+var<uniform> brush: Brush;
+[[group(0), binding(5)]]
+var<uniform> bricks: Bricks;
+
+let ADD: u32 = 0u;
+let SUBTRACT: u32 = 1u;
+let SMOOTH: u32 = 2u;
+let FLATTEN: u32 = 3u;
+
+fn field(coord: vec3<i32>) -> f32 {
+    return textureLoad(field_in, brick_texel(bricks, coord), 0).x;
+}
+
+// Brush weight at `coord` of a grid of `size`, 0 outside the radius.
+fn weight(coord: vec3<i32>, size: vec3<i32>) -> f32 {
+    let p = (vec3<f32>(coord) + 0.5) / vec3<f32>(size);
+    let t = min(distance(p, brush.center) / brush.radius, 1.0);
+    let falloff = 1.0 - t * t;
+    return brush.strength * falloff * falloff;
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn sculpt([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = brush.box_min + vec3<i32>(id);
+    if (any(coord >= brush.box_max)) {
+        return;
+    }
+
+    let w = weight(coord, bricks.grid);
+    let value = field(coord);
+    var result = value;
+    if (brush.kind == ADD) {
+        result = value - w * brush.radius;
+    } else if (brush.kind == SUBTRACT) {
+        result = value + w * brush.radius;
+    } else if (brush.kind == SMOOTH) {
+        let average = (
+            field(coord - vec3<i32>(1, 0, 0)) + field(coord + vec3<i32>(1, 0, 0)) +
+            field(coord - vec3<i32>(0, 1, 0)) + field(coord + vec3<i32>(0, 1, 0)) +
+            field(coord - vec3<i32>(0, 0, 1)) + field(coord + vec3<i32>(0, 0, 1))
+        ) / 6.0;
+        result = mix(value, average, w);
+    } else if (brush.kind == FLATTEN) {
+        let p = (vec3<f32>(coord) + 0.5) / vec3<f32>(bricks.grid);
+        result = mix(value, dot(p - brush.center, brush.normal), w);
+    }
+    for (var i = 0; i < 8; i = i + 1) {
+        let texel = copy_texel(bricks, coord, i);
+        if (texel.x >= 0) {
+            textureStore(field_out, texel, vec4<f32>(result));
+        }
+    }
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn paint([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = brush.box_min + vec3<i32>(id);
+    if (any(coord >= brush.box_max)) {
+        return;
+    }
+
+    let color = textureLoad(paint_in, coord, 0);
+    textureStore(paint_out, coord, mix(color, vec4<f32>(brush.color.rgb, 1.0), weight(coord, textureDimensions(paint_in))));
+}
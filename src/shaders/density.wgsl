@@ -0,0 +1,82 @@
+// Must be kept in sync with `Timeline::density`.
+struct Density {
+    // `timeline::Shape`: 0 sphere, 1 metaballs, 2 noise.
+    shape: u32;
+    time: f32;
+    radius: f32;
+    orbit: f32;
+    frequency: f32;
+    amplitude: f32;
+    speed: f32;
+};
+
+[[group(0), binding(0)]]
+var scalar_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(1)]]
+var<uniform> density: Density;
+[[group(0), binding(2)]]
+var<uniform> bricks: Bricks;
+
+let TAU: f32 = 6.28318530718;
+let METABALLS: u32 = 3u;
+
+fn hash(c: vec3<i32>) -> f32 {
+    var h = (bitcast<u32>(c.x) * 0x8da6b343u) ^ (bitcast<u32>(c.y) * 0xd8163841u) ^
+        (bitcast<u32>(c.z) * 0xcb1ab31fu);
+    h = h ^ (h >> 15u);
+    h = h * 0x2c1b3c6du;
+    h = h ^ (h >> 12u);
+    return f32(h >> 8u) / 16777216.0;
+}
+
+fn value_noise(p: vec3<f32>) -> f32 {
+    let base = floor(p);
+    var f = p - base;
+    f = f * f * (3.0 - 2.0 * f);
+    let b = vec3<i32>(base);
+    let x00 = mix(hash(b), hash(b + vec3<i32>(1, 0, 0)), f.x);
+    let x10 = mix(hash(b + vec3<i32>(0, 1, 0)), hash(b + vec3<i32>(1, 1, 0)), f.x);
+    let x01 = mix(hash(b + vec3<i32>(0, 0, 1)), hash(b + vec3<i32>(1, 0, 1)), f.x);
+    let x11 = mix(hash(b + vec3<i32>(0, 1, 1)), hash(b + vec3<i32>(1, 1, 1)), f.x);
+    return mix(mix(x00, x10, f.y), mix(x01, x11, f.y), f.z);
+}
+
+fn metaballs(c: vec3<f32>) -> f32 {
+    var sum = 0.0;
+    for (var i = 0u; i < METABALLS; i = i + 1u) {
+        let angle = TAU * (density.speed * density.time + f32(i) / f32(METABALLS));
+        let height = (f32(i) - 1.0) * 0.5 * density.orbit;
+        let center = vec3<f32>(cos(angle), 0.0, sin(angle)) * density.orbit +
+            vec3<f32>(0.0, height, 0.0);
+        let d = c - center;
+        sum = sum + density.radius * density.radius / max(dot(d, d), 1e-6);
+    }
+    return density.radius * (1.0 / sqrt(sum) - 1.0);
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let size = bricks.grid;
+    let coord = vec3<i32>(id);
+    if (any(coord >= size)) {
+        return;
+    }
+
+    let p = (vec3<f32>(coord) + 0.5) / vec3<f32>(size);
+    let c = p - vec3<f32>(0.5);
+    var value = length(c) - density.radius;
+    if (density.shape == 1u) {
+        value = metaballs(c);
+    } else if (density.shape == 2u) {
+        let drift = vec3<f32>(0.0, density.speed * density.time, 0.0);
+        let noise = value_noise(p * density.frequency + drift);
+        value = value + density.amplitude * (2.0 * noise - 1.0);
+    }
+
+    for (var i = 0; i < 8; i = i + 1) {
+        let texel = copy_texel(bricks, coord, i);
+        if (texel.x >= 0) {
+            textureStore(scalar_out, texel, vec4<f32>(value));
+        }
+    }
+}
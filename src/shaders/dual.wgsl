@@ -0,0 +1,150 @@
+// Dual methods: one vertex per crossed cell, then one quad per crossed edge.
+
+let MASS_POINT_BIAS: f32 = 0.05;
+
+fn cell_crossings(cell: vec3<i32>, config: u32, mass_point: ptr<function, vec3<f32>>, normal: ptr<function, vec3<f32>>) -> u32 {
+    var count = 0u;
+    var sum = vec3<f32>(0.0);
+    var gradient_sum = vec3<f32>(0.0);
+    for (var edge = 0u; edge < 12u; edge = edge + 1u) {
+        let ends = edge_corners(edge);
+        if (((config >> ends.x) & 1u) != ((config >> ends.y) & 1u)) {
+            let crossing = edge_crossing(cell, edge);
+            sum = sum + crossing.pos;
+            gradient_sum = gradient_sum + crossing.gradient;
+            count = count + 1u;
+        }
+    }
+    *mass_point = sum / f32(max(count, 1u));
+    *normal = gradient_sum;
+    return count;
+}
+
+// The vertex passes also cover the cells just below the region, whose
+// vertices the quads on its lower faces need.
+fn apron_cell(id: vec3<u32>) -> vec3<i32> {
+    return max(params.region_min - 1, vec3<i32>(0)) + vec3<i32>(id);
+}
+
+fn emit_cell_vertex(cell: vec3<i32>, pos: vec3<f32>, gradient: vec3<f32>) {
+    let vertex = atomicAdd(&counters.vertex_count, 1u);
+    write_vertex(vertex, pos, gradient);
+    cell_vertex.data[cell_index(cell)] = vertex;
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn surface_nets_vertex([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let cell = apron_cell(id);
+    if (!in_region(cell)) {
+        return;
+    }
+
+    let config = cell_config(cell);
+    if (config == 0u || config == 255u) {
+        cell_vertex.data[cell_index(cell)] = NO_VERTEX;
+        return;
+    }
+
+    var mass_point: vec3<f32>;
+    var gradient_sum: vec3<f32>;
+    cell_crossings(cell, config, &mass_point, &gradient_sum);
+    emit_cell_vertex(cell, mass_point, gradient_sum);
+}
+
+fn solve(m: mat3x3<f32>, b: vec3<f32>) -> vec3<f32> {
+    let det = determinant(m);
+    if (abs(det) < 1e-8) {
+        return vec3<f32>(0.0);
+    }
+    // Cramer's rule.
+    let x = determinant(mat3x3<f32>(b, m[1], m[2]));
+    let y = determinant(mat3x3<f32>(m[0], b, m[2]));
+    let z = determinant(mat3x3<f32>(m[0], m[1], b));
+    return vec3<f32>(x, y, z) / det;
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn dual_contouring_vertex([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let cell = apron_cell(id);
+    if (!in_region(cell)) {
+        return;
+    }
+
+    let config = cell_config(cell);
+    if (config == 0u || config == 255u) {
+        cell_vertex.data[cell_index(cell)] = NO_VERTEX;
+        return;
+    }
+
+    var mass_point: vec3<f32>;
+    var gradient_sum: vec3<f32>;
+    cell_crossings(cell, config, &mass_point, &gradient_sum);
+
+    // Columns of AtA, naga can't add matrices.
+    var ata_x = vec3<f32>(MASS_POINT_BIAS, 0.0, 0.0);
+    var ata_y = vec3<f32>(0.0, MASS_POINT_BIAS, 0.0);
+    var ata_z = vec3<f32>(0.0, 0.0, MASS_POINT_BIAS);
+    var atb = vec3<f32>(0.0);
+    for (var edge = 0u; edge < 12u; edge = edge + 1u) {
+        let ends = edge_corners(edge);
+        if (((config >> ends.x) & 1u) != ((config >> ends.y) & 1u)) {
+            let crossing = edge_crossing(cell, edge);
+            var n = vec3<f32>(0.0);
+            if (length(crossing.gradient) > 0.0) {
+                n = normalize(crossing.gradient);
+            }
+            ata_x = ata_x + n * n.x;
+            ata_y = ata_y + n * n.y;
+            ata_z = ata_z + n * n.z;
+            atb = atb + n * dot(n, crossing.pos - mass_point);
+        }
+    }
+
+    let p = clamp(mass_point + solve(mat3x3<f32>(ata_x, ata_y, ata_z), atb), vec3<f32>(cell), vec3<f32>(cell + 1));
+    emit_cell_vertex(cell, p, gradient_sum);
+}
+
+// One invocation per region cell, handling the edges from its lower corner
+// towards +x, +y and +z.
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn quads([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let p = region_cell(id);
+    if (!in_region(p)) {
+        return;
+    }
+
+    let inside = density(p) < params.iso;
+    for (var axis = 0; axis < 3; axis = axis + 1) {
+        let e_a = vec3<i32>(vec3<bool>(axis == 0, axis == 1, axis == 2));
+        let e_b = vec3<i32>(vec3<bool>(axis == 2, axis == 0, axis == 1));
+        let e_c = vec3<i32>(vec3<bool>(axis == 1, axis == 2, axis == 0));
+        let b = component(p, e_b);
+        let c = component(p, e_c);
+        if (b == 0 || c == 0) {
+            continue;
+        }
+        if (inside == (density(p + e_a) < params.iso)) {
+            continue;
+        }
+
+        let v0 = cell_vertex.data[cell_index(p - e_b - e_c)];
+        let v1 = cell_vertex.data[cell_index(p - e_c)];
+        let v2 = cell_vertex.data[cell_index(p)];
+        let v3 = cell_vertex.data[cell_index(p - e_b)];
+
+        let base = atomicAdd(&counters.index_count, 6u);
+        write_index(base, v0);
+        write_index(base + 3u, v0);
+        if (inside) {
+            write_index(base + 1u, v1);
+            write_index(base + 2u, v2);
+            write_index(base + 4u, v2);
+            write_index(base + 5u, v3);
+        } else {
+            write_index(base + 1u, v2);
+            write_index(base + 2u, v1);
+            write_index(base + 4u, v3);
+            write_index(base + 5u, v2);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// Bindings and helpers shared by the extraction passes. Mirrors the CPU
+// extractors in `extract`, samples below `params.iso` are inside.
+
+struct Params {
+    iso: f32;
+    vertex_capacity: u32;
+    index_capacity: u32;
+    // Cells `region_min..region_max` are extracted, see `Region`.
+    region_min: vec3<i32>;
+    region_max: vec3<i32>;
+    // Level of detail, see `Lod`. Marching cubes cells are `step` samples
+    // wide, the other passes always run with a step of 1.
+    step: u32;
+    transitions: u32;
+};
+
+// Laid out as indirect draw arguments so the mesh can be drawn without a
+// readback, followed by the vertex counter. The finalize pass keeps the counts
+// before clamping them so a target that overflowed can be grown.
+struct Counters {
+    index_count: atomic<u32>;
+    instance_count: u32;
+    first_index: u32;
+    base_vertex: i32;
+    first_instance: u32;
+    vertex_count: atomic<u32>;
+    emitted_index_count: u32;
+    emitted_vertex_count: u32;
+};
+
+struct Floats {
+    data: array<f32>;
+};
+
+struct Uints {
+    data: array<u32>;
+};
+
+struct Ints {
+    data: array<i32>;
+};
+
+[[group(0), binding(0)]]
+var scalars: texture_3d<f32>;
+[[group(0), binding(1)]]
+var<storage, read_write> vertices: Floats;
+[[group(0), binding(2)]]
+var<storage, read_write> indices: Uints;
+[[group(0), binding(3)]]
+var<storage, read_write> counters: Counters;
+[[group(0), binding(4)]]
+var<uniform> params: Params;
+[[group(0), binding(5)]]
+var<storage, read_write> cell_vertex: Uints;
+[[group(0), binding(6)]]
+var<storage, read> tri_table: Ints;
+// Binding 7 is the transition table of `transvoxel.wgsl`.
+[[group(0), binding(8)]]
+var<uniform> bricks: Bricks;
+
+let NO_VERTEX: u32 = 0xffffffffu;
+
+fn grid_size() -> vec3<i32> {
+    return bricks.grid;
+}
+
+// Index into `cell_vertex`, which covers the cells of the region and the ones
+// just below it, see `scratch_cells` in `extract/gpu.rs`.
+fn cell_index(cell: vec3<i32>) -> u32 {
+    let first = max(params.region_min - 1, vec3<i32>(0));
+    let cells = params.region_max - first;
+    let p = cell - first;
+    return u32(p.x + cells.x * (p.y + cells.y * p.z));
+}
+
+// Cell handled by an invocation of a pass dispatched over the region.
+fn region_cell(id: vec3<u32>) -> vec3<i32> {
+    return params.region_min + vec3<i32>(id) * i32(params.step);
+}
+
+fn in_region(cell: vec3<i32>) -> bool {
+    return all(cell < params.region_max);
+}
+
+// Component of `v` along the unit vector `axis`, naga has no integer dot.
+fn component(v: vec3<i32>, axis: vec3<i32>) -> i32 {
+    return v.x * axis.x + v.y * axis.y + v.z * axis.z;
+}
+
+fn density(p: vec3<i32>) -> f32 {
+    return textureLoad(scalars, brick_texel(bricks, p), 0).x;
+}
+
+fn gradient(p: vec3<i32>) -> vec3<f32> {
+    let lo = max(p - 1, vec3<i32>(0));
+    let hi = min(p + 1, grid_size() - 1);
+    let span = vec3<f32>(max(hi - lo, vec3<i32>(1)));
+    return vec3<f32>(
+        density(vec3<i32>(hi.x, p.y, p.z)) - density(vec3<i32>(lo.x, p.y, p.z)),
+        density(vec3<i32>(p.x, hi.y, p.z)) - density(vec3<i32>(p.x, lo.y, p.z)),
+        density(vec3<i32>(p.x, p.y, hi.z)) - density(vec3<i32>(p.x, p.y, lo.z)),
+    ) / span;
+}
+
+fn corner(i: u32) -> vec3<i32> {
+    return vec3<i32>(i32((i ^ (i >> 1u)) & 1u), i32((i >> 1u) & 1u), i32((i >> 2u) & 1u));
+}
+
+fn edge_corners(edge: u32) -> vec2<u32> {
+    var edges = array<vec2<u32>, 12>(
+        vec2<u32>(0u, 1u),
+        vec2<u32>(1u, 2u),
+        vec2<u32>(2u, 3u),
+        vec2<u32>(3u, 0u),
+        vec2<u32>(4u, 5u),
+        vec2<u32>(5u, 6u),
+        vec2<u32>(6u, 7u),
+        vec2<u32>(7u, 4u),
+        vec2<u32>(0u, 4u),
+        vec2<u32>(1u, 5u),
+        vec2<u32>(2u, 6u),
+        vec2<u32>(3u, 7u),
+    );
+    return edges[edge];
+}
+
+struct Crossing {
+    pos: vec3<f32>;
+    gradient: vec3<f32>;
+};
+
+// Crossing on the segment between samples `pa` and `pb`, which don't have to
+// be neighbours.
+fn segment_crossing(pa: vec3<i32>, pb: vec3<i32>) -> Crossing {
+    let da = density(pa);
+    let db = density(pb);
+    let t = clamp((params.iso - da) / (db - da), 0.0, 1.0);
+
+    var crossing: Crossing;
+    crossing.pos = mix(vec3<f32>(pa), vec3<f32>(pb), vec3<f32>(t));
+    crossing.gradient = mix(gradient(pa), gradient(pb), vec3<f32>(t));
+    return crossing;
+}
+
+fn edge_crossing(cell: vec3<i32>, edge: u32) -> Crossing {
+    let ends = edge_corners(edge);
+    let step = i32(params.step);
+    return segment_crossing(cell + corner(ends.x) * step, cell + corner(ends.y) * step);
+}
+
+fn cell_config(cell: vec3<i32>) -> u32 {
+    var config = 0u;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        if (density(cell + corner(i) * i32(params.step)) < params.iso) {
+            config = config | (1u << i);
+        }
+    }
+    return config;
+}
+
+// Same as `squeeze` in `extract/transvoxel.rs`, makes room for the
+// transition cells.
+let TRANSITION_WIDTH: f32 = 0.5;
+
+fn squeeze(grid_pos: vec3<f32>) -> vec3<f32> {
+    let step = f32(params.step);
+    var p = grid_pos;
+    for (var face = 0u; face < 6u; face = face + 1u) {
+        if ((params.transitions & (1u << face)) == 0u) {
+            continue;
+        }
+        let axis = face / 2u;
+        var plane = f32(params.region_min[axis]);
+        var inward = 1.0;
+        if (face % 2u == 1u) {
+            plane = f32(params.region_max[axis]);
+            inward = -1.0;
+        }
+        let d = (p[axis] - plane) * inward;
+        if (d >= 0.0 && d < step) {
+            p[axis] = plane + inward * (TRANSITION_WIDTH * step + d * (1.0 - TRANSITION_WIDTH));
+        }
+    }
+    return p;
+}
+
+fn store_vertex(i: u32, grid_pos: vec3<f32>, grid_gradient: vec3<f32>) {
+    if (i >= params.vertex_capacity) {
+        return;
+    }
+    let size = vec3<f32>(grid_size());
+    let pos = (grid_pos + 0.5) / size - 0.5;
+    let normal = normalize(grid_gradient * size);
+    let o = i * 6u;
+    vertices.data[o] = pos.x;
+    vertices.data[o + 1u] = pos.y;
+    vertices.data[o + 2u] = pos.z;
+    vertices.data[o + 3u] = normal.x;
+    vertices.data[o + 4u] = normal.y;
+    vertices.data[o + 5u] = normal.z;
+}
+
+fn write_vertex(i: u32, grid_pos: vec3<f32>, grid_gradient: vec3<f32>) {
+    store_vertex(i, squeeze(grid_pos), grid_gradient);
+}
+
+// Triangles referencing vertices past the capacity collapse to vertex 0.
+fn write_index(i: u32, vertex: u32) {
+    if (i >= params.index_capacity) {
+        return;
+    }
+    if (vertex >= params.vertex_capacity) {
+        indices.data[i] = 0u;
+    } else {
+        indices.data[i] = vertex;
+    }
+}
@@ -0,0 +1,11 @@
+// Clamps the counters to what fit into the buffers before they're used as
+// draw arguments, keeping what was emitted.
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    let index_count = atomicLoad(&counters.index_count);
+    counters.emitted_index_count = index_count;
+    atomicStore(&counters.index_count, min(index_count, params.index_capacity / 3u * 3u));
+    let vertex_count = atomicLoad(&counters.vertex_count);
+    counters.emitted_vertex_count = vertex_count;
+    atomicStore(&counters.vertex_count, min(vertex_count, params.vertex_capacity));
+}
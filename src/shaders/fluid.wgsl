@@ -0,0 +1,175 @@
+// Stable fluids on the grid, one entry point per pass of `Fluid::encode` in
+// `fluid.rs`. Positions and velocities are in grid samples, the border
+// samples are solid walls.
+
+struct Params {
+    emitter: vec3<f32>;
+    radius: f32;
+    emitter_velocity: vec3<f32>;
+    // Density added per unit of time at the emitter's center, 0 when not
+    // emitting.
+    amount: f32;
+    dt: f32;
+    buoyancy: f32;
+    dissipation: f32;
+    level: f32;
+};
+
+[[group(0), binding(0)]]
+var velocity_in: texture_3d<f32>;
+[[group(0), binding(1)]]
+var density_in: texture_3d<f32>;
+[[group(0), binding(2)]]
+var pressure_in: texture_3d<f32>;
+[[group(0), binding(3)]]
+var divergence_in: texture_3d<f32>;
+[[group(0), binding(4)]]
+var velocity_out: texture_storage_3d<rgba32float, write>;
+[[group(0), binding(5)]]
+var scalar_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(6)]]
+var field_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(7)]]
+var<uniform> params: Params;
+[[group(0), binding(8)]]
+var<uniform> bricks: Bricks;
+
+fn clamp_coord(coord: vec3<i32>) -> vec3<i32> {
+    return clamp(coord, vec3<i32>(0), textureDimensions(velocity_in) - 1);
+}
+
+fn velocity(coord: vec3<i32>) -> vec3<f32> {
+    return textureLoad(velocity_in, clamp_coord(coord), 0).xyz;
+}
+
+fn density(coord: vec3<i32>) -> f32 {
+    return textureLoad(density_in, clamp_coord(coord), 0).x;
+}
+
+fn pressure(coord: vec3<i32>) -> f32 {
+    return textureLoad(pressure_in, clamp_coord(coord), 0).x;
+}
+
+// First of the 8 samples a trilinear lookup at `p` blends.
+fn cell(p: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor(p));
+}
+
+fn sample_velocity(p: vec3<f32>) -> vec3<f32> {
+    let base = cell(p);
+    let f = p - vec3<f32>(base);
+    let x00 = mix(velocity(base), velocity(base + vec3<i32>(1, 0, 0)), f.x);
+    let x10 = mix(velocity(base + vec3<i32>(0, 1, 0)), velocity(base + vec3<i32>(1, 1, 0)), f.x);
+    let x01 = mix(velocity(base + vec3<i32>(0, 0, 1)), velocity(base + vec3<i32>(1, 0, 1)), f.x);
+    let x11 = mix(velocity(base + vec3<i32>(0, 1, 1)), velocity(base + vec3<i32>(1, 1, 1)), f.x);
+    return mix(mix(x00, x10, f.y), mix(x01, x11, f.y), f.z);
+}
+
+fn sample_density(p: vec3<f32>) -> f32 {
+    let base = cell(p);
+    let f = p - vec3<f32>(base);
+    let x00 = mix(density(base), density(base + vec3<i32>(1, 0, 0)), f.x);
+    let x10 = mix(density(base + vec3<i32>(0, 1, 0)), density(base + vec3<i32>(1, 1, 0)), f.x);
+    let x01 = mix(density(base + vec3<i32>(0, 0, 1)), density(base + vec3<i32>(1, 0, 1)), f.x);
+    let x11 = mix(density(base + vec3<i32>(0, 1, 1)), density(base + vec3<i32>(1, 1, 1)), f.x);
+    return mix(mix(x00, x10, f.y), mix(x01, x11, f.y), f.z);
+}
+
+// Emitter weight at `coord`, 0 outside its radius or when not emitting.
+fn emitter_weight(coord: vec3<i32>) -> f32 {
+    if (params.amount <= 0.0) {
+        return 0.0;
+    }
+    let t = min(distance(vec3<f32>(coord), params.emitter) / params.radius, 1.0);
+    let falloff = 1.0 - t * t;
+    return falloff * falloff;
+}
+
+fn outside(coord: vec3<i32>) -> bool {
+    return any(coord >= textureDimensions(velocity_in));
+}
+
+fn on_border(coord: vec3<i32>) -> bool {
+    return any(coord == vec3<i32>(0)) || any(coord == textureDimensions(velocity_in) - 1);
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn advect_velocity([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (outside(coord)) {
+        return;
+    }
+
+    let p = vec3<f32>(coord);
+    var v = sample_velocity(p - params.dt * velocity(coord));
+    v.y = v.y + params.dt * params.buoyancy * density(coord);
+    v = mix(v, params.emitter_velocity, emitter_weight(coord));
+    textureStore(velocity_out, coord, vec4<f32>(v, 0.0));
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn advect_density([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (outside(coord)) {
+        return;
+    }
+
+    let p = vec3<f32>(coord);
+    var d = sample_density(p - params.dt * velocity(coord));
+    d = d * max(1.0 - params.dissipation * params.dt, 0.0);
+    d = d + params.dt * params.amount * emitter_weight(coord);
+    textureStore(scalar_out, coord, vec4<f32>(d));
+    for (var i = 0; i < 8; i = i + 1) {
+        let texel = copy_texel(bricks, coord, i);
+        if (texel.x >= 0) {
+            textureStore(field_out, texel, vec4<f32>(params.level - d));
+        }
+    }
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn divergence([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (outside(coord)) {
+        return;
+    }
+
+    let dx = velocity(coord + vec3<i32>(1, 0, 0)).x - velocity(coord - vec3<i32>(1, 0, 0)).x;
+    let dy = velocity(coord + vec3<i32>(0, 1, 0)).y - velocity(coord - vec3<i32>(0, 1, 0)).y;
+    let dz = velocity(coord + vec3<i32>(0, 0, 1)).z - velocity(coord - vec3<i32>(0, 0, 1)).z;
+    textureStore(scalar_out, coord, vec4<f32>(0.5 * (dx + dy + dz)));
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn jacobi([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (outside(coord)) {
+        return;
+    }
+
+    let neighbours =
+        pressure(coord - vec3<i32>(1, 0, 0)) + pressure(coord + vec3<i32>(1, 0, 0)) +
+        pressure(coord - vec3<i32>(0, 1, 0)) + pressure(coord + vec3<i32>(0, 1, 0)) +
+        pressure(coord - vec3<i32>(0, 0, 1)) + pressure(coord + vec3<i32>(0, 0, 1));
+    let divergence = textureLoad(divergence_in, coord, 0).x;
+    textureStore(scalar_out, coord, vec4<f32>((neighbours - divergence) / 6.0));
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn project([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (outside(coord)) {
+        return;
+    }
+
+    if (on_border(coord)) {
+        textureStore(velocity_out, coord, vec4<f32>(0.0));
+        return;
+    }
+    let gradient = 0.5 * vec3<f32>(
+        pressure(coord + vec3<i32>(1, 0, 0)) - pressure(coord - vec3<i32>(1, 0, 0)),
+        pressure(coord + vec3<i32>(0, 1, 0)) - pressure(coord - vec3<i32>(0, 1, 0)),
+        pressure(coord + vec3<i32>(0, 0, 1)) - pressure(coord - vec3<i32>(0, 0, 1)),
+    );
+    textureStore(velocity_out, coord, vec4<f32>(velocity(coord) - gradient, 0.0));
+}
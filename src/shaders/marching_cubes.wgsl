@@ -0,0 +1,28 @@
+// One invocation per cell, vertices aren't shared between triangles.
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let cell = region_cell(id);
+    if (!in_region(cell)) {
+        return;
+    }
+
+    let row = cell_config(cell) * 16u;
+    var count = 0u;
+    loop {
+        if (count >= 15u || tri_table.data[row + count] < 0) {
+            break;
+        }
+        count = count + 1u;
+    }
+    if (count == 0u) {
+        return;
+    }
+
+    let vertex_base = atomicAdd(&counters.vertex_count, count);
+    let index_base = atomicAdd(&counters.index_count, count);
+    for (var i = 0u; i < count; i = i + 1u) {
+        let crossing = edge_crossing(cell, u32(tri_table.data[row + i]));
+        write_vertex(vertex_base + i, crossing.pos, crossing.gradient);
+        write_index(index_base + i, vertex_base + i);
+    }
+}
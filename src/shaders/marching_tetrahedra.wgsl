@@ -0,0 +1,93 @@
+// One invocation per cell, split into the same six tetrahedra as the CPU
+// version. Vertices aren't shared between triangles.
+
+fn tet_corner(tet: u32, k: u32) -> u32 {
+    var tets = array<u32, 24>(
+        0u, 1u, 2u, 6u,
+        0u, 1u, 5u, 6u,
+        0u, 3u, 2u, 6u,
+        0u, 3u, 7u, 6u,
+        0u, 4u, 5u, 6u,
+        0u, 4u, 7u, 6u,
+    );
+    return tets[tet * 4u + k];
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let cell = region_cell(id);
+    if (!in_region(cell)) {
+        return;
+    }
+
+    for (var tet = 0u; tet < 6u; tet = tet + 1u) {
+        var inside: array<vec3<i32>, 4>;
+        var outside: array<vec3<i32>, 4>;
+        var inside_count = 0u;
+        var outside_count = 0u;
+        for (var k = 0u; k < 4u; k = k + 1u) {
+            let p = cell + corner(tet_corner(tet, k));
+            if (density(p) < params.iso) {
+                inside[inside_count] = p;
+                inside_count = inside_count + 1u;
+            } else {
+                outside[outside_count] = p;
+                outside_count = outside_count + 1u;
+            }
+        }
+        if (inside_count == 0u || outside_count == 0u) {
+            continue;
+        }
+
+        var polygon: array<Crossing, 4>;
+        var count = 3u;
+        if (inside_count == 1u) {
+            for (var k = 0u; k < 3u; k = k + 1u) {
+                polygon[k] = segment_crossing(inside[0], outside[k]);
+            }
+        } else if (outside_count == 1u) {
+            for (var k = 0u; k < 3u; k = k + 1u) {
+                polygon[k] = segment_crossing(inside[k], outside[0]);
+            }
+        } else {
+            count = 4u;
+            polygon[0] = segment_crossing(inside[0], outside[0]);
+            polygon[1] = segment_crossing(inside[1], outside[0]);
+            polygon[2] = segment_crossing(inside[1], outside[1]);
+            polygon[3] = segment_crossing(inside[0], outside[1]);
+        }
+
+        // Wind the polygon so it faces from the inside to the outside corners.
+        var inside_sum = vec3<f32>(0.0);
+        for (var k = 0u; k < inside_count; k = k + 1u) {
+            inside_sum = inside_sum + vec3<f32>(inside[k]);
+        }
+        var outside_sum = vec3<f32>(0.0);
+        for (var k = 0u; k < outside_count; k = k + 1u) {
+            outside_sum = outside_sum + vec3<f32>(outside[k]);
+        }
+        let outward = outside_sum / f32(outside_count) - inside_sum / f32(inside_count);
+        var normal = vec3<f32>(0.0);
+        for (var k = 0u; k < count; k = k + 1u) {
+            normal = normal + cross(polygon[k].pos, polygon[(k + 1u) % count].pos);
+        }
+        let flip = dot(normal, outward) < 0.0;
+
+        let vertex_base = atomicAdd(&counters.vertex_count, count);
+        let index_base = atomicAdd(&counters.index_count, (count - 2u) * 3u);
+        for (var k = 0u; k < count; k = k + 1u) {
+            write_vertex(vertex_base + k, polygon[k].pos, polygon[k].gradient);
+        }
+        for (var k = 1u; k + 1u < count; k = k + 1u) {
+            let i = index_base + (k - 1u) * 3u;
+            write_index(i, vertex_base);
+            if (flip) {
+                write_index(i + 1u, vertex_base + k + 1u);
+                write_index(i + 2u, vertex_base + k);
+            } else {
+                write_index(i + 1u, vertex_base + k);
+                write_index(i + 2u, vertex_base + k + 1u);
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+#version 460
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec3 v_pos;
+layout(location = 0) out vec4 o_color;
+
+layout(set = 0, binding = 1) uniform Tint {
+    vec4 u_color;
+};
+
+// Painted color, transparent where nothing was painted. Covers the unit cube
+// the volume is drawn in.
+layout(set = 0, binding = 2) uniform texture3D t_paint;
+layout(set = 0, binding = 3) uniform sampler s_paint;
+
+void main() {
+    vec3 light_dir = normalize(vec3(0.4, 0.8, 0.5));
+    float diffuse = abs(dot(normalize(v_normal), light_dir));
+    vec4 paint = texture(sampler3D(t_paint, s_paint), v_pos + 0.5);
+    vec3 base = mix(u_color.rgb, paint.rgb, paint.a);
+    o_color = vec4(base * (0.2 + 0.8 * diffuse), 1.0);
+}
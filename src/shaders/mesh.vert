@@ -0,0 +1,20 @@
+#version 460
+
+layout(location = 0) in vec3 a_pos;
+layout(location = 1) in vec3 a_normal;
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec3 v_pos;
+
+struct Uniforms {
+    mat4 u_transform;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer Locals {
+    Uniforms uniforms[];
+};
+
+void main() {
+    v_normal = a_normal;
+    v_pos = a_pos;
+    gl_Position = uniforms[gl_InstanceIndex].u_transform * vec4(a_pos, 1.0);
+}
@@ -0,0 +1,106 @@
+// Marches a single ray through the field, mirrors `pick::cast_ray`.
+
+struct Ray {
+    // In object space, the volume fills the unit cube around the origin.
+    origin: vec3<f32>;
+    iso: f32;
+    dir: vec3<f32>;
+    _pad: f32;
+};
+
+struct PickResult {
+    pos: vec3<f32>;
+    hit: u32;
+    normal: vec3<f32>;
+    value: f32;
+};
+
+[[group(0), binding(0)]]
+var scalars: texture_3d<f32>;
+[[group(0), binding(1)]]
+var<uniform> ray: Ray;
+[[group(0), binding(2)]]
+var<storage, read_write> result: PickResult;
+[[group(0), binding(3)]]
+var<uniform> bricks: Bricks;
+
+fn load(p: vec3<i32>) -> f32 {
+    return textureLoad(scalars, brick_texel(bricks, p), 0).x;
+}
+
+// Trilinear interpolation at `p` in grid coordinates, like `Volume::sample`.
+fn sample(p: vec3<f32>) -> f32 {
+    let size = vec3<f32>(bricks.grid);
+    let p = clamp(p, vec3<f32>(0.0), size - 1.0);
+    let lo = vec3<i32>(floor(p));
+    let t = p - floor(p);
+    let x00 = mix(load(lo), load(lo + vec3<i32>(1, 0, 0)), t.x);
+    let x10 = mix(load(lo + vec3<i32>(0, 1, 0)), load(lo + vec3<i32>(1, 1, 0)), t.x);
+    let x01 = mix(load(lo + vec3<i32>(0, 0, 1)), load(lo + vec3<i32>(1, 0, 1)), t.x);
+    let x11 = mix(load(lo + vec3<i32>(0, 1, 1)), load(lo + vec3<i32>(1, 1, 1)), t.x);
+    return mix(mix(x00, x10, t.y), mix(x01, x11, t.y), t.z);
+}
+
+fn to_grid(p: vec3<f32>) -> vec3<f32> {
+    return (p + 0.5) * vec3<f32>(bricks.grid) - 0.5;
+}
+
+fn value_at(t: f32) -> f32 {
+    return sample(to_grid(ray.origin + ray.dir * t));
+}
+
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    result.hit = 0u;
+
+    // Clip the ray to the unit cube the volume is drawn in.
+    let inv = 1.0 / ray.dir;
+    let t0 = (vec3<f32>(-0.5) - ray.origin) * inv;
+    let t1 = (vec3<f32>(0.5) - ray.origin) * inv;
+    let t_min = min(t0, t1);
+    let t_max = max(t0, t1);
+    let near = max(max(max(t_min.x, t_min.y), t_min.z), 0.0);
+    let far = min(min(t_max.x, t_max.y), t_max.z);
+    if (near >= far) {
+        return;
+    }
+
+    let size = vec3<f32>(bricks.grid);
+    let step = 0.5 / max(max(size.x, size.y), size.z);
+    var t = near;
+    loop {
+        if (t >= far) {
+            return;
+        }
+        let next = min(t + step, far);
+        if (value_at(next) < ray.iso) {
+            // Bisect down to the crossing.
+            var lo = t;
+            var hi = next;
+            for (var i = 0; i < 16; i = i + 1) {
+                let mid = 0.5 * (lo + hi);
+                if (value_at(mid) < ray.iso) {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                }
+            }
+            let pos = ray.origin + ray.dir * hi;
+            let p = to_grid(pos);
+            let gradient = vec3<f32>(
+                sample(p + vec3<f32>(1.0, 0.0, 0.0)) - sample(p - vec3<f32>(1.0, 0.0, 0.0)),
+                sample(p + vec3<f32>(0.0, 1.0, 0.0)) - sample(p - vec3<f32>(0.0, 1.0, 0.0)),
+                sample(p + vec3<f32>(0.0, 0.0, 1.0)) - sample(p - vec3<f32>(0.0, 0.0, 1.0)),
+            );
+            result.pos = pos;
+            result.hit = 1u;
+            result.normal = vec3<f32>(0.0);
+            if (any(gradient != vec3<f32>(0.0))) {
+                result.normal = normalize(gradient * size);
+            }
+            result.value = sample(p);
+            return;
+        }
+        t = next;
+    }
+}
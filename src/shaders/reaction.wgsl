@@ -0,0 +1,53 @@
+// Gray-Scott reaction-diffusion, mirrors `Chemicals::step` in `reaction.rs`.
+// Reads u and v from one state texture and writes the next step to the other,
+// and the field of v to the brick atlas.
+
+struct Rates {
+    feed: f32;
+    kill: f32;
+    diffusion_u: f32;
+    diffusion_v: f32;
+};
+
+[[group(0), binding(0)]]
+var state_in: texture_3d<f32>;
+[[group(0), binding(1)]]
+var state_out: texture_storage_3d<rg32float, write>;
+[[group(0), binding(2)]]
+var field_out: texture_storage_3d<r32float, write>;
+[[group(0), binding(3)]]
+var<uniform> rates: Rates;
+[[group(0), binding(4)]]
+var<uniform> bricks: Bricks;
+
+// Must be kept in sync with `reaction::LEVEL`.
+let LEVEL: f32 = 0.2;
+
+fn state(coord: vec3<i32>) -> vec2<f32> {
+    return textureLoad(state_in, clamp(coord, vec3<i32>(0), textureDimensions(state_in) - 1), 0).xy;
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let coord = vec3<i32>(id);
+    if (any(coord >= textureDimensions(state_in))) {
+        return;
+    }
+
+    let c = state(coord);
+    let laplacian =
+        state(coord - vec3<i32>(1, 0, 0)) + state(coord + vec3<i32>(1, 0, 0)) +
+        state(coord - vec3<i32>(0, 1, 0)) + state(coord + vec3<i32>(0, 1, 0)) +
+        state(coord - vec3<i32>(0, 0, 1)) + state(coord + vec3<i32>(0, 0, 1)) -
+        6.0 * c;
+    let uvv = c.x * c.y * c.y;
+    let u = c.x + rates.diffusion_u * laplacian.x - uvv + rates.feed * (1.0 - c.x);
+    let v = c.y + rates.diffusion_v * laplacian.y + uvv - (rates.feed + rates.kill) * c.y;
+    textureStore(state_out, coord, vec4<f32>(u, v, 0.0, 0.0));
+    for (var i = 0; i < 8; i = i + 1) {
+        let texel = copy_texel(bricks, coord, i);
+        if (texel.x >= 0) {
+            textureStore(field_out, texel, vec4<f32>(LEVEL - v));
+        }
+    }
+}
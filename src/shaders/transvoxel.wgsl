@@ -0,0 +1,128 @@
+// Transvoxel transition cells on the faces of the region whose neighbour is
+// one level finer, one invocation per coarse cell of a face. Mirrors
+// `extract/transvoxel.rs`, vertices aren't shared between triangles.
+
+[[group(0), binding(7)]]
+var<storage, read> transition_table: Ints;
+
+let TRANSITION_ROW: u32 = 40u;
+
+// Position of a transition cell corner in half steps along the face, corners
+// 9..13 are copies of 0, 2, 6 and 8 on the coarse side.
+fn transition_corner(i: u32) -> vec2<i32> {
+    var corners = array<vec2<i32>, 13>(
+        vec2<i32>(0, 0),
+        vec2<i32>(1, 0),
+        vec2<i32>(2, 0),
+        vec2<i32>(0, 1),
+        vec2<i32>(1, 1),
+        vec2<i32>(2, 1),
+        vec2<i32>(0, 2),
+        vec2<i32>(1, 2),
+        vec2<i32>(2, 2),
+        vec2<i32>(0, 0),
+        vec2<i32>(2, 0),
+        vec2<i32>(0, 2),
+        vec2<i32>(2, 2),
+    );
+    return corners[i];
+}
+
+fn transition_edge(edge: u32) -> vec2<u32> {
+    var edges = array<vec2<u32>, 16>(
+        vec2<u32>(0u, 1u),
+        vec2<u32>(1u, 2u),
+        vec2<u32>(3u, 4u),
+        vec2<u32>(4u, 5u),
+        vec2<u32>(6u, 7u),
+        vec2<u32>(7u, 8u),
+        vec2<u32>(0u, 3u),
+        vec2<u32>(3u, 6u),
+        vec2<u32>(1u, 4u),
+        vec2<u32>(4u, 7u),
+        vec2<u32>(2u, 5u),
+        vec2<u32>(5u, 8u),
+        vec2<u32>(9u, 10u),
+        vec2<u32>(11u, 12u),
+        vec2<u32>(9u, 11u),
+        vec2<u32>(10u, 12u),
+    );
+    return edges[edge];
+}
+
+fn axis_vector(axis: u32) -> vec3<i32> {
+    return vec3<i32>(vec3<bool>(axis == 0u, axis == 1u, axis == 2u));
+}
+
+[[stage(compute), workgroup_size(4, 4, 1)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let face = id.z;
+    if (face >= 6u || (params.transitions & (1u << face)) == 0u) {
+        return;
+    }
+    let axis = face / 2u;
+    let e_a = axis_vector(axis);
+    let e_u = axis_vector((axis + 1u) % 3u);
+    let e_v = axis_vector((axis + 2u) % 3u);
+    let max_face = face % 2u == 1u;
+
+    let step = i32(params.step);
+    let size = params.region_max - params.region_min;
+    if (i32(id.x) >= component(size, e_u) / step || i32(id.y) >= component(size, e_v) / step) {
+        return;
+    }
+
+    var origin = params.region_min + (e_u * i32(id.x) + e_v * i32(id.y)) * step;
+    if (max_face) {
+        origin = origin + e_a * size;
+    }
+    let half = step / 2;
+
+    var config = 0u;
+    for (var i = 0u; i < 9u; i = i + 1u) {
+        let c = transition_corner(i);
+        if (density(origin + (e_u * c.x + e_v * c.y) * half) < params.iso) {
+            config = config | (1u << i);
+        }
+    }
+
+    let row = config * TRANSITION_ROW;
+    var count = 0u;
+    loop {
+        if (count >= TRANSITION_ROW - 1u || transition_table.data[row + count] < 0) {
+            break;
+        }
+        count = count + 1u;
+    }
+    if (count == 0u) {
+        return;
+    }
+
+    let vertex_base = atomicAdd(&counters.vertex_count, count);
+    let index_base = atomicAdd(&counters.index_count, count);
+    for (var i = 0u; i < count; i = i + 1u) {
+        let edge = u32(transition_table.data[row + i]);
+        let ends = transition_edge(edge);
+        let a = transition_corner(ends.x);
+        let b = transition_corner(ends.y);
+        let crossing = segment_crossing(
+            origin + (e_u * a.x + e_v * a.y) * half,
+            origin + (e_u * b.x + e_v * b.y) * half,
+        );
+        // Only the coarse side is squeezed, the fine side matches the neighbour.
+        if (edge < 12u) {
+            store_vertex(vertex_base + i, crossing.pos, crossing.gradient);
+        } else {
+            write_vertex(vertex_base + i, crossing.pos, crossing.gradient);
+        }
+
+        // The frame is left-handed on max faces.
+        var k = i;
+        if (max_face && i % 3u == 1u) {
+            k = i + 1u;
+        } else if (max_face && i % 3u == 2u) {
+            k = i - 1u;
+        }
+        write_index(index_base + k, vertex_base + i);
+    }
+}
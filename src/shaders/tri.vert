@@ -6,6 +6,8 @@ layout(location = 0) out vec4 v_color;
 
 struct Uniforms {
     mat4 u_transform;
+    // Zero alpha keeps the vertex color.
+    vec4 u_color;
 };
 
 layout(std430, set = 0, binding = 0) readonly buffer Locals {
@@ -13,6 +15,7 @@ layout(std430, set = 0, binding = 0) readonly buffer Locals {
 };
 
 void main() {
-    v_color = a_color;
-    gl_Position  =uniforms[gl_InstanceIndex].u_transform * vec4(a_pos, 1.0);
+    Uniforms instance = uniforms[gl_InstanceIndex];
+    v_color = instance.u_color.a > 0.0 ? instance.u_color : a_color;
+    gl_Position = instance.u_transform * vec4(a_pos, 1.0);
 }
@@ -0,0 +1,216 @@
+use crate::contour::{self, Contour};
+use crate::volume::{Axis, Slice, Volume};
+use egui::{Color32, ColorImage, Context, Pos2, Stroke, TextureHandle};
+use serde::{Deserialize, Serialize};
+
+const VIEW_SIZE: f32 = 320.0;
+
+/// What a project keeps of the slice view.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SliceSettings {
+    pub axis: Axis,
+    pub layer: u32,
+    pub iso_levels: Vec<f32>,
+}
+
+impl Default for SliceSettings {
+    fn default() -> SliceSettings {
+        SliceSettings {
+            axis: Axis::Z,
+            layer: 0,
+            iso_levels: vec![-0.1, 0.0, 0.1],
+        }
+    }
+}
+
+/// Egui window showing one axis-aligned slice of the volume with its
+/// marching squares iso-contours drawn on top.
+pub(crate) struct SliceView {
+    axis: Axis,
+    layer: u32,
+    iso_levels: Vec<f32>,
+    svg_path: String,
+    // The window was expanded on the last frame.
+    open: bool,
+
+    dirty: bool,
+    slice: Option<Slice>,
+    contours: Vec<Contour>,
+    texture: Option<TextureHandle>,
+}
+
+impl SliceView {
+    pub fn new() -> SliceView {
+        let SliceSettings {
+            axis,
+            layer,
+            iso_levels,
+        } = SliceSettings::default();
+        SliceView {
+            axis,
+            layer,
+            iso_levels,
+            svg_path: "contours.svg".to_owned(),
+            open: true,
+            dirty: true,
+            slice: None,
+            contours: Vec::new(),
+            texture: None,
+        }
+    }
+
+    pub fn settings(&self) -> SliceSettings {
+        SliceSettings {
+            axis: self.axis,
+            layer: self.layer,
+            iso_levels: self.iso_levels.clone(),
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: SliceSettings) {
+        self.axis = settings.axis;
+        self.layer = settings.layer;
+        self.iso_levels = settings.iso_levels;
+        self.dirty = true;
+    }
+
+    /// Axis and layer of the slice shown.
+    pub fn slice(&self) -> (Axis, u32) {
+        (self.axis, self.layer)
+    }
+
+    pub fn set_layer(&mut self, layer: u32) {
+        self.dirty |= layer != self.layer;
+        self.layer = layer;
+    }
+
+    /// Resamples the slice on the next frame, after `volume` changed.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Whether the window is expanded and shows the volume, a collapsed one
+    /// resamples once it's expanded again.
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn update(&mut self, context: &Context, volume: &Volume) {
+        self.layer = self.layer.min(volume.layer_count(self.axis) - 1);
+        let slice = volume.slice(self.axis, self.layer);
+
+        self.contours = self
+            .iso_levels
+            .iter()
+            .map(|&iso| Contour::new(&slice, iso))
+            .collect();
+
+        let (lo, hi) = slice.range();
+        let pixels = slice
+            .data
+            .iter()
+            .map(|&d| Color32::from_gray((255.0 * (d - lo) / (hi - lo).max(f32::EPSILON)) as u8))
+            .collect();
+        let image = ColorImage {
+            size: [slice.width as usize, slice.height as usize],
+            pixels,
+        };
+        self.texture = Some(context.load_texture("slice", image));
+
+        self.slice = Some(slice);
+        self.dirty = false;
+    }
+
+    pub fn ui(&mut self, context: &Context, volume: &Volume) {
+        if self.dirty && self.open {
+            self.update(context, volume);
+        }
+
+        let shown = egui::Window::new("Slice").show(context, |ui| {
+            ui.horizontal(|ui| {
+                for axis in Axis::ALL {
+                    self.dirty |= ui.radio_value(&mut self.axis, axis, axis.name()).changed();
+                }
+            });
+            let layer_count = volume.layer_count(self.axis);
+            self.dirty |= ui
+                .add(egui::Slider::new(&mut self.layer, 0..=layer_count - 1).text("layer"))
+                .changed();
+
+            ui.separator();
+            let mut remove = None;
+            for (i, iso) in self.iso_levels.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    self.dirty |= ui
+                        .add(egui::DragValue::new(iso).speed(0.01).prefix("iso: "))
+                        .changed();
+                    if ui.small_button("x").clicked() {
+                        remove = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = remove {
+                self.iso_levels.remove(i);
+                self.dirty = true;
+            }
+            if ui.button("Add level").clicked() {
+                self.iso_levels
+                    .push(self.iso_levels.last().map_or(0.0, |iso| iso + 0.1));
+                self.dirty = true;
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.svg_path);
+                if ui.button("Export SVG").clicked() {
+                    if let Some(slice) = &self.slice {
+                        if let Err(e) = contour::write_svg(
+                            &self.svg_path,
+                            &self.contours,
+                            slice.width,
+                            slice.height,
+                        ) {
+                            eprintln!("Failed to write {}: {}", self.svg_path, e);
+                        }
+                    }
+                }
+            });
+
+            ui.separator();
+            if let (Some(slice), Some(texture)) = (&self.slice, &self.texture) {
+                let aspect = slice.height as f32 / slice.width as f32;
+                let rect = ui
+                    .image(texture, egui::vec2(VIEW_SIZE, VIEW_SIZE * aspect))
+                    .rect;
+                let painter = ui.painter_at(rect);
+
+                // Samples sit at texel centers.
+                let to_screen = |p: glam::Vec2| {
+                    Pos2::new(
+                        rect.min.x + (p.x + 0.5) / slice.width as f32 * rect.width(),
+                        rect.min.y + (p.y + 0.5) / slice.height as f32 * rect.height(),
+                    )
+                };
+                for (i, contour) in self.contours.iter().enumerate() {
+                    let [r, g, b] = contour::level_color(i, self.contours.len());
+                    let color = Color32::from_rgb(r, g, b);
+                    for line in &contour.lines {
+                        painter.add(egui::Shape::line(
+                            line.iter().copied().map(to_screen).collect(),
+                            Stroke::new(1.5, color),
+                        ));
+                    }
+                    if let Some(p) = contour.label_anchor() {
+                        painter.text(
+                            to_screen(p),
+                            egui::Align2::LEFT_BOTTOM,
+                            format!("{:.2}", contour.iso),
+                            egui::FontId::proportional(12.0),
+                            color,
+                        );
+                    }
+                }
+            }
+        });
+        self.open = shown.is_some_and(|response| response.inner.is_some());
+    }
+}
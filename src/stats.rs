@@ -0,0 +1,112 @@
+use egui::{Align2, Color32, Context, RichText};
+use glam::UVec3;
+
+/// Bytes taken by a texture of `size` texels in `format`, without mips.
+pub(crate) fn texture_bytes(size: UVec3, format: wgpu::TextureFormat) -> u64 {
+    let info = format.describe();
+    let blocks = size.x.div_ceil(info.block_dimensions.0 as u32) as u64
+        * size.y.div_ceil(info.block_dimensions.1 as u32) as u64;
+    blocks * size.z as u64 * info.block_size as u64
+}
+
+/// The value below which `p` of `values` fall, by nearest rank. Sorts
+/// `values`, zero when empty.
+pub(crate) fn percentile(values: &mut [f32], p: f32) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(f32::total_cmp);
+    let rank = (p * values.len() as f32).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// What the overlay shows of the current frame.
+pub(crate) struct FrameStats {
+    /// Recent CPU frame times in milliseconds.
+    pub frame_times: Vec<f32>,
+    pub triangles: u32,
+    pub vertices: u32,
+    pub gpu_bytes: u64,
+}
+
+/// Overlay in the top right corner with frame statistics, toggled with F3.
+pub(crate) struct StatsOverlay {
+    pub visible: bool,
+    adapter: wgpu::AdapterInfo,
+    present_mode: wgpu::PresentMode,
+}
+
+impl StatsOverlay {
+    pub fn new(adapter: wgpu::AdapterInfo, present_mode: wgpu::PresentMode) -> StatsOverlay {
+        StatsOverlay {
+            visible: false,
+            adapter,
+            present_mode,
+        }
+    }
+
+    pub fn ui(&self, context: &Context, mut stats: FrameStats) {
+        if !self.visible {
+            return;
+        }
+        let frame_times = &mut stats.frame_times;
+        let mean = frame_times.iter().sum::<f32>() / frame_times.len().max(1) as f32;
+        let fps = if mean > 0.0 { 1000.0 / mean } else { 0.0 };
+        let lines = [
+            format!("{:.0} FPS", fps),
+            format!(
+                "frame p50 {:.2} ms, p95 {:.2} ms, p99 {:.2} ms",
+                percentile(frame_times, 0.5),
+                percentile(frame_times, 0.95),
+                percentile(frame_times, 0.99)
+            ),
+            format!("{} triangles, {} vertices", stats.triangles, stats.vertices),
+            format!(
+                "{:.1} MiB in buffers and textures",
+                stats.gpu_bytes as f64 / (1 << 20) as f64
+            ),
+            format!("{} ({:?})", self.adapter.name, self.adapter.backend),
+            format!("{:?} present mode", self.present_mode),
+        ];
+
+        egui::Area::new("stats")
+            .anchor(Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+            .interactable(false)
+            .show(context, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    for line in lines {
+                        ui.label(RichText::new(line).monospace().color(Color32::WHITE));
+                    }
+                });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut values: Vec<f32> = (1..=100).rev().map(|i| i as f32).collect();
+        assert_eq!(percentile(&mut values, 0.5), 50.0);
+        assert_eq!(percentile(&mut values, 0.95), 95.0);
+        assert_eq!(percentile(&mut values, 0.99), 99.0);
+        assert_eq!(percentile(&mut values, 1.0), 100.0);
+        assert_eq!(percentile(&mut values, 0.0), 1.0);
+        assert_eq!(percentile(&mut [], 0.5), 0.0);
+    }
+
+    #[test]
+    fn texture_sizes() {
+        let size = UVec3::new(65, 65, 65);
+        assert_eq!(
+            texture_bytes(size, wgpu::TextureFormat::R32Float),
+            65 * 65 * 65 * 4
+        );
+        assert_eq!(
+            texture_bytes(UVec3::new(5, 5, 1), wgpu::TextureFormat::Bc1RgbaUnorm),
+            2 * 2 * 8
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use crate::volume::Volume;
+use glam::{vec3, IVec3, UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Keys closer than this in seconds are the same key.
+const KEY_SNAP: f32 = 1e-3;
+const METABALLS: u32 = 3;
+
+/// What the density shader fills the grid with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Shape {
+    Sphere,
+    /// Balls orbiting the center, melting into each other when they meet.
+    Metaballs,
+    /// A sphere pushed in and out by drifting value noise.
+    Noise,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 3] = [Shape::Sphere, Shape::Metaballs, Shape::Noise];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Sphere => "Sphere",
+            Shape::Metaballs => "Metaballs",
+            Shape::Noise => "Noise",
+        }
+    }
+}
+
+/// The parameters of the field that can be keyframed. Lengths are in grid
+/// widths.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FieldParams {
+    /// Of the sphere, and of each metaball.
+    pub radius: f32,
+    /// Distance of the metaballs from the center.
+    pub orbit: f32,
+    /// Noise features across the grid.
+    pub frequency: f32,
+    /// How far the noise pushes the surface.
+    pub amplitude: f32,
+    /// Turns per second of the metaballs, grid widths per second the noise
+    /// drifts up by.
+    pub speed: f32,
+}
+
+impl Default for FieldParams {
+    fn default() -> FieldParams {
+        FieldParams {
+            radius: 0.3,
+            orbit: 0.2,
+            frequency: 4.0,
+            amplitude: 0.1,
+            speed: 0.25,
+        }
+    }
+}
+
+impl FieldParams {
+    fn lerp(&self, other: &FieldParams, t: f32) -> FieldParams {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        FieldParams {
+            radius: mix(self.radius, other.radius),
+            orbit: mix(self.orbit, other.orbit),
+            frequency: mix(self.frequency, other.frequency),
+            amplitude: mix(self.amplitude, other.amplitude),
+            speed: mix(self.speed, other.speed),
+        }
+    }
+}
+
+/// How the parameters go from one key to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Interpolation {
+    /// Hold each key until the next one.
+    Step,
+    Linear,
+    /// Ease in and out of each key.
+    Smooth,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 3] = [
+        Interpolation::Step,
+        Interpolation::Linear,
+        Interpolation::Smooth,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Interpolation::Step => "Step",
+            Interpolation::Linear => "Linear",
+            Interpolation::Smooth => "Smooth",
+        }
+    }
+
+    fn ease(self, t: f32) -> f32 {
+        match self {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear => t,
+            Interpolation::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Keyframe {
+    /// In seconds.
+    pub time: f32,
+    pub params: FieldParams,
+}
+
+/// `Timeline` as the density shader reads it.
+#[repr(C)]
+#[derive(Clone, Copy, Default, AsBytes, FromBytes)]
+pub(crate) struct DensityUniforms {
+    shape: u32,
+    time: f32,
+    radius: f32,
+    orbit: f32,
+    frequency: f32,
+    amplitude: f32,
+    speed: f32,
+    _padding: f32,
+}
+
+/// The animated field, with keys on its parameters.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Timeline {
+    pub shape: Shape,
+    /// The parameters at `time`, what the sliders edit.
+    pub params: FieldParams,
+    /// Sorted by time.
+    keyframes: Vec<Keyframe>,
+    pub interpolation: Interpolation,
+    /// In seconds, from 0 to `duration`.
+    pub time: f32,
+    pub duration: f32,
+    /// Seconds of animation per second of playback.
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl Default for Timeline {
+    fn default() -> Timeline {
+        Timeline {
+            shape: Shape::Sphere,
+            params: FieldParams::default(),
+            keyframes: Vec::new(),
+            interpolation: Interpolation::Smooth,
+            time: 0.0,
+            duration: 4.0,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+}
+
+impl Timeline {
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// Keys `params` at `time`, replacing a key already there.
+    pub fn set_key(&mut self) {
+        let key = Keyframe {
+            time: self.time,
+            params: self.params,
+        };
+        match self.key_at(self.time) {
+            Some(i) => self.keyframes[i] = key,
+            None => {
+                let i = self.keyframes.partition_point(|k| k.time < self.time);
+                self.keyframes.insert(i, key);
+            }
+        }
+    }
+
+    /// Removes the key at `time`, returns whether there was one.
+    pub fn remove_key(&mut self) -> bool {
+        match self.key_at(self.time) {
+            Some(i) => {
+                self.keyframes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn key_at(&self, time: f32) -> Option<usize> {
+        self.keyframes
+            .iter()
+            .position(|k| (k.time - time).abs() < KEY_SNAP)
+    }
+
+    /// The keyed parameters at `time`, held before the first key and after
+    /// the last. `params` when there are no keys.
+    pub fn params_at(&self, time: f32) -> FieldParams {
+        let keys = &self.keyframes;
+        let next = keys.partition_point(|k| k.time <= time);
+        match (next.checked_sub(1).map(|i| &keys[i]), keys.get(next)) {
+            (None, None) => self.params,
+            (Some(key), None) | (None, Some(key)) => key.params,
+            (Some(from), Some(to)) => {
+                let t = (time - from.time) / (to.time - from.time);
+                from.params.lerp(&to.params, self.interpolation.ease(t))
+            }
+        }
+    }
+
+    /// Moves to `time`, taking the keyed parameters there.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.duration);
+        self.params = self.params_at(self.time);
+    }
+
+    /// Plays `dt` seconds, wrapping around when looping. Returns false once
+    /// it ran into the end.
+    pub fn advance(&mut self, dt: f32) -> bool {
+        let time = self.time + dt * self.speed;
+        if time <= self.duration {
+            self.seek(time);
+            true
+        } else if self.looping && self.duration > 0.0 {
+            self.seek(time % self.duration);
+            true
+        } else {
+            self.seek(self.duration);
+            false
+        }
+    }
+
+    pub fn uniforms(&self) -> DensityUniforms {
+        let params = &self.params;
+        DensityUniforms {
+            shape: self.shape as u32,
+            time: self.time,
+            radius: params.radius,
+            orbit: params.orbit,
+            frequency: params.frequency,
+            amplitude: params.amplitude,
+            speed: params.speed,
+            _padding: 0.0,
+        }
+    }
+
+    /// The density at `p` in normalized [0, 1] grid coordinates. Must be kept
+    /// in sync with `shaders/density.wgsl`.
+    pub fn density(&self, p: Vec3) -> f32 {
+        let params = &self.params;
+        let c = p - Vec3::splat(0.5);
+        match self.shape {
+            Shape::Sphere => c.length() - params.radius,
+            Shape::Metaballs => {
+                let mut sum = 0.0;
+                for i in 0..METABALLS {
+                    let angle = TAU * (params.speed * self.time + i as f32 / METABALLS as f32);
+                    let height = (i as f32 - 1.0) * 0.5 * params.orbit;
+                    let center =
+                        vec3(angle.cos(), 0.0, angle.sin()) * params.orbit + vec3(0.0, height, 0.0);
+                    sum += params.radius * params.radius / (c - center).length_squared().max(1e-6);
+                }
+                // The distance to the ball when there's only one.
+                params.radius * (1.0 / sum.sqrt() - 1.0)
+            }
+            Shape::Noise => {
+                let drift = vec3(0.0, params.speed * self.time, 0.0);
+                let noise = value_noise(p * params.frequency + drift);
+                c.length() - params.radius + params.amplitude * (2.0 * noise - 1.0)
+            }
+        }
+    }
+
+    pub fn field(&self, size: UVec3) -> Volume {
+        Volume::from_fn(size, |p| self.density(p))
+    }
+}
+
+/// Random in [0, 1) for each lattice point.
+fn hash(c: IVec3) -> f32 {
+    let mut h = (c.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (c.y as u32).wrapping_mul(0xd816_3841)
+        ^ (c.z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Smoothly interpolated `hash` of the surrounding lattice points.
+fn value_noise(p: Vec3) -> f32 {
+    let base = p.floor();
+    let f = p - base;
+    let f = f * f * (Vec3::splat(3.0) - 2.0 * f);
+    let base = base.as_ivec3();
+    let corner = |x, y, z| hash(base + IVec3::new(x, y, z));
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), f.x);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), f.x);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), f.x);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), f.x);
+    lerp(lerp(x00, x10, f.y), lerp(x01, x11, f.y), f.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume;
+    use glam::uvec3;
+
+    fn keyed(radii: &[(f32, f32)]) -> Timeline {
+        let mut timeline = Timeline::default();
+        for &(time, radius) in radii {
+            timeline.time = time;
+            timeline.params.radius = radius;
+            timeline.set_key();
+        }
+        timeline
+    }
+
+    #[test]
+    fn the_default_field_is_the_sphere() {
+        let size = uvec3(9, 9, 9);
+        let mut timeline = Timeline::default();
+        assert_eq!(
+            timeline.field(size).samples().collect::<Vec<_>>(),
+            Volume::from_fn(size, volume::sphere)
+                .samples()
+                .collect::<Vec<_>>()
+        );
+        timeline.advance(1.0);
+        assert_eq!(
+            timeline.field(size).samples().collect::<Vec<_>>(),
+            Volume::from_fn(size, volume::sphere)
+                .samples()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn keys_are_interpolated() {
+        let mut timeline = keyed(&[(2.0, 0.4), (0.0, 0.2)]);
+        assert_eq!(timeline.keyframes()[0].time, 0.0);
+
+        timeline.interpolation = Interpolation::Linear;
+        assert!((timeline.params_at(0.5).radius - 0.25).abs() < 1e-6);
+        timeline.interpolation = Interpolation::Smooth;
+        assert!((timeline.params_at(0.5).radius - 0.23125).abs() < 1e-6);
+        assert!((timeline.params_at(1.0).radius - 0.3).abs() < 1e-6);
+        timeline.interpolation = Interpolation::Step;
+        assert_eq!(timeline.params_at(1.9).radius, 0.2);
+        // Held past the last key.
+        assert_eq!(timeline.params_at(3.0).radius, 0.4);
+    }
+
+    #[test]
+    fn keys_are_replaced_and_removed() {
+        let mut timeline = keyed(&[(0.0, 0.2), (1.0, 0.3), (1.0, 0.35)]);
+        assert_eq!(timeline.keyframes().len(), 2);
+        assert_eq!(timeline.keyframes()[1].params.radius, 0.35);
+        timeline.time = 0.5;
+        assert!(!timeline.remove_key());
+        timeline.time = 1.0;
+        assert!(timeline.remove_key());
+        assert_eq!(timeline.keyframes().len(), 1);
+    }
+
+    #[test]
+    fn playback_loops_or_stops() {
+        let mut timeline = keyed(&[(0.0, 0.2), (4.0, 0.4)]);
+        timeline.interpolation = Interpolation::Linear;
+        timeline.seek(0.0);
+        timeline.speed = 2.0;
+        assert!(timeline.advance(0.5));
+        assert_eq!(timeline.time, 1.0);
+        assert!((timeline.params.radius - 0.25).abs() < 1e-6);
+        assert!(timeline.advance(2.0));
+        assert!((timeline.time - 1.0).abs() < 1e-6);
+
+        timeline.looping = false;
+        assert!(!timeline.advance(2.0));
+        assert_eq!(timeline.time, 4.0);
+        assert_eq!(timeline.params.radius, 0.4);
+    }
+
+    #[test]
+    fn metaballs_melt_into_each_other() {
+        let mut timeline = Timeline {
+            shape: Shape::Metaballs,
+            ..Timeline::default()
+        };
+        timeline.params.radius = 0.1;
+        timeline.params.orbit = 0.3;
+        // Apart, each ball is close to its distance field.
+        let center = vec3(0.8, 0.35, 0.5);
+        assert!(timeline.density(center) < -0.09);
+        assert!(timeline.density(center + vec3(0.1, 0.0, 0.0)).abs() < 0.01);
+        // Together, they make one bigger ball.
+        timeline.params.orbit = 0.0;
+        assert!((timeline.density(vec3(0.5, 0.5, 0.5 + 0.1 * 3f32.sqrt()))).abs() < 1e-5);
+    }
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        let path = |i: i32| value_noise(vec3(i as f32 * 0.01, -3.3 + i as f32 * 0.007, 0.5));
+        for i in 1..1000 {
+            let noise = path(i);
+            assert!((0.0..1.0).contains(&noise));
+            assert!((noise - path(i - 1)).abs() < 0.05);
+        }
+        assert_eq!(
+            value_noise(vec3(2.0, -1.0, 5.0)),
+            hash(IVec3::new(2, -1, 5))
+        );
+    }
+}
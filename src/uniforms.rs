@@ -0,0 +1,381 @@
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{self, Poll, Waker};
+use zerocopy::AsBytes;
+
+/// Frames a `UniformRing` cycles through before reusing a part of its buffer.
+pub(crate) const RING_FRAMES: u32 = 3;
+
+/// The CPU side of a `UniformRing`: the objects of the current frame and where
+/// in the buffer they go.
+struct RingStaging<T> {
+    objects: Vec<T>,
+    capacity: u32,
+    alignment: u64,
+    // Bytes between the parts of consecutive frames.
+    stride: u64,
+    frame: u32,
+}
+
+impl<T: AsBytes + Copy> RingStaging<T> {
+    fn new(capacity: u32, alignment: u64) -> RingStaging<T> {
+        let mut staging = RingStaging {
+            objects: Vec::new(),
+            capacity: 0,
+            alignment,
+            stride: 0,
+            frame: 0,
+        };
+        staging.resize(capacity.max(1));
+        staging
+    }
+
+    fn resize(&mut self, capacity: u32) {
+        let bytes = capacity as u64 * mem::size_of::<T>() as u64;
+        self.objects
+            .reserve((capacity as usize).saturating_sub(self.objects.len()));
+        self.capacity = capacity;
+        self.stride = bytes.div_ceil(self.alignment) * self.alignment;
+        self.frame = 0;
+    }
+
+    /// Grows to fit the objects of this frame, returns whether it had to.
+    fn grow(&mut self) -> bool {
+        let needed = self.objects.len() as u32;
+        if needed <= self.capacity {
+            return false;
+        }
+        self.resize(needed.next_power_of_two());
+        true
+    }
+
+    fn push(&mut self, value: T) -> u32 {
+        self.objects.push(value);
+        self.objects.len() as u32 - 1
+    }
+
+    /// Hands the objects of this frame to `write` with their offset in the
+    /// buffer and moves on to the next frame. Returns the offset.
+    fn finish_frame(&mut self, write: impl FnOnce(u64, &[u8])) -> u64 {
+        let offset = self.frame as u64 * self.stride;
+        if !self.objects.is_empty() {
+            write(offset, self.objects.as_bytes());
+        }
+        self.objects.clear();
+        self.frame = (self.frame + 1) % RING_FRAMES;
+        offset
+    }
+}
+
+/// Storage buffer of per-object uniforms, indexed by instance in the shaders,
+/// with a part for each of the last `RING_FRAMES` frames. Objects are pushed
+/// during the frame and written with one upload through the `UploadBelt`. It
+/// grows to the next power of two when a frame pushes more objects than fit.
+///
+/// The ring owns the bind group of its buffer, at binding 0 of `layout` with a
+/// dynamic offset, since growing replaces both.
+pub(crate) struct UniformRing<T> {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    staging: RingStaging<T>,
+}
+
+fn create_ring(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    stride: u64,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("uniform ring"),
+        size: RING_FRAMES as u64 * stride,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: &buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(stride),
+            }),
+        }],
+        label: Some("uniform ring"),
+    });
+    (buffer, bind_group)
+}
+
+impl<T: AsBytes + Copy> UniformRing<T> {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        capacity: u32,
+    ) -> UniformRing<T> {
+        let alignment = device.limits().min_storage_buffer_offset_alignment as u64;
+        let staging = RingStaging::new(capacity, alignment);
+        let (buffer, bind_group) = create_ring(device, layout, staging.stride);
+        UniformRing {
+            buffer,
+            bind_group,
+            staging,
+        }
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Adds an object to this frame, returns its instance index.
+    pub fn push(&mut self, uniforms: T) -> u32 {
+        self.staging.push(uniforms)
+    }
+
+    /// Number of objects pushed this frame.
+    pub fn len(&self) -> u32 {
+        self.staging.objects.len() as u32
+    }
+
+    /// Writes the objects of this frame, growing the buffer if they don't fit,
+    /// and starts the next one. Returns the dynamic offset to bind this
+    /// frame's part with.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uploads: &mut UploadBelt,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> u32 {
+        if self.staging.grow() {
+            (self.buffer, self.bind_group) = create_ring(device, layout, self.staging.stride);
+        }
+        let buffer = &self.buffer;
+        self.staging
+            .finish_frame(|offset, bytes| uploads.write(device, encoder, buffer, offset, bytes))
+            as u32
+    }
+
+    pub fn bytes(&self) -> u64 {
+        RING_FRAMES as u64 * self.staging.stride
+    }
+}
+
+/// Smallest staging chunk, frames that upload more get a bigger one.
+const CHUNK_BYTES: u64 = 64 * 1024;
+
+/// Which staging chunk the uploads of a frame go to, apart from the buffers.
+#[derive(Default)]
+struct Chunks {
+    sizes: Vec<u64>,
+    /// Mapped and not used by a frame the GPU may still copy out of.
+    free: Vec<bool>,
+    /// Chunks of the current frame, the last one takes the next upload.
+    active: Vec<usize>,
+    /// Bytes of the last active chunk taken.
+    used: u64,
+}
+
+impl Chunks {
+    /// Finds room for `size` bytes, returns the chunk and the offset in it.
+    /// The chunk is new when it is one past the last.
+    fn alloc(&mut self, size: u64) -> (usize, u64) {
+        let size = size.div_ceil(wgpu::MAP_ALIGNMENT) * wgpu::MAP_ALIGNMENT;
+        if let Some(&chunk) = self.active.last() {
+            if self.used + size <= self.sizes[chunk] {
+                self.used += size;
+                return (chunk, self.used - size);
+            }
+        }
+        let chunk = match (0..self.sizes.len()).find(|&i| self.free[i] && self.sizes[i] >= size) {
+            Some(chunk) => chunk,
+            None => {
+                self.sizes.push(size.next_power_of_two().max(CHUNK_BYTES));
+                self.free.push(true);
+                self.sizes.len() - 1
+            }
+        };
+        self.free[chunk] = false;
+        self.active.push(chunk);
+        self.used = size;
+        (chunk, 0)
+    }
+
+    /// Hands over the chunks of this frame, which wait for the GPU from now
+    /// on, and starts the next frame.
+    fn close(&mut self, mut closed: impl FnMut(usize)) {
+        for chunk in self.active.drain(..) {
+            closed(chunk);
+        }
+        self.used = 0;
+    }
+
+    /// `chunk` is mapped again.
+    fn release(&mut self, chunk: usize) {
+        self.free[chunk] = true;
+    }
+}
+
+type Mapping = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Uploads through staging chunks that are mapped again once the GPU copied
+/// out of them, instead of the staging memory `Queue::write_buffer` takes for
+/// every call. The uploads of a frame share a chunk, so new chunks are only
+/// created until there are enough for the frames in flight.
+///
+/// Write during the frame, `finish` before submitting the encoders written to
+/// and `recall` after.
+#[derive(Default)]
+pub(crate) struct UploadBelt {
+    chunks: Chunks,
+    buffers: Vec<wgpu::Buffer>,
+    mappings: Vec<Option<Mapping>>,
+}
+
+impl UploadBelt {
+    /// Copies `bytes` to `offset` in `target` when `encoder` runs.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: u64,
+        bytes: &[u8],
+    ) {
+        if bytes.is_empty() {
+            return;
+        }
+        let size = bytes.len() as u64;
+        let (chunk, at) = self.chunks.alloc(size);
+        if chunk == self.buffers.len() {
+            self.buffers
+                .push(device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("upload belt"),
+                    size: self.chunks.sizes[chunk],
+                    usage: wgpu::BufferUsages::MAP_WRITE | wgpu::BufferUsages::COPY_SRC,
+                    mapped_at_creation: true,
+                }));
+            self.mappings.push(None);
+        }
+        let buffer = &self.buffers[chunk];
+        buffer
+            .slice(at..at + size)
+            .get_mapped_range_mut()
+            .copy_from_slice(bytes);
+        encoder.copy_buffer_to_buffer(buffer, at, target, offset, size);
+    }
+
+    /// Call before submitting the encoders written to.
+    pub fn finish(&mut self) {
+        for &chunk in &self.chunks.active {
+            self.buffers[chunk].unmap();
+        }
+    }
+
+    /// Call after submitting. Maps the chunks of this frame again and takes
+    /// back the ones that are.
+    pub fn recall(&mut self, device: &wgpu::Device) {
+        let (buffers, mappings) = (&self.buffers, &mut self.mappings);
+        self.chunks.close(|chunk| {
+            mappings[chunk] = Some(Box::pin(
+                buffers[chunk].slice(..).map_async(wgpu::MapMode::Write),
+            ));
+        });
+
+        device.poll(wgpu::Maintain::Poll);
+        let mut context = task::Context::from_waker(Waker::noop());
+        for (chunk, mapping) in self.mappings.iter_mut().enumerate() {
+            if let Some(Poll::Ready(result)) =
+                mapping.as_mut().map(|m| m.as_mut().poll(&mut context))
+            {
+                *mapping = None;
+                // Only fails when the device is lost, the chunk stays taken.
+                if result.is_ok() {
+                    self.chunks.release(chunk);
+                }
+            }
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.chunks.sizes.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_cycle_through_the_ring() {
+        let mut staging = RingStaging::<[f32; 16]>::new(3, 256);
+        assert_eq!(staging.stride, 256);
+        let mut offsets = Vec::new();
+        for _ in 0..4 {
+            assert_eq!(staging.push([0.0; 16]), 0);
+            assert_eq!(staging.push([1.0; 16]), 1);
+            offsets.push(staging.finish_frame(|_, bytes| assert_eq!(bytes.len(), 128)));
+        }
+        assert_eq!(offsets, [0, 256, 512, 0]);
+    }
+
+    #[test]
+    fn grows_to_fit_a_frame() {
+        let mut staging = RingStaging::<[f32; 16]>::new(1, 256);
+        assert!(!staging.grow());
+        for i in 0..5 {
+            assert_eq!(staging.push([i as f32; 16]), i);
+        }
+        assert!(staging.grow());
+        assert_eq!(staging.capacity, 8);
+        assert_eq!(staging.stride, 512);
+        let mut written = Vec::new();
+        let offset = staging.finish_frame(|_, bytes| written.extend_from_slice(bytes));
+        assert_eq!(offset, 0);
+        assert_eq!(written.len(), 5 * 64);
+        assert_eq!(&written[4 * 64..][..4], 4.0f32.as_bytes());
+        assert!(!staging.grow());
+    }
+
+    /// Uploads the way `App::draw` does for 1000 triangles and 16 surfaces,
+    /// with the GPU done copying two frames later.
+    #[test]
+    fn chunks_are_only_created_until_the_frames_in_flight_have_theirs() {
+        const FRAMES: usize = 60;
+        let mut chunks = Chunks::default();
+        let mut in_flight = Vec::new();
+        let mut created = Vec::new();
+        for _ in 0..FRAMES {
+            let before = chunks.sizes.len();
+            for _ in 0..16 {
+                chunks.alloc(64);
+            }
+            chunks.alloc(1000 * 64);
+            let mut closed = Vec::new();
+            chunks.close(|chunk| closed.push(chunk));
+            in_flight.push(closed);
+            if in_flight.len() > 2 {
+                for chunk in in_flight.remove(0) {
+                    chunks.release(chunk);
+                }
+            }
+            created.push(chunks.sizes.len() - before);
+        }
+        assert!(created[3..].iter().all(|&n| n == 0), "{:?}", created);
+        assert_eq!(chunks.sizes.len(), 3);
+    }
+
+    #[test]
+    fn uploads_share_a_chunk_until_it_is_full() {
+        let mut chunks = Chunks::default();
+        assert_eq!(chunks.alloc(20), (0, 0));
+        assert_eq!(chunks.alloc(8), (0, 24));
+        assert_eq!(chunks.alloc(CHUNK_BYTES), (1, 0));
+        assert_eq!(chunks.sizes, [CHUNK_BYTES, CHUNK_BYTES]);
+        assert_eq!(chunks.alloc(CHUNK_BYTES + 1), (2, 0));
+        assert_eq!(chunks.sizes[2], 2 * CHUNK_BYTES);
+        chunks.close(|_| {});
+        chunks.release(1);
+        assert_eq!(chunks.alloc(8), (1, 0));
+    }
+}
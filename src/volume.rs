@@ -0,0 +1,304 @@
+use crate::chunk::BRICK_CELLS;
+use crate::extract::{cell_count, Region};
+use glam::{uvec3, vec3, UVec3, Vec3};
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 3] = [Axis::X, Axis::Y, Axis::Z];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Axis::X => "X",
+            Axis::Y => "Y",
+            Axis::Z => "Z",
+        }
+    }
+}
+
+/// Density of the default field, `p` is in normalized [0, 1] grid coordinates.
+/// The same as the default `Timeline`.
+pub(crate) fn sphere(p: Vec3) -> f32 {
+    (p - Vec3::splat(0.5)).length() - 0.3
+}
+
+/// Samples along each side of a brick's slab: its `BRICK_CELLS` cells and
+/// the face it shares with the next brick.
+pub(crate) const SLAB_SAMPLES: u32 = BRICK_CELLS + 1;
+const SLAB_LEN: usize = (SLAB_SAMPLES * SLAB_SAMPLES * SLAB_SAMPLES) as usize;
+
+/// Bricks along each axis of a grid of `size` samples, at least one.
+pub(crate) fn brick_count(size: UVec3) -> UVec3 {
+    let cells = cell_count(size).max(UVec3::ONE);
+    (cells + UVec3::splat(BRICK_CELLS - 1)) / BRICK_CELLS
+}
+
+/// Box of the bricks out of `bricks` whose slabs hold any sample in
+/// `samples`.
+pub(crate) fn bricks_holding(samples: Region, bricks: UVec3) -> Region {
+    Region {
+        min: (samples.min.max(UVec3::ONE) - UVec3::ONE) / BRICK_CELLS,
+        max: ((samples.max.max(UVec3::ONE) - UVec3::ONE) / BRICK_CELLS + UVec3::ONE).min(bricks),
+    }
+}
+
+/// Bricks along one axis whose slabs hold sample `p`: its own and, on the
+/// face it shares with the previous brick, that one.
+fn sharing(p: u32, bricks: u32) -> RangeInclusive<u32> {
+    let own = (p / BRICK_CELLS).min(bricks - 1);
+    let first = if p.is_multiple_of(BRICK_CELLS) && p > 0 {
+        p / BRICK_CELLS - 1
+    } else {
+        own
+    };
+    first..=own
+}
+
+/// CPU copy of a scalar grid, stored like `BrickAtlas`: one slab of
+/// `SLAB_SAMPLES`³ per brick, x-major, so the samples on the faces between
+/// bricks are kept once per brick.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Volume {
+    pub size: UVec3,
+    bricks: UVec3,
+    slabs: Vec<Vec<f32>>,
+}
+
+impl Volume {
+    /// All samples 0.
+    pub fn new(size: UVec3) -> Volume {
+        let bricks = brick_count(size);
+        Volume {
+            size,
+            bricks,
+            slabs: vec![vec![0.0; SLAB_LEN]; (bricks.x * bricks.y * bricks.z) as usize],
+        }
+    }
+
+    pub fn from_fn(size: UVec3, f: impl Fn(Vec3) -> f32) -> Volume {
+        let mut volume = Volume::new(size);
+        for z in 0..size.z {
+            for y in 0..size.y {
+                for x in 0..size.x {
+                    let p = (vec3(x as f32, y as f32, z as f32) + 0.5) / size.as_vec3();
+                    volume.set(x, y, z, f(p));
+                }
+            }
+        }
+        volume
+    }
+
+    /// The grid of `size` with `samples` in x-major order.
+    pub fn from_samples(size: UVec3, samples: impl IntoIterator<Item = f32>) -> Volume {
+        let mut volume = Volume::new(size);
+        let mut samples = samples.into_iter();
+        let all = Region {
+            min: UVec3::ZERO,
+            max: size,
+        };
+        for p in all.cells() {
+            volume.set(p.x, p.y, p.z, samples.next().unwrap());
+        }
+        volume
+    }
+
+    /// Index of a sample in x-major order, for arrays laid out like
+    /// `samples`.
+    pub fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        (x + self.size.x * (y + self.size.y * z)) as usize
+    }
+
+    pub fn len(&self) -> usize {
+        (self.size.x * self.size.y * self.size.z) as usize
+    }
+
+    /// The samples in x-major order.
+    pub fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        let all = Region {
+            min: UVec3::ZERO,
+            max: self.size,
+        };
+        all.cells().map(move |p| self.get(p.x, p.y, p.z))
+    }
+
+    fn slab_index(&self, brick: UVec3) -> usize {
+        (brick.x + self.bricks.x * (brick.y + self.bricks.y * brick.z)) as usize
+    }
+
+    /// Samples of `brick`'s slab, x-major. The ones past the grid are 0.
+    pub fn slab(&self, brick: UVec3) -> &[f32] {
+        &self.slabs[self.slab_index(brick)]
+    }
+
+    pub fn slab_mut(&mut self, brick: UVec3) -> &mut [f32] {
+        let i = self.slab_index(brick);
+        &mut self.slabs[i]
+    }
+
+    pub fn get(&self, x: u32, y: u32, z: u32) -> f32 {
+        let p = uvec3(x, y, z);
+        let brick = (p / BRICK_CELLS).min(self.bricks - UVec3::ONE);
+        self.slab(brick)[slab_offset(p - brick * BRICK_CELLS)]
+    }
+
+    /// Sets the sample in every slab holding it.
+    pub fn set(&mut self, x: u32, y: u32, z: u32, value: f32) {
+        let p = uvec3(x, y, z);
+        for bz in sharing(z, self.bricks.z) {
+            for by in sharing(y, self.bricks.y) {
+                for bx in sharing(x, self.bricks.x) {
+                    let brick = uvec3(bx, by, bz);
+                    let offset = slab_offset(p - brick * BRICK_CELLS);
+                    self.slab_mut(brick)[offset] = value;
+                }
+            }
+        }
+    }
+
+    /// Central difference gradient in grid units, one-sided at the borders.
+    pub fn gradient(&self, x: u32, y: u32, z: u32) -> Vec3 {
+        let p = [x, y, z];
+        let mut gradient = [0.0; 3];
+        for (axis, g) in gradient.iter_mut().enumerate() {
+            let (mut lo, mut hi) = (p, p);
+            lo[axis] = p[axis].saturating_sub(1);
+            hi[axis] = (p[axis] + 1).min(self.size[axis] - 1);
+            if hi[axis] > lo[axis] {
+                *g = (self.get(hi[0], hi[1], hi[2]) - self.get(lo[0], lo[1], lo[2]))
+                    / (hi[axis] - lo[axis]) as f32;
+            }
+        }
+        Vec3::from(gradient)
+    }
+
+    /// Trilinear interpolation at `p` in grid coordinates, clamped to the grid.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let max = (self.size.max(UVec3::ONE) - UVec3::ONE).as_vec3();
+        let p = p.clamp(Vec3::ZERO, max);
+        let lo = p.floor().as_uvec3();
+        let hi = (lo + UVec3::ONE).min(max.as_uvec3());
+        let t = p - lo.as_vec3();
+        let at = |x: bool, y: bool, z: bool| {
+            self.get(
+                if x { hi.x } else { lo.x },
+                if y { hi.y } else { lo.y },
+                if z { hi.z } else { lo.z },
+            )
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let y0 = lerp(
+            lerp(at(false, false, false), at(true, false, false), t.x),
+            lerp(at(false, true, false), at(true, true, false), t.x),
+            t.y,
+        );
+        let y1 = lerp(
+            lerp(at(false, false, true), at(true, false, true), t.x),
+            lerp(at(false, true, true), at(true, true, true), t.x),
+            t.y,
+        );
+        lerp(y0, y1, t.z)
+    }
+
+    /// Trilinearly resamples the field onto a grid of `size`, covering the
+    /// same [0, 1] cube.
+    pub fn resample(&self, size: UVec3) -> Volume {
+        if size == self.size {
+            return self.clone();
+        }
+        let scale = self.size.as_vec3();
+        Volume::from_fn(size, |p| self.sample(p * scale - 0.5))
+    }
+
+    pub fn layer_count(&self, axis: Axis) -> u32 {
+        match axis {
+            Axis::X => self.size.x,
+            Axis::Y => self.size.y,
+            Axis::Z => self.size.z,
+        }
+    }
+
+    /// Samples the plane perpendicular to `axis` at `layer`. The slice's u/v
+    /// axes are the remaining two axes in x, y, z order.
+    pub fn slice(&self, axis: Axis, layer: u32) -> Slice {
+        let (width, height) = match axis {
+            Axis::X => (self.size.y, self.size.z),
+            Axis::Y => (self.size.x, self.size.z),
+            Axis::Z => (self.size.x, self.size.y),
+        };
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for v in 0..height {
+            for u in 0..width {
+                data.push(match axis {
+                    Axis::X => self.get(layer, u, v),
+                    Axis::Y => self.get(u, layer, v),
+                    Axis::Z => self.get(u, v, layer),
+                });
+            }
+        }
+        Slice {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Offset of sample `p` of a slab.
+fn slab_offset(p: UVec3) -> usize {
+    (p.x + SLAB_SAMPLES * (p.y + SLAB_SAMPLES * p.z)) as usize
+}
+
+pub(crate) struct Slice {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl Slice {
+    pub fn get(&self, u: u32, v: u32) -> f32 {
+        self.data[(u + v * self.width) as usize]
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        self.data
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &d| (lo.min(d), hi.max(d)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_samples_are_set_in_every_slab() {
+        let mut volume = Volume::new(UVec3::splat(2 * BRICK_CELLS + 1));
+        assert_eq!(volume.bricks, UVec3::splat(2));
+        let face = BRICK_CELLS;
+        volume.set(face, face, 3, 1.0);
+        for brick in [
+            uvec3(0, 0, 0),
+            uvec3(1, 0, 0),
+            uvec3(0, 1, 0),
+            uvec3(1, 1, 0),
+        ] {
+            let p = uvec3(face, face, 3) - brick * BRICK_CELLS;
+            assert_eq!(volume.slab(brick)[slab_offset(p)], 1.0);
+        }
+        assert_eq!(volume.samples().filter(|&d| d == 1.0).count(), 1);
+
+        let samples = Region {
+            min: uvec3(face, 0, 0),
+            max: uvec3(face + 1, 1, 1),
+        };
+        let bricks = bricks_holding(samples, volume.bricks);
+        assert_eq!((bricks.min, bricks.max), (uvec3(0, 0, 0), uvec3(2, 1, 1)));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::extract::Region;
+use crate::volume::Volume;
+use glam::{UVec3, Vec3};
+use std::io::{self, Read, Write};
+
+const MAGIC: [u8; 4] = *b"ISVF";
+const VERSION: u16 = 1;
+/// Samples along each side of a brick, the bricks at the far borders are
+/// smaller.
+pub(crate) const BRICK_SAMPLES: u32 = 16;
+/// Magic, version, data type, padding, size, spacing, iso, brick size, then
+/// the CRC of all that.
+const HEADER_SIZE: usize = 4 + 2 + 1 + 1 + 12 + 12 + 4 + 4 + 4;
+/// Bigger grids are taken for a corrupt header rather than allocated.
+pub(crate) const MAX_SAMPLES: u64 = 1 << 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DataType {
+    F32,
+}
+
+impl DataType {
+    fn to_byte(self) -> u8 {
+        match self {
+            DataType::F32 => 0,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<DataType> {
+        match byte {
+            0 => Some(DataType::F32),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Header {
+    pub size: UVec3,
+    /// Distance between neighbouring samples, the grid spans the unit cube.
+    pub spacing: Vec3,
+    pub dtype: DataType,
+    pub iso: f32,
+    pub brick_samples: u32,
+}
+
+impl Header {
+    pub fn new(size: UVec3, iso: f32) -> Header {
+        Header {
+            size,
+            spacing: 1.0 / size.as_vec3(),
+            dtype: DataType::F32,
+            iso,
+            brick_samples: BRICK_SAMPLES.min(size.max_element()),
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[self.dtype.to_byte(), 0]);
+        for v in self.size.to_array() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in self.spacing.to_array() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.iso.to_le_bytes());
+        bytes.extend_from_slice(&self.brick_samples.to_le_bytes());
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> io::Result<Header> {
+        if bytes[..4] != MAGIC {
+            return Err(invalid_data("not a volume file"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let f32_at = |i: usize| f32::from_bits(u32_at(i));
+        if crc32fast::hash(&bytes[..HEADER_SIZE - 4]) != u32_at(HEADER_SIZE - 4) {
+            return Err(invalid_data("corrupt volume header"));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "volume file version {} isn't supported",
+                version
+            )));
+        }
+        let dtype = DataType::from_byte(bytes[6])
+            .ok_or_else(|| invalid_data(format!("unknown data type {}", bytes[6])))?;
+        let header = Header {
+            size: UVec3::new(u32_at(8), u32_at(12), u32_at(16)),
+            spacing: Vec3::new(f32_at(20), f32_at(24), f32_at(28)),
+            dtype,
+            iso: f32_at(32),
+            brick_samples: u32_at(36),
+        };
+        let samples = header.size.x as u64 * header.size.y as u64 * header.size.z as u64;
+        // Bricks bigger than the grid would overflow their bounds.
+        let bricks = 1..=header.size.max_element();
+        if samples == 0 || samples > MAX_SAMPLES || !bricks.contains(&header.brick_samples) {
+            return Err(invalid_data("corrupt volume header"));
+        }
+        Ok(header)
+    }
+
+    fn bricks(&self) -> Vec<Region> {
+        let (size, step) = (self.size, self.brick_samples);
+        let mut bricks = Vec::new();
+        for z in (0..size.z).step_by(step as usize) {
+            for y in (0..size.y).step_by(step as usize) {
+                for x in (0..size.x).step_by(step as usize) {
+                    let min = UVec3::new(x, y, z);
+                    bricks.push(Region {
+                        min,
+                        max: (min + step).min(size),
+                    });
+                }
+            }
+        }
+        bricks
+    }
+}
+
+fn invalid_data(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Each sample's bits minus the previous sample's, with the bytes of the
+/// differences grouped by significance. Smooth fields then give LZ4 long runs
+/// of similar high bytes, and the bits come back exactly.
+fn encode_brick(samples: &[f32]) -> Vec<u8> {
+    let n = samples.len();
+    let mut planes = vec![0; n * 4];
+    let mut previous = 0u32;
+    for (i, sample) in samples.iter().enumerate() {
+        let bits = sample.to_bits();
+        for (plane, byte) in bits
+            .wrapping_sub(previous)
+            .to_le_bytes()
+            .into_iter()
+            .enumerate()
+        {
+            planes[plane * n + i] = byte;
+        }
+        previous = bits;
+    }
+    planes
+}
+
+fn decode_brick(planes: &[u8]) -> Vec<f32> {
+    let n = planes.len() / 4;
+    let mut previous = 0u32;
+    (0..n)
+        .map(|i| {
+            let delta = [0, 1, 2, 3].map(|plane| planes[plane * n + i]);
+            previous = previous.wrapping_add(u32::from_le_bytes(delta));
+            f32::from_bits(previous)
+        })
+        .collect()
+}
+
+/// Compresses `samples` the way the bricks of volume files are.
+pub(crate) fn compress_samples(samples: &[f32]) -> Vec<u8> {
+    lz4_flex::block::compress(&encode_brick(samples))
+}
+
+/// The `count` samples `compress_samples` turned into `compressed`, none if
+/// it doesn't hold that many.
+pub(crate) fn decompress_samples(compressed: &[u8], count: usize) -> Option<Vec<f32>> {
+    let raw_len = count * 4;
+    lz4_flex::block::decompress(compressed, raw_len)
+        .ok()
+        .filter(|planes| planes.len() == raw_len)
+        .map(|planes| decode_brick(&planes))
+}
+
+/// Writes `volume` as a header followed by its bricks, each as its
+/// compressed length, the CRC of the compressed bytes and the bytes.
+pub(crate) fn write(mut writer: impl Write, volume: &Volume, iso: f32) -> io::Result<()> {
+    let header = Header::new(volume.size, iso);
+    writer.write_all(&header.to_bytes())?;
+    for brick in header.bricks() {
+        let samples: Vec<f32> = brick.cells().map(|p| volume.get(p.x, p.y, p.z)).collect();
+        let compressed = compress_samples(&samples);
+        writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+        writer.write_all(&crc32fast::hash(&compressed).to_le_bytes())?;
+        writer.write_all(&compressed)?;
+    }
+    writer.flush()
+}
+
+/// Reads a volume file a brick at a time, so the samples can go to the GPU
+/// before the whole file is decoded.
+pub(crate) struct VolumeReader<R> {
+    reader: R,
+    header: Header,
+    bricks: Vec<Region>,
+    next: usize,
+}
+
+impl<R: Read> VolumeReader<R> {
+    pub fn new(mut reader: R) -> io::Result<VolumeReader<R>> {
+        let mut bytes = [0; HEADER_SIZE];
+        reader.read_exact(&mut bytes)?;
+        let header = Header::from_bytes(&bytes)?;
+        Ok(VolumeReader {
+            reader,
+            header,
+            bricks: header.bricks(),
+            next: 0,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The next brick and its samples stored x-major, none after the last.
+    pub fn next_brick(&mut self) -> io::Result<Option<(Region, Vec<f32>)>> {
+        let brick = match self.bricks.get(self.next) {
+            Some(&brick) => brick,
+            None => return Ok(None),
+        };
+        let mut record = [0; 8];
+        self.reader.read_exact(&mut record)?;
+        let len = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(record[4..].try_into().unwrap());
+        let size = brick.size();
+        let count = (size.x * size.y * size.z) as usize;
+        if len > lz4_flex::block::get_maximum_output_size(count * 4) {
+            return Err(invalid_data(format!("corrupt brick {}", self.next)));
+        }
+
+        let mut compressed = vec![0; len];
+        self.reader.read_exact(&mut compressed)?;
+        if crc32fast::hash(&compressed) != crc {
+            return Err(invalid_data(format!("corrupt brick {}", self.next)));
+        }
+        let samples = decompress_samples(&compressed, count)
+            .ok_or_else(|| invalid_data(format!("corrupt brick {}", self.next)))?;
+        self.next += 1;
+        Ok(Some((brick, samples)))
+    }
+
+    /// Reads the remaining bricks into a volume.
+    pub fn read_volume(mut self) -> io::Result<Volume> {
+        let size = self.header.size;
+        let mut volume = Volume::new(size);
+        while let Some((brick, samples)) = self.next_brick()? {
+            insert(&mut volume, brick, &samples);
+        }
+        Ok(volume)
+    }
+}
+
+/// Copies the samples of `brick`, stored x-major, into `volume`.
+pub(crate) fn insert(volume: &mut Volume, brick: Region, samples: &[f32]) {
+    for (p, &sample) in brick.cells().zip(samples) {
+        volume.set(p.x, p.y, p.z, sample);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume;
+    use glam::uvec3;
+
+    fn write_to_vec(volume: &Volume, iso: f32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut bytes, volume, iso).unwrap();
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> io::Result<Volume> {
+        VolumeReader::new(bytes)?.read_volume()
+    }
+
+    #[test]
+    fn round_trips_losslessly() {
+        // Not a multiple of the brick size, with values that don't survive
+        // anything but copying the bits.
+        let mut volume = Volume::from_fn(uvec3(33, 17, 20), volume::sphere);
+        volume.set(0, 0, 0, f32::NAN);
+        volume.set(1, 0, 0, -0.0);
+        volume.set(2, 0, 0, f32::INFINITY);
+        volume.set(3, 0, 0, f32::MIN_POSITIVE / 2.0);
+        let bytes = write_to_vec(&volume, 0.25);
+
+        let reader = VolumeReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.header().size, volume.size);
+        assert_eq!(reader.header().iso, 0.25);
+        assert_eq!(reader.header().dtype, DataType::F32);
+        let loaded = reader.read_volume().unwrap();
+        let bits = |volume: &Volume| -> Vec<u32> { volume.samples().map(f32::to_bits).collect() };
+        assert_eq!(bits(&loaded), bits(&volume));
+    }
+
+    #[test]
+    fn streams_brick_by_brick() {
+        let volume = Volume::from_fn(uvec3(20, 20, 20), volume::sphere);
+        let bytes = write_to_vec(&volume, 0.0);
+        let mut reader = VolumeReader::new(&bytes[..]).unwrap();
+        let mut bricks = 0;
+        while let Some((brick, samples)) = reader.next_brick().unwrap() {
+            let size = brick.size();
+            assert_eq!(samples.len() as u32, size.x * size.y * size.z);
+            assert_eq!(
+                samples[0],
+                volume.get(brick.min.x, brick.min.y, brick.min.z)
+            );
+            bricks += 1;
+        }
+        assert_eq!(bricks, 8);
+    }
+
+    #[test]
+    fn compresses_smooth_fields() {
+        let volume = Volume::from_fn(uvec3(65, 65, 65), |p| p.x - 0.5);
+        let bytes = write_to_vec(&volume, 0.0);
+        assert!(bytes.len() * 4 < volume.len() * 4, "{} bytes", bytes.len());
+    }
+
+    #[test]
+    fn truncated_files_fail() {
+        let volume = Volume::from_fn(uvec3(18, 9, 9), volume::sphere);
+        let bytes = write_to_vec(&volume, 0.0);
+        for len in (0..bytes.len())
+            .step_by(7)
+            .chain([HEADER_SIZE, bytes.len() - 1])
+        {
+            let e = read(&bytes[..len]).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "at {}", len);
+        }
+    }
+
+    #[test]
+    fn corrupt_files_fail() {
+        let volume = Volume::from_fn(uvec3(18, 9, 9), volume::sphere);
+        let bytes = write_to_vec(&volume, 0.0);
+        // Every byte of the header and a sample of the bricks, flipped.
+        for i in (0..HEADER_SIZE).chain((HEADER_SIZE..bytes.len()).step_by(5)) {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x10;
+            assert!(read(&corrupt).is_err(), "byte {} flipped", i);
+        }
+        assert!(read(b"not a volume file at all, but long enough for a header").is_err());
+
+        // A header with a valid CRC, but bricks past the end of the grid.
+        let mut header = Header::new(volume.size, 0.0);
+        header.brick_samples = u32::MAX;
+        let e = read(&header.to_bytes()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let small = Header::new(uvec3(5, 6, 7), 0.0);
+        assert_eq!(small.brick_samples, 7);
+    }
+}